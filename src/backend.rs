@@ -0,0 +1,422 @@
+use std::{
+    fmt,
+    ops::{BitOr, BitOrAssign},
+    path::Path,
+    sync::{Arc, Mutex, MutexGuard},
+    time::Duration,
+};
+
+use notify::{Config, EventHandler, RecursiveMode, Watcher as _, WatcherKind};
+
+#[cfg(all(feature = "fanotify", target_os = "linux"))]
+use crate::fanotify::FanotifyWatcher;
+#[cfg(target_os = "macos")]
+use crate::fsevents::FsEventsWatcher;
+#[cfg(windows)]
+use crate::windows::ReadDirectoryWatcher;
+use crate::{
+    hashpoll::HashPoller,
+    raw::{self, SharedRaw},
+    Error,
+};
+
+/// The mechanism a [`Watcher`](crate::Watcher) uses to learn about changes, see
+/// [`WatcherBuilder::backend`](crate::WatcherBuilder::backend).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Backend {
+    /// The native notifications of the operating system, such as inotify on Linux,
+    /// FSEvents on macOS and `ReadDirectoryChangesW` on Windows.
+    #[default]
+    Auto,
+    /// The inotify API of Linux and Android.
+    Inotify,
+    /// The kqueue API of FreeBSD, OpenBSD, NetBSD and DragonFly BSD.
+    Kqueue,
+    /// The FSEvents API of macOS, with a stream created with the given latency and flags.
+    ///
+    /// [`Backend::Auto`] uses the stream of `notify`, which has a latency of zero and the
+    /// [`FsEventsFlags::default`] flags, so every change is reported per file and as soon as
+    /// possible. A longer latency lets FSEvents coalesce the changes of busy trees, trading
+    /// responsiveness for fewer wake-ups of the watcher.
+    ///
+    /// ```no_run
+    /// use std::time::Duration;
+    /// use watchit::{Backend, FsEventsFlags, Watcher};
+    ///
+    /// let watcher = Watcher::builder()
+    ///     .backend(Backend::FsEvents {
+    ///         latency: Duration::from_millis(500),
+    ///         flags: FsEventsFlags::default() | FsEventsFlags::IGNORE_SELF,
+    ///     })
+    ///     .build(|result| println!("{result:?}"))
+    ///     .unwrap();
+    /// watcher.watch("/Users/me/Projects").unwrap();
+    /// ```
+    FsEvents {
+        /// How long FSEvents waits after a change before reporting it, with the changes
+        /// made in the meantime.
+        latency: Duration,
+        /// The flags the event stream is created with.
+        flags: FsEventsFlags,
+    },
+    /// The `ReadDirectoryChangesW` API of Windows, reading the changes of each watched
+    /// directory into a buffer of `buffer_size` bytes.
+    ///
+    /// [`Backend::Auto`] uses the watcher of `notify`, whose buffer of 16 KiB is easily
+    /// overflowed by heavy churn in a single directory, such as a large checkout, and which
+    /// then drops the changes without notice. A larger buffer holds more changes, and when
+    /// it still overflows the handler receives an [`Error::Overflow`], after which polling
+    /// the directory, or verifying it with
+    /// [`WatcherBuilder::verify`](crate::WatcherBuilder::verify), catches the lost changes.
+    ///
+    /// ```no_run
+    /// use watchit::{Backend, Watcher};
+    ///
+    /// let watcher = Watcher::builder()
+    ///     .backend(Backend::Windows {
+    ///         buffer_size: 1024 * 1024,
+    ///         network_drives: true,
+    ///     })
+    ///     .build(|result| println!("{result:?}"))
+    ///     .unwrap();
+    /// watcher.watch(r"C:\Projects").unwrap();
+    /// ```
+    Windows {
+        /// The size in bytes of the buffer the changes of each watched directory are read
+        /// into.
+        buffer_size: u32,
+        /// Whether the buffer is limited to 64 KiB for directories on network drives, which
+        /// refuse larger buffers, instead of failing to read their changes.
+        network_drives: bool,
+    },
+    /// The fanotify API of Linux, which watches the whole mount point of each watched path.
+    ///
+    /// Unlike inotify, fanotify needs no watch per directory, so it can cover an entire file
+    /// system for host monitoring. It reports the files that are written to, as
+    /// modifications, but not those that are created, removed or renamed, and only the
+    /// events below the watched paths are delivered. It needs the `CAP_SYS_ADMIN`
+    /// capability, without which building the watcher fails, and the `fanotify` feature.
+    Fanotify {
+        /// Whether opening a file below a watched path is reported as well.
+        ///
+        /// The opening process waits until the watcher has allowed the open, which it always
+        /// does, so opens can be audited but not denied.
+        permission_events: bool,
+    },
+    /// Scans the watched paths for changes every `interval`.
+    ///
+    /// Polling works wherever the paths can be read, including network shares mounted over
+    /// NFS or SMB, on which native notifications don't report changes made by other
+    /// machines. It costs a scan of every watched path per interval, and reports changes up
+    /// to one interval late. Writes that preserve a file's modification time are only
+    /// detected with [`WatcherBuilder::poll_hashing`](crate::WatcherBuilder::poll_hashing).
+    Poll {
+        /// How often the watched paths are scanned.
+        interval: Duration,
+    },
+}
+
+impl Backend {
+    /// Returns `true` if the back-end can be used on the current platform.
+    ///
+    /// Building a watcher with a back-end that is not available fails.
+    pub fn is_available(self) -> bool {
+        match self {
+            Self::Auto | Self::Poll { .. } => true,
+            Self::Inotify => cfg!(any(target_os = "linux", target_os = "android")),
+            Self::Kqueue => cfg!(any(
+                target_os = "freebsd",
+                target_os = "openbsd",
+                target_os = "netbsd",
+                target_os = "dragonfly"
+            )),
+            Self::FsEvents { .. } => cfg!(target_os = "macos"),
+            Self::Windows { .. } => cfg!(target_os = "windows"),
+            Self::Fanotify { .. } => cfg!(all(feature = "fanotify", target_os = "linux")),
+        }
+    }
+}
+
+/// The flags of the event stream of [`Backend::FsEvents`], combined with `|`.
+///
+/// They stand for the `kFSEventStreamCreateFlag` constants of the same names. The default
+/// flags are those `notify` uses, [`FsEventsFlags::FILE_EVENTS`] and
+/// [`FsEventsFlags::NO_DEFER`].
+///
+/// ```
+/// use watchit::FsEventsFlags;
+///
+/// let flags = FsEventsFlags::default() | FsEventsFlags::IGNORE_SELF;
+/// assert!(flags.contains(FsEventsFlags::FILE_EVENTS));
+/// assert!(!flags.contains(FsEventsFlags::WATCH_ROOT));
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct FsEventsFlags(pub(crate) u32);
+
+impl FsEventsFlags {
+    /// No flags: only the directories containing changes are reported, once the latency
+    /// has passed.
+    pub const NONE: Self = Self(0);
+    /// Reports the first change after a quiet period right away, and the following ones
+    /// once the latency has passed.
+    pub const NO_DEFER: Self = Self(0x02);
+    /// Reports when a watched path, or a directory above it, is moved or removed.
+    pub const WATCH_ROOT: Self = Self(0x04);
+    /// Leaves out the changes made by the watching process.
+    pub const IGNORE_SELF: Self = Self(0x08);
+    /// Reports the files that changed instead of the directories containing them.
+    pub const FILE_EVENTS: Self = Self(0x10);
+
+    /// Returns `true` if all of `flags` are set.
+    pub fn contains(self, flags: Self) -> bool {
+        self.0 & flags.0 == flags.0
+    }
+}
+
+impl BitOr for FsEventsFlags {
+    type Output = Self;
+
+    fn bitor(self, other: Self) -> Self {
+        Self(self.0 | other.0)
+    }
+}
+
+impl BitOrAssign for FsEventsFlags {
+    fn bitor_assign(&mut self, other: Self) {
+        self.0 |= other.0;
+    }
+}
+
+impl Default for FsEventsFlags {
+    fn default() -> Self {
+        Self::FILE_EVENTS | Self::NO_DEFER
+    }
+}
+
+/// A source of changes that takes the place of the operating system's notifications, see
+/// [`WatcherBuilder::custom_backend`](crate::WatcherBuilder::custom_backend).
+///
+/// A back-end is told which paths to watch, and reports their changes to the
+/// [`EventSink`] it was created with. Its events are debounced, filtered and delivered like
+/// those of the built-in back-ends, so a test double, a FUSE file system or a remote agent
+/// can drive an unmodified [`Watcher`](crate::Watcher).
+pub trait WatchBackend: Send + 'static {
+    /// Starts reporting the changes of `path`, and of everything below it if `recursive` is
+    /// set.
+    fn watch(&mut self, path: &Path, recursive: bool) -> Result<(), Error>;
+
+    /// Stops reporting the changes of `path`.
+    fn unwatch(&mut self, path: &Path) -> Result<(), Error>;
+}
+
+/// Where a [`WatchBackend`] reports the changes it sees.
+///
+/// Clones of a sink report to the same watcher, so a back-end can report from as many
+/// threads as it needs.
+#[derive(Clone)]
+pub struct EventSink {
+    handler: Arc<Mutex<Box<dyn EventHandler>>>,
+}
+
+impl EventSink {
+    pub(crate) fn new(handler: impl EventHandler) -> Self {
+        Self {
+            handler: Arc::new(Mutex::new(Box::new(handler))),
+        }
+    }
+
+    /// Reports a change, in the form of the back-ends of `notify`.
+    pub fn send(&self, event: notify::Event) {
+        self.lock().handle_event(Ok(event));
+    }
+
+    /// Reports an error of the back-end, which reaches the watcher's handler.
+    pub fn error(&self, error: Error) {
+        self.lock().handle_event(Err(error.into_notify()));
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Box<dyn EventHandler>> {
+        self.handler
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl fmt::Debug for EventSink {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("EventSink").finish_non_exhaustive()
+    }
+}
+
+/// Creates the custom back-end of each debouncer of a watcher.
+pub(crate) type Factory =
+    Arc<dyn Fn(EventSink) -> Result<Box<dyn WatchBackend>, Error> + Send + Sync + 'static>;
+
+/// The back-end of the debouncers of a watcher.
+#[derive(Clone)]
+pub(crate) enum Source {
+    Builtin(Backend),
+    /// Polling that compares the content of the files of up to `max_size` bytes.
+    HashPoll {
+        interval: Duration,
+        max_size: u64,
+    },
+    Custom(Factory),
+}
+
+impl From<Backend> for Source {
+    fn from(backend: Backend) -> Self {
+        Self::Builtin(backend)
+    }
+}
+
+/// A back-end chosen when the watcher is created rather than when it is compiled.
+///
+/// The debouncer creates its back-end itself and only hands it the event handler, so the
+/// back-end is idle until [`AnyWatcher::start`] is given its [`Source`] through
+/// [`Debouncer::watcher`](notify_debouncer_full::Debouncer::watcher).
+pub(crate) struct AnyWatcher {
+    /// The debouncer's handler, until the back-end is started.
+    handler: Option<Box<dyn EventHandler>>,
+    config: Config,
+    inner: Option<Inner>,
+}
+
+/// The back-end of a started [`AnyWatcher`].
+enum Inner {
+    Native(Box<dyn notify::Watcher + Send>),
+    Custom(Box<dyn WatchBackend>),
+}
+
+impl AnyWatcher {
+    /// Starts the back-end of `source`, whose raw events pass through `raw` before they
+    /// reach the debouncer.
+    pub(crate) fn start(&mut self, source: &Source, raw: &SharedRaw) -> Result<(), Error> {
+        let Some(mut handler) = self.handler.take() else {
+            return Err(Error::generic("the back-end is already started"));
+        };
+        let handler = raw::counting(raw, move |result| handler.handle_event(result));
+        self.inner = Some(Inner::new(source, handler, self.config)?);
+        Ok(())
+    }
+
+    fn inner(&mut self) -> notify::Result<&mut Inner> {
+        self.inner
+            .as_mut()
+            .ok_or_else(|| notify::Error::generic("the back-end is not started"))
+    }
+}
+
+impl Inner {
+    fn new(
+        source: &Source,
+        event_handler: impl EventHandler,
+        config: Config,
+    ) -> notify::Result<Self> {
+        let backend = match source {
+            Source::Builtin(backend) => *backend,
+            Source::HashPoll { interval, max_size } => {
+                let sink = EventSink::new(event_handler);
+                return HashPoller::new(sink, *interval, *max_size)
+                    .map(|poller| Self::Custom(Box::new(poller)))
+                    .map_err(Error::into_notify);
+            }
+            Source::Custom(factory) => {
+                let sink = EventSink::new(event_handler);
+                return factory(sink).map(Self::Custom).map_err(Error::into_notify);
+            }
+        };
+        let inner: Box<dyn notify::Watcher + Send> = match backend {
+            Backend::Auto => Box::new(notify::RecommendedWatcher::new(event_handler, config)?),
+            #[cfg(any(target_os = "linux", target_os = "android"))]
+            Backend::Inotify => Box::new(notify::INotifyWatcher::new(event_handler, config)?),
+            #[cfg(any(
+                target_os = "freebsd",
+                target_os = "openbsd",
+                target_os = "netbsd",
+                target_os = "dragonfly"
+            ))]
+            Backend::Kqueue => Box::new(notify::KqueueWatcher::new(event_handler, config)?),
+            #[cfg(target_os = "macos")]
+            Backend::FsEvents { latency, flags } => {
+                let sink = EventSink::new(event_handler);
+                return Ok(Self::Custom(Box::new(FsEventsWatcher::new(
+                    sink, latency, flags,
+                ))));
+            }
+            #[cfg(windows)]
+            Backend::Windows {
+                buffer_size,
+                network_drives,
+            } => {
+                let sink = EventSink::new(event_handler);
+                return Ok(Self::Custom(Box::new(ReadDirectoryWatcher::new(
+                    sink,
+                    buffer_size,
+                    network_drives,
+                ))));
+            }
+            #[cfg(all(feature = "fanotify", target_os = "linux"))]
+            Backend::Fanotify { permission_events } => {
+                let sink = EventSink::new(event_handler);
+                return FanotifyWatcher::new(sink, permission_events)
+                    .map(|watcher| Self::Custom(Box::new(watcher)))
+                    .map_err(Error::into_notify);
+            }
+            Backend::Poll { interval } => Box::new(notify::PollWatcher::new(
+                event_handler,
+                config.with_poll_interval(interval),
+            )?),
+            #[allow(unreachable_patterns)]
+            unavailable => {
+                return Err(notify::Error::generic(&format!(
+                    "the {unavailable:?} back-end is not available on this platform"
+                )));
+            }
+        };
+        Ok(Self::Native(inner))
+    }
+}
+
+impl notify::Watcher for AnyWatcher {
+    fn new<F: EventHandler>(event_handler: F, config: Config) -> notify::Result<Self> {
+        Ok(Self {
+            handler: Some(Box::new(event_handler)),
+            config,
+            inner: None,
+        })
+    }
+
+    fn watch(&mut self, path: &Path, recursive_mode: RecursiveMode) -> notify::Result<()> {
+        match self.inner()? {
+            Inner::Native(inner) => inner.watch(path, recursive_mode),
+            Inner::Custom(inner) => inner
+                .watch(path, recursive_mode == RecursiveMode::Recursive)
+                .map_err(Error::into_notify),
+        }
+    }
+
+    fn unwatch(&mut self, path: &Path) -> notify::Result<()> {
+        match self.inner()? {
+            Inner::Native(inner) => inner.unwatch(path),
+            Inner::Custom(inner) => inner.unwatch(path).map_err(Error::into_notify),
+        }
+    }
+
+    fn configure(&mut self, option: Config) -> notify::Result<bool> {
+        match &mut self.inner {
+            Some(Inner::Native(inner)) => inner.configure(option),
+            Some(Inner::Custom(_)) => Ok(false),
+            None => {
+                self.config = option;
+                Ok(true)
+            }
+        }
+    }
+
+    // The kind is only known once a back-end was chosen, which the debouncer never asks.
+    fn kind() -> WatcherKind {
+        notify::RecommendedWatcher::kind()
+    }
+}
@@ -0,0 +1,643 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    future::Future,
+    sync::{mpsc, Arc, Mutex},
+    time::Duration,
+};
+
+use notify::RecursiveMode;
+use notify_debouncer_full::{DebounceEventHandler, DebouncedEvent};
+
+use crate::{
+    backend::Source,
+    dispatch::{self, Dispatcher, Handler},
+    event::EventConfig,
+    flush,
+    middleware::Chain,
+    pool::Pool,
+    rate::RateLimit,
+    raw::{self, SharedRaw},
+    state::{self, State, StateCell},
+    stream::{self, AsyncHandler, InFlight, QueueLimit},
+    AsyncWatcher, Backend, CancellationToken, Error, Event, EventHandler, EventKindMask,
+    EventMiddleware, EventSink, FilterSet, Overflow, Spawn, WatchBackend, Watcher,
+    DEFAULT_DEBOUNCE, DEFAULT_NETWORK_POLL,
+};
+
+/// A predicate deciding whether a debounced event is delivered to the handler.
+pub type EventFilter = Box<dyn Fn(&DebouncedEvent) -> bool + Send + 'static>;
+
+/// A predicate deciding whether an event is delivered to the handler, see
+/// [`WatcherBuilder::filter_event`].
+pub type EventPredicate = Box<dyn Fn(&Event) -> bool + Send + 'static>;
+
+/// A builder for configuring a [`Watcher`] before it is created.
+///
+/// The `WatcherBuilder` collects the watcher's options through fluent setters and creates
+/// the watcher with [`WatcherBuilder::build`]. Options that are not set keep the same
+/// defaults as [`Watcher::new`].
+///
+/// ```no_run
+/// use std::time::Duration;
+/// use watchit::WatcherBuilder;
+///
+/// let watcher = WatcherBuilder::new()
+///     .debounce(Duration::from_millis(250))
+///     .recursive(true)
+///     .filter(|event| !event.paths.iter().any(|path| path.ends_with("Cargo.lock")))
+///     .build(|result| println!("{result:?}"))
+///     .unwrap();
+/// watcher.watch(".").unwrap();
+/// ```
+pub struct WatcherBuilder {
+    pub(crate) debounce: Duration,
+    mode: RecursiveMode,
+    filter: Option<EventFilter>,
+    event_filter: Option<EventPredicate>,
+    filters: FilterSet,
+    kinds: EventKindMask,
+    poll_fallback: Option<Duration>,
+    verify: Option<Duration>,
+    network_poll: Option<Duration>,
+    poll_hashing: Option<u64>,
+    rewatch_recreated: bool,
+    events: EventConfig,
+    raw: Option<Box<dyn notify::EventHandler>>,
+    workers: Option<usize>,
+    queue: Option<QueueLimit>,
+    cancellation: Option<CancellationToken>,
+    in_flight: Option<InFlight>,
+    rate_limit: Option<RateLimit>,
+    middleware: Chain,
+    backend: Source,
+}
+
+impl WatcherBuilder {
+    /// Creates a new builder with the default options.
+    ///
+    /// # Returns
+    /// A new instance of the builder.
+    pub fn new() -> Self {
+        Self {
+            debounce: DEFAULT_DEBOUNCE,
+            mode: RecursiveMode::NonRecursive,
+            filter: None,
+            event_filter: None,
+            filters: FilterSet::default(),
+            kinds: EventKindMask::ALL,
+            poll_fallback: None,
+            verify: None,
+            network_poll: Some(DEFAULT_NETWORK_POLL),
+            poll_hashing: None,
+            rewatch_recreated: false,
+            events: EventConfig::default(),
+            raw: None,
+            workers: None,
+            queue: None,
+            cancellation: None,
+            in_flight: None,
+            rate_limit: None,
+            middleware: Chain::default(),
+            backend: Backend::Auto.into(),
+        }
+    }
+
+    /// Sets how long events are debounced before the handler is triggered.
+    ///
+    /// # Arguments
+    /// * `debounce` - The debounce duration. Defaults to 2 seconds.
+    pub fn debounce(mut self, debounce: Duration) -> Self {
+        self.debounce = debounce;
+        self
+    }
+
+    /// Sets whether [`Watcher::watch`] registers paths recursively.
+    ///
+    /// # Arguments
+    /// * `recursive` - `true` to watch directories and everything below them. Defaults to `false`.
+    pub fn recursive(mut self, recursive: bool) -> Self {
+        self.mode = if recursive {
+            RecursiveMode::Recursive
+        } else {
+            RecursiveMode::NonRecursive
+        };
+        self
+    }
+
+    /// Sets a predicate that decides which debounced events reach the handler.
+    ///
+    /// Events for which the predicate returns `false` are dropped. If every event in a
+    /// debounced batch is dropped, the handler is not called at all.
+    ///
+    /// # Arguments
+    /// * `filter` - The predicate to apply to each debounced event.
+    pub fn filter(mut self, filter: impl Fn(&DebouncedEvent) -> bool + Send + 'static) -> Self {
+        self.filter = Some(Box::new(filter));
+        self
+    }
+
+    /// Sets a predicate on the crate's own [`Event`]s that decides which events reach the
+    /// handler.
+    ///
+    /// Unlike [`WatcherBuilder::filter`], the predicate sees the simplified
+    /// [`EventKind`](crate::EventKind), the watched path the event was reported for, its tags
+    /// and a snapshot of its [`Metadata`](crate::Metadata), so any rule on path, kind or
+    /// metadata fits in one closure:
+    ///
+    /// ```no_run
+    /// use watchit::{EventKind, FileType, WatcherBuilder};
+    ///
+    /// let (watcher, events) = WatcherBuilder::new()
+    ///     .filter_event(|event| {
+    ///         event.kind != EventKind::AttributesChanged
+    ///             && event.metadata.as_ref().is_none_or(|m| m.file_type == FileType::File)
+    ///     })
+    ///     .build_channel()
+    ///     .unwrap();
+    /// ```
+    ///
+    /// The predicate runs on the debouncer thread after every other filter, and applies to
+    /// every handler of the watcher. Its events always carry metadata, or `None` for removed
+    /// paths, but never diffs of content, and their sequence number is `0`, as events are
+    /// only numbered once they have passed every filter.
+    ///
+    /// # Arguments
+    /// * `filter` - The predicate to apply to each event.
+    pub fn filter_event(mut self, filter: impl Fn(&Event) -> bool + Send + 'static) -> Self {
+        self.event_filter = Some(Box::new(filter));
+        self
+    }
+
+    /// Sets glob patterns deciding which paths reach the handler.
+    ///
+    /// Events whose paths the set does not allow are dropped on the debouncer thread,
+    /// after the [`WatcherBuilder::kinds`] mask and before the [`WatcherBuilder::filter`],
+    /// so that excluded trees such as build output never wake the handler. A rename is
+    /// delivered if either of its paths is allowed. The set applies to every handler of the
+    /// watcher.
+    ///
+    /// # Arguments
+    /// * `filters` - The patterns to apply, see [`FilterSet`]. Defaults to a set that allows
+    ///   every path.
+    pub fn filter_set(mut self, filters: FilterSet) -> Self {
+        self.filters = filters;
+        self
+    }
+
+    /// Sets which kinds of events reach the handler.
+    ///
+    /// Events of other kinds are dropped before the [`WatcherBuilder::filter`] is applied,
+    /// so that uninteresting kinds such as file accesses never wake the handler. The mask
+    /// applies to every handler of the watcher, including the handlers of single watches,
+    /// and includes the synthetic events of [`WatchOptions::initial_scan`](crate::WatchOptions::initial_scan).
+    ///
+    /// # Arguments
+    /// * `kinds` - The kinds to deliver, see [`EventKind`](crate::EventKind). Defaults to
+    ///   [`EventKindMask::ALL`].
+    pub fn kinds(mut self, kinds: EventKindMask) -> Self {
+        self.kinds = kinds;
+        self
+    }
+
+    /// Polls paths for changes once the operating system runs out of file watches.
+    ///
+    /// Without a fallback, watching a path beyond the OS limit fails with
+    /// [`Error::WatchLimit`]. With a fallback, such paths are polled instead, which keeps them
+    /// watched at the cost of some latency and periodic disk access. Polled paths are marked
+    /// in [`WatchInfo::polled`](crate::WatchInfo::polled).
+    ///
+    /// # Arguments
+    /// * `interval` - How often polled paths are scanned for changes.
+    pub fn poll_fallback(mut self, interval: Duration) -> Self {
+        self.poll_fallback = Some(interval);
+        self
+    }
+
+    /// Sets whether polling compares the content of files as well as their metadata.
+    ///
+    /// Polling normally detects changes by their modification time, which misses writes that
+    /// preserve it, and writes within the same timestamp on file systems with coarse
+    /// timestamps. With hashing, the content of every file of up to `max_size` bytes is
+    /// hashed on each scan, and a file whose hash changed is reported as modified. Hashing
+    /// applies to [`Backend::Poll`], the [`WatcherBuilder::poll_fallback`], the
+    /// [`WatcherBuilder::network_poll`] and the [`WatcherBuilder::verify`] sweep. It reads
+    /// every small file on each scan, so it suits small trees and long intervals.
+    ///
+    /// # Arguments
+    /// * `max_size` - The largest file, in bytes, whose content is compared, or `None` to
+    ///   compare only metadata. Defaults to `None`.
+    pub fn poll_hashing(mut self, max_size: Option<u64>) -> Self {
+        self.poll_hashing = max_size;
+        self
+    }
+
+    /// Sets how often paths on network file systems are polled instead of watched natively.
+    ///
+    /// Native notifications only report the changes made by the local machine, so on an NFS
+    /// or SMB share, or on a FUSE file system, the changes made elsewhere would never arrive.
+    /// The watcher detects such file systems when a path is watched, logs a warning and polls
+    /// the path instead, which is marked as `polled` in its [`WatchInfo`](crate::WatchInfo).
+    /// Detection is supported on Linux, macOS and the BSDs. Watchers using
+    /// [`Backend::Poll`] or a [custom back-end](WatcherBuilder::custom_backend) are left
+    /// alone.
+    ///
+    /// # Arguments
+    /// * `interval` - How often paths on network file systems are scanned, or `None` to
+    ///   watch them natively anyway. Defaults to [`DEFAULT_NETWORK_POLL`].
+    pub fn network_poll(mut self, interval: Option<Duration>) -> Self {
+        self.network_poll = interval;
+        self
+    }
+
+    /// Sweeps the watched paths for changes the back-end missed.
+    ///
+    /// Native notifications are fast, but they can miss changes, for example when the
+    /// operating system's event queue overflows, or when another machine changes a file on a
+    /// network share. In this hybrid mode, the paths are also polled every `interval`, and the
+    /// changes the back-end did not report within the interval are delivered as regular
+    /// events. A long interval keeps the cost of the sweep low, since it only has to catch
+    /// the rare missed change. Paths served by the [`WatcherBuilder::poll_fallback`] are
+    /// polled already and are not swept again.
+    ///
+    /// # Arguments
+    /// * `interval` - How often the watched paths are swept.
+    pub fn verify(mut self, interval: Duration) -> Self {
+        self.verify = Some(interval);
+        self
+    }
+
+    /// Sets whether watched directories are watched again after being removed and recreated.
+    ///
+    /// When enabled, the removal of a watched directory turns its watch into a pending watch
+    /// on the directory's parent, see [`Watcher::watch_pending`]. Once the directory is
+    /// created again, it is re-registered with its original mode, so recursive watches cover
+    /// the new tree. This suits build output directories that are wiped on every build.
+    ///
+    /// # Arguments
+    /// * `rewatch` - `true` to re-establish watches on recreated directories. Defaults to
+    ///   `false`.
+    pub fn rewatch_recreated(mut self, rewatch: bool) -> Self {
+        self.rewatch_recreated = rewatch;
+        self
+    }
+
+    /// Sets whether events carry a snapshot of their path's metadata.
+    ///
+    /// The snapshot is taken right before the event is delivered, so that handlers don't
+    /// have to race a `fs::metadata` call against further changes. It is only attached to the
+    /// crate's own [`Event`](crate::Event)s, see [`WatcherBuilder::build_with_event_handler`].
+    ///
+    /// # Arguments
+    /// * `metadata` - `true` to attach [`Metadata`](crate::Metadata) to events. Defaults to
+    ///   `false`.
+    pub fn metadata(mut self, metadata: bool) -> Self {
+        self.events.metadata = metadata;
+        self
+    }
+
+    /// Sets whether events of small files carry the file's content.
+    ///
+    /// The content is read right after the events are debounced, before they are delivered,
+    /// so that handlers see the version that triggered the event rather than racing a later
+    /// write with their own `fs::read`. It is only attached to the crate's own
+    /// [`Event`](crate::Event)s, see [`WatcherBuilder::build_with_event_handler`], and never
+    /// to events of removed files or of directories.
+    ///
+    /// Events of these files also carry the encoding of the file, told from its first
+    /// bytes, and the encoding it had before if the change flipped it, such as from UTF-8
+    /// to UTF-16 or from text to binary, see
+    /// [`Event::previous_encoding`](crate::Event::previous_encoding). A file's encoding is
+    /// first remembered with its first event.
+    ///
+    /// # Arguments
+    /// * `max_size` - The largest file, in bytes, whose content is attached. Larger files
+    ///   carry no content.
+    pub fn contents(mut self, max_size: u64) -> Self {
+        self.events.contents = Some(max_size);
+        self
+    }
+
+    /// Sets a handler receiving every raw event of the back-end as soon as it arrives.
+    ///
+    /// Raw events are passed on before they are debounced, filtered or correlated, so the
+    /// handler sees each change immediately, in the form the operating system reported it.
+    /// This includes events for directories watched on the caller's behalf, such as the
+    /// parent of a [pending](Watcher::watch_pending) watch. The watcher's regular handler
+    /// still receives the debounced events. The raw handler runs on the back-end's thread
+    /// and must not call back into the watcher.
+    ///
+    /// # Arguments
+    /// * `handler` - The handler to call with each raw event.
+    pub fn raw(mut self, handler: impl notify::EventHandler) -> Self {
+        self.raw = Some(Box::new(handler));
+        self
+    }
+
+    /// Sets the mechanism the watcher uses to learn about changes.
+    ///
+    /// By default, the watcher uses the native notifications of the operating system. On
+    /// network shares, these miss the changes made by other machines, so a watcher for
+    /// an NFS or SMB mount should poll instead, and so should a container in which inotify
+    /// is restricted. A specific native API can be forced as well, see
+    /// [`Backend::is_available`]. The debouncer and every other option work the same on
+    /// top of any back-end.
+    ///
+    /// ```no_run
+    /// use std::time::Duration;
+    /// use watchit::{Backend, WatcherBuilder};
+    ///
+    /// let (watcher, events) = WatcherBuilder::new()
+    ///     .backend(Backend::Poll { interval: Duration::from_secs(5) })
+    ///     .build_channel()
+    ///     .unwrap();
+    /// watcher.watch("/mnt/share/inbox").unwrap();
+    /// ```
+    ///
+    /// # Arguments
+    /// * `backend` - The back-end to use. Defaults to [`Backend::Auto`]. Building the
+    ///   watcher fails if the back-end is not available on the current platform.
+    pub fn backend(mut self, backend: Backend) -> Self {
+        self.backend = backend.into();
+        self
+    }
+
+    /// Uses a custom source of changes instead of one of the built-in back-ends.
+    ///
+    /// `create` is called with the sink the back-end reports its changes to, once for every
+    /// debouncer of the watcher, that is once, plus once for each distinct
+    /// [`WatchOptions::debounce`](crate::WatchOptions::debounce) duration. The paths that
+    /// run out of OS watches are still polled, see [`WatcherBuilder::poll_fallback`].
+    ///
+    /// # Arguments
+    /// * `create` - Creates a back-end reporting to the given sink, see [`WatchBackend`].
+    pub fn custom_backend<B: WatchBackend>(
+        mut self,
+        create: impl Fn(EventSink) -> Result<B, Error> + Send + Sync + 'static,
+    ) -> Self {
+        self.backend = Source::Custom(Arc::new(move |sink| {
+            create(sink).map(|backend| Box::new(backend) as Box<dyn WatchBackend>)
+        }));
+        self
+    }
+
+    /// Runs the handlers on a pool of worker threads instead of the debouncer thread.
+    ///
+    /// By default, handlers run on the debouncer thread, so a slow handler delays every
+    /// later delivery. With a pool, the debouncer thread only hands each batch to a worker
+    /// and carries on collecting events. Each handler, including the handlers of
+    /// [`Watcher::watch_with`] and [`Watcher::watch_once`], is pinned to one worker in turn,
+    /// so its batches are still handled one at a time and in order, while a slow handler
+    /// only delays the handlers sharing its worker.
+    ///
+    /// # Arguments
+    /// * `workers` - The number of worker threads. A pool of `0` workers has one.
+    pub fn workers(mut self, workers: usize) -> Self {
+        self.workers = Some(workers);
+        self
+    }
+
+    /// Bounds the queues of the streams and iterators receiving the watcher's events.
+    ///
+    /// By default, the events of [`WatcherBuilder::build_async`], [`Watcher::subscribe`] and
+    /// [`Watcher::events`] are queued until they are consumed, so a stalled consumer makes
+    /// the queue grow without limit. With a bound, at most `capacity` events and errors are
+    /// queued, and `overflow` decides what happens once the queue is full. The channel of
+    /// [`Watcher::channel`] stays unbounded; use [`Watcher::events`] for a bounded iterator
+    /// instead.
+    ///
+    /// # Arguments
+    /// * `capacity` - The number of queued items. A queue of `0` items holds one.
+    /// * `overflow` - What happens to events that arrive while the queue is full.
+    pub fn queue(mut self, capacity: usize, overflow: Overflow) -> Self {
+        self.queue = Some(QueueLimit { capacity, overflow });
+        self
+    }
+
+    /// Limits how many events of each path are delivered within a window.
+    ///
+    /// Debouncing merges the events of a burst, but a file that is written continuously,
+    /// such as a log file appended to many times a second, still produces an event every
+    /// debounce period. With a rate limit, at most `max_events` events of each path are
+    /// delivered within `per`, starting with the path's first event. The events beyond the
+    /// limit are coalesced into one, the newest, which is delivered when the window ends.
+    /// Its [`Event::raw_events`](crate::Event::raw_events) covers every event it stands for.
+    ///
+    /// # Arguments
+    /// * `max_events` - The number of events of a path delivered per window. A limit of `0`
+    ///   delivers one.
+    /// * `per` - The length of the window.
+    pub fn rate_limit(mut self, max_events: usize, per: Duration) -> Self {
+        self.rate_limit = Some(RateLimit { max_events, per });
+        self
+    }
+
+    /// Adds a middleware that every event passes through before it reaches the handlers.
+    ///
+    /// Middlewares run in the order they were added, after the kinds, filters and
+    /// predicates of the watcher, and before events are held back by
+    /// [`WatcherBuilder::rate_limit`] or [`WatchOptions::settle`](crate::WatchOptions::settle).
+    /// Each receives the crate's own [`Event`], with [metadata](WatcherBuilder::metadata)
+    /// and [contents](WatcherBuilder::contents) if they are enabled, and decides whether it
+    /// is passed on and in what form. The handlers of the crate's events, streams and
+    /// channels receive the events as the last middleware passed them on, while handlers of
+    /// the back-end's events, see [`WatcherBuilder::build`], receive them with the paths and
+    /// kind the last middleware gave them.
+    ///
+    /// Middlewares run without the watcher locked, so they may use the watcher, for example
+    /// to pause it. Events a middleware causes are passed through the middlewares once the
+    /// current batch has been processed.
+    ///
+    /// # Arguments
+    /// * `middleware` - The middleware to add, see [`EventMiddleware`].
+    pub fn middleware(mut self, middleware: impl EventMiddleware) -> Self {
+        self.middleware.push(middleware);
+        self
+    }
+
+    /// Shuts the watcher down once `token` is cancelled.
+    ///
+    /// Once cancelled, the watcher stops delivering events and drops its handlers, so that
+    /// pending calls to [`AsyncWatcher::next`], the iterators of [`Watcher::events`] and the
+    /// receivers of [`Watcher::channel`] and [`Watcher::errors`] end instead of waiting for
+    /// events that never come. Events still queued in streams and iterators are dropped.
+    /// The watcher itself only stops watching once it is dropped.
+    ///
+    /// # Arguments
+    /// * `token` - The token that shuts the watcher down.
+    pub fn cancellation(mut self, token: CancellationToken) -> Self {
+        self.cancellation = Some(token);
+        self
+    }
+
+    /// Creates the configured file watcher.
+    ///
+    /// # Arguments
+    /// * `handler` - The debounce event handler to call when a file change is detected.
+    ///
+    /// # Returns
+    /// A `Result` containing either a new instance of the file watcher, or an `Error` if the
+    /// underlying OS watcher could not be created.
+    pub fn build(self, handler: impl DebounceEventHandler) -> Result<Watcher, Error> {
+        self.build_dispatching(Handler::Debounced(Box::new(handler)))
+    }
+
+    /// Creates the configured file watcher, delivering the crate's own [`Event`](crate::Event)s.
+    ///
+    /// # Arguments
+    /// * `handler` - The event handler to call when a file change is detected.
+    ///
+    /// # Returns
+    /// A `Result` containing either a new instance of the file watcher, or an `Error` if the
+    /// underlying OS watcher could not be created.
+    pub fn build_with_event_handler(self, handler: impl EventHandler) -> Result<Watcher, Error> {
+        let events = self.events.clone();
+        self.build_dispatching(Handler::Events(Box::new(handler), events))
+    }
+
+    /// Creates the configured file watcher together with a channel receiving its events, see
+    /// [`Watcher::channel`].
+    ///
+    /// # Returns
+    /// A `Result` containing either the file watcher and the receiver of its events, or an
+    /// `Error` if the underlying OS watcher could not be created.
+    pub fn build_channel(self) -> Result<(Watcher, mpsc::Receiver<Event>), Error> {
+        let (sender, receiver) = mpsc::channel();
+        let watcher = self.build_with_event_handler(sender)?;
+        Ok((watcher, receiver))
+    }
+
+    /// Creates the configured file watcher, delivering its events asynchronously.
+    ///
+    /// # Returns
+    /// A `Result` containing either a new instance of the file watcher, or an `Error` if the
+    /// underlying OS watcher could not be created.
+    pub fn build_async(self) -> Result<AsyncWatcher, Error> {
+        let (sender, stream) = stream::event_stream(self.queue);
+        if let Some(token) = &self.cancellation {
+            stream.cancel_on(token);
+        }
+        let watcher = self.build_with_event_handler(sender)?;
+        Ok(AsyncWatcher::from_parts(watcher, stream))
+    }
+
+    /// Creates the configured file watcher with an asynchronous handler, see
+    /// [`Watcher::new_async`].
+    ///
+    /// # Arguments
+    /// * `spawn` - The executor running the futures of the handler.
+    /// * `handler` - The handler to call with each event, or with each error reported
+    ///   instead.
+    ///
+    /// # Returns
+    /// A `Result` containing either a new instance of the file watcher, or an `Error` if the
+    /// underlying OS watcher could not be created.
+    pub fn build_async_handler<F>(
+        mut self,
+        spawn: impl Spawn,
+        handler: impl FnMut(Result<Event, Error>) -> F + Send + 'static,
+    ) -> Result<Watcher, Error>
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        let in_flight = InFlight::default();
+        self.in_flight = Some(in_flight.clone());
+        self.build_with_event_handler(AsyncHandler {
+            spawn,
+            handler,
+            in_flight,
+        })
+    }
+
+    fn build_dispatching(self, handler: Handler) -> Result<Watcher, Error> {
+        let mut dispatcher = Dispatcher::new(handler, self.filter, self.kinds, self.events.clone());
+        if let Some(workers) = self.workers {
+            dispatcher = dispatcher.with_pool(Pool::new(workers));
+        }
+        if let Some(queue) = self.queue {
+            dispatcher = dispatcher.with_queue(queue);
+        }
+        if let Some(filter) = self.event_filter {
+            dispatcher = dispatcher.with_event_filter(filter);
+        }
+        if !self.filters.is_empty() {
+            dispatcher = dispatcher.with_filters(self.filters);
+        }
+        if let Some(token) = &self.cancellation {
+            dispatcher = dispatcher.with_cancellation(token.clone());
+        }
+        if let Some(in_flight) = self.in_flight {
+            dispatcher = dispatcher.with_in_flight(in_flight);
+        }
+        if let Some(limit) = self.rate_limit {
+            dispatcher = dispatcher.with_rate_limit(limit);
+        }
+        if !self.middleware.is_empty() {
+            dispatcher = dispatcher.with_middleware(self.middleware);
+        }
+        let dispatcher = dispatcher.shared();
+        if let Some(token) = &self.cancellation {
+            dispatch::cancel_on(&dispatcher, token);
+        }
+        if self.rate_limit.is_some() {
+            flush::start_flusher(&dispatcher);
+        }
+        let cell = StateCell::default();
+        let raw = SharedRaw::default();
+        raw::lock(&raw).handler = self.raw;
+        let backend = self.backend.hashing(self.poll_hashing);
+        let debouncer = state::new_debouncer(self.debounce, &backend, &dispatcher, &cell, &raw)?;
+
+        tracing::debug!("Created new file watcher with {:?} debounce", self.debounce);
+
+        let state = State {
+            debouncer,
+            backend,
+            debounce: self.debounce,
+            debouncers: BTreeMap::new(),
+            mode: self.mode,
+            rewatch_recreated: self.rewatch_recreated,
+            poll_interval: self.poll_fallback,
+            poller: None,
+            network_poll: self.network_poll,
+            poll_hashing: self.poll_hashing,
+            verify_interval: self.verify,
+            verifier: None,
+            watches: BTreeMap::new(),
+            pending: BTreeMap::new(),
+            links: BTreeMap::new(),
+            files: BTreeMap::new(),
+            helpers: BTreeMap::new(),
+            globs: BTreeMap::new(),
+            depths: BTreeMap::new(),
+            hashes: BTreeMap::new(),
+            contents: BTreeMap::new(),
+            sizes: BTreeMap::new(),
+            checksums: BTreeMap::new(),
+            fingerprints: BTreeMap::new(),
+            documents: BTreeMap::new(),
+            validators: BTreeMap::new(),
+            snapshots: BTreeMap::new(),
+            contents_limit: self.events.contents,
+            encodings: BTreeMap::new(),
+            filters: BTreeMap::new(),
+            leading: BTreeMap::new(),
+            trees: BTreeMap::new(),
+            close_write: BTreeSet::new(),
+            kinds: BTreeMap::new(),
+            dispatcher: dispatcher.clone(),
+            cell: cell.clone(),
+            raw,
+            errors: Vec::new(),
+        };
+        let state = Arc::new(Mutex::new(state));
+        let _ = cell.set(Arc::downgrade(&state));
+
+        Ok(Watcher { state, dispatcher })
+    }
+}
+
+impl Default for WatcherBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
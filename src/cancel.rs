@@ -0,0 +1,89 @@
+use std::sync::{Arc, Mutex, MutexGuard};
+
+/// A callback run once a token is cancelled.
+type Callback = Box<dyn FnOnce() + Send + 'static>;
+
+/// A signal for shutting down watchers cooperatively, see
+/// [`WatcherBuilder::cancellation`](crate::WatcherBuilder::cancellation).
+///
+/// Clones of a token share its state, so a service can hand one clone to each watcher and
+/// cancel all of them at once. Cancelling is permanent.
+///
+/// ```no_run
+/// use watchit::{CancellationToken, WatcherBuilder};
+///
+/// let token = CancellationToken::new();
+/// let watcher = WatcherBuilder::new()
+///     .cancellation(token.clone())
+///     .build_channel();
+/// // ...
+/// token.cancel();
+/// ```
+#[derive(Clone, Default)]
+pub struct CancellationToken {
+    inner: Arc<Mutex<Inner>>,
+}
+
+#[derive(Default)]
+struct Inner {
+    cancelled: bool,
+    callbacks: Vec<Callback>,
+}
+
+impl CancellationToken {
+    /// Creates a token that is not cancelled.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Cancels the token, and every clone of it.
+    ///
+    /// Watchers using the token stop delivering events, and their pending streams,
+    /// iterators and channels end. Cancelling a token again has no effect.
+    pub fn cancel(&self) {
+        let callbacks = {
+            let mut inner = self.lock();
+            if inner.cancelled {
+                return;
+            }
+            inner.cancelled = true;
+            std::mem::take(&mut inner.callbacks)
+        };
+
+        tracing::debug!("Cancelled {} watcher callbacks", callbacks.len());
+
+        for callback in callbacks {
+            callback();
+        }
+    }
+
+    /// Returns `true` if the token was cancelled.
+    pub fn is_cancelled(&self) -> bool {
+        self.lock().cancelled
+    }
+
+    /// Runs `callback` once the token is cancelled, or right away if it already is.
+    pub(crate) fn on_cancel(&self, callback: impl FnOnce() + Send + 'static) {
+        let mut inner = self.lock();
+        if inner.cancelled {
+            drop(inner);
+            callback();
+        } else {
+            inner.callbacks.push(Box::new(callback));
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Inner> {
+        self.inner
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl std::fmt::Debug for CancellationToken {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CancellationToken")
+            .field("cancelled", &self.is_cancelled())
+            .finish()
+    }
+}
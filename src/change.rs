@@ -0,0 +1,154 @@
+use std::{
+    fs::File,
+    hash::{DefaultHasher, Hasher as _},
+    io::{self, Read as _},
+    path::Path,
+};
+
+use crate::{json::Value, state::State, DebouncedEvent, Error, EventKind};
+
+/// A compact summary of how a modification changed a file.
+///
+/// Summaries are attached to events by watches registered with
+/// [`WatchOptions::change_summary`](crate::WatchOptions::change_summary).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChangeSummary {
+    /// The size of the file before the modification, in bytes.
+    pub previous_size: u64,
+    /// The size of the file after the modification, in bytes.
+    pub size: u64,
+    /// How many bytes the file grew by, or `0` if it did not grow.
+    pub bytes_added: u64,
+    /// How many bytes the file shrank by, or `0` if it did not shrink.
+    pub bytes_removed: u64,
+    /// Whether the previous content is unchanged at the start of the file, so that the
+    /// modification only appended to it, as when a log line is written.
+    pub pure_append: bool,
+}
+
+impl ChangeSummary {
+    pub(crate) fn to_json(self) -> Value {
+        Value::object([
+            ("previous_size", Value::number(self.previous_size)),
+            ("size", Value::number(self.size)),
+            ("bytes_added", Value::number(self.bytes_added)),
+            ("bytes_removed", Value::number(self.bytes_removed)),
+            ("pure_append", Value::Bool(self.pure_append)),
+        ])
+    }
+
+    pub(crate) fn from_json(value: &Value) -> Result<Self, Error> {
+        Ok(Self {
+            previous_size: value.field("previous_size")?.as_u64()?,
+            size: value.field("size")?.as_u64()?,
+            bytes_added: value.field("bytes_added")?.as_u64()?,
+            bytes_removed: value.field("bytes_removed")?.as_u64()?,
+            pure_append: value.field("pure_append")?.as_bool()?,
+        })
+    }
+}
+
+/// What is remembered about a file to summarize its next modification.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Fingerprint {
+    size: u64,
+    hash: u64,
+}
+
+impl State {
+    /// Attaches a summary of the change to the modifications of files reported by the watch
+    /// on `filename`.
+    pub(crate) fn add_change_summary(&mut self, filename: &Path) -> Result<(), Error> {
+        let Some(watch) = self.watches.get_mut(filename) else {
+            return Err(Error::watch_not_found().add_path(filename.to_path_buf()));
+        };
+        watch.change_summary = true;
+
+        // Files below a directory are read when they are created or first change.
+        if let Ok((fingerprint, _)) = fingerprint(filename, 0) {
+            self.fingerprints
+                .insert(filename.to_path_buf(), fingerprint);
+        }
+
+        Ok(())
+    }
+
+    /// Forgets the fingerprints of `filename` and everything below it.
+    pub(crate) fn forget_fingerprints(&mut self, filename: &Path) {
+        self.fingerprints
+            .retain(|path, _| !path.starts_with(filename));
+    }
+
+    /// Summarizes how the file an event reports changed since it was last seen, and
+    /// remembers its new content.
+    pub(crate) fn change_summary_for(&mut self, event: &DebouncedEvent) -> Option<ChangeSummary> {
+        let path = event.paths.last()?;
+        let summarized = self
+            .watches
+            .values()
+            .any(|watch| watch.change_summary && self.watch_reports(watch, path));
+        if !summarized {
+            return None;
+        }
+
+        let kind = EventKind::of(event);
+        let previous = self.fingerprints.get(path).copied();
+        let read = match kind {
+            EventKind::Removed => None,
+            _ => fingerprint(path, previous.map_or(0, |previous| previous.size)).ok(),
+        };
+        let Some((current, prefix)) = read else {
+            self.fingerprints.remove(path);
+            return None;
+        };
+        self.fingerprints.insert(path.clone(), current);
+        let previous = previous.filter(|_| kind == EventKind::Modified)?;
+
+        Some(ChangeSummary {
+            previous_size: previous.size,
+            size: current.size,
+            bytes_added: current.size.saturating_sub(previous.size),
+            bytes_removed: previous.size.saturating_sub(current.size),
+            pure_append: current.size > previous.size && prefix == Some(previous.hash),
+        })
+    }
+
+    /// Returns `true` if a watch attaches change summaries to its events.
+    pub(crate) fn has_change_summaries(&self) -> bool {
+        self.watches.values().any(|watch| watch.change_summary)
+    }
+}
+
+/// Reads the file at `path` in one pass, returning its fingerprint and the hash of its
+/// first `prefix` bytes, if it is that long.
+fn fingerprint(path: &Path, prefix: u64) -> io::Result<(Fingerprint, Option<u64>)> {
+    let mut file = File::open(path)?;
+    if !file.metadata()?.is_file() {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "not a file"));
+    }
+    let mut hasher = DefaultHasher::new();
+    let mut buffer = vec![0; 64 * 1024];
+    let mut size = 0;
+    let mut prefix_hash = (prefix == 0).then(|| hasher.finish());
+    loop {
+        let read = file.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        let mut bytes = &buffer[..read];
+        if prefix_hash.is_none() && size + read as u64 >= prefix {
+            // The prefix ends within this read, so its hash is taken on the way.
+            let (head, tail) = bytes.split_at((prefix - size) as usize);
+            hasher.write(head);
+            prefix_hash = Some(hasher.finish());
+            bytes = tail;
+        }
+        hasher.write(bytes);
+        size += read as u64;
+    }
+    let fingerprint = Fingerprint {
+        size,
+        hash: hasher.finish(),
+    };
+    Ok((fingerprint, prefix_hash))
+}
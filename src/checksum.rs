@@ -0,0 +1,502 @@
+use std::{
+    fmt,
+    fs::File,
+    io::{self, Read as _},
+    path::{Path, PathBuf},
+    thread,
+};
+
+use crate::{event::Details, json::Value, state::State, DebouncedEvent, Error, EventKind};
+
+/// How the checksums of watched files are computed, see
+/// [`WatchOptions::checksum`](crate::WatchOptions::checksum).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ChecksumAlgorithm {
+    /// SHA-256, for detecting tampering and for digests shared with other tools.
+    Sha256,
+    /// The 64-bit FNV-1a hash, which is much cheaper but only detects accidental changes.
+    Fnv1a64,
+}
+
+impl ChecksumAlgorithm {
+    /// Returns the name of the algorithm, such as `sha256`.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Sha256 => "sha256",
+            Self::Fnv1a64 => "fnv1a64",
+        }
+    }
+
+    pub(crate) fn from_str(name: &str) -> Result<Self, Error> {
+        match name {
+            "sha256" => Ok(Self::Sha256),
+            "fnv1a64" => Ok(Self::Fnv1a64),
+            _ => Err(Error::generic(&format!(
+                "unknown checksum algorithm: {name}"
+            ))),
+        }
+    }
+}
+
+impl fmt::Display for ChecksumAlgorithm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// The checksum of a file's content.
+///
+/// Digests are written as lowercase hexadecimal, as `sha256sum` writes them.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Digest {
+    algorithm: ChecksumAlgorithm,
+    bytes: Vec<u8>,
+}
+
+impl Digest {
+    /// Computes the digest of `bytes`.
+    pub fn of(algorithm: ChecksumAlgorithm, bytes: &[u8]) -> Self {
+        let mut hasher = Hasher::new(algorithm);
+        hasher.update(bytes);
+        hasher.finish()
+    }
+
+    /// Computes the digest of the content of the file at `path`.
+    ///
+    /// # Errors
+    /// Returns an error if the file cannot be read.
+    pub fn of_file(algorithm: ChecksumAlgorithm, path: impl AsRef<Path>) -> io::Result<Self> {
+        let mut file = File::open(path)?;
+        let mut hasher = Hasher::new(algorithm);
+        let mut buffer = vec![0; 64 * 1024];
+        loop {
+            let read = file.read(&mut buffer)?;
+            if read == 0 {
+                return Ok(hasher.finish());
+            }
+            hasher.update(&buffer[..read]);
+        }
+    }
+
+    /// Returns the algorithm that computed the digest.
+    pub fn algorithm(&self) -> ChecksumAlgorithm {
+        self.algorithm
+    }
+
+    /// Returns the digest's bytes.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    /// Returns the digest as lowercase hexadecimal.
+    pub fn to_hex(&self) -> String {
+        self.bytes
+            .iter()
+            .map(|byte| format!("{byte:02x}"))
+            .collect()
+    }
+
+    pub(crate) fn to_json(&self) -> Value {
+        Value::object([
+            ("algorithm", Value::string(self.algorithm.as_str())),
+            ("hex", Value::string(self.to_hex())),
+        ])
+    }
+
+    pub(crate) fn from_json(value: &Value) -> Result<Self, Error> {
+        let algorithm = ChecksumAlgorithm::from_str(value.field("algorithm")?.as_str()?)?;
+        let hex = value.field("hex")?.as_str()?;
+        let malformed = || Error::generic(&format!("malformed digest: {hex}"));
+        if hex.len() % 2 != 0 {
+            return Err(malformed());
+        }
+        let bytes = (0..hex.len())
+            .step_by(2)
+            .map(|start| {
+                hex.get(start..start + 2)
+                    .and_then(|byte| u8::from_str_radix(byte, 16).ok())
+                    .ok_or_else(malformed)
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Self { algorithm, bytes })
+    }
+}
+
+impl fmt::Display for Digest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.to_hex())
+    }
+}
+
+/// How the checksum of a file changed with a modification, see
+/// [`Event::checksum`](crate::Event::checksum).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChecksumChange {
+    /// The checksum before the modification, if the file was seen before.
+    pub old: Option<Digest>,
+    /// The checksum after the modification.
+    pub new: Digest,
+}
+
+impl ChecksumChange {
+    /// Returns `true` if the content is known to have changed.
+    pub fn changed(&self) -> bool {
+        self.old.as_ref() != Some(&self.new)
+    }
+
+    pub(crate) fn to_json(&self) -> Value {
+        Value::object([
+            ("old", Value::optional(self.old.as_ref(), Digest::to_json)),
+            ("new", self.new.to_json()),
+        ])
+    }
+
+    pub(crate) fn from_json(value: &Value) -> Result<Self, Error> {
+        Ok(Self {
+            old: value.field("old")?.as_optional(Digest::from_json)?,
+            new: Digest::from_json(value.field("new")?)?,
+        })
+    }
+}
+
+/// A file of a batch whose checksum is computed once the state is unlocked.
+pub(crate) struct ChecksumJob {
+    /// The position of the event in the batch.
+    index: usize,
+    path: PathBuf,
+    algorithm: ChecksumAlgorithm,
+}
+
+impl State {
+    /// Attaches the old and new checksums to the modifications of files reported by the
+    /// watch on `filename`.
+    pub(crate) fn add_checksum(
+        &mut self,
+        filename: &Path,
+        algorithm: ChecksumAlgorithm,
+    ) -> Result<(), Error> {
+        let Some(watch) = self.watches.get_mut(filename) else {
+            return Err(Error::watch_not_found().add_path(filename.to_path_buf()));
+        };
+        watch.checksum = Some(algorithm);
+
+        // Files below a directory are hashed when they are created or first change.
+        if filename.is_file() {
+            if let Ok(digest) = Digest::of_file(algorithm, filename) {
+                self.checksums.insert(filename.to_path_buf(), digest);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Forgets the checksums of `filename` and everything below it.
+    pub(crate) fn forget_checksums(&mut self, filename: &Path) {
+        self.checksums.retain(|path, _| !path.starts_with(filename));
+    }
+
+    /// Returns the files of a batch whose checksums must be computed, and forgets the
+    /// checksums of removed files and moves those of renamed ones.
+    pub(crate) fn checksum_jobs(
+        &mut self,
+        events: &[(DebouncedEvent, Details)],
+    ) -> Vec<ChecksumJob> {
+        if self.watches.values().all(|watch| watch.checksum.is_none()) {
+            return Vec::new();
+        }
+        let mut jobs = Vec::new();
+        for (index, (event, details)) in events.iter().enumerate() {
+            let Some(path) = event.paths.last() else {
+                continue;
+            };
+            let Some(algorithm) = self.checksum_algorithm(path) else {
+                continue;
+            };
+            match details.kind(event) {
+                EventKind::Removed => {
+                    self.checksums.remove(path);
+                }
+                EventKind::Renamed => {
+                    if let [from, ..] = event.paths.as_slice() {
+                        if let Some(digest) = self.checksums.remove(from) {
+                            self.checksums.insert(path.clone(), digest);
+                        }
+                    }
+                }
+                EventKind::Created | EventKind::Modified | EventKind::Existing => {
+                    jobs.push(ChecksumJob {
+                        index,
+                        path: path.clone(),
+                        algorithm,
+                    });
+                }
+                _ => {}
+            }
+        }
+        jobs
+    }
+
+    /// Remembers the checksums computed for `jobs`, and attaches the old and the new
+    /// checksum to the modifications among them.
+    pub(crate) fn attach_checksums(
+        &mut self,
+        events: &mut [(DebouncedEvent, Details)],
+        jobs: Vec<ChecksumJob>,
+        digests: Vec<Option<Digest>>,
+    ) {
+        for (job, digest) in jobs.into_iter().zip(digests) {
+            let Some(new) = digest else {
+                self.checksums.remove(&job.path);
+                continue;
+            };
+            let old = self.checksums.insert(job.path, new.clone());
+            let (event, details) = &mut events[job.index];
+            if details.kind(event) == EventKind::Modified {
+                details.checksum = Some(ChecksumChange { old, new });
+            }
+        }
+    }
+
+    /// Returns the algorithm of the most specific watch reporting `path` with checksums.
+    fn checksum_algorithm(&self, path: &Path) -> Option<ChecksumAlgorithm> {
+        self.watches
+            .values()
+            .filter(|watch| watch.checksum.is_some() && self.watch_reports(watch, path))
+            .max_by_key(|watch| watch.path.components().count())
+            .and_then(|watch| watch.checksum)
+    }
+}
+
+/// Computes the checksums of `jobs`, spreading the files across a few threads.
+///
+/// This runs on the debouncer thread, which waits for every file before it delivers the batch.
+///
+/// Files that cannot be read, such as ones that were removed again, have no checksum.
+pub(crate) fn compute(jobs: &[ChecksumJob]) -> Vec<Option<Digest>> {
+    let threads = thread::available_parallelism().map_or(1, |threads| threads.get().min(8));
+    let chunk = jobs.len().div_ceil(threads).max(1);
+    thread::scope(|scope| {
+        let handles: Vec<_> = jobs
+            .chunks(chunk)
+            .map(|jobs| {
+                scope.spawn(move || {
+                    jobs.iter()
+                        .map(|job| {
+                            Digest::of_file(job.algorithm, &job.path)
+                                .map_err(|error| {
+                                    tracing::debug!(
+                                        "Failed to checksum {}: {}",
+                                        job.path.display(),
+                                        error
+                                    );
+                                })
+                                .ok()
+                        })
+                        .collect::<Vec<_>>()
+                })
+            })
+            .collect();
+        handles
+            .into_iter()
+            .flat_map(|handle| handle.join().unwrap_or_default())
+            .collect()
+    })
+}
+
+/// Computes a digest incrementally.
+enum Hasher {
+    Sha256(Sha256),
+    Fnv1a64(u64),
+}
+
+impl Hasher {
+    fn new(algorithm: ChecksumAlgorithm) -> Self {
+        match algorithm {
+            ChecksumAlgorithm::Sha256 => Self::Sha256(Sha256::new()),
+            ChecksumAlgorithm::Fnv1a64 => Self::Fnv1a64(0xcbf2_9ce4_8422_2325),
+        }
+    }
+
+    fn update(&mut self, bytes: &[u8]) {
+        match self {
+            Self::Sha256(sha) => sha.update(bytes),
+            Self::Fnv1a64(hash) => {
+                for &byte in bytes {
+                    *hash ^= u64::from(byte);
+                    *hash = hash.wrapping_mul(0x0000_0100_0000_01b3);
+                }
+            }
+        }
+    }
+
+    fn finish(self) -> Digest {
+        match self {
+            Self::Sha256(sha) => Digest {
+                algorithm: ChecksumAlgorithm::Sha256,
+                bytes: sha.finish().to_vec(),
+            },
+            Self::Fnv1a64(hash) => Digest {
+                algorithm: ChecksumAlgorithm::Fnv1a64,
+                bytes: hash.to_be_bytes().to_vec(),
+            },
+        }
+    }
+}
+
+/// SHA-256 as specified in FIPS 180-4.
+struct Sha256 {
+    state: [u32; 8],
+    block: [u8; 64],
+    filled: usize,
+    len: u64,
+}
+
+const ROUND_CONSTANTS: [u32; 64] = [
+    0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4, 0xab1c5ed5,
+    0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe, 0x9bdc06a7, 0xc19bf174,
+    0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc, 0x2de92c6f, 0x4a7484aa, 0x5cb0a9dc, 0x76f988da,
+    0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7, 0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967,
+    0x27b70a85, 0x2e1b2138, 0x4d2c6dfc, 0x53380d13, 0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85,
+    0xa2bfe8a1, 0xa81a664b, 0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070,
+    0x19a4c116, 0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f, 0x682e6ff3,
+    0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7, 0xc67178f2,
+];
+
+impl Sha256 {
+    fn new() -> Self {
+        Self {
+            state: [
+                0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab,
+                0x5be0cd19,
+            ],
+            block: [0; 64],
+            filled: 0,
+            len: 0,
+        }
+    }
+
+    fn update(&mut self, mut bytes: &[u8]) {
+        self.len = self.len.wrapping_add(bytes.len() as u64);
+        while !bytes.is_empty() {
+            let taken = bytes.len().min(64 - self.filled);
+            self.block[self.filled..self.filled + taken].copy_from_slice(&bytes[..taken]);
+            self.filled += taken;
+            bytes = &bytes[taken..];
+            if self.filled == 64 {
+                self.compress();
+                self.filled = 0;
+            }
+        }
+    }
+
+    fn finish(mut self) -> [u8; 32] {
+        let bits = self.len.wrapping_mul(8);
+        self.block[self.filled] = 0x80;
+        self.block[self.filled + 1..].fill(0);
+        if self.filled >= 56 {
+            self.compress();
+            self.block.fill(0);
+        }
+        self.block[56..].copy_from_slice(&bits.to_be_bytes());
+        self.compress();
+
+        let mut digest = [0; 32];
+        for (bytes, word) in digest.chunks_exact_mut(4).zip(self.state) {
+            bytes.copy_from_slice(&word.to_be_bytes());
+        }
+        digest
+    }
+
+    fn compress(&mut self) {
+        let mut schedule = [0u32; 64];
+        for (word, bytes) in schedule.iter_mut().zip(self.block.chunks_exact(4)) {
+            *word = u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+        }
+        for i in 16..64 {
+            let s0 = schedule[i - 15].rotate_right(7)
+                ^ schedule[i - 15].rotate_right(18)
+                ^ (schedule[i - 15] >> 3);
+            let s1 = schedule[i - 2].rotate_right(17)
+                ^ schedule[i - 2].rotate_right(19)
+                ^ (schedule[i - 2] >> 10);
+            schedule[i] = schedule[i - 16]
+                .wrapping_add(s0)
+                .wrapping_add(schedule[i - 7])
+                .wrapping_add(s1);
+        }
+
+        let [mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut h] = self.state;
+        for (constant, word) in ROUND_CONSTANTS.iter().zip(schedule) {
+            let s1 = e.rotate_right(6) ^ e.rotate_right(11) ^ e.rotate_right(25);
+            let choice = (e & f) ^ (!e & g);
+            let t1 = h
+                .wrapping_add(s1)
+                .wrapping_add(choice)
+                .wrapping_add(*constant)
+                .wrapping_add(word);
+            let s0 = a.rotate_right(2) ^ a.rotate_right(13) ^ a.rotate_right(22);
+            let majority = (a & b) ^ (a & c) ^ (b & c);
+            let t2 = s0.wrapping_add(majority);
+            h = g;
+            g = f;
+            f = e;
+            e = d.wrapping_add(t1);
+            d = c;
+            c = b;
+            b = a;
+            a = t1.wrapping_add(t2);
+        }
+        for (state, value) in self.state.iter_mut().zip([a, b, c, d, e, f, g, h]) {
+            *state = state.wrapping_add(value);
+        }
+    }
+}
+
+#[cfg(test)]
+/// This module contains tests for the checksums.
+///
+/// The `sha256_matches_known_digests` test verifies SHA-256 against the test vectors of
+/// FIPS 180-2, including inputs that span several blocks.
+///
+/// The `fnv1a64_matches_known_digests` test verifies FNV-1a against its published values.
+///
+/// The `digest_round_trips_through_json` test verifies that a digest read back from its JSON
+/// form equals the original.
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sha256_matches_known_digests() {
+        let digest = |bytes: &[u8]| Digest::of(ChecksumAlgorithm::Sha256, bytes).to_hex();
+        assert_eq!(
+            digest(b""),
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+        assert_eq!(
+            digest(b"abc"),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        assert_eq!(
+            digest(b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq"),
+            "248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1"
+        );
+        let long = vec![b'a'; 1_000_000];
+        assert_eq!(
+            digest(&long),
+            "cdc76e5c9914fb9281a1c7e284d73e67f1809a48a497200e046d39ccc7112cd0"
+        );
+    }
+
+    #[test]
+    fn fnv1a64_matches_known_digests() {
+        let digest = |bytes: &[u8]| Digest::of(ChecksumAlgorithm::Fnv1a64, bytes).to_hex();
+        assert_eq!(digest(b""), "cbf29ce484222325");
+        assert_eq!(digest(b"a"), "af63dc4c8601ec8c");
+    }
+
+    #[test]
+    fn digest_round_trips_through_json() {
+        let digest = Digest::of(ChecksumAlgorithm::Sha256, b"abc");
+        assert_eq!(Digest::from_json(&digest.to_json()).unwrap(), digest);
+    }
+}
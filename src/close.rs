@@ -0,0 +1,85 @@
+use std::path::Path;
+
+use notify::{
+    event::{AccessKind, AccessMode, DataChange, ModifyKind},
+    EventKind,
+};
+
+use crate::{backend::Source, state::State, Backend, DebouncedEvent, Error};
+
+impl State {
+    /// Delivers the modifications below the watch on `filename` when the writer closes the
+    /// file, if the back-end reports closes.
+    pub(crate) fn add_close_write(&mut self, filename: &Path) -> Result<(), Error> {
+        let reports_closes = self.reports_closes();
+        let Some(watch) = self.watches.get_mut(filename) else {
+            return Err(Error::watch_not_found().add_path(filename.to_path_buf()));
+        };
+        if !reports_closes || watch.polled {
+            tracing::debug!(
+                "The back-end does not report closed files, delivering the modifications of {} as usual",
+                filename.display()
+            );
+            return Ok(());
+        }
+        watch.close_write = true;
+
+        self.close_write.insert(filename.to_path_buf());
+
+        Ok(())
+    }
+
+    pub(crate) fn forget_close_write(&mut self, filename: &Path) {
+        self.close_write.remove(filename);
+    }
+
+    /// Returns `true` if the back-end reports when a file that was written to is closed,
+    /// which only inotify does.
+    fn reports_closes(&self) -> bool {
+        cfg!(any(target_os = "linux", target_os = "android"))
+            && matches!(
+                self.backend,
+                Source::Builtin(Backend::Auto | Backend::Inotify)
+            )
+    }
+
+    /// Replaces the writes below close-write watches with a modification when their file is
+    /// closed.
+    ///
+    /// A file that was created in the same batch is complete once its creation is delivered,
+    /// so its close is dropped as well.
+    pub(crate) fn close_events(&self, events: &mut Vec<DebouncedEvent>) {
+        if self.close_write.is_empty() {
+            return;
+        }
+        let created: Vec<_> = events
+            .iter()
+            .filter(|event| event.kind.is_create())
+            .filter_map(|event| event.paths.last().cloned())
+            .collect();
+
+        events.retain_mut(|event| {
+            let closing = self
+                .root_of(event)
+                .is_some_and(|root| self.close_write.contains(&root));
+            if !closing {
+                return true;
+            }
+            match event.kind {
+                EventKind::Modify(ModifyKind::Data(_) | ModifyKind::Any) => false,
+                EventKind::Access(AccessKind::Close(AccessMode::Write)) => {
+                    if event
+                        .paths
+                        .last()
+                        .is_some_and(|path| created.contains(path))
+                    {
+                        return false;
+                    }
+                    event.event.kind = EventKind::Modify(ModifyKind::Data(DataChange::Any));
+                    true
+                }
+                _ => true,
+            }
+        });
+    }
+}
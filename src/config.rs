@@ -0,0 +1,163 @@
+use std::{
+    error::Error as StdError,
+    fmt,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, MutexGuard},
+};
+
+use crate::{read, Error, Watcher, WatcherBuilder};
+
+/// Keeps a parsed configuration file up to date, re-parsing it whenever it changes.
+///
+/// The file is parsed once when the watcher is created and again after each debounced
+/// change, and every outcome is passed to the handler. The crate does not depend on a
+/// serialization framework, so the parser is a function from the file's bytes to the
+/// configuration, such as `|bytes| serde_json::from_slice(bytes)` or
+/// `|bytes| toml::from_str(std::str::from_utf8(bytes)?)`. To reject values that parse but
+/// are invalid, return an error from the parser as well.
+///
+/// A broken edit does not replace the configuration: [`ConfigWatcher::current`] keeps
+/// returning the last value that parsed, until the file is fixed. Removing the file is
+/// not passed on either.
+///
+/// ```no_run
+/// use watchit::ConfigWatcher;
+///
+/// let config = ConfigWatcher::new(
+///     "port.conf",
+///     |bytes| String::from_utf8_lossy(bytes).trim().parse::<u16>(),
+///     |result| match result {
+///         Ok(port) => println!("listening on {port}"),
+///         Err(error) => eprintln!("keeping the last good port: {error}"),
+///     },
+/// )
+/// .unwrap();
+/// let port = config.current();
+/// ```
+pub struct ConfigWatcher<T> {
+    watcher: Watcher,
+    current: Shared<T>,
+}
+
+type Shared<T> = Arc<Mutex<Option<Arc<T>>>>;
+
+impl<T: Send + Sync + 'static> ConfigWatcher<T> {
+    /// Watches the configuration file at `path` with the default options of
+    /// [`WatcherBuilder::new`], see [`WatcherBuilder::build_config`].
+    ///
+    /// # Errors
+    /// Returns an error if the watcher cannot be created or the file cannot be read or
+    /// watched. A file that does not parse is passed to the handler instead.
+    pub fn new<E>(
+        path: impl AsRef<Path>,
+        parse: impl Fn(&[u8]) -> Result<T, E> + Send + 'static,
+        handler: impl FnMut(Result<Arc<T>, ParseError>) + Send + 'static,
+    ) -> Result<Self, Error>
+    where
+        E: Into<Box<dyn StdError + Send + Sync>>,
+    {
+        WatcherBuilder::new().build_config(path, parse, handler)
+    }
+
+    /// Returns the last configuration that parsed, or `None` if the file never did.
+    pub fn current(&self) -> Option<Arc<T>> {
+        lock(&self.current).clone()
+    }
+
+    /// Returns the watcher that watches the file, which can watch other paths as well.
+    pub fn watcher(&self) -> &Watcher {
+        &self.watcher
+    }
+}
+
+impl WatcherBuilder {
+    /// Creates the configured file watcher and keeps the configuration file at `path`
+    /// parsed, see [`ConfigWatcher`].
+    ///
+    /// # Arguments
+    /// * `path` - The configuration file to watch.
+    /// * `parse` - The function parsing and validating the contents of the file.
+    /// * `handler` - The function to call with the outcome of each parse, starting with the
+    ///   file as it is when the watcher is created.
+    ///
+    /// # Returns
+    /// A `Result` containing either the configuration watcher, or an `Error` if the watcher
+    /// cannot be created or the file cannot be read or watched.
+    pub fn build_config<T, E>(
+        self,
+        path: impl AsRef<Path>,
+        parse: impl Fn(&[u8]) -> Result<T, E> + Send + 'static,
+        mut handler: impl FnMut(Result<Arc<T>, ParseError>) + Send + 'static,
+    ) -> Result<ConfigWatcher<T>, Error>
+    where
+        T: Send + Sync + 'static,
+        E: Into<Box<dyn StdError + Send + Sync>>,
+    {
+        let path = path.as_ref().to_path_buf();
+        let current = Shared::default();
+        let watcher = self.build(|_| {})?;
+
+        let mut load = {
+            let current = Arc::clone(&current);
+            let path = path.clone();
+            move |contents: Vec<u8>| {
+                let result = match parse(&contents) {
+                    Ok(config) => {
+                        let config = Arc::new(config);
+                        *lock(&current) = Some(Arc::clone(&config));
+                        Ok(config)
+                    }
+                    Err(error) => Err(ParseError {
+                        path: path.clone(),
+                        source: error.into(),
+                    }),
+                };
+                handler(result);
+            }
+        };
+        match read::read_with_retry(&path) {
+            Ok(contents) => load(contents),
+            Err(error) => return Err(Error::io(error).add_path(path)),
+        }
+        watcher.watch_contents(&path, load)?;
+
+        Ok(ConfigWatcher { watcher, current })
+    }
+}
+
+/// A configuration file that did not parse, see [`ConfigWatcher`].
+#[derive(Debug)]
+pub struct ParseError {
+    path: PathBuf,
+    source: Box<dyn StdError + Send + Sync>,
+}
+
+impl ParseError {
+    /// Returns the path of the configuration file.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "failed to parse {}: {}",
+            self.path.display(),
+            self.source
+        )
+    }
+}
+
+impl StdError for ParseError {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        Some(&*self.source)
+    }
+}
+
+fn lock<T>(current: &Shared<T>) -> MutexGuard<'_, Option<Arc<T>>> {
+    current
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
@@ -0,0 +1,80 @@
+use std::{
+    fs,
+    hash::{DefaultHasher, Hasher},
+    path::Path,
+};
+
+use crate::{state::State, DebouncedEvent, Error, EventKind};
+
+impl State {
+    /// Drops the modifications of watches on `filename` that leave a file's content as it was.
+    pub(crate) fn add_content_check(&mut self, filename: &Path) -> Result<(), Error> {
+        let Some(watch) = self.watches.get_mut(filename) else {
+            return Err(Error::watch_not_found().add_path(filename.to_path_buf()));
+        };
+        watch.ignore_unchanged = true;
+
+        // Files below a directory are hashed when they are first seen changing.
+        if let Some(hash) = hash_file(filename) {
+            self.hashes.insert(filename.to_path_buf(), hash);
+        }
+
+        Ok(())
+    }
+
+    /// Forgets the content hashes of `filename` and everything below it.
+    pub(crate) fn forget_hashes(&mut self, filename: &Path) {
+        self.hashes.retain(|path, _| !path.starts_with(filename));
+    }
+
+    /// Drops modifications and accesses that did not change the content of their file, and
+    /// remembers the content of files that did change.
+    pub(crate) fn suppress_unchanged(&mut self, events: &mut Vec<DebouncedEvent>) {
+        if self.watches.values().all(|watch| !watch.ignore_unchanged) {
+            return;
+        }
+
+        events.retain(|event| {
+            let Some(path) = event.paths.last() else {
+                return true;
+            };
+            let checked = self
+                .watches
+                .values()
+                .any(|watch| watch.ignore_unchanged && self.watch_reports(watch, path));
+            if !checked {
+                return true;
+            }
+
+            let kind = EventKind::of(event);
+            if kind == EventKind::Removed {
+                self.hashes.remove(path);
+                return true;
+            }
+            let Some(hash) = hash_file(path) else {
+                return true;
+            };
+            let previous = self.hashes.insert(path.clone(), hash);
+            if matches!(
+                kind,
+                EventKind::Modified | EventKind::AttributesChanged | EventKind::Other
+            ) && previous == Some(hash)
+            {
+                tracing::trace!("Dropped event for unchanged content: {}", path.display());
+                return false;
+            }
+            true
+        });
+    }
+}
+
+/// Hashes the content of `path`, or returns `None` if it is not a readable file.
+fn hash_file(path: &Path) -> Option<u64> {
+    if !path.is_file() {
+        return None;
+    }
+    let content = fs::read(path).ok()?;
+    let mut hasher = DefaultHasher::new();
+    hasher.write(&content);
+    Some(hasher.finish())
+}
@@ -0,0 +1,183 @@
+use std::{
+    collections::BTreeSet,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use notify::{RecursiveMode, Watcher as _};
+
+use crate::{
+    state::{Served, State},
+    DebouncedEvent, Error, WatchInfo,
+};
+
+/// A recursive watch that only descends a limited number of levels below its path.
+///
+/// Every directory within the limit is watched on its own, non-recursively, so that deeper
+/// trees are never registered with the backend.
+#[derive(Debug, Clone)]
+pub(crate) struct DepthWatch {
+    /// How many levels below the watched path are reported.
+    max_depth: usize,
+    /// The directories currently registered with the backend.
+    dirs: BTreeSet<PathBuf>,
+}
+
+impl State {
+    /// Watches `dirname` and the directories below it down to `max_depth` levels.
+    pub(crate) fn add_depth_watch(
+        &mut self,
+        dirname: &Path,
+        max_depth: usize,
+    ) -> Result<(), Error> {
+        let max_depth = max_depth.max(1);
+        if !dirname.is_dir() {
+            self.remove_depth_watch(dirname);
+            return self.add_watch(dirname, RecursiveMode::NonRecursive);
+        }
+
+        // A watch registered again keeps the directories it shares with its previous limit,
+        // and only loses the others once the new limit is in place.
+        let previous = self.depths.remove(dirname);
+        let kept = |dir: &Path| {
+            previous
+                .as_ref()
+                .is_some_and(|watch| watch.dirs.contains(dir))
+        };
+        let mut watch = DepthWatch {
+            max_depth,
+            dirs: BTreeSet::new(),
+        };
+        if let Err(error) = self.watch_levels(&mut watch, dirname, 0) {
+            for dir in watch.dirs.iter().filter(|dir| !kept(dir)) {
+                self.unwatch_level(dir);
+            }
+            if let Some(previous) = previous {
+                self.depths.insert(dirname.to_path_buf(), previous);
+            }
+            return Err(error);
+        }
+        for dir in previous.iter().flat_map(|previous| &previous.dirs) {
+            if !watch.dirs.contains(dir) {
+                self.unwatch_level(dir);
+            }
+        }
+
+        tracing::debug!(
+            "Watching {} directories {} levels deep for changes: {}",
+            watch.dirs.len(),
+            max_depth,
+            dirname.display()
+        );
+
+        self.depths.insert(dirname.to_path_buf(), watch);
+        self.watches.insert(
+            dirname.to_path_buf(),
+            WatchInfo::new(dirname.to_path_buf(), RecursiveMode::Recursive).max_depth(max_depth),
+        );
+
+        Ok(())
+    }
+
+    /// Removes a depth-limited watch together with all of its directories.
+    ///
+    /// Returns `false` if `dirname` is not a depth-limited watch.
+    pub(crate) fn remove_depth_watch(&mut self, dirname: &Path) -> bool {
+        let Some(watch) = self.depths.remove(dirname) else {
+            return false;
+        };
+
+        for dir in &watch.dirs {
+            self.unwatch_level(dir);
+        }
+        self.watches.remove(dirname);
+
+        true
+    }
+
+    /// Watches directories created within the limit of a depth-limited watch, and forgets
+    /// the ones that were removed.
+    pub(crate) fn resolve_depths(&mut self, events: &[DebouncedEvent]) {
+        if self.depths.is_empty() {
+            return;
+        }
+
+        let roots: Vec<PathBuf> = self.depths.keys().cloned().collect();
+        for root in roots {
+            let Some(mut watch) = self.depths.remove(&root) else {
+                continue;
+            };
+
+            let removed: Vec<PathBuf> = watch
+                .dirs
+                .iter()
+                .filter(|dir| !dir.is_dir())
+                .cloned()
+                .collect();
+            for dir in removed {
+                watch.dirs.remove(&dir);
+                self.unwatch_level(&dir);
+            }
+
+            for path in events.iter().flat_map(|event| &event.paths) {
+                let Ok(rest) = path.strip_prefix(&root) else {
+                    continue;
+                };
+                let depth = rest.components().count();
+                if depth == 0 || depth >= watch.max_depth || watch.dirs.contains(path) {
+                    continue;
+                }
+                if path.is_dir() {
+                    if let Err(error) = self.watch_levels(&mut watch, path, depth) {
+                        tracing::debug!("Failed to watch {}: {}", path.display(), error);
+                        self.report(error);
+                    }
+                }
+            }
+
+            self.depths.insert(root, watch);
+        }
+    }
+
+    /// Watches `dir`, which is `depth` levels below the root of `watch`, and the directories
+    /// below it that are still within the limit.
+    fn watch_levels(
+        &mut self,
+        watch: &mut DepthWatch,
+        dir: &Path,
+        depth: usize,
+    ) -> Result<(), Error> {
+        if !watch.dirs.contains(dir) {
+            if self.serve(dir, RecursiveMode::NonRecursive, None)? == Served::Native {
+                self.debouncer
+                    .cache()
+                    .add_root(dir, RecursiveMode::NonRecursive);
+            }
+            watch.dirs.insert(dir.to_path_buf());
+        }
+
+        if depth + 1 >= watch.max_depth {
+            return Ok(());
+        }
+
+        let entries =
+            fs::read_dir(dir).map_err(|error| Error::io(error).add_path(dir.to_path_buf()))?;
+        for entry in entries.flatten() {
+            if entry.file_type().is_ok_and(|file_type| file_type.is_dir()) {
+                self.watch_levels(watch, &entry.path(), depth + 1)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Stops watching a directory of a depth-limited watch, whichever way it was served.
+    fn unwatch_level(&mut self, dir: &Path) {
+        // Directories that were deleted have already lost their OS watch.
+        if self.debouncer.watcher().unwatch(dir).is_err() {
+            self.unpoll(dir);
+        }
+        self.debouncer.cache().remove_root(dir);
+        self.forget_verify(dir);
+    }
+}
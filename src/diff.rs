@@ -0,0 +1,254 @@
+use std::{fs, path::Path};
+
+use crate::{json::Value, state::State, DebouncedEvent, Error, EventKind};
+
+/// The lines that differ between two versions of a text file.
+///
+/// Diffs are attached to events by watches registered with
+/// [`WatchOptions::diff`](crate::WatchOptions::diff).
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TextDiff {
+    /// The lines of the previous version that are gone, numbered as in the previous version.
+    pub removed: Vec<DiffLine>,
+    /// The lines of the new version that are new, numbered as in the new version.
+    pub added: Vec<DiffLine>,
+}
+
+/// A line of a [`TextDiff`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DiffLine {
+    /// The 1-based number of the line.
+    pub number: usize,
+    /// The line without its line ending.
+    pub text: String,
+}
+
+/// The largest number of line pairs compared exactly. Bigger changes are reported as
+/// replacing every line between the common start and end of both versions.
+const MAX_COMPARISONS: usize = 1 << 20;
+
+impl TextDiff {
+    /// Computes the lines removed from `old` and added in `new`.
+    pub(crate) fn between(old: &str, new: &str) -> Self {
+        let old: Vec<&str> = old.lines().collect();
+        let new: Vec<&str> = new.lines().collect();
+
+        let prefix = old
+            .iter()
+            .zip(&new)
+            .take_while(|(old, new)| old == new)
+            .count();
+        let suffix = old[prefix..]
+            .iter()
+            .rev()
+            .zip(new[prefix..].iter().rev())
+            .take_while(|(old, new)| old == new)
+            .count();
+        let old_middle = &old[prefix..old.len() - suffix];
+        let new_middle = &new[prefix..new.len() - suffix];
+
+        let (kept_old, kept_new) = common_lines(old_middle, new_middle);
+        let removed = old_middle
+            .iter()
+            .enumerate()
+            .filter(|(index, _)| !kept_old[*index])
+            .map(|(index, text)| DiffLine::new(prefix + index, text))
+            .collect();
+        let added = new_middle
+            .iter()
+            .enumerate()
+            .filter(|(index, _)| !kept_new[*index])
+            .map(|(index, text)| DiffLine::new(prefix + index, text))
+            .collect();
+
+        Self { removed, added }
+    }
+
+    /// Returns `true` if both versions have the same lines.
+    pub fn is_empty(&self) -> bool {
+        self.removed.is_empty() && self.added.is_empty()
+    }
+
+    pub(crate) fn to_json(&self) -> Value {
+        let lines = |lines: &[DiffLine]| {
+            Value::Array(
+                lines
+                    .iter()
+                    .map(|line| {
+                        Value::object([
+                            ("number", Value::number(line.number)),
+                            ("text", Value::string(line.text.as_str())),
+                        ])
+                    })
+                    .collect(),
+            )
+        };
+        Value::object([
+            ("removed", lines(&self.removed)),
+            ("added", lines(&self.added)),
+        ])
+    }
+
+    pub(crate) fn from_json(value: &Value) -> Result<Self, Error> {
+        let lines = |key: &str| -> Result<Vec<DiffLine>, Error> {
+            value
+                .field(key)?
+                .as_array()?
+                .iter()
+                .map(|line| {
+                    Ok(DiffLine {
+                        number: {
+                            let number = line.field("number")?.as_u64()?;
+                            usize::try_from(number).map_err(|_| {
+                                Error::generic(&format!("invalid line number {number}"))
+                            })?
+                        },
+                        text: line.field("text")?.as_str()?.to_string(),
+                    })
+                })
+                .collect()
+        };
+        Ok(Self {
+            removed: lines("removed")?,
+            added: lines("added")?,
+        })
+    }
+}
+
+impl DiffLine {
+    fn new(index: usize, text: &str) -> Self {
+        Self {
+            number: index + 1,
+            text: text.to_string(),
+        }
+    }
+}
+
+/// Marks the lines of `old` and `new` that belong to their longest common subsequence.
+fn common_lines(old: &[&str], new: &[&str]) -> (Vec<bool>, Vec<bool>) {
+    let mut kept_old = vec![false; old.len()];
+    let mut kept_new = vec![false; new.len()];
+    if old.is_empty() || new.is_empty() || old.len().saturating_mul(new.len()) > MAX_COMPARISONS {
+        return (kept_old, kept_new);
+    }
+
+    // lengths[i][j] is the length of the longest common subsequence of old[i..] and new[j..].
+    let width = new.len() + 1;
+    let mut lengths = vec![0u32; (old.len() + 1) * width];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            lengths[i * width + j] = if old[i] == new[j] {
+                lengths[(i + 1) * width + j + 1] + 1
+            } else {
+                lengths[(i + 1) * width + j].max(lengths[i * width + j + 1])
+            };
+        }
+    }
+
+    let (mut i, mut j) = (0, 0);
+    while i < old.len() && j < new.len() {
+        if old[i] == new[j] {
+            kept_old[i] = true;
+            kept_new[j] = true;
+            i += 1;
+            j += 1;
+        } else if lengths[(i + 1) * width + j] >= lengths[i * width + j + 1] {
+            i += 1;
+        } else {
+            j += 1;
+        }
+    }
+
+    (kept_old, kept_new)
+}
+
+impl State {
+    /// Attaches line diffs to the events of the watch on `filename`, for text files of up
+    /// to `max_size` bytes.
+    pub(crate) fn add_diff(&mut self, filename: &Path, max_size: u64) -> Result<(), Error> {
+        let Some(watch) = self.watches.get_mut(filename) else {
+            return Err(Error::watch_not_found().add_path(filename.to_path_buf()));
+        };
+        watch.diff_limit = Some(max_size);
+
+        if let Some(text) = read_text(filename, max_size) {
+            self.contents.insert(filename.to_path_buf(), text);
+        }
+
+        Ok(())
+    }
+
+    /// Forgets the remembered content of `filename` and everything below it.
+    pub(crate) fn forget_contents(&mut self, filename: &Path) {
+        self.contents.retain(|path, _| !path.starts_with(filename));
+    }
+
+    /// Computes the diff of the file an event reports against its remembered content, and
+    /// remembers the new content.
+    pub(crate) fn diff_for(&mut self, event: &DebouncedEvent) -> Option<TextDiff> {
+        let path = event.paths.last()?;
+        let max_size = self
+            .watches
+            .values()
+            .filter(|watch| self.watch_reports(watch, path))
+            .filter_map(|watch| watch.diff_limit)
+            .max()?;
+
+        let kind = EventKind::of(event);
+        if !matches!(kind, EventKind::Created | EventKind::Modified) {
+            return None;
+        }
+        let Some(text) = read_text(path, max_size) else {
+            self.contents.remove(path);
+            return None;
+        };
+        let previous = self.contents.insert(path.clone(), text);
+        let previous = match (previous, kind) {
+            (Some(previous), _) => previous,
+            (None, EventKind::Created) => String::new(),
+            (None, _) => return None,
+        };
+
+        Some(TextDiff::between(&previous, &self.contents[path]))
+    }
+
+    /// Returns `true` if a watch attaches diffs to its events.
+    pub(crate) fn has_diffs(&self) -> bool {
+        self.watches
+            .values()
+            .any(|watch| watch.diff_limit.is_some())
+    }
+}
+
+/// Reads `path` if it is a text file of up to `max_size` bytes.
+fn read_text(path: &Path, max_size: u64) -> Option<String> {
+    let metadata = fs::metadata(path).ok()?;
+    if !metadata.is_file() || metadata.len() > max_size {
+        return None;
+    }
+    String::from_utf8(fs::read(path).ok()?).ok()
+}
+
+#[cfg(test)]
+/// This module contains tests for the line diff.
+///
+/// The `reports_changed_lines` test verifies that only changed lines are reported, numbered
+/// as in their own version.
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reports_changed_lines() {
+        let diff = TextDiff::between("a\nb\nc\nd\n", "a\nB\nc\nd\ne\n");
+        assert_eq!(diff.removed, vec![DiffLine::new(1, "b")]);
+        assert_eq!(
+            diff.added,
+            vec![DiffLine::new(1, "B"), DiffLine::new(4, "e")]
+        );
+        assert!(TextDiff::between("same\n", "same").is_empty());
+        assert_eq!(
+            TextDiff::between("", "new").added,
+            vec![DiffLine::new(0, "new")]
+        );
+    }
+}
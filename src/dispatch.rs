@@ -0,0 +1,799 @@
+use std::{
+    collections::{BTreeMap, VecDeque},
+    path::{Path, PathBuf},
+    sync::{mpsc, Arc, Mutex, MutexGuard, TryLockError},
+    thread::{self, Thread, ThreadId},
+    time::{Duration, Instant, SystemTime},
+};
+
+use crate::{
+    event::{self, Batch, Details, EventConfig},
+    middleware::Chain,
+    panic,
+    pool::Pool,
+    rate::{RateLimit, RateLimiter},
+    settle::Settler,
+    stream::{self, InFlight, QueueLimit, StreamSender},
+    CancellationToken, DebounceEventHandler, DebouncedEvent, Error, Event, EventFilter,
+    EventHandler, EventKindMask, EventPredicate, EventStream, FilterSet,
+};
+
+/// What happens to events that arrive while a [`Watcher`](crate::Watcher) is paused.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PauseMode {
+    /// Events are discarded and never reach the handler.
+    Drop,
+    /// Events are kept and delivered to the handler when the watcher is resumed.
+    Queue,
+}
+
+/// How long the flusher sleeps while no event is held, unless it is woken.
+const IDLE_WAIT: Duration = Duration::from_secs(1);
+
+/// Routes debounced events from the debouncer thread to the user's handler.
+///
+/// The dispatcher is shared between the [`Watcher`](crate::Watcher) and the debouncer thread,
+/// so that the watcher can change how events are delivered without recreating the debouncer.
+pub(crate) struct Dispatcher {
+    handler: Handler,
+    routes: BTreeMap<PathBuf, Route>,
+    filter: Option<EventFilter>,
+    /// The glob patterns applied before the filter.
+    filters: FilterSet,
+    /// The predicate on the crate's own events, applied after the filter.
+    event_filter: Option<EventPredicate>,
+    /// The middlewares every event passes through after the filters, unless they are running
+    /// without the dispatcher locked, see [`dispatch`].
+    middleware: Chain,
+    /// Whether a thread is running the middlewares.
+    processing: bool,
+    /// The filtered batches waiting for the middlewares.
+    unprocessed: VecDeque<Vec<(DebouncedEvent, Details)>>,
+    kinds: EventKindMask,
+    paused: Option<PauseMode>,
+    queued_events: Vec<(DebouncedEvent, Details)>,
+    queued_errors: Vec<Error>,
+    /// Where errors are sent instead of the watcher's handler, see
+    /// [`Watcher::errors`](crate::Watcher::errors).
+    errors: Option<mpsc::Sender<Error>>,
+    /// The paths of one-shot routes that have fired and whose watches should be removed.
+    expired: Vec<PathBuf>,
+    /// The batches waiting to be handed to their handlers, see [`drain`].
+    outbox: VecDeque<Delivery>,
+    /// The thread handing the batches of the outbox to their handlers, if any.
+    drainer: Option<ThreadId>,
+    /// The sequence number of the next event handed to the watcher's handler.
+    sequence: u64,
+    /// The streams receiving every event, see [`Watcher::subscribe`](crate::Watcher::subscribe).
+    subscribers: Vec<StreamSender>,
+    /// The bound of the queues of new subscribers.
+    queue: Option<QueueLimit>,
+    /// Which optional details are attached to the events of subscribers.
+    config: EventConfig,
+    /// The sequence number of the next event handed to the subscribers.
+    broadcast_sequence: u64,
+    /// The worker threads running the handlers, if they don't run on the debouncer thread.
+    pool: Option<Pool>,
+    /// The token that shuts the dispatcher down, see
+    /// [`WatcherBuilder::cancellation`](crate::WatcherBuilder::cancellation).
+    cancellation: Option<CancellationToken>,
+    /// The futures of the watcher's asynchronous handler that are still running.
+    in_flight: Option<InFlight>,
+    /// The events held back per path, see
+    /// [`WatcherBuilder::rate_limit`](crate::WatcherBuilder::rate_limit).
+    rate_limiter: Option<RateLimiter>,
+    /// The events held back until their file stops changing, see
+    /// [`WatchOptions::settle`](crate::WatchOptions::settle).
+    settler: Option<Settler>,
+    /// The thread delivering held events once they are due, see [`flush::start_flusher`].
+    flusher: Option<Thread>,
+    /// Whether the flusher was started.
+    flusher_claimed: bool,
+}
+
+/// The watcher's handler, receiving either the back-end's events or the crate's own.
+pub(crate) enum Handler {
+    Debounced(Box<dyn DebounceEventHandler>),
+    Events(Box<dyn EventHandler>, EventConfig),
+    /// The handler is running without the dispatcher locked, see [`drain`].
+    Busy,
+    /// The handler was dropped because the watcher was cancelled.
+    Closed,
+}
+
+impl Handler {
+    /// Hands a batch to the handler, returning the error of a panic instead of unwinding.
+    fn handle_event(&mut self, batch: Batch, sequence: &mut u64) -> Result<(), Error> {
+        panic::isolate(|| match self {
+            Self::Debounced(handler) => handler.handle_event(event::strip(batch)),
+            Self::Events(handler, config) => {
+                handler.handle_event(event::convert(batch, config, sequence))
+            }
+            Self::Busy | Self::Closed => {}
+        })
+    }
+}
+
+/// A handler receiving the events below one watched path.
+struct Route {
+    /// The handler, or `None` while it runs without the dispatcher locked.
+    handler: Option<Box<dyn DebounceEventHandler>>,
+    /// Whether the route is removed after its first delivery.
+    once: bool,
+}
+
+/// A batch waiting in the outbox of a [`Dispatcher`] to be handed to its handler.
+enum Delivery {
+    /// A batch for the watcher's handler.
+    Handler(Batch),
+    /// Events for the route on a path.
+    Route(PathBuf, Vec<(DebouncedEvent, Details)>),
+    /// Errors for the error receiver, or for the watcher's handler if there is none.
+    Errors(Vec<Error>),
+}
+
+/// A handle to a [`Dispatcher`] that can be moved onto the debouncer thread.
+pub(crate) type SharedDispatcher = Arc<Mutex<Dispatcher>>;
+
+impl Dispatcher {
+    pub(crate) fn new(
+        handler: Handler,
+        filter: Option<EventFilter>,
+        kinds: EventKindMask,
+        config: EventConfig,
+    ) -> Self {
+        Self {
+            handler,
+            kinds,
+            routes: BTreeMap::new(),
+            filter,
+            filters: FilterSet::default(),
+            event_filter: None,
+            middleware: Chain::default(),
+            processing: false,
+            unprocessed: VecDeque::new(),
+            paused: None,
+            queued_events: Vec::new(),
+            queued_errors: Vec::new(),
+            errors: None,
+            expired: Vec::new(),
+            outbox: VecDeque::new(),
+            drainer: None,
+            sequence: 0,
+            subscribers: Vec::new(),
+            queue: None,
+            config,
+            broadcast_sequence: 0,
+            pool: None,
+            cancellation: None,
+            in_flight: None,
+            rate_limiter: None,
+            settler: None,
+            flusher: None,
+            flusher_claimed: false,
+        }
+    }
+
+    /// Runs the handlers on the worker threads of `pool`, including the handlers of paths
+    /// that are added later.
+    pub(crate) fn with_pool(mut self, mut pool: Pool) -> Self {
+        self.handler = match self.handler {
+            Handler::Debounced(handler) => Handler::Debounced(Box::new(pool.pin(handler))),
+            Handler::Events(handler, config) => {
+                Handler::Events(Box::new(pool.pin(handler)), config)
+            }
+            handler @ (Handler::Busy | Handler::Closed) => handler,
+        };
+        self.pool = Some(pool);
+        self
+    }
+
+    /// Bounds the queues of the subscribers.
+    pub(crate) fn with_queue(mut self, queue: QueueLimit) -> Self {
+        self.queue = Some(queue);
+        self
+    }
+
+    /// Drops events whose paths `filters` does not allow.
+    pub(crate) fn with_filters(mut self, filters: FilterSet) -> Self {
+        self.filters = filters;
+        self
+    }
+
+    /// Drops events for which `filter` returns `false`.
+    pub(crate) fn with_event_filter(mut self, filter: EventPredicate) -> Self {
+        self.event_filter = Some(filter);
+        self
+    }
+
+    /// Passes every event through `middleware` after the filters.
+    pub(crate) fn with_middleware(mut self, middleware: Chain) -> Self {
+        self.middleware = middleware;
+        self
+    }
+
+    /// Stops delivering events once `token` is cancelled.
+    pub(crate) fn with_cancellation(mut self, token: CancellationToken) -> Self {
+        self.cancellation = Some(token);
+        self
+    }
+
+    /// Remembers the futures of the watcher's asynchronous handler, see
+    /// [`Dispatcher::in_flight`].
+    pub(crate) fn with_in_flight(mut self, in_flight: InFlight) -> Self {
+        self.in_flight = Some(in_flight);
+        self
+    }
+
+    /// Limits the events of each path to `limit`, coalescing the excess.
+    pub(crate) fn with_rate_limit(mut self, limit: RateLimit) -> Self {
+        self.rate_limiter = Some(RateLimiter::new(Some(limit)));
+        self
+    }
+
+    /// Limits the paths below the watch on `root` to `limit`.
+    pub(crate) fn limit_root(&mut self, root: &Path, limit: RateLimit) {
+        self.rate_limiter
+            .get_or_insert_with(|| RateLimiter::new(None))
+            .limit_root(root.to_path_buf(), limit);
+    }
+
+    /// Removes the limit of the paths below the watch on `root`.
+    pub(crate) fn unlimit_root(&mut self, root: &Path) {
+        if let Some(limiter) = &mut self.rate_limiter {
+            limiter.forget_root(root);
+        }
+    }
+
+    /// Returns the limit of the paths below the watch on `root`, see
+    /// [`Dispatcher::limit_root`].
+    pub(crate) fn root_limit(&self, root: &Path) -> Option<RateLimit> {
+        self.rate_limiter.as_ref()?.root_limit(root)
+    }
+
+    /// Holds back the events below the watch on `root` until their file has been unchanged
+    /// for `settle`.
+    pub(crate) fn settle_root(&mut self, root: &Path, settle: Duration) {
+        self.settler
+            .get_or_insert_with(Settler::default)
+            .settle_root(root.to_path_buf(), settle);
+    }
+
+    /// Delivers the events below the watch on `root` right away again, dropping those that
+    /// are still held.
+    pub(crate) fn unsettle_root(&mut self, root: &Path) {
+        if let Some(settler) = &mut self.settler {
+            settler.forget_root(root);
+        }
+    }
+
+    /// Returns how long the files below the watch on `root` must stay unchanged, see
+    /// [`Dispatcher::settle_root`].
+    pub(crate) fn root_settle(&self, root: &Path) -> Option<Duration> {
+        self.settler.as_ref()?.root_settle(root)
+    }
+
+    /// Returns `true` the first time it is called, for the caller to start the flusher.
+    pub(crate) fn claim_flusher(&mut self) -> bool {
+        !std::mem::replace(&mut self.flusher_claimed, true)
+    }
+
+    /// Records the flusher's thread, so that it can be woken when an event is held.
+    pub(crate) fn set_flusher(&mut self, flusher: Thread) {
+        self.flusher = Some(flusher);
+    }
+
+    /// Returns the futures of the watcher's asynchronous handler that are still running, if
+    /// its handler is asynchronous.
+    pub(crate) fn in_flight(&self) -> Option<InFlight> {
+        self.in_flight.clone()
+    }
+
+    /// Wraps the dispatcher so it can be shared with the debouncer thread.
+    pub(crate) fn shared(self) -> SharedDispatcher {
+        Arc::new(Mutex::new(self))
+    }
+
+    /// Applies the kinds, filters and predicates to a debounced batch.
+    ///
+    /// Returns `None` if nothing is left to deliver or the dispatcher was shut down.
+    fn filter(&mut self, batch: Batch) -> Option<Batch> {
+        if self.is_cancelled() {
+            self.shut_down();
+            return None;
+        }
+
+        let Ok(events) = batch else {
+            return Some(batch);
+        };
+        let events: Vec<_> = events
+            .into_iter()
+            .filter(|(event, details)| {
+                self.kinds.contains(details.kind(event))
+                    && self.filters.allows_event(event, details)
+                    && self.filter.as_ref().is_none_or(|filter| filter(event))
+                    && self
+                        .event_filter
+                        .as_ref()
+                        .is_none_or(|filter| passes(filter, event, details))
+            })
+            .collect();
+        if events.is_empty() && self.settler.is_none() && self.rate_limiter.is_none() {
+            return None;
+        }
+        Some(Ok(events))
+    }
+
+    /// Holds back the events of a processed batch that are not due yet, and delivers the rest
+    /// to the handler, applying the pause state.
+    fn admit(&mut self, batch: Batch) {
+        let Ok(events) = batch else {
+            self.release(batch);
+            return;
+        };
+        let now = Instant::now();
+        let events = match &mut self.settler {
+            Some(settler) => settler.admit(events, now),
+            None => events,
+        };
+        let events = match &mut self.rate_limiter {
+            Some(limiter) => limiter.admit(events, now),
+            None => events,
+        };
+        if let Some(flusher) = &self.flusher {
+            flusher.unpark();
+        }
+        if !events.is_empty() {
+            self.release(Ok(events));
+        }
+    }
+
+    /// Delivers a filtered batch to the handler, or keeps it while the watcher is paused.
+    pub(crate) fn release(&mut self, result: Batch) {
+        match (self.paused, result) {
+            (None, result) => self.deliver(result),
+            (Some(PauseMode::Drop), _) => {
+                tracing::trace!("Dropped debounced events while paused");
+            }
+            (Some(PauseMode::Queue), Ok(events)) => self.queued_events.extend(events),
+            (Some(PauseMode::Queue), Err(errors)) => self.queued_errors.extend(errors),
+        }
+    }
+
+    /// Delivers the held events that are due.
+    ///
+    /// Returns how long until the next held event may be due, or `None` once the
+    /// dispatcher was shut down.
+    pub(crate) fn flush_held(&mut self, now: Instant) -> Option<Duration> {
+        if self.is_closed() {
+            return None;
+        }
+
+        let mut due = Vec::new();
+        if let Some(settler) = &mut self.settler {
+            due = settler.take_settled(now);
+        }
+        if let Some(limiter) = &mut self.rate_limiter {
+            due = limiter.admit(due, now);
+        }
+        if !due.is_empty() {
+            self.release(Ok(due));
+        }
+
+        let settler = self
+            .settler
+            .as_ref()
+            .and_then(|settler| settler.next_check(now));
+        let limiter = self
+            .rate_limiter
+            .as_ref()
+            .and_then(|limiter| limiter.next_due(now));
+        Some(
+            settler
+                .into_iter()
+                .chain(limiter)
+                .min()
+                .unwrap_or(IDLE_WAIT),
+        )
+    }
+
+    /// Returns `true` once the dispatcher was shut down.
+    pub(crate) fn is_closed(&self) -> bool {
+        matches!(&self.handler, Handler::Closed)
+    }
+
+    fn is_cancelled(&self) -> bool {
+        self.cancellation
+            .as_ref()
+            .is_some_and(CancellationToken::is_cancelled)
+    }
+
+    /// Drops every handler, subscriber and queued event, so that everything waiting for
+    /// events from the dispatcher ends.
+    pub(crate) fn shut_down(&mut self) {
+        if self.is_closed() {
+            return;
+        }
+        self.handler = Handler::Closed;
+        self.routes.clear();
+        self.subscribers.clear();
+        self.queued_events.clear();
+        self.queued_errors.clear();
+        self.unprocessed.clear();
+        self.outbox.clear();
+        self.errors = None;
+        self.rate_limiter = None;
+        self.settler = None;
+
+        tracing::debug!("Shut down the dispatcher");
+    }
+
+    pub(crate) fn pause(&mut self, mode: PauseMode) {
+        self.paused = Some(mode);
+    }
+
+    /// Resumes delivery, handing any queued events and errors to the handler first.
+    pub(crate) fn resume(&mut self) {
+        self.paused = None;
+
+        let events = std::mem::take(&mut self.queued_events);
+        if !events.is_empty() {
+            self.deliver(Ok(events));
+        }
+
+        let errors = std::mem::take(&mut self.queued_errors);
+        if !errors.is_empty() {
+            self.deliver(Err(errors));
+        }
+    }
+
+    /// Sends errors to the returned receiver instead of the watcher's handler.
+    pub(crate) fn subscribe_errors(&mut self) -> mpsc::Receiver<Error> {
+        let (sender, receiver) = mpsc::channel();
+        self.errors = Some(sender);
+        receiver
+    }
+
+    /// Returns a stream receiving every event delivered from now on, in addition to the
+    /// handlers.
+    pub(crate) fn subscribe(&mut self) -> EventStream {
+        let (sender, stream) = stream::event_stream(self.queue);
+        if let Some(token) = &self.cancellation {
+            stream.cancel_on(token);
+        }
+        self.subscribers.push(sender);
+        stream
+    }
+
+    /// Sends events under `path` to `handler` instead of the watcher's handler.
+    ///
+    /// If `once` is set, the route is removed after its first delivery and `path` is
+    /// reported by [`Dispatcher::take_expired`].
+    pub(crate) fn add_route(
+        &mut self,
+        path: &Path,
+        handler: impl DebounceEventHandler,
+        once: bool,
+    ) {
+        let handler: Box<dyn DebounceEventHandler> = Box::new(handler);
+        let handler = match &mut self.pool {
+            Some(pool) => Box::new(pool.pin(handler)),
+            None => handler,
+        };
+        self.routes.insert(
+            path.to_path_buf(),
+            Route {
+                handler: Some(handler),
+                once,
+            },
+        );
+    }
+
+    pub(crate) fn remove_route(&mut self, path: &Path) {
+        self.routes.remove(path);
+    }
+
+    /// Queues a batch for the handlers, splitting events between the per-path routes.
+    ///
+    /// Each event goes to the route with the most specific path containing it, or to the
+    /// watcher's handler if no route matches. Errors go to the error receiver, if there is
+    /// one, and to the watcher's handler otherwise, and so do the panics of the handlers.
+    /// The batches are handed over by [`drain`], once the dispatcher is unlocked.
+    fn deliver(&mut self, batch: Batch) {
+        if self.is_closed() {
+            return;
+        }
+        if let Ok(events) = &batch {
+            self.broadcast(events);
+        }
+
+        let events = match batch {
+            Err(errors) => {
+                self.outbox.push_back(Delivery::Errors(errors));
+                return;
+            }
+            Ok(events) if !self.routes.is_empty() => events,
+            batch => {
+                self.outbox.push_back(Delivery::Handler(batch));
+                return;
+            }
+        };
+
+        let mut batches: BTreeMap<Option<PathBuf>, Vec<(DebouncedEvent, Details)>> =
+            BTreeMap::new();
+        for event in events {
+            let route = self.route_for(&event.0);
+            batches.entry(route).or_default().push(event);
+        }
+        for (path, events) in batches {
+            self.outbox.push_back(match path {
+                Some(path) => Delivery::Route(path, events),
+                None => Delivery::Handler(Ok(events)),
+            });
+        }
+    }
+
+    /// Hands a copy of the events to every subscriber, forgetting the ones that are gone.
+    fn broadcast(&mut self, events: &[(DebouncedEvent, Details)]) {
+        self.subscribers
+            .retain(|subscriber| !subscriber.is_closed());
+        if self.subscribers.is_empty() {
+            return;
+        }
+
+        let batch = Ok(events.to_vec());
+        let Ok(events) = event::convert(batch, &self.config, &mut self.broadcast_sequence) else {
+            return;
+        };
+        for subscriber in &mut self.subscribers {
+            subscriber.handle_event(Ok(events.clone()));
+        }
+    }
+
+    /// Sends errors to the error receiver, returning those that go to the watcher's handler
+    /// instead because there is no receiver or it is gone.
+    fn send_errors(&mut self, errors: Vec<Error>) -> Vec<Error> {
+        let Some(sender) = &self.errors else {
+            return errors;
+        };
+
+        let mut errors = errors.into_iter();
+        while let Some(error) = errors.next() {
+            if let Err(mpsc::SendError(error)) = sender.send(error) {
+                // The receiver is gone, so errors go back to the handler.
+                self.errors = None;
+                return std::iter::once(error).chain(errors).collect();
+            }
+        }
+        Vec::new()
+    }
+
+    /// Returns `true` if batches are being handed to their handlers on a thread other than
+    /// the current one, see [`drain`].
+    pub(crate) fn is_draining_elsewhere(&self) -> bool {
+        self.drainer
+            .is_some_and(|drainer| drainer != thread::current().id())
+    }
+
+    /// Returns the paths of one-shot routes that fired since the last call.
+    pub(crate) fn take_expired(&mut self) -> Vec<PathBuf> {
+        std::mem::take(&mut self.expired)
+    }
+
+    /// Finds the most specific route containing one of the event's paths.
+    ///
+    /// Later paths are preferred because rename events list their target path last.
+    fn route_for(&self, event: &DebouncedEvent) -> Option<PathBuf> {
+        event.paths.iter().rev().find_map(|path| {
+            self.routes
+                .keys()
+                .filter(|route| path.starts_with(route))
+                .max_by_key(|route| route.components().count())
+                .cloned()
+        })
+    }
+
+    pub(crate) fn is_paused(&self) -> bool {
+        self.paused.is_some()
+    }
+}
+
+/// Locks the dispatcher, recovering it if a previous handler call panicked.
+pub(crate) fn lock(dispatcher: &SharedDispatcher) -> MutexGuard<'_, Dispatcher> {
+    dispatcher
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// Delivers a debounced batch to the handlers, applying the filters, the middlewares and the
+/// pause state.
+///
+/// The middlewares run without the dispatcher locked, so that they may use the watcher like
+/// handlers may. Batches dispatched while they run, including by a middleware itself, are
+/// passed through them in order by the thread already running them. The batches are handed to
+/// the handlers by [`drain`].
+pub(crate) fn dispatch(shared: &SharedDispatcher, batch: Batch) {
+    let mut dispatcher = lock(shared);
+    let Some(batch) = dispatcher.filter(batch) else {
+        return;
+    };
+    match batch {
+        Ok(events) if !dispatcher.middleware.is_empty() || dispatcher.processing => {
+            dispatcher.unprocessed.push_back(events);
+        }
+        batch => return dispatcher.admit(batch),
+    }
+    if dispatcher.processing {
+        return;
+    }
+    dispatcher.processing = true;
+
+    while let Some(events) = dispatcher.unprocessed.pop_front() {
+        let mut middleware = std::mem::take(&mut dispatcher.middleware);
+        let config = dispatcher.config.clone();
+        drop(dispatcher);
+
+        let mut processed = Vec::new();
+        let handled = panic::isolate(|| processed = middleware.apply(events, &config));
+
+        dispatcher = lock(shared);
+        dispatcher.middleware = middleware;
+        match handled {
+            Ok(()) => dispatcher.admit(Ok(processed)),
+            Err(error) => dispatcher.outbox.push_back(Delivery::Errors(vec![error])),
+        }
+    }
+
+    dispatcher.processing = false;
+}
+
+/// Hands the batches queued by [`dispatch`] to their handlers, unless another
+/// thread is already doing so, and returns the dispatcher locked again.
+///
+/// Handlers run without the dispatcher locked, so that they may unwatch paths, pause the
+/// watcher or subscribe to it. Batches queued while a handler runs, including by the
+/// handler itself, are handed over in order by the thread already draining the outbox, so
+/// handlers are never called concurrently.
+pub(crate) fn drain(shared: &SharedDispatcher) -> MutexGuard<'_, Dispatcher> {
+    let mut dispatcher = lock(shared);
+    if dispatcher.drainer.is_some() {
+        return dispatcher;
+    }
+    dispatcher.drainer = Some(thread::current().id());
+
+    while let Some(delivery) = dispatcher.outbox.pop_front() {
+        dispatcher = match delivery {
+            Delivery::Handler(batch) => {
+                let (mut dispatcher, handled) = run_handler(shared, dispatcher, batch);
+                if let Err(error) = handled {
+                    dispatcher.outbox.push_back(Delivery::Errors(vec![error]));
+                }
+                dispatcher
+            }
+            Delivery::Errors(errors) => {
+                let errors = dispatcher.send_errors(errors);
+                if errors.is_empty() {
+                    dispatcher
+                } else {
+                    // A panic of the handler while handling errors is only logged.
+                    run_handler(shared, dispatcher, Err(errors)).0
+                }
+            }
+            Delivery::Route(path, events) => run_route(shared, dispatcher, path, events),
+        };
+    }
+
+    dispatcher.drainer = None;
+    dispatcher
+}
+
+/// Hands a batch to the watcher's handler with the dispatcher unlocked.
+fn run_handler<'a>(
+    shared: &'a SharedDispatcher,
+    mut dispatcher: MutexGuard<'a, Dispatcher>,
+    batch: Batch,
+) -> (MutexGuard<'a, Dispatcher>, Result<(), Error>) {
+    let mut handler = std::mem::replace(&mut dispatcher.handler, Handler::Busy);
+    let mut sequence = dispatcher.sequence;
+    drop(dispatcher);
+
+    let handled = handler.handle_event(batch, &mut sequence);
+
+    let mut dispatcher = lock(shared);
+    dispatcher.sequence = sequence;
+    if matches!(dispatcher.handler, Handler::Busy) {
+        dispatcher.handler = handler;
+    } else {
+        // The dispatcher was shut down while the handler ran, which drops it unlocked.
+        drop(dispatcher);
+        drop(handler);
+        dispatcher = lock(shared);
+    }
+    (dispatcher, handled)
+}
+
+/// Hands events to the route on `path` with the dispatcher unlocked, dropping them if the
+/// route was removed since they were queued.
+fn run_route<'a>(
+    shared: &'a SharedDispatcher,
+    mut dispatcher: MutexGuard<'a, Dispatcher>,
+    path: PathBuf,
+    events: Vec<(DebouncedEvent, Details)>,
+) -> MutexGuard<'a, Dispatcher> {
+    let Some(mut handler) = dispatcher
+        .routes
+        .get_mut(&path)
+        .and_then(|route| route.handler.take())
+    else {
+        return dispatcher;
+    };
+    drop(dispatcher);
+
+    let handled = panic::isolate(|| handler.handle_event(event::strip(Ok(events))));
+
+    let mut dispatcher = lock(shared);
+    if let Err(error) = handled {
+        dispatcher.outbox.push_back(Delivery::Errors(vec![error]));
+    }
+    // The route may have been removed or replaced while its handler ran.
+    let once = match dispatcher.routes.get_mut(&path) {
+        Some(route) if route.handler.is_none() => {
+            if !route.once {
+                route.handler = Some(handler);
+                return dispatcher;
+            }
+            true
+        }
+        _ => false,
+    };
+    if once {
+        dispatcher.routes.remove(&path);
+        dispatcher.expired.push(path);
+    }
+    drop(dispatcher);
+    drop(handler);
+    lock(shared)
+}
+
+/// Shuts `dispatcher` down once `token` is cancelled.
+///
+/// A token cancelled while the dispatcher is locked, for example while a batch is being
+/// filtered, shuts the dispatcher down from another thread once the lock is released.
+pub(crate) fn cancel_on(dispatcher: &SharedDispatcher, token: &CancellationToken) {
+    let dispatcher = Arc::downgrade(dispatcher);
+    token.on_cancel(move || {
+        let Some(dispatcher) = dispatcher.upgrade() else {
+            return;
+        };
+        let busy = match dispatcher.try_lock() {
+            Ok(mut locked) => {
+                locked.shut_down();
+                false
+            }
+            Err(TryLockError::Poisoned(poisoned)) => {
+                poisoned.into_inner().shut_down();
+                false
+            }
+            Err(TryLockError::WouldBlock) => true,
+        };
+        if busy {
+            thread::spawn(move || lock(&dispatcher).shut_down());
+        }
+    });
+}
+
+/// Converts an event for the predicate of
+/// [`WatcherBuilder::filter_event`](crate::WatcherBuilder::filter_event), with metadata but
+/// without contents.
+fn passes(filter: &EventPredicate, event: &DebouncedEvent, details: &Details) -> bool {
+    let config = EventConfig {
+        metadata: true,
+        contents: None,
+    };
+    let details = Details {
+        diff: None,
+        ..details.clone()
+    };
+    Event::from_debounced(event, details, &config, SystemTime::now())
+        .is_some_and(|event| filter(&event))
+}
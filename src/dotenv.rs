@@ -0,0 +1,306 @@
+use std::{
+    collections::BTreeMap,
+    env,
+    path::Path,
+    sync::{Arc, Mutex, MutexGuard},
+};
+
+use crate::{read, Error, Watcher, WatcherBuilder};
+
+/// Keeps the variables of a `.env` file up to date, reporting which of them changed
+/// whenever the file does.
+///
+/// The file is parsed once when the watcher is created, reporting all its variables as
+/// added, and again after each debounced change. Each line holds a `KEY=value` pair, which
+/// may start with `export`. Values may be quoted with single quotes, taken literally, or
+/// with double quotes, which may span lines and understand the escapes `\n`, `\r`, `\t`,
+/// `\"` and `\\`. Unquoted values end at a `#` that follows whitespace. Blank lines,
+/// comments and lines that are not pairs are skipped, and variables are not expanded.
+///
+/// Watchers created with `apply` set also apply every change to the environment of the
+/// process, so that code reading variables with [`std::env::var`] sees the file's values.
+/// Modifying the environment is not thread-safe on every platform, so only apply the file
+/// if other threads do not read the environment through the C library.
+///
+/// ```no_run
+/// use watchit::EnvWatcher;
+///
+/// let env = EnvWatcher::new(".env", true, |change| {
+///     for (key, (old, new)) in &change.changed {
+///         println!("{key} changed from {old} to {new}");
+///     }
+/// })
+/// .unwrap();
+/// let port = env.get("PORT");
+/// ```
+pub struct EnvWatcher {
+    watcher: Watcher,
+    vars: Shared,
+}
+
+type Shared = Arc<Mutex<BTreeMap<String, String>>>;
+
+impl EnvWatcher {
+    /// Watches the `.env` file at `path` with the default options of
+    /// [`WatcherBuilder::new`], see [`WatcherBuilder::build_env`].
+    ///
+    /// # Errors
+    /// Returns an error if the watcher cannot be created or the file cannot be read or
+    /// watched.
+    pub fn new(
+        path: impl AsRef<Path>,
+        apply: bool,
+        handler: impl FnMut(EnvChange) + Send + 'static,
+    ) -> Result<Self, Error> {
+        WatcherBuilder::new().build_env(path, apply, handler)
+    }
+
+    /// Returns the variables the file currently defines.
+    pub fn vars(&self) -> BTreeMap<String, String> {
+        lock(&self.vars).clone()
+    }
+
+    /// Returns the value the file currently assigns to `key`, if it defines it.
+    pub fn get(&self, key: &str) -> Option<String> {
+        lock(&self.vars).get(key).cloned()
+    }
+
+    /// Returns the watcher that watches the file, which can watch other paths as well.
+    pub fn watcher(&self) -> &Watcher {
+        &self.watcher
+    }
+}
+
+impl WatcherBuilder {
+    /// Creates the configured file watcher and keeps the variables of the `.env` file at
+    /// `path` up to date, see [`EnvWatcher`].
+    ///
+    /// # Arguments
+    /// * `path` - The `.env` file to watch.
+    /// * `apply` - `true` to apply every change to the environment of the process.
+    /// * `handler` - The function to call with the variables that changed, starting with
+    ///   the file as it is when the watcher is created. Changes of the file that leave its
+    ///   variables as they were are not passed on.
+    ///
+    /// # Returns
+    /// A `Result` containing either the `.env` watcher, or an `Error` if the watcher cannot
+    /// be created or the file cannot be read or watched.
+    pub fn build_env(
+        self,
+        path: impl AsRef<Path>,
+        apply: bool,
+        mut handler: impl FnMut(EnvChange) + Send + 'static,
+    ) -> Result<EnvWatcher, Error> {
+        let path = path.as_ref();
+        let vars = Shared::default();
+        let watcher = self.build(|_| {})?;
+
+        let mut load = {
+            let vars = Arc::clone(&vars);
+            move |contents: Vec<u8>| {
+                let new = parse(&String::from_utf8_lossy(&contents));
+                let change = EnvChange::between(&lock(&vars), &new);
+                *lock(&vars) = new;
+                if change.is_empty() {
+                    return;
+                }
+                if apply {
+                    change.apply();
+                }
+                handler(change);
+            }
+        };
+        match read::read_with_retry(path) {
+            Ok(contents) => load(contents),
+            Err(error) => return Err(Error::io(error).add_path(path.to_path_buf())),
+        }
+        watcher.watch_contents(path, load)?;
+
+        Ok(EnvWatcher { watcher, vars })
+    }
+}
+
+/// The variables that a change of a `.env` file added, removed and changed, see
+/// [`EnvWatcher`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct EnvChange {
+    /// The variables the file did not define before, with their values.
+    pub added: BTreeMap<String, String>,
+    /// The variables the file no longer defines, with their previous values.
+    pub removed: BTreeMap<String, String>,
+    /// The variables whose values changed, with their previous and new values.
+    pub changed: BTreeMap<String, (String, String)>,
+}
+
+impl EnvChange {
+    fn between(old: &BTreeMap<String, String>, new: &BTreeMap<String, String>) -> Self {
+        let mut change = Self::default();
+        for (key, value) in new {
+            match old.get(key) {
+                None => {
+                    change.added.insert(key.clone(), value.clone());
+                }
+                Some(previous) if previous != value => {
+                    change
+                        .changed
+                        .insert(key.clone(), (previous.clone(), value.clone()));
+                }
+                Some(_) => {}
+            }
+        }
+        for (key, value) in old {
+            if !new.contains_key(key) {
+                change.removed.insert(key.clone(), value.clone());
+            }
+        }
+        change
+    }
+
+    /// Returns `true` if no variable was added, removed or changed.
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+
+    /// Applies the change to the environment of the process.
+    fn apply(&self) {
+        for (key, value) in &self.added {
+            env::set_var(key, value);
+        }
+        for (key, (_, value)) in &self.changed {
+            env::set_var(key, value);
+        }
+        for key in self.removed.keys() {
+            env::remove_var(key);
+        }
+    }
+}
+
+/// Parses the variables of a `.env` file, later assignments replacing earlier ones.
+fn parse(text: &str) -> BTreeMap<String, String> {
+    let mut vars = BTreeMap::new();
+    let mut rest = text;
+    while !rest.is_empty() {
+        let (line, next) = rest.split_once('\n').unwrap_or((rest, ""));
+        rest = next;
+        let line = line.trim_start();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let line = line
+            .strip_prefix("export")
+            .filter(|line| line.starts_with([' ', '\t']))
+            .map_or(line, str::trim_start);
+        let Some((key, value)) = line.split_once('=') else {
+            tracing::debug!("Skipping a .env line without an assignment: {}", line);
+            continue;
+        };
+        let key = key.trim();
+        let valid = !key.is_empty()
+            && key
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '.' | '-'));
+        if !valid {
+            tracing::debug!("Skipping a .env line with an invalid key: {}", line);
+            continue;
+        }
+        let value = value.trim_start();
+        let value = match value.chars().next() {
+            Some('\'') => match value[1..].split_once('\'') {
+                Some((value, _)) => value.to_string(),
+                None => value.trim_end().to_string(),
+            },
+            Some('"') => {
+                let (value, next) = double_quoted(&value[1..], rest);
+                rest = next;
+                value
+            }
+            _ => unquoted(value),
+        };
+        vars.insert(key.to_string(), value);
+    }
+    vars
+}
+
+/// Reads a double-quoted value starting with `value` and continuing into the lines of
+/// `rest` until its closing quote, returning it and the lines after it.
+fn double_quoted<'a>(value: &'a str, rest: &'a str) -> (String, &'a str) {
+    let mut result = String::new();
+    let mut chars = value.chars();
+    let mut rest = rest;
+    loop {
+        match chars.next() {
+            Some('"') => return (result, rest),
+            Some('\\') => match chars.next() {
+                Some('n') => result.push('\n'),
+                Some('r') => result.push('\r'),
+                Some('t') => result.push('\t'),
+                Some(c @ ('"' | '\\' | '$')) => result.push(c),
+                Some(c) => {
+                    result.push('\\');
+                    result.push(c);
+                }
+                None => result.push('\\'),
+            },
+            Some(c) => result.push(c),
+            None if rest.is_empty() => return (result, rest),
+            None => {
+                // The value continues on the next line.
+                let (line, next) = rest.split_once('\n').unwrap_or((rest, ""));
+                result.push('\n');
+                chars = line.chars();
+                rest = next;
+            }
+        }
+    }
+}
+
+/// Returns an unquoted value without its trailing comment and whitespace.
+fn unquoted(value: &str) -> String {
+    let end = value
+        .char_indices()
+        .find(|&(i, c)| c == '#' && (i == 0 || value[..i].ends_with([' ', '\t'])))
+        .map_or(value.len(), |(i, _)| i);
+    value[..end].trim_end().to_string()
+}
+
+fn lock(vars: &Shared) -> MutexGuard<'_, BTreeMap<String, String>> {
+    vars.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+#[cfg(test)]
+/// This module contains tests for the `.env` parser.
+///
+/// The `parses_assignments` test verifies that comments, `export` prefixes, quoted and
+/// multi-line values and trailing comments are parsed as other `.env` loaders do.
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_assignments() {
+        let vars = parse(
+            "# comment\n\
+             export PORT=8080\n\
+             HOST = localhost # trailing\n\
+             URL=http://host/#anchor\n\
+             LITERAL='a \\n b'\n\
+             QUOTED=\"line\\tone\nline two\"\n\
+             \n\
+             not an assignment\n\
+             EMPTY=\n\
+             PORT=9090\r\n",
+        );
+        let expected = [
+            ("EMPTY", ""),
+            ("HOST", "localhost"),
+            ("LITERAL", "a \\n b"),
+            ("PORT", "9090"),
+            ("QUOTED", "line\tone\nline two"),
+            ("URL", "http://host/#anchor"),
+        ];
+        let expected: BTreeMap<String, String> = expected
+            .into_iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect();
+        assert_eq!(vars, expected);
+    }
+}
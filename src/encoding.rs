@@ -0,0 +1,209 @@
+use std::{
+    fmt,
+    fs::File,
+    io::{self, Read as _},
+    path::Path,
+};
+
+use crate::{json::Value, state::State, DebouncedEvent, Error, EventKind};
+
+/// How many bytes at the start of a file are looked at to tell its encoding, as many as
+/// Git looks at to tell binary files from text.
+const SNIFF_LEN: u64 = 8000;
+
+/// The encoding of a file's content, as told from its first bytes.
+///
+/// Encodings are attached to events when the watcher reads contents, see
+/// [`WatcherBuilder::contents`](crate::WatcherBuilder::contents).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Encoding {
+    /// UTF-8 text, including plain ASCII.
+    Utf8,
+    /// Little-endian UTF-16 text.
+    Utf16Le,
+    /// Big-endian UTF-16 text.
+    Utf16Be,
+    /// Text in a single-byte encoding such as ISO 8859-1, which is not valid UTF-8.
+    Latin1,
+    /// Content that is not text.
+    Binary,
+}
+
+impl Encoding {
+    /// Returns `true` for every encoding but [`Encoding::Binary`].
+    pub fn is_text(self) -> bool {
+        self != Self::Binary
+    }
+
+    /// Returns the name of the encoding, such as `"utf-8"`.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Utf8 => "utf-8",
+            Self::Utf16Le => "utf-16le",
+            Self::Utf16Be => "utf-16be",
+            Self::Latin1 => "latin-1",
+            Self::Binary => "binary",
+        }
+    }
+
+    /// Tells the encoding of content starting with `bytes`, or returns `None` if it is empty.
+    ///
+    /// Byte order marks are trusted. Without them, content containing NUL bytes is taken for
+    /// UTF-16 if they fall on the high bytes of its characters and for binary otherwise, and
+    /// content that is not valid UTF-8 is taken for Latin-1 unless it contains control
+    /// characters that text does not.
+    pub(crate) fn detect(bytes: &[u8]) -> Option<Self> {
+        match bytes {
+            [] => return None,
+            [0xef, 0xbb, 0xbf, ..] => return Some(Self::Utf8),
+            [0xff, 0xfe, ..] => return Some(Self::Utf16Le),
+            [0xfe, 0xff, ..] => return Some(Self::Utf16Be),
+            _ => {}
+        }
+        if bytes.contains(&0) {
+            return Some(utf16_order(bytes).unwrap_or(Self::Binary));
+        }
+        match std::str::from_utf8(bytes) {
+            Ok(_) => return Some(Self::Utf8),
+            // The sniffed bytes of a longer file may end within a character.
+            Err(error) if error.error_len().is_none() && bytes.len() as u64 >= SNIFF_LEN => {
+                return Some(Self::Utf8)
+            }
+            Err(_) => {}
+        }
+        let binary = bytes
+            .iter()
+            .any(|&byte| matches!(byte, 0x01..=0x08 | 0x0e..=0x1a | 0x1c..=0x1f | 0x7f));
+        Some(if binary { Self::Binary } else { Self::Latin1 })
+    }
+
+    pub(crate) fn to_json(self) -> Value {
+        Value::string(self.as_str())
+    }
+
+    pub(crate) fn from_json(value: &Value) -> Result<Self, Error> {
+        match value.as_str()? {
+            "utf-8" => Ok(Self::Utf8),
+            "utf-16le" => Ok(Self::Utf16Le),
+            "utf-16be" => Ok(Self::Utf16Be),
+            "latin-1" => Ok(Self::Latin1),
+            "binary" => Ok(Self::Binary),
+            other => Err(Error::generic(&format!("unknown encoding {other:?}"))),
+        }
+    }
+}
+
+impl fmt::Display for Encoding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Returns the byte order of content that looks like UTF-16 text without a byte order
+/// mark, which is the case if nearly all its NUL bytes are in the same half of its pairs.
+fn utf16_order(bytes: &[u8]) -> Option<Encoding> {
+    let pairs = bytes.len() / 2;
+    if pairs == 0 {
+        return None;
+    }
+    let (mut even, mut odd) = (0, 0);
+    for pair in bytes.chunks_exact(2) {
+        even += usize::from(pair[0] == 0);
+        odd += usize::from(pair[1] == 0);
+    }
+    if odd * 10 >= pairs * 9 && even * 10 < pairs {
+        Some(Encoding::Utf16Le)
+    } else if even * 10 >= pairs * 9 && odd * 10 < pairs {
+        Some(Encoding::Utf16Be)
+    } else {
+        None
+    }
+}
+
+/// The encoding of the file an event reports, and the one it had before if it changed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct EncodingChange {
+    pub(crate) current: Encoding,
+    pub(crate) previous: Option<Encoding>,
+}
+
+impl State {
+    /// Tells the encoding of the file an event reports, if the watcher reads contents and
+    /// the file is small enough, and compares it to the encoding the file had last.
+    pub(crate) fn encoding_for(&mut self, event: &DebouncedEvent) -> Option<EncodingChange> {
+        let max_size = self.contents_limit?;
+        let path = event.paths.last()?;
+        if EventKind::of(event) == EventKind::Removed {
+            self.encodings.remove(path);
+            return None;
+        }
+        let current = match sniff(path, max_size) {
+            Ok(bytes) => Encoding::detect(&bytes)?,
+            Err(_) => {
+                self.encodings.remove(path);
+                return None;
+            }
+        };
+        let previous = self
+            .encodings
+            .insert(path.clone(), current)
+            .filter(|&previous| previous != current);
+        Some(EncodingChange { current, previous })
+    }
+
+    /// Forgets the encodings of `filename` and everything below it.
+    pub(crate) fn forget_encodings(&mut self, filename: &Path) {
+        self.encodings.retain(|path, _| !path.starts_with(filename));
+    }
+}
+
+/// Reads the first bytes of the file at `path`, if it is no larger than `max_size` bytes.
+fn sniff(path: &Path, max_size: u64) -> io::Result<Vec<u8>> {
+    let file = File::open(path)?;
+    let metadata = file.metadata()?;
+    if !metadata.is_file() || metadata.len() > max_size {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "not read"));
+    }
+    let mut bytes = Vec::new();
+    file.take(SNIFF_LEN).read_to_end(&mut bytes)?;
+    Ok(bytes)
+}
+
+#[cfg(test)]
+/// This module contains tests for telling encodings apart.
+///
+/// The `detects_encodings` test verifies that UTF-8, UTF-16 with and without byte order
+/// marks, Latin-1 and binary content are told apart, and that the first bytes of a longer
+/// file ending within a UTF-8 character are still taken for UTF-8.
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detects_encodings() {
+        assert_eq!(Encoding::detect(b""), None);
+        assert_eq!(Encoding::detect(b"plain"), Some(Encoding::Utf8));
+        assert_eq!(
+            Encoding::detect("caf\u{e9}".as_bytes()),
+            Some(Encoding::Utf8)
+        );
+        let mut cut = vec![b'a'; SNIFF_LEN as usize - 1];
+        cut.push(0xc3);
+        assert_eq!(Encoding::detect(&cut), Some(Encoding::Utf8));
+        assert_eq!(Encoding::detect(b"caf\xc3"), Some(Encoding::Latin1));
+        assert_eq!(Encoding::detect(b"\xff\xfea\0"), Some(Encoding::Utf16Le));
+        assert_eq!(Encoding::detect(b"a\0b\0c\0"), Some(Encoding::Utf16Le));
+        assert_eq!(Encoding::detect(b"\0a\0b\0c"), Some(Encoding::Utf16Be));
+        assert_eq!(
+            Encoding::detect(b"caf\xe9 cr\xe8me"),
+            Some(Encoding::Latin1)
+        );
+        assert_eq!(
+            Encoding::detect(b"\x7fELF\x02\x01\x01\0\0\0"),
+            Some(Encoding::Binary)
+        );
+        assert_eq!(
+            Encoding::detect(b"\x89PNG\r\n\x1a\n"),
+            Some(Encoding::Binary)
+        );
+    }
+}
@@ -0,0 +1,129 @@
+use std::{error::Error as StdError, fmt, io, path::PathBuf};
+
+/// The error type of the [`Watcher`](crate::Watcher).
+///
+/// Most errors come straight from the notification back-end and are passed through as
+/// [`Error::Notify`]. Failures the caller is expected to act on get their own variant.
+#[derive(Debug)]
+pub enum Error {
+    /// An error reported by the notification back-end, or a path that cannot be watched.
+    Notify(notify::Error),
+    /// The operating system's limit on file watches was reached.
+    ///
+    /// On Linux the limit is `fs.inotify.max_user_watches`. Raise the limit, watch fewer
+    /// paths, or build the watcher with
+    /// [`WatcherBuilder::poll_fallback`](crate::WatcherBuilder::poll_fallback) to poll the
+    /// paths that no longer fit.
+    WatchLimit {
+        /// The path that could not be watched.
+        path: PathBuf,
+        /// How many paths were registered with the watcher when the limit was reached.
+        registered: usize,
+    },
+    /// A handler panicked while handling a batch of events.
+    ///
+    /// The watcher catches the panic and keeps running, so later changes are delivered as
+    /// usual. The events of the batch the handler panicked on are lost. Handlers running on
+    /// the [workers](crate::WatcherBuilder::workers) of a watcher are isolated as well, but
+    /// their panics are only logged.
+    HandlerPanic {
+        /// The message the handler panicked with.
+        message: String,
+    },
+    /// The back-end's event queue overflowed, so some changes were lost.
+    ///
+    /// This is reported when inotify's queue, an FSEvents stream or the change buffer of
+    /// [`Backend::Windows`](crate::Backend::Windows) drops events, typically during heavy
+    /// churn such as a large checkout. The changes since the last delivered event are
+    /// unknown, so state derived from the events should be rebuilt from the watched paths.
+    Overflow,
+}
+
+impl Error {
+    /// Creates an error with a free-form message.
+    pub fn generic(message: &str) -> Self {
+        Self::Notify(notify::Error::generic(message))
+    }
+
+    /// Creates an error from an I/O error.
+    pub fn io(error: io::Error) -> Self {
+        Self::Notify(notify::Error::io(error))
+    }
+
+    /// Creates an error for a path that does not exist.
+    pub fn path_not_found() -> Self {
+        Self::Notify(notify::Error::path_not_found())
+    }
+
+    /// Creates an error for a path that is not being watched.
+    pub fn watch_not_found() -> Self {
+        Self::Notify(notify::Error::watch_not_found())
+    }
+
+    /// Adds a path to the error.
+    pub fn add_path(self, path: PathBuf) -> Self {
+        match self {
+            Self::Notify(error) => Self::Notify(error.add_path(path)),
+            error => error,
+        }
+    }
+
+    /// Converts the error for handlers of the back-end's events.
+    pub(crate) fn into_notify(self) -> notify::Error {
+        match self {
+            Self::Notify(error) => error,
+            Self::WatchLimit { path, .. } => {
+                notify::Error::new(notify::ErrorKind::MaxFilesWatch).add_path(path)
+            }
+            error @ (Self::HandlerPanic { .. } | Self::Overflow) => {
+                notify::Error::generic(&error.to_string())
+            }
+        }
+    }
+
+    /// Returns `true` if the error means the operating system ran out of file watches.
+    pub(crate) fn is_watch_limit(error: &notify::Error) -> bool {
+        match &error.kind {
+            notify::ErrorKind::MaxFilesWatch => true,
+            // ENOSPC from inotify and EMFILE from kqueue, which needs a descriptor per path.
+            #[cfg(unix)]
+            notify::ErrorKind::Io(error) => matches!(error.raw_os_error(), Some(24 | 28)),
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Notify(error) => error.fmt(f),
+            Self::WatchLimit { path, registered } => write!(
+                f,
+                "OS file watch limit reached while watching {} with {} paths registered; \
+                 raise the limit or enable polling with WatcherBuilder::poll_fallback",
+                path.display(),
+                registered
+            ),
+            Self::HandlerPanic { message } => write!(f, "event handler panicked: {message}"),
+            Self::Overflow => write!(
+                f,
+                "the back-end's event queue overflowed, changes were lost"
+            ),
+        }
+    }
+}
+
+impl StdError for Error {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        match self {
+            Self::Notify(error) => Some(error),
+            Self::WatchLimit { .. } | Self::HandlerPanic { .. } | Self::Overflow => None,
+        }
+    }
+}
+
+impl From<notify::Error> for Error {
+    fn from(error: notify::Error) -> Self {
+        Self::Notify(error)
+    }
+}
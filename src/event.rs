@@ -0,0 +1,1048 @@
+use std::{
+    ffi::{OsStr, OsString},
+    fmt, fs,
+    ops::{BitOr, BitOrAssign},
+    path::{Path, PathBuf},
+    sync::mpsc,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use notify::event::{
+    CreateKind, EventKind as NotifyKind, MetadataKind, ModifyKind, RemoveKind, RenameMode,
+};
+
+use crate::{
+    encoding::EncodingChange, json::Value, ChangeSummary, ChecksumChange, DebounceEventResult,
+    DebouncedEvent, Encoding, Error, EventTags, FileId, SizeDelta, TextDiff, Validation,
+};
+
+/// The result handed to an [`EventHandler`]: a debounced batch of events, or the errors the
+/// backend reported instead.
+pub type EventResult = Result<Vec<Event>, Vec<Error>>;
+
+/// What happened to the path of an [`Event`].
+///
+/// This is a simplified view of the backend's event kinds, which differ between platforms.
+/// Kinds that don't fit any of the simple categories, such as file accesses, are reported as
+/// [`EventKind::Other`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum EventKind {
+    /// The path was created, or moved into a watched directory.
+    Created,
+    /// The path already existed when it was watched, see
+    /// [`WatchOptions::initial_scan`](crate::WatchOptions::initial_scan).
+    Existing,
+    /// The content of the path changed.
+    Modified,
+    /// Only the metadata of the path changed, such as its permissions, owner or timestamps.
+    AttributesChanged,
+    /// The path was removed, or moved out of a watched directory.
+    Removed,
+    /// The path was renamed, and both its old and new name are known, see [`Event::from`].
+    Renamed,
+    /// Something else happened to the path.
+    Other,
+}
+
+impl EventKind {
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Created => "created",
+            Self::Existing => "existing",
+            Self::Modified => "modified",
+            Self::AttributesChanged => "attributes_changed",
+            Self::Removed => "removed",
+            Self::Renamed => "renamed",
+            Self::Other => "other",
+        }
+    }
+
+    fn from_str(kind: &str) -> Result<Self, Error> {
+        [
+            Self::Created,
+            Self::Existing,
+            Self::Modified,
+            Self::AttributesChanged,
+            Self::Removed,
+            Self::Renamed,
+            Self::Other,
+        ]
+        .into_iter()
+        .find(|candidate| candidate.as_str() == kind)
+        .ok_or_else(|| Error::generic(&format!("unknown event kind {kind:?}")))
+    }
+
+    /// Returns the kind of a debounced event from the back-end.
+    pub(crate) fn of(event: &DebouncedEvent) -> Self {
+        match event.paths.last() {
+            Some(path) => Self::from_notify(&event.kind, path),
+            None => Self::Other,
+        }
+    }
+
+    /// Returns the back-end's kind closest to this one, for events whose kind a middleware
+    /// changed.
+    fn to_notify(self) -> NotifyKind {
+        match self {
+            Self::Created | Self::Existing => NotifyKind::Create(CreateKind::Any),
+            Self::Modified => NotifyKind::Modify(ModifyKind::Any),
+            Self::AttributesChanged => NotifyKind::Modify(ModifyKind::Metadata(MetadataKind::Any)),
+            Self::Removed => NotifyKind::Remove(RemoveKind::Any),
+            Self::Renamed => NotifyKind::Modify(ModifyKind::Name(RenameMode::Both)),
+            Self::Other => NotifyKind::Other,
+        }
+    }
+
+    fn from_notify(kind: &NotifyKind, path: &Path) -> Self {
+        match kind {
+            NotifyKind::Create(_) => Self::Created,
+            NotifyKind::Modify(ModifyKind::Name(RenameMode::Both)) => Self::Renamed,
+            NotifyKind::Modify(ModifyKind::Name(RenameMode::To)) => Self::Created,
+            NotifyKind::Modify(ModifyKind::Name(RenameMode::From)) => Self::Removed,
+            // Some back-ends, such as FSEvents, never tell the two sides of a rename apart.
+            NotifyKind::Modify(ModifyKind::Name(_)) if path.exists() => Self::Created,
+            NotifyKind::Modify(ModifyKind::Name(_)) => Self::Removed,
+            NotifyKind::Modify(ModifyKind::Metadata(_)) => Self::AttributesChanged,
+            NotifyKind::Modify(_) => Self::Modified,
+            NotifyKind::Remove(_) => Self::Removed,
+            NotifyKind::Any | NotifyKind::Access(_) | NotifyKind::Other => Self::Other,
+        }
+    }
+}
+
+/// A set of [`EventKind`]s, used to select which events reach the handler.
+///
+/// Masks are combined with `|`:
+///
+/// ```
+/// use watchit::{EventKind, EventKindMask};
+///
+/// let mask = EventKindMask::CREATED | EventKindMask::MODIFIED;
+/// assert!(mask.contains(EventKind::Modified));
+/// assert!(!mask.contains(EventKind::Removed));
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct EventKindMask(u8);
+
+impl fmt::Display for EventKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.as_str().replace('_', " "))
+    }
+}
+
+impl EventKindMask {
+    /// No kinds at all.
+    pub const NONE: Self = Self(0);
+    /// [`EventKind::Created`].
+    pub const CREATED: Self = Self(1 << 0);
+    /// [`EventKind::Modified`].
+    pub const MODIFIED: Self = Self(1 << 1);
+    /// [`EventKind::Removed`].
+    pub const REMOVED: Self = Self(1 << 2);
+    /// [`EventKind::Renamed`].
+    pub const RENAMED: Self = Self(1 << 3);
+    /// [`EventKind::Other`], which includes file accesses.
+    pub const OTHER: Self = Self(1 << 4);
+    /// [`EventKind::AttributesChanged`].
+    pub const ATTRIBUTES_CHANGED: Self = Self(1 << 5);
+    /// [`EventKind::Existing`].
+    pub const EXISTING: Self = Self(1 << 6);
+    /// Every kind.
+    pub const ALL: Self = Self(u8::MAX);
+
+    /// Returns `true` if `kind` is in the set.
+    pub fn contains(self, kind: EventKind) -> bool {
+        self.0 & Self::from(kind).0 != 0
+    }
+
+    pub(crate) fn to_json(self) -> Value {
+        Value::number(self.0)
+    }
+
+    pub(crate) fn from_json(value: &Value) -> Result<Self, Error> {
+        let bits = value.as_u64()?;
+        u8::try_from(bits)
+            .map(Self)
+            .map_err(|_| Error::generic(&format!("invalid event kind mask {bits}")))
+    }
+}
+
+impl From<EventKind> for EventKindMask {
+    fn from(kind: EventKind) -> Self {
+        match kind {
+            EventKind::Created => Self::CREATED,
+            EventKind::Existing => Self::EXISTING,
+            EventKind::Modified => Self::MODIFIED,
+            EventKind::AttributesChanged => Self::ATTRIBUTES_CHANGED,
+            EventKind::Removed => Self::REMOVED,
+            EventKind::Renamed => Self::RENAMED,
+            EventKind::Other => Self::OTHER,
+        }
+    }
+}
+
+impl BitOr for EventKindMask {
+    type Output = Self;
+
+    fn bitor(self, other: Self) -> Self {
+        Self(self.0 | other.0)
+    }
+}
+
+impl BitOrAssign for EventKindMask {
+    fn bitor_assign(&mut self, other: Self) {
+        self.0 |= other.0;
+    }
+}
+
+impl Default for EventKindMask {
+    fn default() -> Self {
+        Self::ALL
+    }
+}
+
+/// The type of a file system entry, see [`Metadata::file_type`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum FileType {
+    /// A regular file.
+    File,
+    /// A directory.
+    Dir,
+    /// A symbolic link. The link itself is described, not its target.
+    Symlink,
+    /// Anything else, such as a socket or a device.
+    Other,
+}
+
+impl FileType {
+    pub(crate) fn to_json(self) -> Value {
+        Value::string(match self {
+            Self::File => "file",
+            Self::Dir => "dir",
+            Self::Symlink => "symlink",
+            Self::Other => "other",
+        })
+    }
+
+    pub(crate) fn from_json(value: &Value) -> Result<Self, Error> {
+        match value.as_str()? {
+            "file" => Ok(Self::File),
+            "dir" => Ok(Self::Dir),
+            "symlink" => Ok(Self::Symlink),
+            "other" => Ok(Self::Other),
+            other => Err(Error::generic(&format!("unknown file type {other:?}"))),
+        }
+    }
+}
+
+/// A snapshot of a path's metadata, taken when its event was delivered.
+///
+/// Snapshots are only taken if the watcher was built with
+/// [`WatcherBuilder::metadata`](crate::WatcherBuilder::metadata).
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct Metadata {
+    /// The type of the entry.
+    pub file_type: FileType,
+    /// The size of the entry in bytes.
+    pub size: u64,
+    /// When the entry was last modified, if the platform records it.
+    pub modified: Option<SystemTime>,
+    /// Whether the entry is read-only.
+    pub readonly: bool,
+    /// The Unix permission bits of the entry, or `None` on other platforms.
+    pub mode: Option<u32>,
+}
+
+impl Metadata {
+    /// Reads the metadata of `path` without following symbolic links.
+    pub(crate) fn read(path: &Path) -> Option<Self> {
+        let metadata = fs::symlink_metadata(path).ok()?;
+        let file_type = metadata.file_type();
+        let file_type = if file_type.is_symlink() {
+            FileType::Symlink
+        } else if file_type.is_dir() {
+            FileType::Dir
+        } else if file_type.is_file() {
+            FileType::File
+        } else {
+            FileType::Other
+        };
+        Some(Self {
+            file_type,
+            size: metadata.len(),
+            modified: metadata.modified().ok(),
+            readonly: metadata.permissions().readonly(),
+            mode: mode(&metadata),
+        })
+    }
+
+    fn to_json(&self) -> Value {
+        Value::object([
+            ("file_type", self.file_type.to_json()),
+            ("size", Value::number(self.size)),
+            ("modified", Value::optional(self.modified, time_to_json)),
+            ("readonly", Value::Bool(self.readonly)),
+            ("mode", Value::optional(self.mode, Value::number)),
+        ])
+    }
+
+    fn from_json(value: &Value) -> Result<Self, Error> {
+        Ok(Self {
+            file_type: FileType::from_json(value.field("file_type")?)?,
+            size: value.field("size")?.as_u64()?,
+            modified: value.field("modified")?.as_optional(time_from_json)?,
+            readonly: value.field("readonly")?.as_bool()?,
+            mode: value.field("mode")?.as_optional(|mode| {
+                let mode = mode.as_u64()?;
+                u32::try_from(mode)
+                    .map_err(|_| Error::generic(&format!("invalid file mode {mode}")))
+            })?,
+        })
+    }
+}
+
+#[cfg(unix)]
+fn mode(metadata: &fs::Metadata) -> Option<u32> {
+    use std::os::unix::fs::PermissionsExt;
+    Some(metadata.permissions().mode())
+}
+
+#[cfg(not(unix))]
+fn mode(_metadata: &fs::Metadata) -> Option<u32> {
+    None
+}
+
+/// Details the watch set attaches to a debounced event before it is dispatched.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Details {
+    pub(crate) root: Option<PathBuf>,
+    pub(crate) raw_count: usize,
+    pub(crate) diff: Option<TextDiff>,
+    pub(crate) size_delta: Option<SizeDelta>,
+    pub(crate) checksum: Option<ChecksumChange>,
+    pub(crate) change: Option<ChangeSummary>,
+    /// The JSON pointers of the keys that changed, if the file is a tracked document.
+    pub(crate) changed_keys: Vec<String>,
+    pub(crate) validation: Option<Validation>,
+    pub(crate) snapshot: Option<PathBuf>,
+    pub(crate) encoding: Option<EncodingChange>,
+    /// The paths that changed below the watched path, if the event summarizes them.
+    pub(crate) changed: Vec<PathBuf>,
+    pub(crate) file_id: Option<FileId>,
+    /// Whether the event was synthesized for a path that existed when it was watched.
+    pub(crate) existing: bool,
+    /// The event as the middlewares passed it on, delivered in place of the debounced one.
+    pub(crate) processed: Option<Box<Event>>,
+}
+
+impl Details {
+    /// Returns the kind of `event`, which these details belong to.
+    pub(crate) fn kind(&self, event: &DebouncedEvent) -> EventKind {
+        if self.existing {
+            EventKind::Existing
+        } else {
+            EventKind::of(event)
+        }
+    }
+}
+
+/// A debounced batch on its way from the watch set to the handlers.
+pub(crate) type Batch = Result<Vec<(DebouncedEvent, Details)>, Vec<Error>>;
+
+/// Removes the details from a batch for a handler of the back-end's events, giving the events
+/// the paths and kinds the middlewares passed them on with.
+pub(crate) fn strip(batch: Batch) -> DebounceEventResult {
+    match batch {
+        Ok(events) => Ok(events
+            .into_iter()
+            .map(|(event, details)| match &details.processed {
+                Some(processed) => reshape(event, &details, processed),
+                None => event,
+            })
+            .collect()),
+        Err(errors) => Err(errors.into_iter().map(Error::into_notify).collect()),
+    }
+}
+
+/// Rewrites a debounced event to match the event a middleware passed on in its place.
+fn reshape(mut event: DebouncedEvent, details: &Details, processed: &Event) -> DebouncedEvent {
+    if details.kind(&event) != processed.kind {
+        event.kind = processed.kind.to_notify();
+    }
+    event.paths = processed
+        .from
+        .iter()
+        .chain([&processed.path])
+        .cloned()
+        .collect();
+    event
+}
+
+/// Which optional details are attached to the events given to an [`EventHandler`].
+#[derive(Debug, Clone, Default)]
+pub(crate) struct EventConfig {
+    pub(crate) metadata: bool,
+    /// The largest file whose content is attached, if any.
+    pub(crate) contents: Option<u64>,
+}
+
+/// A debounced change to a watched path.
+///
+/// Events are delivered to handlers registered with
+/// [`Watcher::with_event_handler`](crate::Watcher::with_event_handler) or
+/// [`WatcherBuilder::build_with_event_handler`](crate::WatcherBuilder::build_with_event_handler).
+/// Unlike [`DebouncedEvent`], the type is owned by this crate and stays the same across
+/// versions of the notification back-end.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct Event {
+    /// The position of the event among all events the watcher delivered, starting at `0`.
+    ///
+    /// Each event handed to the watcher's handler takes the next number, so consumers that
+    /// forward or store events can detect gaps, drop duplicates and restore the order.
+    pub sequence: u64,
+    /// The path that changed. For renames, this is the new path.
+    pub path: PathBuf,
+    /// The old path, if the event is a rename.
+    pub from: Option<PathBuf>,
+    /// What happened to the path.
+    pub kind: EventKind,
+    /// The registered path of the watch that reported the event, as it was passed to the
+    /// [`Watcher`](crate::Watcher). If several watches report the path, this is the most
+    /// specific one.
+    pub root: Option<PathBuf>,
+    /// How many raw events of the back-end were debounced for the event's paths.
+    ///
+    /// A single save usually produces a handful of raw events, while bulk operations such
+    /// as `git checkout` produce many more. Events of the same path in one batch share the
+    /// count.
+    pub raw_events: usize,
+    /// When the back-end reported the first change that was debounced into the event.
+    pub time: SystemTime,
+    /// When the event was handed to the handler, see [`Event::latency`].
+    pub delivered: SystemTime,
+    /// The tags of every watch that reported the event, in sorted order, see
+    /// [`Watcher::watch_tagged`](crate::Watcher::watch_tagged).
+    pub tags: Vec<String>,
+    /// The metadata of the path when the event was delivered, if the watcher takes metadata
+    /// snapshots and the path still exists.
+    pub metadata: Option<Metadata>,
+    /// The lines that changed since the previous version of the file, if the watch computes
+    /// diffs, see [`WatchOptions::diff`](crate::WatchOptions::diff).
+    pub diff: Option<TextDiff>,
+    /// How much the file grew or shrank, if the event is a modification and the watch tracks
+    /// file sizes, see [`WatchOptions::track_size`](crate::WatchOptions::track_size).
+    pub size_delta: Option<SizeDelta>,
+    /// The checksum of the file before and after the change, if the event is a modification
+    /// and the watch tracks checksums, see
+    /// [`WatchOptions::checksum`](crate::WatchOptions::checksum).
+    pub checksum: Option<ChecksumChange>,
+    /// How the size and content of the file changed, if the event is a modification and the
+    /// watch summarizes changes, see
+    /// [`WatchOptions::change_summary`](crate::WatchOptions::change_summary).
+    pub change: Option<ChangeSummary>,
+    /// The JSON pointers of the keys that changed, such as `/server/port`, if the event is a
+    /// modification of a JSON or TOML file and the watch tracks key changes, see
+    /// [`WatchOptions::key_changes`](crate::WatchOptions::key_changes). Empty otherwise.
+    pub changed_keys: Vec<String>,
+    /// Whether the validator of the watch accepted the file, if the event created or changed
+    /// it and the watch has a validator, see
+    /// [`WatchOptions::validate`](crate::WatchOptions::validate).
+    pub validation: Option<Validation>,
+    /// The copy of the version of the file before the modification, if the event is a
+    /// modification and the watch keeps snapshots, see
+    /// [`WatchOptions::snapshots`](crate::WatchOptions::snapshots).
+    pub snapshot: Option<PathBuf>,
+    /// The paths that changed below the watched path, if the event summarizes a batch, see
+    /// [`WatchOptions::summarize`](crate::WatchOptions::summarize). Empty otherwise.
+    pub changed: Vec<PathBuf>,
+    /// The platform's ID of the file, which follows it across renames and is shared by its
+    /// hard links. For removals, this is the ID the file had before it was removed, if it
+    /// is still known.
+    pub file_id: Option<FileId>,
+    /// The content of the file right after the change, if the watcher attaches the content
+    /// of small files, see [`WatcherBuilder::contents`](crate::WatcherBuilder::contents).
+    pub contents: Option<Vec<u8>>,
+    /// The encoding of the file right after the change, told from its first bytes, if the
+    /// watcher attaches the content of small files and the file is one of them.
+    pub encoding: Option<Encoding>,
+    /// The encoding the file had before, if the change flipped it between text encodings
+    /// or between text and binary, so that text-processing handlers can skip the file.
+    pub previous_encoding: Option<Encoding>,
+}
+
+impl Event {
+    /// Returns the old and the new path, if the event is a rename.
+    pub fn rename(&self) -> Option<(&Path, &Path)> {
+        self.from.as_deref().map(|from| (from, self.path.as_path()))
+    }
+
+    /// Returns how long it took from detecting the first change to delivering the event.
+    ///
+    /// This includes the debounce duration, so it is never shorter than that unless the
+    /// system clock was adjusted in between.
+    pub fn latency(&self) -> Duration {
+        self.delivered
+            .duration_since(self.time)
+            .unwrap_or(Duration::ZERO)
+    }
+
+    /// Converts the event to a JSON object, for logging, sending or storing it.
+    ///
+    /// Paths that are not valid Unicode are written as an object holding their bytes in
+    /// hexadecimal. Times are written as seconds and nanoseconds since the Unix epoch.
+    pub fn to_json(&self) -> String {
+        Value::object([
+            ("sequence", Value::number(self.sequence)),
+            ("path", path_to_json(&self.path)),
+            ("from", Value::optional(self.from.as_deref(), path_to_json)),
+            ("kind", Value::string(self.kind.as_str())),
+            ("root", Value::optional(self.root.as_deref(), path_to_json)),
+            ("raw_events", Value::number(self.raw_events)),
+            ("time", time_to_json(self.time)),
+            ("delivered", time_to_json(self.delivered)),
+            (
+                "tags",
+                Value::Array(self.tags.iter().map(Value::string).collect()),
+            ),
+            (
+                "metadata",
+                Value::optional(self.metadata.as_ref(), Metadata::to_json),
+            ),
+            (
+                "diff",
+                Value::optional(self.diff.as_ref(), TextDiff::to_json),
+            ),
+            (
+                "size_delta",
+                Value::optional(self.size_delta, SizeDelta::to_json),
+            ),
+            (
+                "checksum",
+                Value::optional(self.checksum.as_ref(), ChecksumChange::to_json),
+            ),
+            (
+                "change",
+                Value::optional(self.change, ChangeSummary::to_json),
+            ),
+            (
+                "changed_keys",
+                Value::Array(self.changed_keys.iter().map(Value::string).collect()),
+            ),
+            (
+                "validation",
+                Value::optional(self.validation.as_ref(), Validation::to_json),
+            ),
+            (
+                "snapshot",
+                Value::optional(self.snapshot.as_deref(), path_to_json),
+            ),
+            (
+                "changed",
+                Value::Array(self.changed.iter().map(|path| path_to_json(path)).collect()),
+            ),
+            ("file_id", Value::optional(self.file_id, FileId::to_json)),
+            (
+                "contents",
+                Value::optional(self.contents.as_deref(), bytes_to_json),
+            ),
+            (
+                "encoding",
+                Value::optional(self.encoding, Encoding::to_json),
+            ),
+            (
+                "previous_encoding",
+                Value::optional(self.previous_encoding, Encoding::to_json),
+            ),
+        ])
+        .to_string()
+    }
+
+    /// Reads an event written by [`Event::to_json`].
+    ///
+    /// # Arguments
+    /// * `json` - The JSON object describing the event.
+    ///
+    /// # Returns
+    /// A `Result` containing either the event, or an `Error` if `json` is malformed or
+    /// doesn't describe an event.
+    pub fn from_json(json: &str) -> Result<Self, Error> {
+        let value = Value::parse(json)?;
+        Ok(Self {
+            sequence: value.field("sequence")?.as_u64()?,
+            path: path_from_json(value.field("path")?)?,
+            from: value.field("from")?.as_optional(path_from_json)?,
+            kind: EventKind::from_str(value.field("kind")?.as_str()?)?,
+            root: value.field("root")?.as_optional(path_from_json)?,
+            raw_events: {
+                let count = value.field("raw_events")?.as_u64()?;
+                usize::try_from(count)
+                    .map_err(|_| Error::generic(&format!("invalid raw event count {count}")))?
+            },
+            time: time_from_json(value.field("time")?)?,
+            delivered: time_from_json(value.field("delivered")?)?,
+            tags: value
+                .field("tags")?
+                .as_array()?
+                .iter()
+                .map(|tag| tag.as_str().map(str::to_string))
+                .collect::<Result<_, _>>()?,
+            metadata: value.field("metadata")?.as_optional(Metadata::from_json)?,
+            diff: value.field("diff")?.as_optional(TextDiff::from_json)?,
+            size_delta: value
+                .field("size_delta")?
+                .as_optional(SizeDelta::from_json)?,
+            checksum: value
+                .field("checksum")?
+                .as_optional(ChecksumChange::from_json)?,
+            change: value
+                .field("change")?
+                .as_optional(ChangeSummary::from_json)?,
+            changed_keys: value
+                .field("changed_keys")?
+                .as_array()?
+                .iter()
+                .map(|key| key.as_str().map(str::to_string))
+                .collect::<Result<_, _>>()?,
+            validation: value
+                .field("validation")?
+                .as_optional(Validation::from_json)?,
+            snapshot: value.field("snapshot")?.as_optional(path_from_json)?,
+            changed: value
+                .field("changed")?
+                .as_array()?
+                .iter()
+                .map(path_from_json)
+                .collect::<Result<_, _>>()?,
+            file_id: value.field("file_id")?.as_optional(FileId::from_json)?,
+            contents: value.field("contents")?.as_optional(bytes_from_json)?,
+            encoding: value.field("encoding")?.as_optional(Encoding::from_json)?,
+            previous_encoding: value
+                .field("previous_encoding")?
+                .as_optional(Encoding::from_json)?,
+        })
+    }
+
+    /// Converts a debounced event from the back-end that is delivered at `delivered`.
+    pub(crate) fn from_debounced(
+        event: &DebouncedEvent,
+        details: Details,
+        config: &EventConfig,
+        delivered: SystemTime,
+    ) -> Option<Self> {
+        if let Some(processed) = details.processed {
+            return Some(Self {
+                delivered,
+                ..*processed
+            });
+        }
+        let path = event.paths.last()?;
+        let kind = if details.existing {
+            EventKind::Existing
+        } else {
+            EventKind::from_notify(&event.kind, path)
+        };
+        let from = match (kind, event.paths.as_slice()) {
+            (EventKind::Renamed, [from, _, ..]) => Some(from.clone()),
+            _ => None,
+        };
+        Some(Self {
+            sequence: 0,
+            path: path.clone(),
+            from,
+            kind,
+            root: details.root,
+            raw_events: details.raw_count,
+            time: system_time(event.time, delivered),
+            delivered,
+            tags: event.tags().map(str::to_string).collect(),
+            metadata: config.metadata.then(|| Metadata::read(path)).flatten(),
+            diff: details.diff,
+            size_delta: details.size_delta,
+            checksum: details.checksum,
+            change: details.change,
+            changed_keys: details.changed_keys,
+            validation: details.validation,
+            snapshot: details.snapshot,
+            changed: details.changed,
+            file_id: details.file_id,
+            contents: config
+                .contents
+                .filter(|_| kind != EventKind::Removed)
+                .and_then(|max_size| read_contents(path, max_size)),
+            encoding: details.encoding.map(|encoding| encoding.current),
+            previous_encoding: details.encoding.and_then(|encoding| encoding.previous),
+        })
+    }
+}
+
+/// Writes the event in a short, human-readable form, such as
+/// `modified src/main.rs (2 raw events, 120ms)`.
+///
+/// Renames are written with both paths, as in `renamed old.txt -> new.txt (3 raw events, 90ms)`.
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ", self.kind)?;
+        if let Some(from) = &self.from {
+            write!(f, "{} -> ", from.display())?;
+        }
+        write!(f, "{} (", self.path.display())?;
+        if !self.changed.is_empty() {
+            write!(f, "{} changed paths, ", self.changed.len())?;
+        }
+        match self.raw_events {
+            0 => {}
+            1 => f.write_str("1 raw event, ")?,
+            count => write!(f, "{count} raw events, ")?,
+        }
+        write!(f, "{}ms)", self.latency().as_millis())
+    }
+}
+
+/// Converts a debounced batch for an [`EventHandler`], numbering its events from `sequence`.
+pub(crate) fn convert(batch: Batch, config: &EventConfig, sequence: &mut u64) -> EventResult {
+    match batch {
+        Ok(events) => {
+            let delivered = SystemTime::now();
+            Ok(events
+                .into_iter()
+                .filter_map(|(event, details)| {
+                    Event::from_debounced(&event, details, config, delivered)
+                })
+                .map(|mut event| {
+                    event.sequence = *sequence;
+                    *sequence += 1;
+                    event
+                })
+                .collect())
+        }
+        Err(errors) => Err(errors),
+    }
+}
+
+/// Reads the content of the file at `path`, if it is no larger than `max_size` bytes.
+fn read_contents(path: &Path, max_size: u64) -> Option<Vec<u8>> {
+    let metadata = fs::metadata(path).ok()?;
+    if !metadata.is_file() || metadata.len() > max_size {
+        return None;
+    }
+    // The file may have grown since its size was read.
+    fs::read(path)
+        .ok()
+        .filter(|contents| contents.len() as u64 <= max_size)
+}
+
+/// Writes a path as a string, or as an object with its bytes if it is not valid Unicode, so
+/// that every path is read back unchanged.
+pub(crate) fn path_to_json(path: &Path) -> Value {
+    match path.to_str() {
+        Some(path) => Value::string(path),
+        None => Value::object([("bytes", bytes_to_json(&os_str_bytes(path.as_os_str())))]),
+    }
+}
+
+pub(crate) fn path_from_json(value: &Value) -> Result<PathBuf, Error> {
+    match value {
+        Value::String(path) => Ok(PathBuf::from(path)),
+        _ => os_string_from_bytes(bytes_from_json(value.field("bytes")?)?).map(PathBuf::from),
+    }
+}
+
+#[cfg(unix)]
+fn os_str_bytes(string: &OsStr) -> Vec<u8> {
+    use std::os::unix::ffi::OsStrExt as _;
+
+    string.as_bytes().to_vec()
+}
+
+#[cfg(unix)]
+fn os_string_from_bytes(bytes: Vec<u8>) -> Result<OsString, Error> {
+    use std::os::unix::ffi::OsStringExt as _;
+
+    Ok(OsString::from_vec(bytes))
+}
+
+/// Windows strings are sequences of 16-bit units, written in little-endian order.
+#[cfg(windows)]
+fn os_str_bytes(string: &OsStr) -> Vec<u8> {
+    use std::os::windows::ffi::OsStrExt as _;
+
+    string.encode_wide().flat_map(u16::to_le_bytes).collect()
+}
+
+#[cfg(windows)]
+fn os_string_from_bytes(bytes: Vec<u8>) -> Result<OsString, Error> {
+    use std::os::windows::ffi::OsStringExt as _;
+
+    if bytes.len() % 2 != 0 {
+        return Err(Error::generic("invalid JSON: malformed path bytes"));
+    }
+    let wide: Vec<u16> = bytes
+        .chunks_exact(2)
+        .map(|unit| u16::from_le_bytes([unit[0], unit[1]]))
+        .collect();
+    Ok(OsString::from_wide(&wide))
+}
+
+/// Other platforms only have Unicode paths.
+#[cfg(not(any(unix, windows)))]
+fn os_str_bytes(string: &OsStr) -> Vec<u8> {
+    string.to_string_lossy().into_owned().into_bytes()
+}
+
+#[cfg(not(any(unix, windows)))]
+fn os_string_from_bytes(bytes: Vec<u8>) -> Result<OsString, Error> {
+    String::from_utf8(bytes)
+        .map(OsString::from)
+        .map_err(|_| Error::generic("invalid JSON: malformed path bytes"))
+}
+
+/// Writes bytes as a string of hexadecimal digits.
+fn bytes_to_json(bytes: &[u8]) -> Value {
+    Value::String(bytes.iter().map(|byte| format!("{byte:02x}")).collect())
+}
+
+fn bytes_from_json(value: &Value) -> Result<Vec<u8>, Error> {
+    let hex = value.as_str()?;
+    if hex.len() % 2 != 0 || !hex.is_ascii() {
+        return Err(Error::generic("invalid JSON: malformed hexadecimal bytes"));
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|index| {
+            u8::from_str_radix(&hex[index..index + 2], 16)
+                .map_err(|_| Error::generic("invalid JSON: malformed hexadecimal bytes"))
+        })
+        .collect()
+}
+
+pub(crate) fn time_to_json(time: SystemTime) -> Value {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or(Duration::ZERO);
+    Value::object([
+        ("secs", Value::number(since_epoch.as_secs())),
+        ("nanos", Value::number(since_epoch.subsec_nanos())),
+    ])
+}
+
+pub(crate) fn time_from_json(value: &Value) -> Result<SystemTime, Error> {
+    let secs = value.field("secs")?.as_u64()?;
+    let nanos = value.field("nanos")?.as_u64()?;
+    if nanos >= 1_000_000_000 {
+        return Err(Error::generic(&format!("invalid nanoseconds {nanos}")));
+    }
+    UNIX_EPOCH
+        .checked_add(Duration::from_secs(secs))
+        .and_then(|time| time.checked_add(Duration::from_nanos(nanos)))
+        .ok_or_else(|| Error::generic(&format!("time of {secs}s since the epoch out of range")))
+}
+
+/// Maps an instant onto the wall clock, as it was that long before `now`.
+fn system_time(instant: Instant, now: SystemTime) -> SystemTime {
+    now.checked_sub(instant.elapsed()).unwrap_or(now)
+}
+
+/// A handler for the [`Event`]s of a [`Watcher`](crate::Watcher).
+///
+/// The trait is implemented for closures taking an [`EventResult`] and for the sending half
+/// of a channel, so that events can also be received on another thread.
+pub trait EventHandler: Send + 'static {
+    /// Handles a debounced batch of events, or the errors reported instead.
+    fn handle_event(&mut self, result: EventResult);
+}
+
+impl<F> EventHandler for F
+where
+    F: FnMut(EventResult) + Send + 'static,
+{
+    fn handle_event(&mut self, result: EventResult) {
+        (self)(result);
+    }
+}
+
+impl EventHandler for mpsc::Sender<EventResult> {
+    fn handle_event(&mut self, result: EventResult) {
+        let _ = self.send(result);
+    }
+}
+
+/// Sends batches on a channel of `crossbeam-channel`, so that they can be received with
+/// `select!` together with other sources.
+///
+/// A bounded channel that is full blocks the debouncer thread until the batch fits, which
+/// applies back-pressure to the watcher instead of dropping events.
+#[cfg(feature = "crossbeam-channel")]
+impl EventHandler for crossbeam_channel::Sender<EventResult> {
+    fn handle_event(&mut self, result: EventResult) {
+        let _ = self.send(result);
+    }
+}
+
+/// Sends each event on its own on a channel of `crossbeam-channel`, like the standard
+/// library's sender of [`Event`]s.
+#[cfg(feature = "crossbeam-channel")]
+impl EventHandler for crossbeam_channel::Sender<Event> {
+    fn handle_event(&mut self, result: EventResult) {
+        match result {
+            Ok(events) => {
+                for event in events {
+                    if self.send(event).is_err() {
+                        return;
+                    }
+                }
+            }
+            Err(errors) => {
+                for error in errors {
+                    tracing::warn!("Dropped watcher error without a receiver: {}", error);
+                }
+            }
+        }
+    }
+}
+
+/// Sends each event on its own, for [`Watcher::channel`](crate::Watcher::channel).
+///
+/// Errors have no place in the channel, so they are logged unless they are received with
+/// [`Watcher::errors`](crate::Watcher::errors).
+impl EventHandler for mpsc::Sender<Event> {
+    fn handle_event(&mut self, result: EventResult) {
+        match result {
+            Ok(events) => {
+                for event in events {
+                    if self.send(event).is_err() {
+                        return;
+                    }
+                }
+            }
+            Err(errors) => {
+                for error in errors {
+                    tracing::warn!("Dropped watcher error without a receiver: {}", error);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+/// This module contains tests for the crate's event type.
+///
+/// The `json_round_trip` test verifies that an event read back from its JSON form equals the
+/// original.
+///
+/// The `display_is_concise` test verifies the human-readable form of events.
+///
+/// The `json_rejects_out_of_range_values` test verifies that times and file modes that do
+/// not fit their types are reported as errors rather than panicking or being truncated.
+///
+/// The `json_keeps_non_unicode_paths` test verifies that paths that are not valid Unicode
+/// survive a round trip unchanged.
+mod tests {
+    use notify_debouncer_full::file_id::FileId as BackendId;
+
+    use super::*;
+    use crate::{ChecksumAlgorithm, Digest};
+
+    fn sample() -> Event {
+        let time = UNIX_EPOCH + Duration::new(1_700_000_000, 123_456_789);
+        Event {
+            sequence: 7,
+            path: PathBuf::from("/srv/\"new\".txt"),
+            from: Some(PathBuf::from("/srv/old.txt")),
+            kind: EventKind::Renamed,
+            root: Some(PathBuf::from("/srv")),
+            raw_events: 3,
+            time,
+            delivered: time + Duration::from_millis(250),
+            tags: vec!["config".to_string()],
+            metadata: Some(Metadata {
+                file_type: FileType::File,
+                size: 42,
+                modified: Some(time),
+                readonly: false,
+                mode: Some(0o644),
+            }),
+            diff: Some(TextDiff::between("old\n", "new\n")),
+            size_delta: Some(SizeDelta::Shrank(4)),
+            checksum: Some(ChecksumChange {
+                old: Some(Digest::of(ChecksumAlgorithm::Sha256, b"old\n")),
+                new: Digest::of(ChecksumAlgorithm::Sha256, b"new\n\xff"),
+            }),
+            change: Some(ChangeSummary {
+                previous_size: 4,
+                size: 5,
+                bytes_added: 1,
+                bytes_removed: 0,
+                pure_append: false,
+            }),
+            changed_keys: vec!["/server/port".to_string()],
+            validation: Some(Validation::Invalid("unexpected \"}\"".to_string())),
+            snapshot: Some(PathBuf::from("/srv/.snapshots/app.json.~3~")),
+            changed: Vec::new(),
+            file_id: Some(FileId::from(BackendId::new_inode(2049, 131_072))),
+            contents: Some(b"new\n\xff".to_vec()),
+            encoding: Some(Encoding::Latin1),
+            previous_encoding: Some(Encoding::Utf8),
+        }
+    }
+
+    #[test]
+    fn json_round_trip() {
+        let event = sample();
+        assert_eq!(Event::from_json(&event.to_json()).unwrap(), event);
+        assert!(Event::from_json(r#"{"path":"/srv"}"#).is_err());
+    }
+
+    #[test]
+    fn display_is_concise() {
+        let event = sample();
+        assert_eq!(
+            event.to_string(),
+            "renamed /srv/old.txt -> /srv/\"new\".txt (3 raw events, 250ms)"
+        );
+
+        let event = Event {
+            from: None,
+            kind: EventKind::AttributesChanged,
+            raw_events: 1,
+            ..sample()
+        };
+        assert_eq!(
+            event.to_string(),
+            "attributes changed /srv/\"new\".txt (1 raw event, 250ms)"
+        );
+    }
+
+    #[test]
+    fn json_rejects_out_of_range_values() {
+        let json = sample().to_json();
+        assert!(json.contains(r#""secs":1700000000"#) && json.contains(r#""mode":420"#));
+
+        let far = json.replacen(r#""secs":1700000000"#, r#""secs":18446744073709551615"#, 1);
+        assert!(Event::from_json(&far).is_err());
+        let nanos = json.replacen(r#""nanos":123456789"#, r#""nanos":1000000000"#, 1);
+        assert!(Event::from_json(&nanos).is_err());
+        let mode = json.replace(r#""mode":420"#, r#""mode":4294967716"#);
+        assert!(Event::from_json(&mode).is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn json_keeps_non_unicode_paths() {
+        use std::os::unix::ffi::OsStrExt as _;
+
+        let path = PathBuf::from(OsStr::from_bytes(b"/srv/caf\xe9.txt"));
+        let event = Event {
+            path: path.clone(),
+            changed: vec![path],
+            ..sample()
+        };
+        let json = event.to_json();
+        assert!(
+            json.contains(r#""path":{"bytes":"2f7372762f636166e92e747874"}"#),
+            "{json}"
+        );
+        assert_eq!(Event::from_json(&json).unwrap(), event);
+    }
+}
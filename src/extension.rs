@@ -0,0 +1,57 @@
+use std::path::Path;
+
+use notify::RecursiveMode;
+
+use crate::{state::State, Error, WatchInfo};
+
+impl State {
+    /// Watches everything below `dirname`, reporting only files with one of `extensions`.
+    ///
+    /// The directory is watched as a helper directory, so events for other files are dropped
+    /// unless another watch covers them.
+    pub(crate) fn add_extension_watch(
+        &mut self,
+        dirname: &Path,
+        extensions: Vec<String>,
+    ) -> Result<(), Error> {
+        if !dirname.is_dir() {
+            return Err(Error::path_not_found().add_path(dirname.to_path_buf()));
+        }
+        if extensions.is_empty() {
+            return self.add_watch(dirname, RecursiveMode::Recursive);
+        }
+
+        self.acquire_helper(dirname, RecursiveMode::Recursive)?;
+        if let Some(previous) = self.watches.get(dirname) {
+            if !previous.extensions.is_empty() {
+                self.release_helper(dirname, RecursiveMode::Recursive);
+            }
+        }
+
+        tracing::debug!(
+            "Watching {} for changes to {:?} files",
+            dirname.display(),
+            extensions
+        );
+
+        let mut info = WatchInfo::new(dirname.to_path_buf(), RecursiveMode::Recursive);
+        info.extensions = extensions;
+        self.watches.insert(dirname.to_path_buf(), info);
+
+        Ok(())
+    }
+
+    /// Removes a watch registered with [`State::add_extension_watch`].
+    ///
+    /// Returns `false` if `dirname` is not an extension-filtered watch.
+    pub(crate) fn remove_extension_watch(&mut self, dirname: &Path) -> bool {
+        match self.watches.get(dirname) {
+            Some(watch) if !watch.extensions.is_empty() => {}
+            _ => return false,
+        }
+
+        self.watches.remove(dirname);
+        self.release_helper(dirname, RecursiveMode::Recursive);
+        true
+    }
+}
@@ -0,0 +1,302 @@
+use std::{
+    collections::BTreeMap,
+    ffi::CString,
+    fs, io,
+    os::{
+        fd::{AsRawFd as _, FromRawFd as _, OwnedFd, RawFd},
+        unix::{ffi::OsStrExt as _, fs::MetadataExt as _},
+    },
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex, MutexGuard,
+    },
+    thread,
+};
+
+use notify::{
+    event::{AccessKind, AccessMode, DataChange, Flag, ModifyKind},
+    EventKind,
+};
+
+use crate::{Error, EventSink, WatchBackend};
+
+/// How long the reading thread waits for events before checking whether it should stop.
+const POLL_TIMEOUT_MS: libc::c_int = 200;
+
+/// A back-end watching whole mount points through fanotify, see
+/// [`Backend::Fanotify`](crate::Backend::Fanotify).
+pub(crate) struct FanotifyWatcher {
+    group: SharedGroup,
+    mask: u64,
+    roots: SharedRoots,
+    stop: Arc<AtomicBool>,
+}
+
+type SharedRoots = Arc<Mutex<BTreeMap<PathBuf, Root>>>;
+
+/// The descriptor of the fanotify group, which the reader closes when it stops, so that the
+/// kernel lets the processes waiting on unanswered permission events go ahead.
+type SharedGroup = Arc<Mutex<Option<OwnedFd>>>;
+
+struct Root {
+    recursive: bool,
+    /// The device of the root, whose mount carries the mark.
+    device: u64,
+}
+
+impl FanotifyWatcher {
+    /// Creates a fanotify group, answering permission events if `permission_events` is set.
+    pub(crate) fn new(sink: EventSink, permission_events: bool) -> Result<Self, Error> {
+        let class = if permission_events {
+            libc::FAN_CLASS_CONTENT
+        } else {
+            libc::FAN_CLASS_NOTIF
+        };
+        let event_flags = (libc::O_RDONLY | libc::O_CLOEXEC | libc::O_LARGEFILE) as libc::c_uint;
+        // SAFETY: `fanotify_init` takes no pointers, and a valid descriptor is owned below.
+        let fd = unsafe { libc::fanotify_init(libc::FAN_CLOEXEC | class, event_flags) };
+        if fd < 0 {
+            return Err(Error::io(io::Error::last_os_error()));
+        }
+        // SAFETY: `fd` was just returned by `fanotify_init` and is owned by nobody else.
+        let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+
+        let mut mask = libc::FAN_MODIFY | libc::FAN_CLOSE_WRITE;
+        if permission_events {
+            mask |= libc::FAN_OPEN_PERM;
+        }
+
+        let roots = SharedRoots::default();
+        let stop = Arc::new(AtomicBool::new(false));
+        let reader = Reader {
+            fd: fd.as_raw_fd(),
+            group: Arc::new(Mutex::new(Some(fd))),
+            roots: Arc::clone(&roots),
+            stop: Arc::clone(&stop),
+            sink,
+        };
+        let group = Arc::clone(&reader.group);
+        thread::Builder::new()
+            .name("watchit-fanotify".to_string())
+            .spawn(move || reader.run())
+            .map_err(Error::io)?;
+
+        Ok(Self {
+            group,
+            mask,
+            roots,
+            stop,
+        })
+    }
+
+    fn mark(&self, flags: libc::c_uint, path: &Path) -> Result<(), Error> {
+        let c_path = CString::new(path.as_os_str().as_bytes())
+            .map_err(|error| Error::io(io::Error::new(io::ErrorKind::InvalidInput, error)))?;
+        let group = lock(&self.group);
+        let Some(fd) = group.as_ref() else {
+            return Err(Error::generic("the fanotify reader stopped").add_path(path.to_path_buf()));
+        };
+        // SAFETY: `c_path` is a valid C string that outlives the call, and `fd` stays open
+        // while `group` is locked.
+        let marked = unsafe {
+            libc::fanotify_mark(
+                fd.as_raw_fd(),
+                flags | libc::FAN_MARK_MOUNT,
+                self.mask,
+                libc::AT_FDCWD,
+                c_path.as_ptr(),
+            )
+        };
+        if marked < 0 {
+            return Err(Error::io(io::Error::last_os_error()).add_path(path.to_path_buf()));
+        }
+        Ok(())
+    }
+}
+
+impl WatchBackend for FanotifyWatcher {
+    fn watch(&mut self, path: &Path, recursive: bool) -> Result<(), Error> {
+        let device = fs::metadata(path)
+            .map_err(|error| Error::io(error).add_path(path.to_path_buf()))?
+            .dev();
+        self.mark(libc::FAN_MARK_ADD, path)?;
+        lock(&self.roots).insert(path.to_path_buf(), Root { recursive, device });
+        Ok(())
+    }
+
+    fn unwatch(&mut self, path: &Path) -> Result<(), Error> {
+        let mut roots = lock(&self.roots);
+        let Some(root) = roots.remove(path) else {
+            return Err(Error::watch_not_found().add_path(path.to_path_buf()));
+        };
+        // The mark covers the whole mount, which other roots may still need.
+        if roots.values().any(|other| other.device == root.device) {
+            return Ok(());
+        }
+        drop(roots);
+        self.mark(libc::FAN_MARK_REMOVE, path)
+    }
+}
+
+impl Drop for FanotifyWatcher {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+    }
+}
+
+/// Reads the events of a fanotify group on its own thread.
+struct Reader {
+    /// The descriptor of `group`, which only the reader closes.
+    fd: RawFd,
+    group: SharedGroup,
+    roots: SharedRoots,
+    stop: Arc<AtomicBool>,
+    sink: EventSink,
+}
+
+impl Reader {
+    fn run(self) {
+        // Aligned for `fanotify_event_metadata`.
+        let mut buffer = vec![0u64; 1024];
+        while !self.stop.load(Ordering::Relaxed) {
+            let mut poll = libc::pollfd {
+                fd: self.fd,
+                events: libc::POLLIN,
+                revents: 0,
+            };
+            // SAFETY: `poll` is a single valid `pollfd`.
+            if unsafe { libc::poll(&mut poll, 1, POLL_TIMEOUT_MS) } <= 0 {
+                continue;
+            }
+            let size = std::mem::size_of_val(buffer.as_slice());
+            // SAFETY: `buffer` is valid for writes of `size` bytes.
+            let read = unsafe { libc::read(self.fd, buffer.as_mut_ptr().cast(), size) };
+            let Ok(read) = usize::try_from(read) else {
+                let error = io::Error::last_os_error();
+                if error.kind() == io::ErrorKind::Interrupted {
+                    continue;
+                }
+                self.sink.error(Error::io(error));
+                break;
+            };
+            self.handle(&buffer, read);
+        }
+    }
+
+    /// Handles the `read` bytes of events at the start of `buffer`.
+    ///
+    /// Every permission event is answered, even those of an unsupported version, as the
+    /// opening process waits for the answer.
+    fn handle(&self, buffer: &[u64], read: usize) {
+        let header = std::mem::size_of::<libc::fanotify_event_metadata>();
+        let bytes = buffer.as_ptr().cast::<u8>();
+        let mut offset = 0;
+        while offset + header <= read {
+            // SAFETY: the kernel wrote a whole event at `offset`, which keeps the alignment
+            // of `buffer` as event lengths are multiples of 8.
+            let metadata = unsafe { &*bytes.add(offset).cast::<libc::fanotify_event_metadata>() };
+            if (metadata.event_len as usize) < header {
+                // The following events cannot be found, closing the group answers them.
+                tracing::warn!("Malformed fanotify event of {} bytes", metadata.event_len);
+                return;
+            }
+            offset += metadata.event_len as usize;
+            if metadata.vers != libc::FANOTIFY_METADATA_VERSION {
+                tracing::debug!("Unsupported fanotify event version {}", metadata.vers);
+                self.release(metadata);
+                continue;
+            }
+
+            if metadata.mask & libc::FAN_Q_OVERFLOW != 0 {
+                self.sink
+                    .send(notify::Event::new(EventKind::Other).set_flag(Flag::Rescan));
+                continue;
+            }
+            if metadata.fd < 0 {
+                continue;
+            }
+            let path = fs::read_link(format!("/proc/self/fd/{}", metadata.fd));
+            self.release(metadata);
+
+            let Ok(path) = path else {
+                continue;
+            };
+            if self.is_watched(&path) {
+                for kind in kinds(metadata.mask) {
+                    self.sink
+                        .send(notify::Event::new(kind).add_path(path.clone()));
+                }
+            }
+        }
+    }
+
+    /// Answers the permission event of `metadata`, if it is one, and closes its file.
+    fn release(&self, metadata: &libc::fanotify_event_metadata) {
+        if metadata.fd < 0 {
+            return;
+        }
+        // SAFETY: the kernel opened `fd` for this event, and nobody else owns it.
+        let file = unsafe { OwnedFd::from_raw_fd(metadata.fd) };
+        if metadata.mask & libc::FAN_OPEN_PERM != 0 {
+            allow(self.fd, file.as_raw_fd());
+        }
+    }
+
+    fn is_watched(&self, path: &Path) -> bool {
+        lock(&self.roots).iter().any(|(root, watch)| {
+            path == root
+                || if watch.recursive {
+                    path.starts_with(root)
+                } else {
+                    path.parent() == Some(root)
+                }
+        })
+    }
+}
+
+impl Drop for Reader {
+    /// Closes the group when the reader stops, even on a panic, as the kernel then lets the
+    /// processes waiting on permission events that were not read yet go ahead.
+    fn drop(&mut self) {
+        drop(lock(&self.group).take());
+    }
+}
+
+/// Returns the kinds of the events that `mask` stands for.
+fn kinds(mask: u64) -> Vec<EventKind> {
+    let mut kinds = Vec::new();
+    if mask & libc::FAN_OPEN_PERM != 0 {
+        kinds.push(EventKind::Access(AccessKind::Open(AccessMode::Any)));
+    }
+    if mask & libc::FAN_MODIFY != 0 {
+        kinds.push(EventKind::Modify(ModifyKind::Data(DataChange::Any)));
+    }
+    if mask & libc::FAN_CLOSE_WRITE != 0 {
+        kinds.push(EventKind::Access(AccessKind::Close(AccessMode::Write)));
+    }
+    kinds
+}
+
+/// Lets the process waiting on the permission event of `file` go ahead.
+fn allow(group: RawFd, file: RawFd) {
+    let response = libc::fanotify_response {
+        fd: file,
+        response: libc::FAN_ALLOW,
+    };
+    let size = std::mem::size_of_val(&response);
+    // SAFETY: `response` is valid for reads of `size` bytes.
+    let written = unsafe { libc::write(group, std::ptr::addr_of!(response).cast(), size) };
+    if written < 0 {
+        tracing::warn!(
+            "Failed to answer a fanotify permission event: {}",
+            io::Error::last_os_error()
+        );
+    }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
@@ -0,0 +1,82 @@
+use std::fmt;
+
+use notify_debouncer_full::file_id::{self, FileId as BackendId};
+
+use crate::{json::Value, state::State, DebouncedEvent, Error, EventKind};
+
+/// The platform's identifier of a file, which stays the same when the file is renamed.
+///
+/// On Unix, this is the device and inode number of the file; on Windows, the volume serial
+/// number and file index. Two paths with the same ID are hard links to the same file, and
+/// an ID reported before a rename identifies the file under its new name as well. IDs may be
+/// reused once a file is removed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct FileId {
+    device: u64,
+    file: u128,
+}
+
+impl FileId {
+    /// Returns the device or volume the file is stored on.
+    pub fn device(&self) -> u64 {
+        self.device
+    }
+
+    /// Returns the number of the file on its device.
+    pub fn file(&self) -> u128 {
+        self.file
+    }
+
+    pub(crate) fn to_json(self) -> Value {
+        Value::object([
+            ("device", Value::number(self.device)),
+            ("file", Value::number(self.file)),
+        ])
+    }
+
+    pub(crate) fn from_json(value: &Value) -> Result<Self, Error> {
+        Ok(Self {
+            device: value.field("device")?.as_u64()?,
+            file: value.field("file")?.as_u128()?,
+        })
+    }
+}
+
+impl From<BackendId> for FileId {
+    fn from(id: BackendId) -> Self {
+        let (device, file) = match id {
+            BackendId::Inode {
+                device_id,
+                inode_number,
+            } => (device_id, u128::from(inode_number)),
+            BackendId::LowRes {
+                volume_serial_number,
+                file_index,
+            } => (u64::from(volume_serial_number), u128::from(file_index)),
+            BackendId::HighRes {
+                volume_serial_number,
+                file_id,
+            } => (volume_serial_number, file_id),
+        };
+        Self { device, file }
+    }
+}
+
+impl fmt::Display for FileId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.device, self.file)
+    }
+}
+
+impl State {
+    /// Returns the ID of the file an event reports.
+    ///
+    /// Removed files are looked up among the IDs the debouncers remember for them.
+    pub(crate) fn file_id_of(&mut self, event: &DebouncedEvent) -> Option<FileId> {
+        let path = event.paths.last()?;
+        if EventKind::of(event) == EventKind::Removed {
+            return self.removed_id(path).map(FileId::from);
+        }
+        file_id::get_file_id(path).ok().map(FileId::from)
+    }
+}
@@ -0,0 +1,501 @@
+use std::{
+    fmt,
+    path::{Component, Path},
+    sync::Arc,
+};
+
+use crate::{
+    event::Details, gitignore::IgnoreFiles, json::Value, state::State, DebouncedEvent, Error,
+    EventKindMask, Glob,
+};
+
+/// A predicate deciding whether a path is delivered, see [`FilterSet::matching`].
+type PathPredicate = Arc<dyn Fn(&Path) -> bool + Send + Sync + 'static>;
+
+/// The temporary and backup files that editors and file managers leave next to the files
+/// they touch, see [`FilterSet::editor_noise`].
+const EDITOR_NOISE: &[&str] = &[
+    // Vim swap files, and the file Vim creates to probe whether a directory is writable.
+    "**/*.sw[a-p]",
+    "**/*.swx",
+    "**/4913",
+    // Backups of Vim, Emacs, nano and many others.
+    "**/*~",
+    // Emacs lock and auto-save files.
+    "**/.#*",
+    "**/#*#",
+    // JetBrains IDEs' safe writes.
+    "**/*___jb_tmp___",
+    "**/*___jb_old___",
+    // LibreOffice lock files.
+    "**/.~lock.*#",
+    // Generic temporary files.
+    "**/*.tmp",
+    // Folder metadata of macOS Finder and Windows Explorer.
+    "**/.DS_Store",
+    "**/Thumbs.db",
+    "**/desktop.ini",
+];
+
+/// A set of glob patterns deciding which paths are delivered, see
+/// [`WatcherBuilder::filter_set`](crate::WatcherBuilder::filter_set) and
+/// [`WatchOptions::filter_set`](crate::WatchOptions::filter_set).
+///
+/// A path is delivered if it matches one of the included patterns, or if there are none,
+/// and matches none of the excluded patterns. Patterns are matched against the full path
+/// of an event and against its path relative to the watched directory it was reported
+/// for, so `target/**` excludes the `target` directory of a watched project wherever the
+/// project lives:
+///
+/// ```
+/// use watchit::FilterSet;
+///
+/// let filters = FilterSet::new()
+///     .include("**/*.rs")?
+///     .exclude("target/**")?;
+/// assert!(filters.allows("src/main.rs"));
+/// assert!(!filters.allows("target/debug/build.rs"));
+/// assert!(!filters.allows("README.md"));
+/// # Ok::<(), watchit::Error>(())
+/// ```
+#[derive(Clone, Default)]
+pub struct FilterSet {
+    include: Vec<Glob>,
+    exclude: Vec<Glob>,
+    predicates: Vec<PathPredicate>,
+    gitignore: Option<IgnoreFiles>,
+    watchitignore: Option<IgnoreFiles>,
+    ignore_hidden: bool,
+    min_size: Option<u64>,
+    max_size: Option<u64>,
+}
+
+impl FilterSet {
+    /// Creates a filter set that delivers every path.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates a filter set that drops the temporary and backup files of common editors.
+    ///
+    /// Saving a file in an editor often creates, renames and removes several helper files,
+    /// which would otherwise each trigger the handler. The set drops Vim swap files and the
+    /// `4913` probe file, `~` backups, Emacs lock files, JetBrains `___jb_tmp___` files,
+    /// LibreOffice lock files, `.tmp` files, and the `.DS_Store`, `Thumbs.db` and
+    /// `desktop.ini` files of file managers. It can be extended like any other set:
+    ///
+    /// ```
+    /// use watchit::FilterSet;
+    ///
+    /// let filters = FilterSet::editor_noise().exclude("target/**")?;
+    /// assert!(filters.allows("src/main.rs"));
+    /// assert!(!filters.allows("src/.main.rs.swp"));
+    /// assert!(!filters.allows("src/main.rs~"));
+    /// assert!(!filters.allows("src/4913"));
+    /// assert!(!filters.allows("src/main.rs___jb_tmp___"));
+    /// # Ok::<(), watchit::Error>(())
+    /// ```
+    pub fn editor_noise() -> Self {
+        let exclude = EDITOR_NOISE
+            .iter()
+            .map(|pattern| Glob::new(pattern).expect("editor noise patterns are valid"))
+            .collect();
+        Self {
+            exclude,
+            ..Self::default()
+        }
+    }
+
+    /// Delivers only paths matching `pattern`, or one of the other included patterns.
+    ///
+    /// # Returns
+    /// A `Result` containing either the extended filter set, or an `Error` if the pattern
+    /// is malformed, see [`Glob::new`].
+    pub fn include(mut self, pattern: &str) -> Result<Self, Error> {
+        self.include.push(Glob::new(pattern)?);
+        Ok(self)
+    }
+
+    /// Drops paths matching `pattern`, even if they match an included pattern.
+    ///
+    /// # Returns
+    /// A `Result` containing either the extended filter set, or an `Error` if the pattern
+    /// is malformed, see [`Glob::new`].
+    pub fn exclude(mut self, pattern: &str) -> Result<Self, Error> {
+        self.exclude.push(Glob::new(pattern)?);
+        Ok(self)
+    }
+
+    /// Delivers only paths for which `predicate` returns `true`, and which the rest of the
+    /// set delivers as well.
+    ///
+    /// This covers rules that don't map to globs, such as regular expressions:
+    ///
+    /// ```ignore
+    /// let pattern = regex::Regex::new(r"/\d{4}-\d{2}-\d{2}\.log$").unwrap();
+    /// let filters = FilterSet::new()
+    ///     .matching(move |path| pattern.is_match(&path.to_string_lossy()));
+    /// ```
+    ///
+    /// # Arguments
+    /// * `predicate` - The predicate to apply to the full path of each event.
+    pub fn matching(mut self, predicate: impl Fn(&Path) -> bool + Send + Sync + 'static) -> Self {
+        self.predicates.push(Arc::new(predicate));
+        self
+    }
+
+    /// Sets whether paths ignored by `.gitignore` and `.ignore` files are dropped.
+    ///
+    /// The ignore files of every directory between a path and the top of its repository
+    /// apply, so watching a subdirectory of a project honors the project's `.gitignore`.
+    /// Outside of repositories, the ignore files between a path and the watched directory
+    /// apply. Later and deeper rules override earlier ones, `!` re-includes paths, and the
+    /// paths below an ignored directory stay ignored, as with `git` itself. Ignore files are
+    /// read again once they change. Global excludes and `.git/info/exclude` are not read.
+    ///
+    /// # Arguments
+    /// * `enabled` - `true` to honor ignore files. Defaults to `false`.
+    pub fn gitignore(mut self, enabled: bool) -> Self {
+        self.gitignore = enabled.then(IgnoreFiles::gitignore);
+        self
+    }
+
+    /// Sets whether paths ignored by a `.watchitignore` file at the root of their watch are
+    /// dropped.
+    ///
+    /// The file uses the syntax of `.gitignore`, see [`FilterSet::gitignore`], and only the
+    /// file in the watched directory itself applies. It is read again once it changes, so
+    /// a deployment can tune which paths trigger the handler without a rebuild or restart.
+    /// A watch without the file drops nothing.
+    ///
+    /// # Arguments
+    /// * `enabled` - `true` to honor `.watchitignore` files. Defaults to `false`.
+    pub fn watchitignore(mut self, enabled: bool) -> Self {
+        self.watchitignore = enabled.then(IgnoreFiles::watchitignore);
+        self
+    }
+
+    /// Sets whether hidden files and directories, and everything below them, are dropped.
+    ///
+    /// A path is hidden if its name, or the name of a directory between it and the watched
+    /// directory, starts with a `.`. On Windows, paths with the hidden attribute are hidden
+    /// as well; removed paths can only be recognized by their name there. The watched
+    /// directory itself may be hidden.
+    ///
+    /// # Arguments
+    /// * `ignore` - `true` to drop hidden paths. Defaults to `false`.
+    pub fn ignore_hidden(mut self, ignore: bool) -> Self {
+        self.ignore_hidden = ignore;
+        self
+    }
+
+    /// Drops the events of files larger than `max_size` bytes.
+    ///
+    /// This keeps handlers that read the files they are told about from being triggered by
+    /// huge artifacts, such as disk images or archives being written into a watched
+    /// directory. The size is read when the event is filtered. Directories, and paths that
+    /// no longer exist, are never dropped by size.
+    ///
+    /// # Arguments
+    /// * `max_size` - The size of the largest file delivered, in bytes.
+    pub fn max_size(mut self, max_size: u64) -> Self {
+        self.max_size = Some(max_size);
+        self
+    }
+
+    /// Drops the events of files smaller than `min_size` bytes, such as files that were
+    /// just created and are still empty, see [`FilterSet::max_size`].
+    ///
+    /// # Arguments
+    /// * `min_size` - The size of the smallest file delivered, in bytes.
+    pub fn min_size(mut self, min_size: u64) -> Self {
+        self.min_size = Some(min_size);
+        self
+    }
+
+    /// Returns `true` if `path` is delivered.
+    ///
+    /// # Arguments
+    /// * `path` - The path to test, either in full or relative to the watched directory.
+    pub fn allows(&self, path: impl AsRef<Path>) -> bool {
+        let path = path.as_ref();
+        self.allows_below(path, None) && self.allows_size(path)
+    }
+
+    /// Returns `true` if the filter set delivers every path.
+    pub fn is_empty(&self) -> bool {
+        self.include.is_empty()
+            && self.exclude.is_empty()
+            && self.predicates.is_empty()
+            && self.gitignore.is_none()
+            && self.watchitignore.is_none()
+            && !self.ignore_hidden
+            && self.min_size.is_none()
+            && self.max_size.is_none()
+    }
+
+    /// Returns `true` if one of the event's paths is delivered, so that renames across the
+    /// boundary of the set are reported from both sides.
+    pub(crate) fn allows_event(&self, event: &DebouncedEvent, details: &Details) -> bool {
+        self.is_empty()
+            || (event
+                .paths
+                .iter()
+                .any(|path| self.allows_below(path, details.root.as_deref()))
+                && event.paths.last().is_none_or(|path| self.allows_size(path)))
+    }
+
+    /// Returns `true` if `path` is not a file, or a file within the size limits.
+    fn allows_size(&self, path: &Path) -> bool {
+        if self.min_size.is_none() && self.max_size.is_none() {
+            return true;
+        }
+        let Ok(metadata) = std::fs::metadata(path) else {
+            return true;
+        };
+        !metadata.is_file()
+            || (self.min_size.is_none_or(|min| metadata.len() >= min)
+                && self.max_size.is_none_or(|max| metadata.len() <= max))
+    }
+
+    fn allows_below(&self, path: &Path, root: Option<&Path>) -> bool {
+        let relative = root.and_then(|root| path.strip_prefix(root).ok());
+        let matches = |glob: &Glob| glob.matches(path) || relative.is_some_and(|p| glob.matches(p));
+        (self.include.is_empty() || self.include.iter().any(matches))
+            && !self.exclude.iter().any(matches)
+            && self.predicates.iter().all(|predicate| predicate(path))
+            && !(self.ignore_hidden && is_hidden(path, root))
+            && ![&self.gitignore, &self.watchitignore]
+                .into_iter()
+                .flatten()
+                .any(|ignore_files| ignore_files.is_ignored(path, root))
+    }
+
+    /// Writes the declarative rules of the set. Predicates added with
+    /// [`FilterSet::matching`] are code and cannot be written.
+    pub(crate) fn to_json(&self) -> Value {
+        let patterns = |globs: &[Glob]| {
+            Value::Array(
+                globs
+                    .iter()
+                    .map(|glob| Value::string(glob.as_str()))
+                    .collect(),
+            )
+        };
+        Value::object([
+            ("include", patterns(&self.include)),
+            ("exclude", patterns(&self.exclude)),
+            ("gitignore", Value::Bool(self.gitignore.is_some())),
+            ("watchitignore", Value::Bool(self.watchitignore.is_some())),
+            ("ignore_hidden", Value::Bool(self.ignore_hidden)),
+            ("min_size", Value::optional(self.min_size, Value::number)),
+            ("max_size", Value::optional(self.max_size, Value::number)),
+        ])
+    }
+
+    pub(crate) fn from_json(value: &Value) -> Result<Self, Error> {
+        let patterns = |key: &str| -> Result<Vec<Glob>, Error> {
+            value
+                .field(key)?
+                .as_array()?
+                .iter()
+                .map(|pattern| Glob::new(pattern.as_str()?))
+                .collect()
+        };
+        Ok(Self {
+            include: patterns("include")?,
+            exclude: patterns("exclude")?,
+            predicates: Vec::new(),
+            gitignore: value
+                .field("gitignore")?
+                .as_bool()?
+                .then(IgnoreFiles::gitignore),
+            watchitignore: value
+                .field("watchitignore")?
+                .as_bool()?
+                .then(IgnoreFiles::watchitignore),
+            ignore_hidden: value.field("ignore_hidden")?.as_bool()?,
+            min_size: value.field("min_size")?.as_optional(Value::as_u64)?,
+            max_size: value.field("max_size")?.as_optional(Value::as_u64)?,
+        })
+    }
+}
+
+/// Returns `true` if `path`, or one of its ancestors below `root`, is hidden.
+fn is_hidden(path: &Path, root: Option<&Path>) -> bool {
+    let relative = root
+        .and_then(|root| path.strip_prefix(root).ok())
+        .unwrap_or(path);
+    let dotted = relative.components().any(|component| match component {
+        Component::Normal(name) => name.as_encoded_bytes().starts_with(b"."),
+        _ => false,
+    });
+    dotted || has_hidden_attribute(path, relative)
+}
+
+/// Returns `true` if `path`, or one of its ancestors within `relative`, has the hidden
+/// attribute.
+#[cfg(windows)]
+fn has_hidden_attribute(path: &Path, relative: &Path) -> bool {
+    use std::os::windows::fs::MetadataExt;
+
+    const FILE_ATTRIBUTE_HIDDEN: u32 = 0x2;
+    path.ancestors()
+        .take(relative.components().count())
+        .any(|ancestor| {
+            std::fs::symlink_metadata(ancestor)
+                .is_ok_and(|metadata| metadata.file_attributes() & FILE_ATTRIBUTE_HIDDEN != 0)
+        })
+}
+
+#[cfg(not(windows))]
+fn has_hidden_attribute(_path: &Path, _relative: &Path) -> bool {
+    false
+}
+
+impl fmt::Debug for FilterSet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("FilterSet")
+            .field("include", &self.include)
+            .field("exclude", &self.exclude)
+            .field("predicates", &self.predicates.len())
+            .field("gitignore", &self.gitignore.is_some())
+            .field("watchitignore", &self.watchitignore.is_some())
+            .field("ignore_hidden", &self.ignore_hidden)
+            .field("min_size", &self.min_size)
+            .field("max_size", &self.max_size)
+            .finish()
+    }
+}
+
+impl State {
+    /// Delivers only the events below the watch on `filename` that `filters` allows.
+    pub(crate) fn add_filter_set(
+        &mut self,
+        filename: &Path,
+        filters: FilterSet,
+    ) -> Result<(), Error> {
+        let Some(watch) = self.watches.get_mut(filename) else {
+            return Err(Error::watch_not_found().add_path(filename.to_path_buf()));
+        };
+        watch.filtered = true;
+        self.filters.insert(filename.to_path_buf(), filters);
+
+        Ok(())
+    }
+
+    /// Delivers only the events of `kinds` below the watch on `filename`.
+    pub(crate) fn add_kind_mask(
+        &mut self,
+        filename: &Path,
+        kinds: EventKindMask,
+    ) -> Result<(), Error> {
+        let Some(watch) = self.watches.get_mut(filename) else {
+            return Err(Error::watch_not_found().add_path(filename.to_path_buf()));
+        };
+        watch.kinds = kinds;
+        self.kinds.insert(filename.to_path_buf(), kinds);
+
+        Ok(())
+    }
+
+    pub(crate) fn forget_filters(&mut self, filename: &Path) {
+        self.filters.remove(filename);
+        self.kinds.remove(filename);
+    }
+
+    /// Drops the events whose kind or path the options of their watch do not allow.
+    pub(crate) fn filter_events(
+        &self,
+        mut events: Vec<(DebouncedEvent, Details)>,
+    ) -> Vec<(DebouncedEvent, Details)> {
+        if self.filters.is_empty() && self.kinds.is_empty() {
+            return events;
+        }
+        events.retain(|(event, details)| {
+            let Some(root) = &details.root else {
+                return true;
+            };
+            self.kinds
+                .get(root)
+                .is_none_or(|kinds| kinds.contains(details.kind(event)))
+                && self
+                    .filters
+                    .get(root)
+                    .is_none_or(|filters| filters.allows_event(event, details))
+        });
+        events
+    }
+}
+
+#[cfg(test)]
+/// This module contains tests for filter sets.
+///
+/// The `matches_relative_to_root` test verifies that patterns match the path of an event
+/// relative to its watched directory as well as in full.
+///
+/// The `editor_noise_drops_helper_files` test verifies that the preset drops the helper
+/// files of common editors and keeps regular files.
+///
+/// The `ignore_hidden_drops_dotted_paths` test verifies that hidden paths and the paths below
+/// hidden directories are dropped, while a hidden watched directory is not.
+mod tests {
+    use std::{path::PathBuf, time::Instant};
+
+    use notify::event::{CreateKind, EventKind};
+
+    use super::*;
+
+    fn created(path: &str) -> DebouncedEvent {
+        let event = notify::Event::new(EventKind::Create(CreateKind::File)).add_path(path.into());
+        DebouncedEvent::new(event, Instant::now())
+    }
+
+    #[test]
+    fn matches_relative_to_root() {
+        let filters = FilterSet::new().exclude("target/**").unwrap();
+        let details = Details {
+            root: Some(PathBuf::from("/srv/project")),
+            ..Details::default()
+        };
+        assert!(!filters.allows_event(&created("/srv/project/target/debug/app"), &details));
+        assert!(filters.allows_event(&created("/srv/project/src/target.rs"), &details));
+        assert!(filters.allows_event(&created("/srv/project/src/target/app"), &details));
+    }
+
+    #[test]
+    fn editor_noise_drops_helper_files() {
+        let filters = FilterSet::editor_noise();
+        for noise in [
+            "src/.lib.rs.swp",
+            "src/.lib.rs.swo",
+            "src/lib.rs~",
+            "src/.#lib.rs",
+            "src/#lib.rs#",
+            "src/4913",
+            "src/lib.rs___jb_old___",
+            "docs/.~lock.notes.odt#",
+            "build/output.tmp",
+            "assets/.DS_Store",
+        ] {
+            assert!(!filters.allows(noise), "{noise} is noise");
+        }
+        for path in ["src/lib.rs", "src/swap.rs", "notes.odt", "src/4913.rs"] {
+            assert!(filters.allows(path), "{path} is not noise");
+        }
+    }
+
+    #[test]
+    fn ignore_hidden_drops_dotted_paths() {
+        let filters = FilterSet::new().ignore_hidden(true);
+        let details = Details {
+            root: Some(PathBuf::from("/home/user/.config")),
+            ..Details::default()
+        };
+        assert!(filters.allows_event(&created("/home/user/.config/app/settings.toml"), &details));
+        assert!(!filters.allows_event(&created("/home/user/.config/app/.cache"), &details));
+        assert!(!filters.allows_event(&created("/home/user/.config/.git/HEAD"), &details));
+        assert!(filters.allows("src/main.rs"));
+        assert!(!filters.allows(".env"));
+    }
+}
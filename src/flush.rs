@@ -0,0 +1,39 @@
+use std::{sync::Arc, thread, time::Instant};
+
+use crate::dispatch::{self, SharedDispatcher};
+
+/// Starts the thread delivering the events that the dispatcher holds back once they are
+/// due, unless it is already running.
+///
+/// The thread exits once the dispatcher is dropped or shut down.
+pub(crate) fn start_flusher(dispatcher: &SharedDispatcher) {
+    if !dispatch::lock(dispatcher).claim_flusher() {
+        return;
+    }
+
+    let dispatcher = Arc::downgrade(dispatcher);
+    let spawned = thread::Builder::new()
+        .name("watchit-flusher".into())
+        .spawn(move || {
+            if let Some(shared) = dispatcher.upgrade() {
+                dispatch::lock(&shared).set_flusher(thread::current());
+            }
+            loop {
+                let Some(shared) = dispatcher.upgrade() else {
+                    return;
+                };
+                let Some(wait) = dispatch::lock(&shared).flush_held(Instant::now()) else {
+                    return;
+                };
+                drop(dispatch::drain(&shared));
+                drop(shared);
+                thread::park_timeout(wait);
+            }
+        });
+    if let Err(error) = spawned {
+        tracing::warn!(
+            "Failed to start the thread delivering held events: {}",
+            error
+        );
+    }
+}
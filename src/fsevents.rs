@@ -0,0 +1,477 @@
+use std::{
+    collections::BTreeMap,
+    ffi::{c_char, c_void, CStr, OsStr},
+    os::unix::ffi::OsStrExt as _,
+    panic::{self, AssertUnwindSafe},
+    path::{Path, PathBuf},
+    ptr,
+    sync::mpsc,
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use notify::{
+    event::{CreateKind, DataChange, Flag, MetadataKind, ModifyKind, RemoveKind, RenameMode},
+    EventKind,
+};
+
+use crate::{Error, EventSink, FsEventsFlags, WatchBackend};
+
+type CFIndex = isize;
+type CFRef = *const c_void;
+type CFRunLoopRef = *mut c_void;
+type FSEventStreamRef = *mut c_void;
+type FSEventStreamCallback = extern "C" fn(
+    stream: FSEventStreamRef,
+    info: *mut c_void,
+    count: usize,
+    paths: *mut c_void,
+    flags: *const u32,
+    ids: *const u64,
+);
+
+const STRING_ENCODING_UTF8: u32 = 0x0800_0100;
+const EVENT_ID_SINCE_NOW: u64 = u64::MAX;
+/// Makes the stream report `CFString`s instead of C strings, which the callback cannot read.
+const CREATE_FLAG_USE_CF_TYPES: u32 = 0x01;
+
+const MUST_SCAN_SUB_DIRS: u32 = 0x01;
+const USER_DROPPED: u32 = 0x02;
+const KERNEL_DROPPED: u32 = 0x04;
+const ITEM_CREATED: u32 = 0x100;
+const ITEM_REMOVED: u32 = 0x200;
+const ITEM_INODE_META_MOD: u32 = 0x400;
+const ITEM_RENAMED: u32 = 0x800;
+const ITEM_MODIFIED: u32 = 0x1000;
+const ITEM_FINDER_INFO_MOD: u32 = 0x2000;
+const ITEM_CHANGE_OWNER: u32 = 0x4000;
+const ITEM_XATTR_MOD: u32 = 0x8000;
+const ITEM_IS_FILE: u32 = 0x10000;
+const ITEM_IS_DIR: u32 = 0x20000;
+
+#[repr(C)]
+struct CFArrayCallBacks {
+    version: CFIndex,
+    retain: *const c_void,
+    release: *const c_void,
+    copy_description: *const c_void,
+    equal: *const c_void,
+}
+
+#[repr(C)]
+struct FSEventStreamContext {
+    version: CFIndex,
+    info: *mut c_void,
+    retain: *const c_void,
+    release: *const c_void,
+    copy_description: *const c_void,
+}
+
+#[link(name = "CoreFoundation", kind = "framework")]
+extern "C" {
+    static kCFTypeArrayCallBacks: CFArrayCallBacks;
+    static kCFRunLoopDefaultMode: CFRef;
+
+    fn CFArrayCreateMutable(
+        allocator: CFRef,
+        capacity: CFIndex,
+        callbacks: *const CFArrayCallBacks,
+    ) -> *mut c_void;
+    fn CFArrayAppendValue(array: *mut c_void, value: CFRef);
+    fn CFStringCreateWithBytes(
+        allocator: CFRef,
+        bytes: *const u8,
+        length: CFIndex,
+        encoding: u32,
+        external: u8,
+    ) -> CFRef;
+    fn CFRelease(object: CFRef);
+    fn CFRunLoopGetCurrent() -> CFRunLoopRef;
+    fn CFRunLoopRun();
+    fn CFRunLoopStop(run_loop: CFRunLoopRef);
+    fn CFRunLoopIsWaiting(run_loop: CFRunLoopRef) -> u8;
+}
+
+#[link(name = "CoreServices", kind = "framework")]
+extern "C" {
+    fn FSEventStreamCreate(
+        allocator: CFRef,
+        callback: FSEventStreamCallback,
+        context: *const FSEventStreamContext,
+        paths: CFRef,
+        since: u64,
+        latency: f64,
+        flags: u32,
+    ) -> FSEventStreamRef;
+    fn FSEventStreamScheduleWithRunLoop(
+        stream: FSEventStreamRef,
+        run_loop: CFRunLoopRef,
+        mode: CFRef,
+    );
+    fn FSEventStreamStart(stream: FSEventStreamRef) -> u8;
+    fn FSEventStreamStop(stream: FSEventStreamRef);
+    fn FSEventStreamInvalidate(stream: FSEventStreamRef);
+    fn FSEventStreamRelease(stream: FSEventStreamRef);
+}
+
+/// A back-end reading an FSEvents stream created with the latency and flags of
+/// [`Backend::FsEvents`](crate::Backend::FsEvents).
+///
+/// The paths of a stream are fixed when it is created, so the stream is created again
+/// whenever a path is watched or unwatched.
+pub(crate) struct FsEventsWatcher {
+    sink: EventSink,
+    latency: Duration,
+    flags: FsEventsFlags,
+    roots: BTreeMap<PathBuf, Root>,
+    stream: Option<Stream>,
+}
+
+#[derive(Clone)]
+struct Root {
+    /// The path FSEvents reports the changes below the root with.
+    canonical: PathBuf,
+    recursive: bool,
+}
+
+impl FsEventsWatcher {
+    pub(crate) fn new(sink: EventSink, latency: Duration, flags: FsEventsFlags) -> Self {
+        Self {
+            sink,
+            latency,
+            flags,
+            roots: BTreeMap::new(),
+            stream: None,
+        }
+    }
+
+    /// Replaces the stream with one for the current roots.
+    fn restart(&mut self) -> Result<(), Error> {
+        if let Some(stream) = self.stream.take() {
+            stream.stop();
+        }
+        if self.roots.is_empty() {
+            return Ok(());
+        }
+        let context = Context {
+            sink: self.sink.clone(),
+            roots: self.roots.clone(),
+        };
+        self.stream = Some(Stream::start(context, self.latency, self.flags)?);
+        Ok(())
+    }
+}
+
+impl WatchBackend for FsEventsWatcher {
+    fn watch(&mut self, path: &Path, recursive: bool) -> Result<(), Error> {
+        let canonical = path
+            .canonicalize()
+            .map_err(|error| Error::io(error).add_path(path.to_path_buf()))?;
+        let previous = self.roots.insert(
+            path.to_path_buf(),
+            Root {
+                canonical,
+                recursive,
+            },
+        );
+        if let Err(error) = self.restart() {
+            match previous {
+                Some(previous) => self.roots.insert(path.to_path_buf(), previous),
+                None => self.roots.remove(path),
+            };
+            let _ = self.restart();
+            return Err(error.add_path(path.to_path_buf()));
+        }
+        Ok(())
+    }
+
+    fn unwatch(&mut self, path: &Path) -> Result<(), Error> {
+        if self.roots.remove(path).is_none() {
+            return Err(Error::watch_not_found().add_path(path.to_path_buf()));
+        }
+        self.restart()
+    }
+}
+
+impl Drop for FsEventsWatcher {
+    fn drop(&mut self) {
+        if let Some(stream) = self.stream.take() {
+            stream.stop();
+        }
+    }
+}
+
+/// What the callback of a stream needs, owned by the thread running the stream.
+struct Context {
+    sink: EventSink,
+    roots: BTreeMap<PathBuf, Root>,
+}
+
+impl Context {
+    /// Reports the change FSEvents described with `flags` at `path`, if it is watched.
+    fn report(&self, path: &Path, flags: u32) {
+        let Some(path) = self.watched_path(path) else {
+            return;
+        };
+        if flags & (MUST_SCAN_SUB_DIRS | USER_DROPPED | KERNEL_DROPPED) != 0 {
+            self.sink.send(
+                notify::Event::new(EventKind::Other)
+                    .add_path(path.clone())
+                    .set_flag(Flag::Rescan),
+            );
+        }
+        for kind in kinds(flags) {
+            self.sink
+                .send(notify::Event::new(kind).add_path(path.clone()));
+        }
+    }
+
+    /// Returns `path` as it is below the watched path covering it, if any.
+    fn watched_path(&self, path: &Path) -> Option<PathBuf> {
+        self.roots.iter().find_map(|(watched, root)| {
+            let covered = path == root.canonical
+                || if root.recursive {
+                    path.starts_with(&root.canonical)
+                } else {
+                    path.parent() == Some(root.canonical.as_path())
+                };
+            let rest = path
+                .strip_prefix(&root.canonical)
+                .ok()
+                .filter(|_| covered)?;
+            Some(if rest.as_os_str().is_empty() {
+                watched.clone()
+            } else {
+                watched.join(rest)
+            })
+        })
+    }
+}
+
+/// Returns the kinds of the events that the FSEvents `flags` of a change stand for.
+fn kinds(flags: u32) -> Vec<EventKind> {
+    let mut kinds = Vec::new();
+    if flags & ITEM_CREATED != 0 {
+        kinds.push(EventKind::Create(if flags & ITEM_IS_DIR != 0 {
+            CreateKind::Folder
+        } else if flags & ITEM_IS_FILE != 0 {
+            CreateKind::File
+        } else {
+            CreateKind::Any
+        }));
+    }
+    if flags & ITEM_RENAMED != 0 {
+        kinds.push(EventKind::Modify(ModifyKind::Name(RenameMode::Any)));
+    }
+    if flags & ITEM_MODIFIED != 0 {
+        kinds.push(EventKind::Modify(ModifyKind::Data(DataChange::Content)));
+    }
+    if flags & ITEM_INODE_META_MOD != 0 {
+        kinds.push(EventKind::Modify(ModifyKind::Metadata(MetadataKind::Any)));
+    }
+    if flags & ITEM_CHANGE_OWNER != 0 {
+        kinds.push(EventKind::Modify(ModifyKind::Metadata(
+            MetadataKind::Ownership,
+        )));
+    }
+    if flags & (ITEM_XATTR_MOD | ITEM_FINDER_INFO_MOD) != 0 {
+        kinds.push(EventKind::Modify(ModifyKind::Metadata(
+            MetadataKind::Extended,
+        )));
+    }
+    if flags & ITEM_REMOVED != 0 {
+        kinds.push(EventKind::Remove(if flags & ITEM_IS_DIR != 0 {
+            RemoveKind::Folder
+        } else if flags & ITEM_IS_FILE != 0 {
+            RemoveKind::File
+        } else {
+            RemoveKind::Any
+        }));
+    }
+    kinds
+}
+
+/// The run loop of the thread running a stream.
+struct RunLoop(CFRunLoopRef);
+
+// SAFETY: run loops may be stopped from any thread.
+unsafe impl Send for RunLoop {}
+
+/// A stream running on its own thread until it is stopped.
+struct Stream {
+    run_loop: RunLoop,
+    thread: JoinHandle<()>,
+}
+
+impl Stream {
+    fn start(context: Context, latency: Duration, flags: FsEventsFlags) -> Result<Self, Error> {
+        let (started_tx, started_rx) = mpsc::sync_channel(1);
+        let thread = thread::Builder::new()
+            .name("watchit-fsevents".to_string())
+            .spawn(move || run(&context, latency, flags, &started_tx))
+            .map_err(Error::io)?;
+        match started_rx.recv() {
+            Ok(Ok(run_loop)) => Ok(Self { run_loop, thread }),
+            Ok(Err(error)) => {
+                let _ = thread.join();
+                Err(error)
+            }
+            Err(_) => {
+                let _ = thread.join();
+                Err(Error::generic("the FSEvents stream thread stopped"))
+            }
+        }
+    }
+
+    fn stop(self) {
+        // Stopping a run loop that has not started running yet would be lost.
+        // SAFETY: the run loop lives as long as its thread, which is joined below.
+        while unsafe { CFRunLoopIsWaiting(self.run_loop.0) } == 0 {
+            thread::yield_now();
+        }
+        // SAFETY: as above.
+        unsafe { CFRunLoopStop(self.run_loop.0) };
+        let _ = self.thread.join();
+    }
+}
+
+/// Creates a stream for the roots of `context`, and runs it until its run loop is stopped.
+fn run(
+    context: &Context,
+    latency: Duration,
+    flags: FsEventsFlags,
+    started: &mpsc::SyncSender<Result<RunLoop, Error>>,
+) {
+    // SAFETY: every object created here is released before returning, and `context`
+    // outlives the stream whose callback reads it.
+    unsafe {
+        let paths = CFArrayCreateMutable(ptr::null(), 0, ptr::addr_of!(kCFTypeArrayCallBacks));
+        for root in context.roots.values() {
+            let bytes = root.canonical.as_os_str().as_bytes();
+            let path = CFStringCreateWithBytes(
+                ptr::null(),
+                bytes.as_ptr(),
+                bytes.len() as CFIndex,
+                STRING_ENCODING_UTF8,
+                0,
+            );
+            CFArrayAppendValue(paths, path);
+            CFRelease(path);
+        }
+        let stream_context = FSEventStreamContext {
+            version: 0,
+            info: (context as *const Context).cast_mut().cast(),
+            retain: ptr::null(),
+            release: ptr::null(),
+            copy_description: ptr::null(),
+        };
+        let stream = FSEventStreamCreate(
+            ptr::null(),
+            callback,
+            &stream_context,
+            paths,
+            EVENT_ID_SINCE_NOW,
+            latency.as_secs_f64(),
+            flags.0 & !CREATE_FLAG_USE_CF_TYPES,
+        );
+        CFRelease(paths);
+        if stream.is_null() {
+            let _ = started.send(Err(Error::generic("failed to create the FSEvents stream")));
+            return;
+        }
+
+        let run_loop = CFRunLoopGetCurrent();
+        FSEventStreamScheduleWithRunLoop(stream, run_loop, kCFRunLoopDefaultMode);
+        if FSEventStreamStart(stream) == 0 {
+            FSEventStreamInvalidate(stream);
+            FSEventStreamRelease(stream);
+            let _ = started.send(Err(Error::generic("failed to start the FSEvents stream")));
+            return;
+        }
+        let _ = started.send(Ok(RunLoop(run_loop)));
+        CFRunLoopRun();
+
+        FSEventStreamStop(stream);
+        FSEventStreamInvalidate(stream);
+        FSEventStreamRelease(stream);
+    }
+}
+
+extern "C" fn callback(
+    _stream: FSEventStreamRef,
+    info: *mut c_void,
+    count: usize,
+    paths: *mut c_void,
+    flags: *const u32,
+    _ids: *const u64,
+) {
+    // SAFETY: `info` is the context the stream was created with, and FSEvents passes
+    // `count` C strings and flags.
+    let report = || unsafe {
+        let context = &*info.cast::<Context>();
+        let paths = paths.cast::<*const c_char>();
+        for index in 0..count {
+            let path = CStr::from_ptr(*paths.add(index));
+            let path = Path::new(OsStr::from_bytes(path.to_bytes()));
+            context.report(path, *flags.add(index));
+        }
+    };
+    // Unwinding out of the callback into FSEvents would abort the process.
+    if panic::catch_unwind(AssertUnwindSafe(report)).is_err() {
+        tracing::error!("Panicked while reporting FSEvents changes");
+    }
+}
+
+#[cfg(test)]
+/// This module contains tests for the FSEvents back-end.
+///
+/// The `kinds_follow_flags` test verifies that the flags of a change are turned into the
+/// kinds of events they stand for.
+///
+/// The `watched_path_keeps_watched_prefix` test verifies that changes are reported below the
+/// path that was watched rather than its canonical form, and only if the watch covers them.
+mod tests {
+    use super::*;
+
+    #[test]
+    fn kinds_follow_flags() {
+        assert_eq!(
+            kinds(ITEM_CREATED | ITEM_MODIFIED | ITEM_IS_FILE),
+            vec![
+                EventKind::Create(CreateKind::File),
+                EventKind::Modify(ModifyKind::Data(DataChange::Content)),
+            ]
+        );
+        assert_eq!(
+            kinds(ITEM_REMOVED | ITEM_IS_DIR),
+            vec![EventKind::Remove(RemoveKind::Folder)]
+        );
+        assert!(kinds(MUST_SCAN_SUB_DIRS).is_empty());
+    }
+
+    #[test]
+    fn watched_path_keeps_watched_prefix() {
+        let sink = EventSink::new(|_: notify::Result<notify::Event>| {});
+        let roots = BTreeMap::from([(
+            PathBuf::from("/tmp/watched"),
+            Root {
+                canonical: PathBuf::from("/private/tmp/watched"),
+                recursive: false,
+            },
+        )]);
+        let context = Context { sink, roots };
+
+        assert_eq!(
+            context.watched_path(Path::new("/private/tmp/watched/file.txt")),
+            Some(PathBuf::from("/tmp/watched/file.txt"))
+        );
+        assert_eq!(
+            context.watched_path(Path::new("/private/tmp/watched")),
+            Some(PathBuf::from("/tmp/watched"))
+        );
+        assert_eq!(
+            context.watched_path(Path::new("/private/tmp/watched/dir/file.txt")),
+            None
+        );
+    }
+}
@@ -0,0 +1,239 @@
+use std::{
+    collections::BTreeMap,
+    fmt, fs,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, MutexGuard},
+    time::SystemTime,
+};
+
+use crate::Glob;
+
+/// The ignore files read in every directory, in order of increasing precedence.
+const IGNORE_FILES: &[&str] = &[".gitignore", ".ignore"];
+
+/// The ignore file read at the root of each watch, see
+/// [`FilterSet::watchitignore`](crate::FilterSet::watchitignore).
+const WATCHIT_IGNORE: &[&str] = &[".watchitignore"];
+
+/// The rules of the ignore files around watched paths, see
+/// [`FilterSet::gitignore`](crate::FilterSet::gitignore).
+///
+/// Ignore files are read lazily and cached per directory. A cached directory is read again
+/// once one of its ignore files changes, so edits to `.gitignore` apply to the next event.
+#[derive(Clone)]
+pub(crate) struct IgnoreFiles {
+    cache: Arc<Mutex<Cache>>,
+    /// The names of the ignore files, in order of increasing precedence.
+    files: &'static [&'static str],
+    /// Whether only the ignore files at the root of a watch are read, rather than those
+    /// of every directory up to the top of the repository.
+    root_only: bool,
+}
+
+#[derive(Default)]
+struct Cache {
+    /// The rules of each directory.
+    dirs: BTreeMap<PathBuf, Rules>,
+    /// The outermost directory whose ignore files apply below each watched root.
+    tops: BTreeMap<PathBuf, PathBuf>,
+}
+
+/// The rules of the ignore files in one directory.
+struct Rules {
+    /// The modification times of the ignore files, or `None` for missing files.
+    modified: Vec<Option<SystemTime>>,
+    rules: Arc<Vec<Rule>>,
+}
+
+/// One line of an ignore file.
+struct Rule {
+    glob: Glob,
+    /// Whether the line starts with `!`, re-including what earlier lines ignored.
+    negated: bool,
+    /// Whether the line ends with `/`, matching directories only.
+    dir_only: bool,
+}
+
+impl IgnoreFiles {
+    /// Reads the `.gitignore` and `.ignore` files up to the top of each repository.
+    pub(crate) fn gitignore() -> Self {
+        Self {
+            cache: Arc::default(),
+            files: IGNORE_FILES,
+            root_only: false,
+        }
+    }
+
+    /// Reads the `.watchitignore` file at the root of each watch.
+    pub(crate) fn watchitignore() -> Self {
+        Self {
+            cache: Arc::default(),
+            files: WATCHIT_IGNORE,
+            root_only: true,
+        }
+    }
+
+    /// Returns `true` if `path`, or a directory containing it, is ignored by the ignore
+    /// files between `path` and the top of its repository.
+    ///
+    /// The top is the closest directory containing `.git` at or above `root`, the watched
+    /// directory the path was reported for, or `root` itself outside of repositories.
+    pub(crate) fn is_ignored(&self, path: &Path, root: Option<&Path>) -> bool {
+        let Some(top) = self.top(path, root) else {
+            return false;
+        };
+
+        let mut chain: Vec<&Path> = path
+            .ancestors()
+            .take_while(|ancestor| *ancestor != top)
+            .collect();
+        if chain.last().and_then(|outermost| outermost.parent()) != Some(top.as_path()) {
+            return false;
+        }
+        chain.reverse();
+
+        // A path below an ignored directory is ignored, whatever the rules say about it.
+        let last = chain.len() - 1;
+        chain.iter().enumerate().any(|(index, candidate)| {
+            let is_dir = index < last || fs::metadata(candidate).is_ok_and(|m| m.is_dir());
+            self.ignores(&top, candidate, is_dir)
+        })
+    }
+
+    /// Applies the rules of every directory from `top` down to the parent of `candidate`,
+    /// letting later rules override earlier ones.
+    fn ignores(&self, top: &Path, candidate: &Path, is_dir: bool) -> bool {
+        let mut ignored = false;
+        let dirs = candidate
+            .ancestors()
+            .skip(1)
+            .take_while(|dir| dir.starts_with(top))
+            .filter(|dir| !self.root_only || *dir == top)
+            .collect::<Vec<_>>();
+        for dir in dirs.into_iter().rev() {
+            let Ok(relative) = candidate.strip_prefix(dir) else {
+                continue;
+            };
+            for rule in self.rules(dir).iter() {
+                if (!rule.dir_only || is_dir) && rule.glob.matches(relative) {
+                    ignored = !rule.negated;
+                }
+            }
+        }
+        ignored
+    }
+
+    /// Returns the outermost directory whose ignore files apply to `path`.
+    fn top(&self, path: &Path, root: Option<&Path>) -> Option<PathBuf> {
+        let root = match root {
+            Some(root) if root != path => root,
+            _ => path.parent()?,
+        };
+        if let Some(top) = self.lock().tops.get(root) {
+            return Some(top.clone());
+        }
+
+        let top = root
+            .ancestors()
+            .find(|dir| !self.root_only && dir.join(".git").exists())
+            .unwrap_or(root)
+            .to_path_buf();
+        self.lock().tops.insert(root.to_path_buf(), top.clone());
+        Some(top)
+    }
+
+    /// Returns the rules of the ignore files in `dir`, reading them if they changed.
+    fn rules(&self, dir: &Path) -> Arc<Vec<Rule>> {
+        let modified: Vec<Option<SystemTime>> = self
+            .files
+            .iter()
+            .map(|name| fs::metadata(dir.join(name)).and_then(|m| m.modified()).ok())
+            .collect();
+        if let Some(cached) = self.lock().dirs.get(dir) {
+            if cached.modified == modified {
+                return cached.rules.clone();
+            }
+        }
+
+        let rules: Vec<Rule> = self
+            .files
+            .iter()
+            .filter_map(|name| fs::read_to_string(dir.join(name)).ok())
+            .flat_map(|contents| parse(&contents))
+            .collect();
+        if !rules.is_empty() {
+            tracing::debug!("Read {} ignore rules in {}", rules.len(), dir.display());
+        }
+        let rules = Arc::new(rules);
+        let cached = Rules {
+            modified,
+            rules: rules.clone(),
+        };
+        self.lock().dirs.insert(dir.to_path_buf(), cached);
+        rules
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Cache> {
+        self.cache
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl fmt::Debug for IgnoreFiles {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("IgnoreFiles")
+            .field("files", &self.files)
+            .finish()
+    }
+}
+
+/// Parses the lines of an ignore file, skipping blank lines, comments and malformed
+/// patterns.
+fn parse(contents: &str) -> Vec<Rule> {
+    contents.lines().filter_map(parse_line).collect()
+}
+
+fn parse_line(line: &str) -> Option<Rule> {
+    let line = line.trim_end();
+    if line.is_empty() || line.starts_with('#') {
+        return None;
+    }
+
+    let (negated, line) = match line.strip_prefix('!') {
+        Some(rest) => (true, rest),
+        // A leading backslash escapes a `#` or `!` that is part of the name.
+        None if line.starts_with("\\#") || line.starts_with("\\!") => (false, &line[1..]),
+        None => (false, line),
+    };
+    let (dir_only, line) = match line.strip_suffix('/') {
+        Some(rest) => (true, rest),
+        None => (false, line),
+    };
+
+    // A slash anywhere but at the end anchors the pattern to the directory of the file.
+    let anchored = line.contains('/');
+    let line = line.strip_prefix('/').unwrap_or(line);
+    if line.is_empty() {
+        return None;
+    }
+
+    // Braces are literal in ignore files, but alternatives in globs.
+    let escaped = line.replace('{', "\\{").replace('}', "\\}");
+    let pattern = if anchored {
+        escaped
+    } else {
+        format!("**/{escaped}")
+    };
+    match Glob::new(&pattern) {
+        Ok(glob) => Some(Rule {
+            glob,
+            negated,
+            dir_only,
+        }),
+        Err(error) => {
+            tracing::debug!("Skipped ignore rule {:?}: {}", line, error);
+            None
+        }
+    }
+}
@@ -0,0 +1,514 @@
+use std::{
+    fmt,
+    path::{Component, Path, PathBuf},
+};
+
+use notify::RecursiveMode;
+
+use crate::{state::State, Error, WatchInfo};
+
+/// A compiled glob pattern that can be matched against paths.
+///
+/// Patterns are matched one path component at a time and support the usual syntax:
+///
+/// * `?` matches any single character.
+/// * `*` matches any run of characters within a component.
+/// * `**` as a whole component matches any number of components, including none.
+/// * `[abc]`, `[a-z]` and `[!a-z]` match one character from, or not from, a set.
+/// * `{a,b}` matches either alternative. Alternatives may span several components.
+/// * `\` escapes the following character.
+///
+/// ```
+/// use watchit::Glob;
+///
+/// let glob = Glob::new("src/**/*.{rs,toml}").unwrap();
+/// assert!(glob.matches("src/main.rs"));
+/// assert!(glob.matches("src/bin/tool/Cargo.toml"));
+/// assert!(!glob.matches("tests/main.rs"));
+/// ```
+#[derive(Clone, PartialEq, Eq)]
+pub struct Glob {
+    pattern: String,
+    alternatives: Vec<Vec<Segment>>,
+}
+
+/// One component of a pattern.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Segment {
+    /// `**`, matching any number of components.
+    AnyDepth,
+    /// A component without any special characters.
+    Literal(String),
+    /// A component containing wildcards.
+    Wildcard(Vec<Token>),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Char(char),
+    AnyChar,
+    AnyRun,
+    Class {
+        negated: bool,
+        ranges: Vec<(char, char)>,
+    },
+}
+
+impl Glob {
+    /// Compiles a glob pattern.
+    ///
+    /// # Arguments
+    /// * `pattern` - The pattern to compile, using `/` to separate components.
+    ///
+    /// # Returns
+    /// A `Result` containing either the compiled pattern, or an `Error` if the pattern is
+    /// malformed, such as an unclosed `[` or `{`.
+    pub fn new(pattern: &str) -> Result<Self, Error> {
+        let alternatives = expand_braces(pattern)?
+            .iter()
+            .map(|alternative| parse_alternative(alternative))
+            .collect::<Result<_, _>>()
+            .map_err(|reason| {
+                Error::generic(&format!("invalid glob pattern {pattern:?}: {reason}"))
+            })?;
+
+        Ok(Self {
+            pattern: pattern.to_string(),
+            alternatives,
+        })
+    }
+
+    /// Returns the pattern this glob was compiled from.
+    pub fn as_str(&self) -> &str {
+        &self.pattern
+    }
+
+    /// Returns `true` if `path` matches the pattern.
+    ///
+    /// # Arguments
+    /// * `path` - The path to test. It is compared component by component, so relative
+    ///   patterns only match relative paths and absolute patterns only match absolute paths.
+    pub fn matches(&self, path: impl AsRef<Path>) -> bool {
+        let components = components(path.as_ref());
+        self.alternatives
+            .iter()
+            .any(|segments| match_segments(segments, &components))
+    }
+
+    /// Returns the longest directory that contains every path the pattern can match.
+    pub(crate) fn base(&self) -> PathBuf {
+        let base = self.base_components();
+        if base.is_empty() {
+            PathBuf::from(".")
+        } else {
+            base.iter().collect()
+        }
+    }
+
+    /// Returns `true` if every path the pattern can match is a direct child of its base.
+    pub(crate) fn is_shallow(&self) -> bool {
+        let depth = self.base_components().len();
+        self.alternatives.iter().all(|segments| {
+            segments.len() == depth + 1
+                && !segments
+                    .iter()
+                    .any(|segment| matches!(segment, Segment::AnyDepth))
+        })
+    }
+
+    /// Returns the literal leading components shared by every alternative, leaving out the
+    /// component that names the matched path itself.
+    fn base_components(&self) -> Vec<&str> {
+        let mut base: Option<Vec<&str>> = None;
+        for segments in &self.alternatives {
+            let literals: Vec<&str> = segments[..segments.len() - 1]
+                .iter()
+                .map_while(|segment| match segment {
+                    Segment::Literal(literal) => Some(literal.as_str()),
+                    _ => None,
+                })
+                .collect();
+
+            base = Some(match base {
+                None => literals,
+                Some(mut base) => {
+                    let common = base
+                        .iter()
+                        .zip(&literals)
+                        .take_while(|(left, right)| left == right)
+                        .count();
+                    base.truncate(common);
+                    base
+                }
+            });
+        }
+        base.unwrap_or_default()
+    }
+}
+
+impl fmt::Debug for Glob {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("Glob").field(&self.pattern).finish()
+    }
+}
+
+impl fmt::Display for Glob {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.pattern)
+    }
+}
+
+/// Splits a path into the strings its components are compared by, ignoring `.` components.
+fn components(path: &Path) -> Vec<String> {
+    path.components()
+        .filter(|component| *component != Component::CurDir)
+        .map(|component| match component {
+            Component::RootDir => "/".to_string(),
+            component => component.as_os_str().to_string_lossy().into_owned(),
+        })
+        .collect()
+}
+
+/// Expands every `{a,b}` group, returning one pattern per combination of alternatives.
+fn expand_braces(pattern: &str) -> Result<Vec<String>, Error> {
+    let chars: Vec<char> = pattern.chars().collect();
+    let mut depth = 0;
+    let mut open = None;
+    let mut commas = Vec::new();
+    let mut index = 0;
+
+    while index < chars.len() {
+        match chars[index] {
+            '\\' => index += 1,
+            '{' => {
+                if depth == 0 {
+                    open = Some(index);
+                    commas.clear();
+                }
+                depth += 1;
+            }
+            ',' if depth == 1 => commas.push(index),
+            '}' if depth > 0 => {
+                depth -= 1;
+                if depth == 0 {
+                    let open = open.unwrap_or_default();
+                    let prefix: String = chars[..open].iter().collect();
+                    let suffix: String = chars[index + 1..].iter().collect();
+                    let mut bounds = vec![open];
+                    bounds.extend(&commas);
+                    bounds.push(index);
+
+                    let mut expanded = Vec::new();
+                    for window in bounds.windows(2) {
+                        let alternative: String = chars[window[0] + 1..window[1]].iter().collect();
+                        expanded.extend(expand_braces(&format!("{prefix}{alternative}{suffix}"))?);
+                    }
+                    return Ok(expanded);
+                }
+            }
+            _ => {}
+        }
+        index += 1;
+    }
+
+    if depth > 0 {
+        return Err(Error::generic(&format!(
+            "invalid glob pattern {pattern:?}: unclosed '{{'"
+        )));
+    }
+
+    Ok(vec![pattern.to_string()])
+}
+
+fn parse_alternative(pattern: &str) -> Result<Vec<Segment>, String> {
+    let mut segments = Vec::new();
+
+    if pattern.starts_with('/') {
+        segments.push(Segment::Literal("/".to_string()));
+    }
+
+    for component in pattern
+        .split('/')
+        .filter(|component| !component.is_empty() && *component != ".")
+    {
+        let segment = if component == "**" {
+            Segment::AnyDepth
+        } else {
+            let tokens = parse_component(component)?;
+            if tokens.iter().all(|token| matches!(token, Token::Char(_))) {
+                Segment::Literal(
+                    tokens
+                        .iter()
+                        .map(|token| match token {
+                            Token::Char(c) => *c,
+                            _ => unreachable!(),
+                        })
+                        .collect(),
+                )
+            } else {
+                Segment::Wildcard(tokens)
+            }
+        };
+
+        // Consecutive `**` components match exactly what a single one does.
+        if !(segment == Segment::AnyDepth && segments.last() == Some(&Segment::AnyDepth)) {
+            segments.push(segment);
+        }
+    }
+
+    if segments.is_empty() {
+        return Err("the pattern is empty".to_string());
+    }
+
+    Ok(segments)
+}
+
+fn parse_component(component: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = component.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        let token = match c {
+            '\\' => Token::Char(chars.next().ok_or("trailing '\\'")?),
+            '?' => Token::AnyChar,
+            '*' => {
+                while chars.peek() == Some(&'*') {
+                    chars.next();
+                }
+                Token::AnyRun
+            }
+            '[' => {
+                let negated = matches!(chars.peek(), Some('!' | '^'));
+                if negated {
+                    chars.next();
+                }
+
+                let mut ranges = Vec::new();
+                let mut first = true;
+                loop {
+                    let start = match chars.next() {
+                        None => return Err("unclosed '['".to_string()),
+                        Some(']') if !first => break,
+                        Some('\\') => chars.next().ok_or("unclosed '['")?,
+                        Some(c) => c,
+                    };
+                    first = false;
+
+                    let mut lookahead = chars.clone();
+                    if lookahead.next() == Some('-')
+                        && !matches!(lookahead.next(), Some(']') | None)
+                    {
+                        chars.next();
+                        let end = match chars.next() {
+                            Some('\\') => chars.next().ok_or("unclosed '['")?,
+                            Some(c) => c,
+                            None => return Err("unclosed '['".to_string()),
+                        };
+                        ranges.push((start, end));
+                    } else {
+                        ranges.push((start, start));
+                    }
+                }
+
+                Token::Class { negated, ranges }
+            }
+            c => Token::Char(c),
+        };
+        tokens.push(token);
+    }
+
+    Ok(tokens)
+}
+
+fn match_segments(segments: &[Segment], components: &[String]) -> bool {
+    match segments.split_first() {
+        None => components.is_empty(),
+        Some((Segment::AnyDepth, rest)) => {
+            (0..=components.len()).any(|skip| match_segments(rest, &components[skip..]))
+        }
+        Some((segment, rest)) => match components.split_first() {
+            Some((component, remaining)) => {
+                match_segment(segment, component) && match_segments(rest, remaining)
+            }
+            None => false,
+        },
+    }
+}
+
+fn match_segment(segment: &Segment, component: &str) -> bool {
+    match segment {
+        Segment::AnyDepth => true,
+        Segment::Literal(literal) => literal == component,
+        Segment::Wildcard(tokens) => {
+            let chars: Vec<char> = component.chars().collect();
+            match_tokens(tokens, &chars)
+        }
+    }
+}
+
+fn match_tokens(tokens: &[Token], chars: &[char]) -> bool {
+    match tokens.split_first() {
+        None => chars.is_empty(),
+        Some((Token::AnyRun, rest)) => {
+            (0..=chars.len()).any(|skip| match_tokens(rest, &chars[skip..]))
+        }
+        Some((token, rest)) => match chars.split_first() {
+            Some((c, remaining)) => match_token(token, *c) && match_tokens(rest, remaining),
+            None => false,
+        },
+    }
+}
+
+fn match_token(token: &Token, c: char) -> bool {
+    match token {
+        Token::Char(expected) => *expected == c,
+        Token::AnyChar => true,
+        Token::AnyRun => true,
+        Token::Class { negated, ranges } => {
+            ranges
+                .iter()
+                .any(|(start, end)| (*start..=*end).contains(&c))
+                != *negated
+        }
+    }
+}
+
+/// A watch on every path matching a glob pattern.
+///
+/// The base directory of the pattern is watched in its place, and only events for matching
+/// paths are reported.
+#[derive(Debug, Clone)]
+pub(crate) struct GlobWatch {
+    glob: Glob,
+    /// The directory watched on behalf of the pattern.
+    base: PathBuf,
+    /// The mode the base directory is watched with.
+    mode: RecursiveMode,
+}
+
+impl State {
+    /// Watches every path matching `pattern`, including paths created later on.
+    pub(crate) fn add_glob_watch(&mut self, pattern: &str) -> Result<(), Error> {
+        let glob = Glob::new(pattern)?;
+        let base = glob.base();
+        if !base.is_dir() {
+            return Err(Error::path_not_found().add_path(base));
+        }
+
+        let mode = if glob.is_shallow() {
+            RecursiveMode::NonRecursive
+        } else {
+            RecursiveMode::Recursive
+        };
+        self.acquire_helper(&base, mode)?;
+
+        let key = PathBuf::from(pattern);
+        if let Some(previous) = self.globs.insert(
+            key.clone(),
+            GlobWatch {
+                glob,
+                base: base.clone(),
+                mode,
+            },
+        ) {
+            self.release_helper(&previous.base, previous.mode);
+        }
+        self.watches
+            .insert(key.clone(), WatchInfo::new(key, mode).glob());
+
+        tracing::debug!(
+            "Watching {:?} for paths matching {}: {}",
+            mode,
+            pattern,
+            base.display()
+        );
+
+        Ok(())
+    }
+
+    /// Removes a glob watch, releasing its base directory if nothing else needs it.
+    ///
+    /// Returns `false` if `pattern` is not a glob watch.
+    pub(crate) fn remove_glob_watch(&mut self, pattern: &Path) -> bool {
+        let Some(watch) = self.globs.remove(pattern) else {
+            return false;
+        };
+        self.watches.remove(pattern);
+        self.release_helper(&watch.base, watch.mode);
+        true
+    }
+
+    /// Returns `true` if `path` matches the pattern of a glob watch.
+    pub(crate) fn is_glob_match(&self, path: &Path) -> bool {
+        self.globs.values().any(|watch| watch.glob.matches(path))
+    }
+
+    /// Returns `true` if `path` matches the pattern of the glob watch registered as `pattern`.
+    pub(crate) fn glob_matches(&self, pattern: &Path, path: &Path) -> bool {
+        self.globs
+            .get(pattern)
+            .is_some_and(|watch| watch.glob.matches(path))
+    }
+}
+
+#[cfg(test)]
+/// This module contains tests for the glob pattern matcher.
+///
+/// The `matches_wildcards_and_classes` test verifies that `?`, `*` and character classes
+/// match within a single path component.
+///
+/// The `matches_any_depth_and_braces` test verifies that `**` spans any number of components
+/// and that brace alternatives are expanded.
+///
+/// The `computes_base_directory` test verifies which directory is watched in place of a
+/// pattern, and whether it needs to be watched recursively.
+///
+/// The `rejects_malformed_patterns` test verifies that unclosed classes and alternatives are
+/// reported as errors.
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_wildcards_and_classes() {
+        let glob = Glob::new("logs/app-[0-9]?.log").unwrap();
+        assert!(glob.matches("logs/app-12.log"));
+        assert!(!glob.matches("logs/app-x2.log"));
+        assert!(!glob.matches("logs/nested/app-12.log"));
+
+        let glob = Glob::new("*.[!o]").unwrap();
+        assert!(glob.matches("main.c"));
+        assert!(!glob.matches("main.o"));
+    }
+
+    #[test]
+    fn matches_any_depth_and_braces() {
+        let glob = Glob::new("src/**/*.{rs,toml}").unwrap();
+        assert!(glob.matches("src/lib.rs"));
+        assert!(glob.matches("src/a/b/c/Cargo.toml"));
+        assert!(!glob.matches("src/lib.c"));
+    }
+
+    #[test]
+    fn computes_base_directory() {
+        assert_eq!(Glob::new("src/**/*.rs").unwrap().base(), Path::new("src"));
+        assert_eq!(
+            Glob::new("/var/log/*.log").unwrap().base(),
+            Path::new("/var/log")
+        );
+        assert_eq!(
+            Glob::new("{src,tests}/*.rs").unwrap().base(),
+            Path::new(".")
+        );
+        assert_eq!(Glob::new("./*.rs").unwrap().base(), Path::new("."));
+        assert!(Glob::new("/var/log/*.log").unwrap().is_shallow());
+        assert!(Glob::new("*.rs").unwrap().matches("./main.rs"));
+        assert!(!Glob::new("src/**/*.rs").unwrap().is_shallow());
+    }
+
+    #[test]
+    fn rejects_malformed_patterns() {
+        assert!(Glob::new("src/[ab").is_err());
+        assert!(Glob::new("src/{a,b").is_err());
+        assert!(Glob::new("").is_err());
+    }
+}
@@ -0,0 +1,233 @@
+use std::{
+    collections::{hash_map::DefaultHasher, BTreeMap},
+    fs::{self, File},
+    hash::Hasher as _,
+    io::{self, Read as _},
+    path::{Path, PathBuf},
+    sync::{
+        mpsc::{self, RecvTimeoutError},
+        Arc, Mutex, MutexGuard,
+    },
+    thread,
+    time::{Duration, SystemTime},
+};
+
+use notify::{
+    event::{CreateKind, DataChange, ModifyKind, RemoveKind},
+    EventKind,
+};
+
+use crate::{backend::Source, state::State, Backend, Error, EventSink, WatchBackend};
+
+/// A polling back-end that compares the content of small files as well as their
+/// modification time and size, see
+/// [`WatcherBuilder::poll_hashing`](crate::WatcherBuilder::poll_hashing).
+pub(crate) struct HashPoller {
+    roots: SharedRoots,
+    max_size: u64,
+    /// Stops the scanning thread when the poller is dropped.
+    _stop: mpsc::Sender<()>,
+}
+
+type SharedRoots = Arc<Mutex<BTreeMap<PathBuf, Root>>>;
+
+struct Root {
+    recursive: bool,
+    snapshot: BTreeMap<PathBuf, Stamp>,
+}
+
+/// What a poller remembers about a path between two scans.
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) struct Stamp {
+    pub(crate) is_dir: bool,
+    pub(crate) len: u64,
+    pub(crate) modified: Option<SystemTime>,
+    /// The hash of the file's content, if it is a file no larger than the size limit.
+    pub(crate) hash: Option<u64>,
+}
+
+impl HashPoller {
+    /// Starts scanning the watched paths every `interval`, hashing the files of up to
+    /// `max_size` bytes.
+    pub(crate) fn new(sink: EventSink, interval: Duration, max_size: u64) -> Result<Self, Error> {
+        let roots = SharedRoots::default();
+        let (stop, stopped) = mpsc::channel();
+
+        let scanned = Arc::clone(&roots);
+        thread::Builder::new()
+            .name("watchit-poller".to_string())
+            .spawn(move || {
+                while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(interval) {
+                    scan(&scanned, &sink, max_size);
+                }
+            })
+            .map_err(Error::io)?;
+
+        tracing::debug!(
+            "Polling every {:?}, hashing files of up to {} bytes",
+            interval,
+            max_size
+        );
+
+        Ok(Self {
+            roots,
+            max_size,
+            _stop: stop,
+        })
+    }
+}
+
+impl WatchBackend for HashPoller {
+    fn watch(&mut self, path: &Path, recursive: bool) -> Result<(), Error> {
+        if fs::metadata(path).is_err() {
+            return Err(Error::path_not_found().add_path(path.to_path_buf()));
+        }
+        let snapshot = snapshot(path, recursive, self.max_size);
+        lock(&self.roots).insert(
+            path.to_path_buf(),
+            Root {
+                recursive,
+                snapshot,
+            },
+        );
+        Ok(())
+    }
+
+    fn unwatch(&mut self, path: &Path) -> Result<(), Error> {
+        match lock(&self.roots).remove(path) {
+            Some(_) => Ok(()),
+            None => Err(Error::watch_not_found().add_path(path.to_path_buf())),
+        }
+    }
+}
+
+/// Scans every root again, reporting the differences to its previous snapshot.
+fn scan(roots: &SharedRoots, sink: &EventSink, max_size: u64) {
+    let mut events = Vec::new();
+    for (path, root) in lock(roots).iter_mut() {
+        let snapshot = snapshot(path, root.recursive, max_size);
+        compare(&root.snapshot, &snapshot, &mut events);
+        root.snapshot = snapshot;
+    }
+    // The lock is released first, as the sink waits for the debouncer.
+    for event in events {
+        sink.send(event);
+    }
+}
+
+/// Adds an event for every path that was created, removed or changed between `old` and `new`.
+pub(crate) fn compare(
+    old: &BTreeMap<PathBuf, Stamp>,
+    new: &BTreeMap<PathBuf, Stamp>,
+    events: &mut Vec<notify::Event>,
+) {
+    for (path, stamp) in new {
+        let kind = match old.get(path) {
+            None if stamp.is_dir => EventKind::Create(CreateKind::Folder),
+            None => EventKind::Create(CreateKind::File),
+            Some(previous) if previous.len != stamp.len || previous.modified != stamp.modified => {
+                EventKind::Modify(ModifyKind::Data(DataChange::Any))
+            }
+            // Only the content tells, as on file systems with coarse timestamps.
+            Some(previous) if previous.hash != stamp.hash => {
+                EventKind::Modify(ModifyKind::Data(DataChange::Content))
+            }
+            Some(_) => continue,
+        };
+        events.push(notify::Event::new(kind).add_path(path.clone()));
+    }
+    for (path, stamp) in old {
+        if !new.contains_key(path) {
+            let kind = if stamp.is_dir {
+                RemoveKind::Folder
+            } else {
+                RemoveKind::File
+            };
+            events.push(notify::Event::new(EventKind::Remove(kind)).add_path(path.clone()));
+        }
+    }
+}
+
+/// Returns the stamps of `path` and, for a directory, of its entries, of all its descendants
+/// if `recursive` is set.
+fn snapshot(path: &Path, recursive: bool, max_size: u64) -> BTreeMap<PathBuf, Stamp> {
+    let mut snapshot = BTreeMap::new();
+    let Ok(metadata) = fs::metadata(path) else {
+        return snapshot;
+    };
+    let mut directories = Vec::new();
+    if metadata.is_dir() {
+        directories.push(path.to_path_buf());
+    }
+    snapshot.insert(path.to_path_buf(), stamp(path, &metadata, max_size));
+
+    while let Some(directory) = directories.pop() {
+        let Ok(entries) = fs::read_dir(&directory) else {
+            continue;
+        };
+        for entry in entries.flatten() {
+            // Symbolic links are not followed, so that a link cycle ends the scan.
+            let Ok(metadata) = entry.metadata() else {
+                continue;
+            };
+            let path = entry.path();
+            if recursive && metadata.is_dir() {
+                directories.push(path.clone());
+            }
+            let stamp = stamp(&path, &metadata, max_size);
+            snapshot.insert(path, stamp);
+        }
+    }
+    snapshot
+}
+
+fn stamp(path: &Path, metadata: &fs::Metadata, max_size: u64) -> Stamp {
+    let hash = (metadata.is_file() && metadata.len() <= max_size)
+        .then(|| hash(path).ok())
+        .flatten();
+    Stamp {
+        is_dir: metadata.is_dir(),
+        len: metadata.len(),
+        modified: metadata.modified().ok(),
+        hash,
+    }
+}
+
+fn hash(path: &Path) -> io::Result<u64> {
+    let mut file = File::open(path)?;
+    let mut hasher = DefaultHasher::new();
+    let mut buffer = [0; 8192];
+    loop {
+        let read = file.read(&mut buffer)?;
+        if read == 0 {
+            return Ok(hasher.finish());
+        }
+        hasher.write(&buffer[..read]);
+    }
+}
+
+fn lock(roots: &SharedRoots) -> MutexGuard<'_, BTreeMap<PathBuf, Root>> {
+    roots
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+impl State {
+    /// Returns the back-end polling every `interval`, the way the watcher polls.
+    pub(crate) fn poll_source(&self, interval: Duration) -> Source {
+        Source::from(Backend::Poll { interval }).hashing(self.poll_hashing)
+    }
+}
+
+impl Source {
+    /// Turns a polling back-end into one that hashes the files of up to `max_size` bytes,
+    /// if a limit is set.
+    pub(crate) fn hashing(self, max_size: Option<u64>) -> Self {
+        match (self, max_size) {
+            (Self::Builtin(Backend::Poll { interval }), Some(max_size)) => {
+                Self::HashPoll { interval, max_size }
+            }
+            (source, _) => source,
+        }
+    }
+}
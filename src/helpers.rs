@@ -0,0 +1,175 @@
+use std::path::{Path, PathBuf};
+
+use notify::{RecursiveMode, Watcher as _};
+
+use crate::{state::State, DebouncedEvent, Error, WatchInfo};
+
+/// A directory watched on behalf of watches that need to see changes next to their path,
+/// such as pending watches waiting for their file to appear.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct HelperDir {
+    /// How many watches currently rely on the directory.
+    refs: usize,
+    /// How many of those watches need everything below the directory as well.
+    recursive: usize,
+    /// The mode the directory is registered with the backend in, if it is registered. It is
+    /// not registered while a direct watch already reports the changes it is needed for.
+    registered: Option<RecursiveMode>,
+}
+
+impl HelperDir {
+    /// The mode that satisfies every watch relying on the directory.
+    fn mode(&self) -> RecursiveMode {
+        if self.recursive > 0 {
+            RecursiveMode::Recursive
+        } else {
+            RecursiveMode::NonRecursive
+        }
+    }
+}
+
+impl State {
+    /// Starts watching the children of `dir`, or everything below it if `mode` is recursive,
+    /// on behalf of another watch.
+    pub(crate) fn acquire_helper(&mut self, dir: &Path, mode: RecursiveMode) -> Result<(), Error> {
+        let previous = self.helpers.get(dir).cloned();
+        let mut helper = previous.clone().unwrap_or_default();
+        helper.refs += 1;
+        if mode == RecursiveMode::Recursive {
+            helper.recursive += 1;
+        }
+        self.helpers.insert(dir.to_path_buf(), helper);
+
+        if let Err(error) = self.sync_helper(dir) {
+            match previous {
+                Some(previous) => self.helpers.insert(dir.to_path_buf(), previous),
+                None => self.helpers.remove(dir),
+            };
+            return Err(error);
+        }
+
+        Ok(())
+    }
+
+    /// Stops watching `dir` on behalf of a watch that was acquired with `mode`, unregistering
+    /// it once no watch relies on it any more.
+    pub(crate) fn release_helper(&mut self, dir: &Path, mode: RecursiveMode) {
+        let Some(helper) = self.helpers.get_mut(dir) else {
+            return;
+        };
+
+        helper.refs -= 1;
+        if mode == RecursiveMode::Recursive {
+            helper.recursive -= 1;
+        }
+        if helper.refs > 0 {
+            if let Err(error) = self.sync_helper(dir) {
+                tracing::debug!("Failed to re-watch {}: {}", dir.display(), error);
+                self.report(error);
+            }
+            return;
+        }
+
+        let registered = helper.registered;
+        self.helpers.remove(dir);
+
+        if registered.is_some() {
+            if let Err(error) = self.debouncer.watcher().unwatch(dir) {
+                tracing::debug!("Failed to release {}: {}", dir.display(), error);
+                self.report(error);
+            }
+        }
+    }
+
+    /// Registers helper directories that were covered by a direct watch which has since
+    /// been removed.
+    pub(crate) fn refresh_helpers(&mut self) {
+        let dirs: Vec<PathBuf> = self.helpers.keys().cloned().collect();
+        for dir in dirs {
+            if let Err(error) = self.sync_helper(&dir) {
+                tracing::debug!("Failed to watch {}: {}", dir.display(), error);
+                self.report(error);
+            }
+        }
+    }
+
+    /// Brings the backend registration of the helper directory `dir` in line with what the
+    /// watches relying on it need.
+    fn sync_helper(&mut self, dir: &Path) -> Result<(), Error> {
+        let Some(helper) = self.helpers.get(dir).cloned() else {
+            return Ok(());
+        };
+
+        let mode = helper.mode();
+        let covered = match mode {
+            RecursiveMode::Recursive => self.covers_everything_below(dir),
+            RecursiveMode::NonRecursive => self.covers_children_of(dir),
+        };
+
+        let registered = match (helper.registered, covered) {
+            // The direct watch shares the backend registration, so it must not be removed.
+            (_, true) => None,
+            (Some(registered), false) if registered == mode => return Ok(()),
+            (registered, false) => {
+                if registered == Some(RecursiveMode::Recursive) {
+                    let _ = self.debouncer.watcher().unwatch(dir);
+                }
+                self.debouncer
+                    .watcher()
+                    .watch(dir, mode)
+                    .map_err(|error| self.backend_error(dir, error))?;
+                Some(mode)
+            }
+        };
+
+        if let Some(helper) = self.helpers.get_mut(dir) {
+            helper.registered = registered;
+        }
+
+        Ok(())
+    }
+
+    /// Returns `true` if the event was only seen because of a helper directory and is not
+    /// relevant to any watch.
+    pub(crate) fn is_helper_noise(&self, event: &DebouncedEvent) -> bool {
+        !self.helpers.is_empty()
+            && event.paths.iter().all(|path| {
+                !self.covers(path)
+                    && !self.is_expected(path)
+                    && self.helpers.iter().any(|(dir, helper)| {
+                        path.parent() == Some(dir.as_path())
+                            || (helper.recursive > 0 && path.starts_with(dir))
+                    })
+            })
+    }
+
+    /// Returns `true` if events for `path` are reported by a direct or glob watch.
+    pub(crate) fn covers(&self, path: &Path) -> bool {
+        self.watches.values().any(|watch| watch.reports(path)) || self.is_glob_match(path)
+    }
+
+    /// Returns `true` if `watch` reports events for `path`.
+    pub(crate) fn watch_reports(&self, watch: &WatchInfo, path: &Path) -> bool {
+        if watch.glob {
+            self.glob_matches(&watch.path, path)
+        } else if watch.pending {
+            self.is_pending_path_of(&watch.path, path)
+        } else {
+            watch.reports(path)
+        }
+    }
+
+    /// Returns `true` if events for the children of `dir` are reported by a direct watch.
+    pub(crate) fn covers_children_of(&self, dir: &Path) -> bool {
+        self.watches
+            .values()
+            .any(|watch| watch.reports_children_of(dir))
+    }
+
+    /// Returns `true` if events for everything below `dir` are reported by a direct watch.
+    fn covers_everything_below(&self, dir: &Path) -> bool {
+        self.watches
+            .values()
+            .any(|watch| watch.reports_everything_below(dir))
+    }
+}
@@ -0,0 +1,382 @@
+use std::{collections::BTreeMap, fmt, iter::Peekable, str::Chars};
+
+use crate::Error;
+
+/// A JSON value, used to convert the crate's types to and from JSON without depending on a
+/// serialization framework.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Value {
+    Null,
+    Bool(bool),
+    /// A number, kept as written so that integers of any size survive a round trip.
+    Number(String),
+    String(String),
+    Array(Vec<Value>),
+    Object(BTreeMap<String, Value>),
+}
+
+/// How deeply arrays and objects may be nested in a parsed document, so that hostile input
+/// cannot overflow the stack of the thread parsing it.
+pub(crate) const MAX_DEPTH: usize = 128;
+
+impl Value {
+    /// Parses a JSON document.
+    pub(crate) fn parse(text: &str) -> Result<Self, Error> {
+        let mut parser = Parser {
+            chars: text.chars().peekable(),
+            depth: 0,
+        };
+        let value = parser.value()?;
+        parser.skip_whitespace();
+        match parser.chars.next() {
+            None => Ok(value),
+            Some(c) => Err(invalid(&format!("unexpected {c:?} after the document"))),
+        }
+    }
+
+    pub(crate) fn object(fields: impl IntoIterator<Item = (&'static str, Value)>) -> Self {
+        Self::Object(
+            fields
+                .into_iter()
+                .map(|(key, value)| (key.to_string(), value))
+                .collect(),
+        )
+    }
+
+    pub(crate) fn number(number: impl fmt::Display) -> Self {
+        Self::Number(number.to_string())
+    }
+
+    pub(crate) fn string(string: impl Into<String>) -> Self {
+        Self::String(string.into())
+    }
+
+    /// Returns `value` or `null`.
+    pub(crate) fn optional<T>(value: Option<T>, to_json: impl FnOnce(T) -> Value) -> Self {
+        value.map_or(Self::Null, to_json)
+    }
+
+    /// Returns the field `key` of an object, or an error naming the missing field.
+    pub(crate) fn field(&self, key: &str) -> Result<&Value, Error> {
+        match self {
+            Self::Object(fields) => fields
+                .get(key)
+                .ok_or_else(|| invalid(&format!("missing field {key:?}"))),
+            _ => Err(invalid(&format!("expected an object with field {key:?}"))),
+        }
+    }
+
+    pub(crate) fn as_str(&self) -> Result<&str, Error> {
+        match self {
+            Self::String(string) => Ok(string),
+            _ => Err(invalid("expected a string")),
+        }
+    }
+
+    pub(crate) fn as_bool(&self) -> Result<bool, Error> {
+        match self {
+            Self::Bool(value) => Ok(*value),
+            _ => Err(invalid("expected a boolean")),
+        }
+    }
+
+    pub(crate) fn as_u64(&self) -> Result<u64, Error> {
+        match self {
+            Self::Number(number) => number
+                .parse()
+                .map_err(|_| invalid(&format!("expected an unsigned integer, found {number}"))),
+            _ => Err(invalid("expected a number")),
+        }
+    }
+
+    pub(crate) fn as_u128(&self) -> Result<u128, Error> {
+        match self {
+            Self::Number(number) => number
+                .parse()
+                .map_err(|_| invalid(&format!("expected an unsigned integer, found {number}"))),
+            _ => Err(invalid("expected a number")),
+        }
+    }
+
+    pub(crate) fn as_array(&self) -> Result<&[Value], Error> {
+        match self {
+            Self::Array(values) => Ok(values),
+            _ => Err(invalid("expected an array")),
+        }
+    }
+
+    /// Returns `None` for `null`, and converts any other value with `from_json`.
+    pub(crate) fn as_optional<'a, T>(
+        &'a self,
+        from_json: impl FnOnce(&'a Value) -> Result<T, Error>,
+    ) -> Result<Option<T>, Error> {
+        match self {
+            Self::Null => Ok(None),
+            value => from_json(value).map(Some),
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Null => f.write_str("null"),
+            Self::Bool(value) => write!(f, "{value}"),
+            Self::Number(number) => f.write_str(number),
+            Self::String(string) => write_string(f, string),
+            Self::Array(values) => {
+                f.write_str("[")?;
+                for (index, value) in values.iter().enumerate() {
+                    if index > 0 {
+                        f.write_str(",")?;
+                    }
+                    write!(f, "{value}")?;
+                }
+                f.write_str("]")
+            }
+            Self::Object(fields) => {
+                f.write_str("{")?;
+                for (index, (key, value)) in fields.iter().enumerate() {
+                    if index > 0 {
+                        f.write_str(",")?;
+                    }
+                    write_string(f, key)?;
+                    write!(f, ":{value}")?;
+                }
+                f.write_str("}")
+            }
+        }
+    }
+}
+
+fn write_string(f: &mut fmt::Formatter<'_>, string: &str) -> fmt::Result {
+    f.write_str("\"")?;
+    for c in string.chars() {
+        match c {
+            '"' => f.write_str("\\\"")?,
+            '\\' => f.write_str("\\\\")?,
+            '\n' => f.write_str("\\n")?,
+            '\r' => f.write_str("\\r")?,
+            '\t' => f.write_str("\\t")?,
+            c if u32::from(c) < 0x20 => write!(f, "\\u{:04x}", u32::from(c))?,
+            c => write!(f, "{c}")?,
+        }
+    }
+    f.write_str("\"")
+}
+
+fn invalid(message: &str) -> Error {
+    Error::generic(&format!("invalid JSON: {message}"))
+}
+
+struct Parser<'a> {
+    chars: Peekable<Chars<'a>>,
+    /// How many arrays and objects contain the value being parsed.
+    depth: usize,
+}
+
+impl Parser<'_> {
+    fn value(&mut self) -> Result<Value, Error> {
+        self.skip_whitespace();
+        match self.chars.peek() {
+            Some('n') => self.literal("null", Value::Null),
+            Some('t') => self.literal("true", Value::Bool(true)),
+            Some('f') => self.literal("false", Value::Bool(false)),
+            Some('"') => self.string().map(Value::String),
+            Some('[') => self.nested(Self::array),
+            Some('{') => self.nested(Self::object),
+            Some(c) if *c == '-' || c.is_ascii_digit() => self.number(),
+            Some(c) => Err(invalid(&format!("unexpected {c:?}"))),
+            None => Err(invalid("unexpected end of input")),
+        }
+    }
+
+    /// Parses an array or object with `parse`, unless it would be nested too deeply.
+    fn nested(&mut self, parse: fn(&mut Self) -> Result<Value, Error>) -> Result<Value, Error> {
+        if self.depth == MAX_DEPTH {
+            return Err(invalid(&format!("nested deeper than {MAX_DEPTH} levels")));
+        }
+        self.depth += 1;
+        let value = parse(self);
+        self.depth -= 1;
+        value
+    }
+
+    fn literal(&mut self, literal: &str, value: Value) -> Result<Value, Error> {
+        for expected in literal.chars() {
+            if self.chars.next() != Some(expected) {
+                return Err(invalid(&format!("expected {literal}")));
+            }
+        }
+        Ok(value)
+    }
+
+    fn number(&mut self) -> Result<Value, Error> {
+        let mut number = String::new();
+        while let Some(c) = self
+            .chars
+            .next_if(|c| c.is_ascii_digit() || matches!(c, '-' | '+' | '.' | 'e' | 'E'))
+        {
+            number.push(c);
+        }
+        if number.parse::<f64>().is_err() {
+            return Err(invalid(&format!("malformed number {number}")));
+        }
+        Ok(Value::Number(number))
+    }
+
+    fn string(&mut self) -> Result<String, Error> {
+        self.chars.next();
+        let mut string = String::new();
+        loop {
+            match self.chars.next() {
+                Some('"') => return Ok(string),
+                Some('\\') => match self.chars.next() {
+                    Some('"') => string.push('"'),
+                    Some('\\') => string.push('\\'),
+                    Some('/') => string.push('/'),
+                    Some('b') => string.push('\u{8}'),
+                    Some('f') => string.push('\u{c}'),
+                    Some('n') => string.push('\n'),
+                    Some('r') => string.push('\r'),
+                    Some('t') => string.push('\t'),
+                    Some('u') => string.push(self.unicode_escape()?),
+                    _ => return Err(invalid("malformed escape sequence")),
+                },
+                Some(c) => string.push(c),
+                None => return Err(invalid("unterminated string")),
+            }
+        }
+    }
+
+    fn unicode_escape(&mut self) -> Result<char, Error> {
+        let high = self.hex4()?;
+        if !(0xD800..0xDC00).contains(&high) {
+            return char::from_u32(high).ok_or_else(|| invalid("invalid unicode escape"));
+        }
+        if self.chars.next() != Some('\\') || self.chars.next() != Some('u') {
+            return Err(invalid("unpaired surrogate"));
+        }
+        let low = self.hex4()?;
+        if !(0xDC00..0xE000).contains(&low) {
+            return Err(invalid("unpaired surrogate"));
+        }
+        char::from_u32(0x10000 + ((high - 0xD800) << 10) + (low - 0xDC00))
+            .ok_or_else(|| invalid("invalid unicode escape"))
+    }
+
+    fn hex4(&mut self) -> Result<u32, Error> {
+        let mut code = 0;
+        for _ in 0..4 {
+            let digit = self
+                .chars
+                .next()
+                .and_then(|c| c.to_digit(16))
+                .ok_or_else(|| invalid("malformed unicode escape"))?;
+            code = code * 16 + digit;
+        }
+        Ok(code)
+    }
+
+    fn array(&mut self) -> Result<Value, Error> {
+        self.chars.next();
+        let mut values = Vec::new();
+        self.skip_whitespace();
+        if self.chars.next_if_eq(&']').is_some() {
+            return Ok(Value::Array(values));
+        }
+        loop {
+            values.push(self.value()?);
+            self.skip_whitespace();
+            match self.chars.next() {
+                Some(',') => continue,
+                Some(']') => return Ok(Value::Array(values)),
+                _ => return Err(invalid("expected ',' or ']'")),
+            }
+        }
+    }
+
+    fn object(&mut self) -> Result<Value, Error> {
+        self.chars.next();
+        let mut fields = BTreeMap::new();
+        self.skip_whitespace();
+        if self.chars.next_if_eq(&'}').is_some() {
+            return Ok(Value::Object(fields));
+        }
+        loop {
+            self.skip_whitespace();
+            if self.chars.peek() != Some(&'"') {
+                return Err(invalid("expected a field name"));
+            }
+            let key = self.string()?;
+            self.skip_whitespace();
+            if self.chars.next() != Some(':') {
+                return Err(invalid("expected ':'"));
+            }
+            fields.insert(key, self.value()?);
+            self.skip_whitespace();
+            match self.chars.next() {
+                Some(',') => continue,
+                Some('}') => return Ok(Value::Object(fields)),
+                _ => return Err(invalid("expected ',' or '}'")),
+            }
+        }
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.chars.next_if(|c| c.is_ascii_whitespace()).is_some() {}
+    }
+}
+
+#[cfg(test)]
+/// This module contains tests for the JSON conversion.
+///
+/// The `round_trip` test verifies that parsing the output of a value yields the same value.
+///
+/// The `rejects_malformed_documents` test verifies that truncated and trailing input is
+/// reported as an error.
+///
+/// The `rejects_deep_nesting` test verifies that documents nested beyond the limit are
+/// reported as an error instead of overflowing the stack.
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let value = Value::object([
+            ("null", Value::Null),
+            ("flag", Value::Bool(true)),
+            ("big", Value::number(u128::MAX)),
+            ("text", Value::string("quote \" tab \t \u{1} é")),
+            (
+                "list",
+                Value::Array(vec![Value::number(-1.5), Value::Array(Vec::new())]),
+            ),
+        ]);
+        assert_eq!(Value::parse(&value.to_string()).unwrap(), value);
+        assert_eq!(
+            Value::parse(r#" { "a" : [ 1 , "😀" ] } "#).unwrap(),
+            Value::object([(
+                "a",
+                Value::Array(vec![Value::number(1), Value::string("😀")])
+            )])
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_documents() {
+        assert!(Value::parse("").is_err());
+        assert!(Value::parse("[1,").is_err());
+        assert!(Value::parse(r#"{"a" 1}"#).is_err());
+        assert!(Value::parse("true false").is_err());
+        assert!(Value::parse("\"open").is_err());
+    }
+
+    #[test]
+    fn rejects_deep_nesting() {
+        let nested = format!("{}{}", "[".repeat(MAX_DEPTH), "]".repeat(MAX_DEPTH));
+        assert!(Value::parse(&nested).is_ok());
+        assert!(Value::parse(&"[".repeat(100_000)).is_err());
+        assert!(Value::parse(&"{\"a\":".repeat(100_000)).is_err());
+    }
+}
@@ -0,0 +1,140 @@
+use std::{collections::BTreeSet, fs, path::Path};
+
+use crate::{json::Value, state::State, toml, DebouncedEvent, Error, EventKind};
+
+impl State {
+    /// Attaches the keys that changed to the modifications of structured files reported by
+    /// the watch on `filename`.
+    pub(crate) fn add_key_tracking(&mut self, filename: &Path) -> Result<(), Error> {
+        let Some(watch) = self.watches.get_mut(filename) else {
+            return Err(Error::watch_not_found().add_path(filename.to_path_buf()));
+        };
+        watch.key_changes = true;
+
+        // Files below a directory are parsed when they are created or first change.
+        if let Some(document) = read_document(filename) {
+            self.documents.insert(filename.to_path_buf(), document);
+        }
+
+        Ok(())
+    }
+
+    /// Forgets the parsed documents of `filename` and everything below it.
+    pub(crate) fn forget_documents(&mut self, filename: &Path) {
+        self.documents.retain(|path, _| !path.starts_with(filename));
+    }
+
+    /// Returns the JSON pointers of the keys that changed since the file an event reports
+    /// was last parsed, and remembers the new version.
+    ///
+    /// A version that does not parse is skipped, so that the next edit is compared to the
+    /// last version that did.
+    pub(crate) fn changed_keys_for(&mut self, event: &DebouncedEvent) -> Vec<String> {
+        let Some(path) = event.paths.last() else {
+            return Vec::new();
+        };
+        let tracked = self
+            .watches
+            .values()
+            .any(|watch| watch.key_changes && self.watch_reports(watch, path));
+        if !tracked {
+            return Vec::new();
+        }
+
+        let kind = EventKind::of(event);
+        if kind == EventKind::Removed {
+            self.documents.remove(path);
+            return Vec::new();
+        }
+        let Some(document) = read_document(path) else {
+            return Vec::new();
+        };
+        let previous = self.documents.insert(path.clone(), document);
+        match (previous, kind) {
+            (Some(previous), EventKind::Modified) => {
+                let mut keys = Vec::new();
+                changed_pointers(
+                    &previous,
+                    &self.documents[path],
+                    &mut String::new(),
+                    &mut keys,
+                );
+                keys
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    /// Returns `true` if a watch attaches the keys that changed to its events.
+    pub(crate) fn has_key_tracking(&self) -> bool {
+        self.watches.values().any(|watch| watch.key_changes)
+    }
+}
+
+/// Parses the file at `path` as TOML if its extension is `toml`, and as JSON otherwise.
+fn read_document(path: &Path) -> Option<Value> {
+    let text = fs::read_to_string(path).ok()?;
+    let is_toml = path
+        .extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("toml"));
+    let parsed = if is_toml {
+        toml::parse(&text)
+    } else {
+        Value::parse(&text)
+    };
+    parsed
+        .map_err(|error| tracing::debug!("Failed to parse {}: {}", path.display(), error))
+        .ok()
+}
+
+/// Adds the JSON pointer of every value that differs between `old` and `new` to `keys`.
+///
+/// Objects are compared key by key, so a changed value is reported with its full pointer,
+/// such as `/server/port`. Added and removed keys are reported as well. Any other values,
+/// arrays included, are compared as a whole.
+fn changed_pointers(old: &Value, new: &Value, pointer: &mut String, keys: &mut Vec<String>) {
+    let (Value::Object(old_fields), Value::Object(new_fields)) = (old, new) else {
+        if old != new {
+            keys.push(pointer.clone());
+        }
+        return;
+    };
+    let names: BTreeSet<&String> = old_fields.keys().chain(new_fields.keys()).collect();
+    for name in names {
+        let len = pointer.len();
+        pointer.push('/');
+        pointer.push_str(&name.replace('~', "~0").replace('/', "~1"));
+        match (old_fields.get(name), new_fields.get(name)) {
+            (Some(old), Some(new)) => changed_pointers(old, new, pointer, keys),
+            _ => keys.push(pointer.clone()),
+        }
+        pointer.truncate(len);
+    }
+}
+
+#[cfg(test)]
+/// This module contains tests for the key-level comparison.
+///
+/// The `reports_changed_pointers` test verifies that changed, added and removed keys are
+/// reported with their escaped JSON pointers, and that arrays are compared as a whole.
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reports_changed_pointers() {
+        let old = Value::parse(
+            r#"{"server": {"port": 80, "hosts": ["a"]}, "a/b": 1, "same": true, "gone": 0}"#,
+        )
+        .unwrap();
+        let new = Value::parse(
+            r#"{"server": {"port": 81, "hosts": ["a", "b"]}, "a/b": 2, "same": true, "new": 0}"#,
+        )
+        .unwrap();
+        let mut keys = Vec::new();
+        changed_pointers(&old, &new, &mut String::new(), &mut keys);
+        assert_eq!(
+            keys,
+            ["/a~1b", "/gone", "/new", "/server/hosts", "/server/port"]
+        );
+    }
+}
@@ -14,7 +14,11 @@
 //! Create and instance of the Watcher with a callback:
 //!
 //! ```Rust
-//! let mut watcher = Watcher::new(|event| println!(event));
+//! let watcher = Watcher::with_event_handler(|result: EventResult| {
+//!     for event in result.unwrap_or_default() {
+//!         println!("{event}");
+//!     }
+//! })?;
 //! ```
 //!
 //! Add a file to be watched:
@@ -23,98 +27,107 @@
 //! watcher.watch("file.txt");
 //! ```
 
-use std::{path::Path, time::Duration};
+mod backend;
+mod builder;
+mod cancel;
+mod change;
+mod checksum;
+mod close;
+mod config;
+mod content;
+mod depth;
+mod diff;
+mod dispatch;
+mod dotenv;
+mod encoding;
+mod error;
+mod event;
+mod extension;
+#[cfg(all(feature = "fanotify", target_os = "linux"))]
+mod fanotify;
+mod file_id;
+mod filter;
+mod flush;
+#[cfg(target_os = "macos")]
+mod fsevents;
+mod gitignore;
+mod glob;
+mod hashpoll;
+mod helpers;
+mod json;
+mod keys;
+mod middleware;
+mod network;
+mod panic;
+mod pending;
+mod persist;
+mod poll;
+mod pool;
+mod rate;
+mod raw;
+mod read;
+#[cfg(feature = "ssh")]
+mod remote;
+mod rename;
+mod replace;
+mod scan;
+mod settle;
+mod size;
+mod snapshot;
+mod state;
+mod strategy;
+mod stream;
+mod summary;
+mod symlink;
+mod tags;
+mod tail;
+mod toml;
+mod tree;
+mod validate;
+mod verify;
+mod wait;
+mod watch;
+mod watcher;
+#[cfg(windows)]
+mod windows;
 
-pub use notify::Error;
-use notify::{RecursiveMode, Watcher as _};
-use notify_debouncer_full::{self, new_debouncer, DebounceEventHandler};
-use tracing;
+use std::time::Duration;
 
-/// A watcher that monitors files for changes and debounces events.
-///
-/// The `Watcher` struct is responsible for setting up a file watcher and debouncing
-/// file change events. It uses the `notify` crate to watch for file changes, and the
-/// `notify-debouncer-full` crate to debounce those events.
-pub struct Watcher {
-    debouncer: notify_debouncer_full::Debouncer<
-        notify::RecommendedWatcher,
-        notify_debouncer_full::FileIdMap,
-    >,
-}
+pub use backend::{Backend, EventSink, FsEventsFlags, WatchBackend};
+pub use builder::{EventFilter, EventPredicate, WatcherBuilder};
+pub use cancel::CancellationToken;
+pub use change::ChangeSummary;
+pub use checksum::{ChecksumAlgorithm, ChecksumChange, Digest};
+pub use config::{ConfigWatcher, ParseError};
+pub use diff::{DiffLine, TextDiff};
+pub use dispatch::PauseMode;
+pub use dotenv::{EnvChange, EnvWatcher};
+pub use encoding::Encoding;
+pub use error::Error;
+pub use event::{Event, EventHandler, EventKind, EventKindMask, EventResult, FileType, Metadata};
+pub use file_id::FileId;
+pub use filter::FilterSet;
+pub use glob::Glob;
+pub use middleware::EventMiddleware;
+pub use notify_debouncer_full::{DebounceEventHandler, DebounceEventResult, DebouncedEvent};
+#[cfg(feature = "ssh")]
+pub use remote::SshBackend;
+pub use size::SizeDelta;
+pub use strategy::DebounceStrategy;
+pub use stream::{
+    AsyncWatcher, EventStream, Events, HandlerFuture, Next, Overflow, Shutdown, Spawn,
+};
+pub use tags::EventTags;
+pub use tail::Tailer;
+pub use tree::{IndexEntry, TreeWatcher};
+pub use validate::Validation;
+pub use wait::{wait_for_change, wait_for_change_async};
+pub use watch::{WatchGuard, WatchInfo, WatchMode, WatchOptions, WatchReport};
+pub use watcher::Watcher;
 
-impl Watcher {
-    /// Creates a new file watcher with the provided debounce event handler.
-    ///
-    /// The file watcher will debounce events for 2 seconds before triggering the provided handler.
-    /// This helps to reduce the number of events that need to be processed, especially when
-    /// many files are being watched and modified in quick succession.
-    ///
-    /// # Arguments
-    /// * `handler` - The debounce event handler to call when a file change is detected.
-    ///
-    /// # Returns
-    /// A new instance of the file watcher.
-    pub fn new(handler: impl DebounceEventHandler) -> Self {
-        let result = Self {
-            debouncer: new_debouncer(Duration::from_secs(2), None, handler).unwrap(),
-        };
-        tracing::debug!("Created new file watcher");
-        result
-    }
+/// The debounce duration used by [`Watcher::new`].
+pub const DEFAULT_DEBOUNCE: Duration = Duration::from_secs(2);
 
-    /// Watches the specified file for changes.
-    ///
-    /// This function sets up a file watcher to monitor the specified file for any changes.
-    /// When a change is detected, the file is added to the debouncer's cache to be processed later.
-    /// The function returns a `Result` indicating whether the file watcher was successfully set up.
-    ///
-    /// # Arguments
-    /// * `filename` - The path to the file to be watched.
-    ///
-    /// # Returns
-    /// A `Result` containing either an empty `()` value on success, or an `Error` on failure.
-    pub fn watch(&mut self, filename: &str) -> Result<(), Error> {
-        let result = self
-            .debouncer
-            .watcher()
-            .watch(Path::new(filename), RecursiveMode::NonRecursive);
-
-        self.debouncer
-            .cache()
-            .add_root(Path::new(filename), RecursiveMode::NonRecursive);
-
-        tracing::debug!("Watching file for changes: {}", filename);
-
-        result
-    }
-}
-
-#[cfg(test)]
-/// This module contains tests for the functionality of the `Watcher` struct.
-///
-/// The `it_works` test verifies that the `Watcher` correctly detects changes to a file.
-/// It creates a file, sets up a `Watcher` to monitor the file, and then checks that the
-/// `Watcher` correctly reports the file change event.
-mod tests {
-    use std::{fs::File, io::Write as _, thread::sleep};
-
-    use super::*;
-
-    #[test]
-    fn it_works() {
-        static mut FILE_CHANGED: bool = false;
-        let mut file = File::create(Path::new("test.testfile")).unwrap();
-        let mut watcher = Watcher::new(move |event| {
-            tracing::debug!("Event: {:#?}", event);
-            unsafe { FILE_CHANGED = true };
-        });
-        watcher.watch("test.testfile").unwrap();
-        assert_eq!(unsafe { FILE_CHANGED }, false);
-        file.write_all(b"test").unwrap();
-        file.flush().unwrap();
-        drop(file);
-        sleep(Duration::from_secs(4));
-        assert_eq!(unsafe { FILE_CHANGED }, true);
-        std::fs::remove_file(Path::new("test.testfile")).unwrap();
-    }
-}
+/// How often paths on network file systems are polled by default, see
+/// [`WatcherBuilder::network_poll`].
+pub const DEFAULT_NETWORK_POLL: Duration = Duration::from_secs(2);
@@ -0,0 +1,84 @@
+use std::time::SystemTime;
+
+use crate::{
+    event::{Details, EventConfig},
+    DebouncedEvent, Event,
+};
+
+/// A step that every event passes through before it reaches the handlers, see
+/// [`WatcherBuilder::middleware`](crate::WatcherBuilder::middleware).
+///
+/// A middleware can drop an event by returning `None`, or change it, for example to enrich
+/// it with tags, before passing it on. The trait is implemented for closures taking and
+/// returning an [`Event`], so a middleware can be as small as a filter:
+///
+/// ```no_run
+/// use watchit::{Event, WatcherBuilder};
+///
+/// let watcher = WatcherBuilder::new()
+///     .middleware(|event: Event| {
+///         let temporary = event.path.extension().is_some_and(|extension| extension == "tmp");
+///         (!temporary).then_some(event)
+///     })
+///     .middleware(|mut event: Event| {
+///         event.tags.push("audited".to_string());
+///         Some(event)
+///     })
+///     .build_channel();
+/// ```
+pub trait EventMiddleware: Send + 'static {
+    /// Processes an event, returning the event to pass on, or `None` to drop it.
+    fn process(&mut self, event: Event) -> Option<Event>;
+}
+
+impl<F> EventMiddleware for F
+where
+    F: FnMut(Event) -> Option<Event> + Send + 'static,
+{
+    fn process(&mut self, event: Event) -> Option<Event> {
+        (self)(event)
+    }
+}
+
+/// The middlewares of a watcher, in the order they were installed.
+#[derive(Default)]
+pub(crate) struct Chain {
+    middlewares: Vec<Box<dyn EventMiddleware>>,
+}
+
+impl Chain {
+    pub(crate) fn push(&mut self, middleware: impl EventMiddleware) {
+        self.middlewares.push(Box::new(middleware));
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.middlewares.is_empty()
+    }
+
+    /// Passes each event through every middleware, dropping the events a middleware drops
+    /// and attaching the result to the rest.
+    pub(crate) fn apply(
+        &mut self,
+        events: Vec<(DebouncedEvent, Details)>,
+        config: &EventConfig,
+    ) -> Vec<(DebouncedEvent, Details)> {
+        let now = SystemTime::now();
+        events
+            .into_iter()
+            .filter_map(|(event, details)| {
+                let converted = Event::from_debounced(&event, details.clone(), config, now)?;
+                let processed = self
+                    .middlewares
+                    .iter_mut()
+                    .try_fold(converted, |converted, middleware| {
+                        middleware.process(converted)
+                    })?;
+                let details = Details {
+                    processed: Some(Box::new(processed)),
+                    ..details
+                };
+                Some((event, details))
+            })
+            .collect()
+    }
+}
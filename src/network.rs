@@ -0,0 +1,124 @@
+use std::{path::Path, time::Duration};
+
+use crate::{backend::Source, state::State, Backend};
+
+impl State {
+    /// Returns how often `filename` is polled instead of watched natively, if it lives on a
+    /// network or FUSE file system, on which native notifications miss the changes made
+    /// elsewhere.
+    ///
+    /// Returns `None` if `filename` is left to the back-end.
+    pub(crate) fn network_poll_interval(&self, filename: &Path) -> Option<Duration> {
+        // A custom back-end or an explicit choice of polling knows better.
+        let native = matches!(&self.backend, Source::Builtin(backend) if !matches!(backend, Backend::Poll { .. }));
+        let (Some(interval), true) = (self.network_poll, native) else {
+            return None;
+        };
+        let filesystem = filesystem_of(filename)?;
+
+        tracing::warn!(
+            "{} is on a {} file system, which does not report all changes, polling it every {:?}",
+            filename.display(),
+            filesystem,
+            interval
+        );
+
+        Some(interval)
+    }
+}
+
+/// Returns the type of the network or FUSE file system that `path` lives on, or `None` if
+/// it lives on a local file system, or the type cannot be told.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub(crate) fn filesystem_of(path: &Path) -> Option<String> {
+    // The magic numbers of `statfs(2)`.
+    const FILESYSTEMS: &[(u32, &str)] = &[
+        (0x6969, "nfs"),
+        (0x517b, "smb"),
+        (0xff53_4d42, "cifs"),
+        (0xfe53_4d42, "smb2"),
+        (0x6573_5546, "fuse"),
+        (0x0102_1997, "9p"),
+        (0x5346_414f, "afs"),
+        (0x7375_7245, "coda"),
+        (0x00c3_6400, "ceph"),
+    ];
+
+    let stat = statfs(path)?;
+    // The type of `f_type` depends on the architecture, and some magic numbers overflow it.
+    #[allow(clippy::unnecessary_cast)]
+    let magic = stat.f_type as u32;
+    FILESYSTEMS
+        .iter()
+        .find(|(known, _)| *known == magic)
+        .map(|(_, name)| (*name).to_string())
+}
+
+/// Returns the type of the network or FUSE file system that `path` lives on, or `None` if
+/// it lives on a local file system, or the type cannot be told.
+#[cfg(any(
+    target_os = "macos",
+    target_os = "ios",
+    target_os = "freebsd",
+    target_os = "openbsd",
+    target_os = "dragonfly"
+))]
+pub(crate) fn filesystem_of(path: &Path) -> Option<String> {
+    const FILESYSTEMS: &[&str] = &[
+        "nfs", "smbfs", "afpfs", "webdav", "cifs", "fusefs", "macfuse", "osxfuse", "fuse",
+    ];
+
+    let stat = statfs(path)?;
+    let name: Vec<u8> = stat
+        .f_fstypename
+        .iter()
+        .take_while(|&&byte| byte != 0)
+        .map(|&byte| byte as u8)
+        .collect();
+    let name = String::from_utf8_lossy(&name);
+    FILESYSTEMS
+        .iter()
+        .any(|known| name.starts_with(known))
+        .then(|| name.into_owned())
+}
+
+/// Returns the type of the network or FUSE file system that `path` lives on, or `None` if
+/// it lives on a local file system, or the type cannot be told.
+///
+/// File system types are not detected on this platform, so paths are always left to the
+/// back-end.
+#[cfg(not(any(
+    target_os = "linux",
+    target_os = "android",
+    target_os = "macos",
+    target_os = "ios",
+    target_os = "freebsd",
+    target_os = "openbsd",
+    target_os = "dragonfly"
+)))]
+pub(crate) fn filesystem_of(_path: &Path) -> Option<String> {
+    None
+}
+
+#[cfg(any(
+    target_os = "linux",
+    target_os = "android",
+    target_os = "macos",
+    target_os = "ios",
+    target_os = "freebsd",
+    target_os = "openbsd",
+    target_os = "dragonfly"
+))]
+fn statfs(path: &Path) -> Option<libc::statfs> {
+    use std::{ffi::CString, mem::MaybeUninit, os::unix::ffi::OsStrExt as _};
+
+    let path = CString::new(path.as_os_str().as_bytes()).ok()?;
+    let mut stat = MaybeUninit::<libc::statfs>::uninit();
+    // SAFETY: `path` is a valid C string, and `stat` is only read if `statfs` filled it in.
+    unsafe {
+        if libc::statfs(path.as_ptr(), stat.as_mut_ptr()) != 0 {
+            return None;
+        }
+        Some(stat.assume_init())
+    }
+}
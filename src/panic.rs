@@ -0,0 +1,31 @@
+use std::{
+    any::Any,
+    panic::{self, AssertUnwindSafe},
+};
+
+use crate::Error;
+
+/// Runs a user callback, turning a panic into an [`Error::HandlerPanic`].
+///
+/// The callbacks only ever see their own state behind the panic, which is why asserting
+/// unwind safety is sound: a callback that panicked sees the state it left behind the next
+/// time it is called, just like on any other thread.
+pub(crate) fn isolate(callback: impl FnOnce()) -> Result<(), Error> {
+    panic::catch_unwind(AssertUnwindSafe(callback)).map_err(|payload| {
+        let message = message(payload.as_ref());
+        tracing::error!("Event handler panicked: {}", message);
+        Error::HandlerPanic { message }
+    })
+}
+
+/// Extracts the message of a panic, which is a `&str` or a `String` unless the panic was
+/// started with `panic_any`.
+fn message(payload: &(dyn Any + Send)) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "unknown panic".to_string()
+    }
+}
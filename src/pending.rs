@@ -0,0 +1,155 @@
+use std::path::{Path, PathBuf};
+
+use notify::{
+    event::{EventKind, ModifyKind},
+    RecursiveMode,
+};
+
+use crate::{
+    state::{Served, State},
+    DebouncedEvent, Error, WatchInfo,
+};
+
+/// A watch on a path that did not exist yet when it was registered.
+///
+/// Until the path appears, its parent directory is watched in its place.
+#[derive(Debug, Clone)]
+pub(crate) struct PendingWatch {
+    /// The mode the path is watched with once it exists.
+    mode: RecursiveMode,
+    /// The directory watched while waiting for the path to appear.
+    parent: PathBuf,
+    /// The path as it will be reported in events from the parent watch.
+    expected: PathBuf,
+}
+
+impl State {
+    /// Registers `filename` as a pending watch, watching its parent until it appears.
+    pub(crate) fn add_pending_watch(
+        &mut self,
+        filename: &Path,
+        mode: RecursiveMode,
+    ) -> Result<(), Error> {
+        let (parent, name) = match (filename.parent(), filename.file_name()) {
+            (Some(parent), Some(name)) if parent.as_os_str().is_empty() => (Path::new("."), name),
+            (Some(parent), Some(name)) => (parent, name),
+            _ => return Err(Error::path_not_found().add_path(filename.to_path_buf())),
+        };
+
+        self.acquire_helper(parent, RecursiveMode::NonRecursive)?;
+
+        self.pending.insert(
+            filename.to_path_buf(),
+            PendingWatch {
+                mode,
+                parent: parent.to_path_buf(),
+                expected: parent.join(name),
+            },
+        );
+        self.watches.insert(
+            filename.to_path_buf(),
+            WatchInfo::new(filename.to_path_buf(), mode).pending(),
+        );
+
+        tracing::debug!(
+            "Waiting for {} to appear in {}",
+            filename.display(),
+            parent.display()
+        );
+
+        // The path may have been created between the caller's check and the parent watch.
+        if filename.exists() {
+            self.activate_pending(filename);
+        }
+
+        Ok(())
+    }
+
+    /// Removes a pending watch, releasing its parent directory if nothing else needs it.
+    ///
+    /// Returns `false` if `filename` is not a pending watch.
+    pub(crate) fn remove_pending_watch(&mut self, filename: &Path) -> bool {
+        let Some(pending) = self.pending.remove(filename) else {
+            return false;
+        };
+        self.watches.remove(filename);
+        self.release_helper(&pending.parent, RecursiveMode::NonRecursive);
+        true
+    }
+
+    /// Upgrades pending watches whose paths were created by one of the events.
+    pub(crate) fn resolve_pending(&mut self, events: &[DebouncedEvent]) {
+        let appeared: Vec<PathBuf> = self
+            .pending
+            .iter()
+            .filter(|(filename, pending)| {
+                filename.exists()
+                    && events.iter().any(|event| {
+                        matches!(
+                            event.kind,
+                            EventKind::Create(_) | EventKind::Modify(ModifyKind::Name(_))
+                        ) && event.paths.contains(&pending.expected)
+                    })
+            })
+            .map(|(filename, _)| filename.clone())
+            .collect();
+
+        for filename in appeared {
+            self.activate_pending(&filename);
+        }
+    }
+
+    /// Returns `true` if `path` is where a pending watch expects its file to appear.
+    pub(crate) fn is_pending_path(&self, path: &Path) -> bool {
+        self.pending
+            .values()
+            .any(|pending| pending.expected == path)
+    }
+
+    /// Returns `true` if `path` is where the pending watch on `filename` expects its file.
+    pub(crate) fn is_pending_path_of(&self, filename: &Path, path: &Path) -> bool {
+        self.pending
+            .get(filename)
+            .is_some_and(|pending| pending.expected == path)
+    }
+
+    /// Turns the pending watch on `filename` into a direct watch.
+    fn activate_pending(&mut self, filename: &Path) {
+        let Some(pending) = self.pending.remove(filename) else {
+            return;
+        };
+
+        let served = match self.serve(filename, pending.mode, None) {
+            Ok(served) => served,
+            Err(error) => {
+                tracing::debug!(
+                    "Failed to watch pending path {}: {}",
+                    filename.display(),
+                    error
+                );
+                self.report(error);
+                self.pending.insert(filename.to_path_buf(), pending);
+                return;
+            }
+        };
+
+        let tags = self
+            .watches
+            .get(filename)
+            .map(|watch| watch.tags.clone())
+            .unwrap_or_default();
+        let mut info = served.mark(WatchInfo::new(filename.to_path_buf(), pending.mode));
+        info.tags = tags;
+        self.watches.insert(filename.to_path_buf(), info);
+        self.release_helper(&pending.parent, RecursiveMode::NonRecursive);
+        if served == Served::Native {
+            self.debouncer.cache().add_root(filename, pending.mode);
+            self.track_file(filename);
+        }
+
+        tracing::debug!(
+            "Pending path appeared, now watching: {}",
+            filename.display()
+        );
+    }
+}
@@ -0,0 +1,343 @@
+use std::{
+    fs::{self, File},
+    io::Write as _,
+    path::{Path, PathBuf},
+    process,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use notify::RecursiveMode;
+
+use crate::{
+    event::{path_from_json, path_to_json},
+    json::Value,
+    state::{self, State},
+    tree::{self, TreeIndex},
+    ChecksumAlgorithm, DebounceStrategy, Error, EventKindMask, FilterSet, WatchInfo, WatchOptions,
+    WatchReport, Watcher,
+};
+
+/// The version of the format written by [`Watcher::save_state`].
+const VERSION: u64 = 1;
+
+impl Watcher {
+    /// Saves the watched paths with their options, tags and filter sets to `path`, so that
+    /// a restarted process can restore them with [`Watcher::restore_state`] instead of
+    /// deriving its configuration again.
+    ///
+    /// If the watcher keeps the index of a tree, see [`TreeWatcher`](crate::TreeWatcher),
+    /// the index is saved as well, so that the changes made until the state is restored are
+    /// reported then. Only what can be written down is saved: handlers, validators and the
+    /// predicates of filter sets are code, and have to be set up again by the restarted
+    /// process. The state is written to a temporary file next to `path` first, which then
+    /// replaces `path`, so that a crash while saving does not leave a partial state behind.
+    ///
+    /// # Arguments
+    /// * `path` - The file to save the state to.
+    ///
+    /// # Returns
+    /// A `Result` containing either an empty `()` value on success, or an `Error` if the
+    /// state cannot be written.
+    pub fn save_state(&self, path: impl AsRef<Path>) -> Result<(), Error> {
+        let (watches, trees) = {
+            let state = state::lock(&self.state);
+            let watches = state
+                .watches
+                .values()
+                .map(|watch| watch_to_json(&state, watch))
+                .collect();
+            let trees: Vec<_> = state
+                .trees
+                .iter()
+                .map(|(root, index)| (root.clone(), Arc::clone(index)))
+                .collect();
+            (watches, trees)
+        };
+        let trees = trees
+            .iter()
+            .map(|(root, index)| tree::lock(index).to_json(root))
+            .collect();
+        let json = Value::object([
+            ("version", Value::number(VERSION)),
+            ("watches", Value::Array(watches)),
+            ("trees", Value::Array(trees)),
+        ]);
+        write_atomically(path.as_ref(), &json.to_string())
+    }
+
+    /// Restores the watches saved to `path` by [`Watcher::save_state`].
+    ///
+    /// Each saved path is watched again with the options, tags and filter sets it had,
+    /// unless it is already watched, in which case only its tags are added. Paths that no
+    /// longer exist are reported as failed and do not keep the others from being restored,
+    /// except for paths that were saved while waiting to be created, see
+    /// [`Watcher::watch_pending`], which wait again. Saved tree indexes are compared to the
+    /// trees this watcher indexes, and the handler receives events for what changed since
+    /// the state was saved, as with
+    /// [`WatcherBuilder::build_tree_with_baseline`](crate::WatcherBuilder::build_tree_with_baseline).
+    /// The indexes of other trees are not used.
+    ///
+    /// # Arguments
+    /// * `path` - The file the state was saved to.
+    ///
+    /// # Returns
+    /// A `Result` containing either a [`WatchReport`] listing which saved paths are now
+    /// being watched and which failed, and why, or an `Error` if the state cannot be read.
+    pub fn restore_state(&self, path: impl AsRef<Path>) -> Result<WatchReport, Error> {
+        let path = path.as_ref();
+        let text = fs::read_to_string(path)
+            .map_err(|error| Error::io(error).add_path(path.to_path_buf()))?;
+        let saved = SavedState::parse(&text).map_err(|error| error.add_path(path.to_path_buf()))?;
+
+        let mut report = WatchReport::default();
+        for watch in saved.watches {
+            let path = watch.path.clone();
+            match self.restore_watch(watch) {
+                Ok(()) => report.watched.push(path),
+                Err(error) => report.failed.push((path, error)),
+            }
+        }
+        for (root, baseline) in saved.trees {
+            let Some(index) = state::lock(&self.state).trees.get(&root).cloned() else {
+                continue;
+            };
+            {
+                let mut current = tree::lock(&index);
+                let window = current.window;
+                *current = TreeIndex { window, ..baseline };
+            }
+            self.rescan_tree(&root, &index, true);
+        }
+
+        Ok(report)
+    }
+
+    fn restore_watch(&self, watch: SavedWatch) -> Result<(), Error> {
+        let registered = state::lock(&self.state).watches.contains_key(&watch.path);
+        if !registered {
+            if watch.glob {
+                let pattern = watch
+                    .path
+                    .to_str()
+                    .ok_or_else(|| Error::generic("saved glob pattern is not valid Unicode"))?;
+                self.watch_glob(pattern)?;
+            } else if !watch.extensions.is_empty() {
+                self.watch_dir_ext(&watch.path, &watch.extensions)?;
+            } else if watch.pending && !watch.path.exists() {
+                let mode = watch.options.mode(RecursiveMode::NonRecursive);
+                state::lock(&self.state).add_pending_watch(&watch.path, mode)?;
+            } else {
+                self.watch_with_options(&watch.path, watch.options)?;
+            }
+        }
+        for tag in &watch.tags {
+            self.watch_tagged(&watch.path, tag)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// A state written by [`Watcher::save_state`].
+struct SavedState {
+    watches: Vec<SavedWatch>,
+    /// The indexes of the saved trees, with their roots.
+    trees: Vec<(PathBuf, TreeIndex)>,
+}
+
+impl SavedState {
+    fn parse(text: &str) -> Result<Self, Error> {
+        let value = Value::parse(text)?;
+        let version = value.field("version")?.as_u64()?;
+        if version != VERSION {
+            return Err(Error::generic(&format!(
+                "unsupported watcher state version {version}"
+            )));
+        }
+        let watches = value
+            .field("watches")?
+            .as_array()?
+            .iter()
+            .map(watch_from_json)
+            .collect::<Result<_, _>>()?;
+        let trees = value
+            .field("trees")?
+            .as_array()?
+            .iter()
+            .map(TreeIndex::from_json)
+            .collect::<Result<_, _>>()?;
+        Ok(Self { watches, trees })
+    }
+}
+
+/// A watch read from a saved state.
+struct SavedWatch {
+    path: PathBuf,
+    pending: bool,
+    glob: bool,
+    extensions: Vec<String>,
+    tags: Vec<String>,
+    options: WatchOptions,
+}
+
+/// Writes the registration and options of `watch`.
+fn watch_to_json(state: &State, watch: &WatchInfo) -> Value {
+    let strings = |strings: &[String]| Value::Array(strings.iter().map(Value::string).collect());
+    // The debouncer of leading and throttled watches is not the interval they deliver at.
+    let debounce = match watch.strategy {
+        DebounceStrategy::Trailing => watch.debounce,
+        DebounceStrategy::Leading | DebounceStrategy::Throttle => {
+            state.strategy_interval(&watch.path)
+        }
+    };
+    let snapshots = watch.snapshot_dir.as_ref().map(|dir| {
+        Value::object([
+            ("dir", path_to_json(dir)),
+            ("keep", Value::number(watch.snapshot_versions)),
+        ])
+    });
+    Value::object([
+        ("path", path_to_json(&watch.path)),
+        ("recursive", Value::Bool(watch.recursive)),
+        ("pending", Value::Bool(watch.pending)),
+        ("glob", Value::Bool(watch.glob)),
+        ("extensions", strings(&watch.extensions)),
+        ("tags", strings(&watch.tags)),
+        (
+            "follow_symlinks",
+            Value::Bool(watch.symlink_target.is_some()),
+        ),
+        ("max_depth", Value::optional(watch.max_depth, Value::number)),
+        ("debounce", Value::optional(debounce, duration_to_json)),
+        ("ignore_unchanged", Value::Bool(watch.ignore_unchanged)),
+        ("diff", Value::optional(watch.diff_limit, Value::number)),
+        ("track_size", Value::Bool(watch.track_size)),
+        (
+            "checksum",
+            Value::optional(watch.checksum, |algorithm| {
+                Value::string(algorithm.as_str())
+            }),
+        ),
+        ("change_summary", Value::Bool(watch.change_summary)),
+        ("key_changes", Value::Bool(watch.key_changes)),
+        ("snapshots", Value::optional(snapshots, |value| value)),
+        ("summarize", Value::Bool(watch.summarize)),
+        (
+            "filters",
+            Value::optional(state.filters.get(&watch.path), FilterSet::to_json),
+        ),
+        ("strategy", watch.strategy.to_json()),
+        ("settle", Value::optional(watch.settle, duration_to_json)),
+        ("kinds", watch.kinds.to_json()),
+        ("close_write", Value::Bool(watch.close_write)),
+    ])
+}
+
+/// Reads a watch written by [`watch_to_json`].
+fn watch_from_json(value: &Value) -> Result<SavedWatch, Error> {
+    let strings = |key: &str| -> Result<Vec<String>, Error> {
+        value
+            .field(key)?
+            .as_array()?
+            .iter()
+            .map(|string| string.as_str().map(str::to_string))
+            .collect()
+    };
+    let flag = |key: &str| value.field(key)?.as_bool();
+
+    let mut options = WatchOptions::new()
+        .recursive(flag("recursive")?)
+        .follow_symlinks(flag("follow_symlinks")?)
+        .ignore_unchanged(flag("ignore_unchanged")?)
+        .track_size(flag("track_size")?)
+        .change_summary(flag("change_summary")?)
+        .key_changes(flag("key_changes")?)
+        .summarize(flag("summarize")?)
+        .strategy(DebounceStrategy::from_json(value.field("strategy")?)?)
+        .close_write(flag("close_write")?);
+    if let Some(max_depth) = value.field("max_depth")?.as_optional(Value::as_u64)? {
+        options = options.max_depth(usize::try_from(max_depth).unwrap_or(usize::MAX));
+    }
+    if let Some(debounce) = value.field("debounce")?.as_optional(duration_from_json)? {
+        options = options.debounce(debounce);
+    }
+    if let Some(max_size) = value.field("diff")?.as_optional(Value::as_u64)? {
+        options = options.diff(max_size);
+    }
+    if let Some(algorithm) = value
+        .field("checksum")?
+        .as_optional(|algorithm| ChecksumAlgorithm::from_str(algorithm.as_str()?))?
+    {
+        options = options.checksum(algorithm);
+    }
+    if let Some(snapshots) = value.field("snapshots")?.as_optional(Ok)? {
+        let dir = path_from_json(snapshots.field("dir")?)?;
+        let keep = snapshots.field("keep")?.as_u64()?;
+        options = options.snapshots(dir, usize::try_from(keep).unwrap_or(usize::MAX));
+    }
+    if let Some(filters) = value.field("filters")?.as_optional(FilterSet::from_json)? {
+        options = options.filter_set(filters);
+    }
+    if let Some(settle) = value.field("settle")?.as_optional(duration_from_json)? {
+        options = options.settle(settle);
+    }
+    let kinds = EventKindMask::from_json(value.field("kinds")?)?;
+    if kinds != EventKindMask::ALL {
+        options = options.kinds(kinds);
+    }
+
+    Ok(SavedWatch {
+        path: path_from_json(value.field("path")?)?,
+        pending: flag("pending")?,
+        glob: flag("glob")?,
+        extensions: strings("extensions")?,
+        tags: strings("tags")?,
+        options,
+    })
+}
+
+fn duration_to_json(duration: Duration) -> Value {
+    Value::object([
+        ("secs", Value::number(duration.as_secs())),
+        ("nanos", Value::number(duration.subsec_nanos())),
+    ])
+}
+
+fn duration_from_json(value: &Value) -> Result<Duration, Error> {
+    let secs = value.field("secs")?.as_u64()?;
+    let nanos = value.field("nanos")?.as_u64()?;
+    match u32::try_from(nanos) {
+        Ok(nanos) if nanos < 1_000_000_000 => Ok(Duration::new(secs, nanos)),
+        _ => Err(Error::generic(&format!("invalid nanoseconds {nanos}"))),
+    }
+}
+
+/// Writes `contents` to a temporary file next to `path`, which then replaces `path`, so
+/// that a crash while writing does not leave a partial file behind.
+///
+/// The temporary file is named after the process and a counter, so that concurrent saves
+/// to the same path do not write to the same file, and it is synced before the rename, so
+/// that `path` never refers to contents that have not reached the disk.
+pub(crate) fn write_atomically(path: &Path, contents: &str) -> Result<(), Error> {
+    static SAVES: AtomicU64 = AtomicU64::new(0);
+
+    let mut temporary = path.as_os_str().to_owned();
+    let save = SAVES.fetch_add(1, Ordering::Relaxed);
+    temporary.push(format!(".{}.{save}.tmp", process::id()));
+    let temporary = PathBuf::from(temporary);
+    let written = File::create(&temporary)
+        .and_then(|mut file| {
+            file.write_all(contents.as_bytes())?;
+            file.sync_all()
+        })
+        .and_then(|()| fs::rename(&temporary, path));
+    if let Err(error) = written {
+        let _ = fs::remove_file(&temporary);
+        return Err(Error::io(error).add_path(path.to_path_buf()));
+    }
+    Ok(())
+}
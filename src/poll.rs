@@ -0,0 +1,107 @@
+use std::{path::Path, time::Duration};
+
+use notify::{RecursiveMode, Watcher as _};
+
+use crate::{
+    state::{self, State},
+    Error,
+};
+
+impl State {
+    /// Handles a backend failure to watch `filename`, polling the path instead if the OS ran
+    /// out of file watches and a polling fallback is configured.
+    pub(crate) fn poll_fallback(
+        &mut self,
+        filename: &Path,
+        mode: RecursiveMode,
+        error: notify::Error,
+    ) -> Result<(), Error> {
+        if !Error::is_watch_limit(&error) {
+            return Err(error.into());
+        }
+        let Some(interval) = self.poll_interval else {
+            return Err(self.watch_limit(filename));
+        };
+
+        self.poll(filename, mode, interval)?;
+
+        tracing::warn!(
+            "OS file watch limit reached with {} paths registered, polling {}",
+            self.watches.len(),
+            filename.display()
+        );
+
+        Ok(())
+    }
+
+    /// Polls `filename`, creating the poller with `interval` if no path is polled yet.
+    pub(crate) fn poll(
+        &mut self,
+        filename: &Path,
+        mode: RecursiveMode,
+        interval: Duration,
+    ) -> Result<(), Error> {
+        if self.poller.is_none() {
+            self.poller = Some(state::new_debouncer(
+                self.debounce,
+                &self.poll_source(interval),
+                &self.dispatcher,
+                &self.cell,
+                &self.raw,
+            )?);
+            tracing::debug!("Created poller with {:?} interval", interval);
+        }
+        let poller = self.poller.as_mut().expect("poller was just created");
+
+        poller.watcher().watch(filename, mode)?;
+        poller.cache().add_root(filename, mode);
+
+        Ok(())
+    }
+
+    /// Stops polling `filename`, if the poller watches it.
+    pub(crate) fn unpoll(&mut self, filename: &Path) {
+        let Some(poller) = &mut self.poller else {
+            return;
+        };
+        if poller.watcher().unwatch(filename).is_ok() {
+            poller.cache().remove_root(filename);
+        }
+    }
+
+    /// Converts a backend error about `filename` into a [`Error::WatchLimit`] if the OS ran
+    /// out of file watches.
+    pub(crate) fn backend_error(&self, filename: &Path, error: notify::Error) -> Error {
+        if Error::is_watch_limit(&error) {
+            self.watch_limit(filename)
+        } else {
+            error.into()
+        }
+    }
+
+    /// Removes a watch that is served by the polling fallback.
+    ///
+    /// Returns `false` if `filename` is not polled.
+    pub(crate) fn remove_polled_watch(&mut self, filename: &Path) -> bool {
+        if !self.watches.get(filename).is_some_and(|watch| watch.polled) {
+            return false;
+        }
+
+        if let Some(poller) = &mut self.poller {
+            if let Err(error) = poller.watcher().unwatch(filename) {
+                tracing::debug!("Failed to stop polling {}: {}", filename.display(), error);
+            }
+            poller.cache().remove_root(filename);
+        }
+        self.watches.remove(filename);
+
+        true
+    }
+
+    fn watch_limit(&self, filename: &Path) -> Error {
+        Error::WatchLimit {
+            path: filename.to_path_buf(),
+            registered: self.watches.len(),
+        }
+    }
+}
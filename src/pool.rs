@@ -0,0 +1,104 @@
+use std::{
+    sync::{mpsc, Arc, Mutex, MutexGuard},
+    thread,
+};
+
+use crate::{panic, DebounceEventHandler, DebounceEventResult, Error, EventHandler, EventResult};
+
+/// A unit of work run on a worker thread.
+type Job = Box<dyn FnOnce() + Send + 'static>;
+
+/// The worker threads that run the handlers of a watcher, see
+/// [`WatcherBuilder::workers`](crate::WatcherBuilder::workers).
+///
+/// Every handler is pinned to one worker, so that its batches are handled in order while a
+/// slow handler only delays the handlers sharing its worker. The workers exit once every
+/// handler is dropped and their queues are drained.
+pub(crate) struct Pool {
+    workers: Vec<mpsc::Sender<Job>>,
+    next: usize,
+}
+
+impl Pool {
+    /// Starts `size` worker threads, or one if `size` is `0`.
+    pub(crate) fn new(size: usize) -> Self {
+        let workers = (0..size.max(1))
+            .map(|index| {
+                let (sender, receiver) = mpsc::channel::<Job>();
+                let spawned = thread::Builder::new()
+                    .name(format!("watchit-worker-{index}"))
+                    .spawn(move || {
+                        for job in receiver {
+                            job();
+                        }
+                    });
+                if let Err(error) = spawned {
+                    tracing::warn!("Failed to start handler worker {}: {}", index, error);
+                }
+                sender
+            })
+            .collect();
+
+        tracing::debug!("Started {} handler workers", size.max(1));
+
+        Self { workers, next: 0 }
+    }
+
+    /// Moves `handler` onto the next worker, in turn.
+    pub(crate) fn pin<H>(&mut self, handler: H) -> Pooled<H> {
+        let worker = self.workers[self.next % self.workers.len()].clone();
+        self.next += 1;
+        Pooled {
+            handler: Arc::new(Mutex::new(handler)),
+            worker,
+        }
+    }
+}
+
+/// A handler that runs on a worker thread of a [`Pool`].
+pub(crate) struct Pooled<H> {
+    handler: Arc<Mutex<H>>,
+    worker: mpsc::Sender<Job>,
+}
+
+impl<H: Send + 'static> Pooled<H> {
+    /// Runs `job` on the worker. A panic of the handler is handed to it by `report`, and the
+    /// worker carries on with the next batch.
+    fn run(&self, job: impl FnOnce(&mut H) + Send + 'static, report: fn(&mut H, Error)) {
+        let handler = self.handler.clone();
+        let job: Job = Box::new(move || {
+            let mut handler = lock(&handler);
+            if let Err(error) = panic::isolate(|| job(&mut handler)) {
+                // A panic of the handler while handling the error is only logged.
+                let _ = panic::isolate(|| report(&mut handler, error));
+            }
+        });
+        if self.worker.send(job).is_err() {
+            tracing::warn!("Dropped a batch because its handler worker is gone");
+        }
+    }
+}
+
+impl DebounceEventHandler for Pooled<Box<dyn DebounceEventHandler>> {
+    fn handle_event(&mut self, result: DebounceEventResult) {
+        self.run(
+            move |handler| handler.handle_event(result),
+            |handler, error| handler.handle_event(Err(vec![error.into_notify()])),
+        );
+    }
+}
+
+impl EventHandler for Pooled<Box<dyn EventHandler>> {
+    fn handle_event(&mut self, result: EventResult) {
+        self.run(
+            move |handler| handler.handle_event(result),
+            |handler, error| handler.handle_event(Err(vec![error])),
+        );
+    }
+}
+
+fn lock<H>(handler: &Mutex<H>) -> MutexGuard<'_, H> {
+    handler
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
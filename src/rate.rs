@@ -0,0 +1,153 @@
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+use crate::{event::Details, DebouncedEvent};
+
+/// The number of events delivered per path within a window, see
+/// [`WatcherBuilder::rate_limit`](crate::WatcherBuilder::rate_limit).
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct RateLimit {
+    pub(crate) max_events: usize,
+    pub(crate) per: Duration,
+}
+
+/// Holds back the events of paths that exceed their [`RateLimit`].
+///
+/// Each path gets a window starting with its first event. Up to `max_events` events are
+/// delivered within the window, and the rest are coalesced into one event that is delivered
+/// when the window ends and counts towards the next window.
+pub(crate) struct RateLimiter {
+    /// The limit of every path, if the whole watcher is limited.
+    limit: Option<RateLimit>,
+    /// The limits of the paths below throttled watches, see
+    /// [`DebounceStrategy::Throttle`](crate::DebounceStrategy::Throttle).
+    roots: BTreeMap<PathBuf, RateLimit>,
+    windows: BTreeMap<PathBuf, Window>,
+}
+
+struct Window {
+    start: Instant,
+    count: usize,
+    limit: RateLimit,
+    /// The newest event beyond the limit, standing for every event held back in the window.
+    held: Option<(DebouncedEvent, Details)>,
+}
+
+impl RateLimit {
+    fn normalized(self) -> Self {
+        Self {
+            max_events: self.max_events.max(1),
+            per: self.per,
+        }
+    }
+}
+
+impl RateLimiter {
+    pub(crate) fn new(limit: Option<RateLimit>) -> Self {
+        Self {
+            limit: limit.map(RateLimit::normalized),
+            roots: BTreeMap::new(),
+            windows: BTreeMap::new(),
+        }
+    }
+
+    /// Limits the paths below the watch on `root`, overriding the watcher's limit.
+    pub(crate) fn limit_root(&mut self, root: PathBuf, limit: RateLimit) {
+        self.roots.insert(root, limit.normalized());
+    }
+
+    /// Returns the limit of the paths below the watch on `root`, if it overrides the
+    /// watcher's.
+    pub(crate) fn root_limit(&self, root: &Path) -> Option<RateLimit> {
+        self.roots.get(root).copied()
+    }
+
+    pub(crate) fn forget_root(&mut self, root: &Path) {
+        self.roots.remove(root);
+    }
+
+    fn limit_of(&self, details: &Details) -> Option<RateLimit> {
+        details
+            .root
+            .as_ref()
+            .and_then(|root| self.roots.get(root))
+            .copied()
+            .or(self.limit)
+    }
+
+    /// Returns the events within their path's limit, preceded by held events that are due,
+    /// and holds back the rest.
+    pub(crate) fn admit(
+        &mut self,
+        events: Vec<(DebouncedEvent, Details)>,
+        now: Instant,
+    ) -> Vec<(DebouncedEvent, Details)> {
+        let mut admitted = self.take_due(now);
+        for (event, details) in events {
+            let (Some(path), Some(limit)) = (event.paths.last().cloned(), self.limit_of(&details))
+            else {
+                admitted.push((event, details));
+                continue;
+            };
+            let window = self.windows.entry(path).or_insert(Window {
+                start: now,
+                count: 0,
+                limit,
+                held: None,
+            });
+            if window.count < window.limit.max_events {
+                window.count += 1;
+                admitted.push((event, details));
+                continue;
+            }
+
+            let raw_count = window
+                .held
+                .take()
+                .map_or(0, |(_, previous)| previous.raw_count);
+            window.held = Some((
+                event,
+                Details {
+                    raw_count: raw_count + details.raw_count,
+                    ..details
+                },
+            ));
+        }
+        admitted
+    }
+
+    /// Removes the held events whose window has ended, starting a new window for each.
+    pub(crate) fn take_due(&mut self, now: Instant) -> Vec<(DebouncedEvent, Details)> {
+        let mut due = Vec::new();
+        self.windows.retain(|_, window| {
+            if now.duration_since(window.start) < window.limit.per {
+                return true;
+            }
+            match window.held.take() {
+                Some(event) => {
+                    due.push(event);
+                    window.start = now;
+                    window.count = 1;
+                    true
+                }
+                None => false,
+            }
+        });
+        if !due.is_empty() {
+            tracing::trace!("Released {} rate limited events", due.len());
+        }
+        due
+    }
+
+    /// Returns how long until the next held event is due, if any is held.
+    pub(crate) fn next_due(&self, now: Instant) -> Option<Duration> {
+        self.windows
+            .values()
+            .filter(|window| window.held.is_some())
+            .map(|window| (window.start + window.limit.per).saturating_duration_since(now))
+            .min()
+    }
+}
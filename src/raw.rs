@@ -0,0 +1,103 @@
+use std::{
+    collections::HashMap,
+    path::PathBuf,
+    sync::{Arc, Mutex, MutexGuard},
+};
+
+use notify::EventHandler;
+
+use crate::{panic, state::State, DebouncedEvent, Error};
+
+/// What happens to raw back-end events before the debouncer merges them.
+///
+/// This lives behind its own lock, as it is used on the back-end's thread, which must never
+/// wait for the [`State`].
+#[derive(Default)]
+pub(crate) struct Raw {
+    /// The raw events seen for each path since its events were last dispatched.
+    counts: HashMap<PathBuf, usize>,
+    /// The handler receiving every raw event as soon as it arrives.
+    pub(crate) handler: Option<Box<dyn EventHandler>>,
+    /// The panics of the raw handler, delivered with the next debounced batch.
+    panics: Vec<Error>,
+}
+
+/// A handle to the [`Raw`] state shared with the back-end threads.
+pub(crate) type SharedRaw = Arc<Mutex<Raw>>;
+
+/// How many paths may have uncollected counts before they are all discarded. Counts for
+/// paths whose events are never dispatched, such as those of helper directories, would
+/// otherwise accumulate.
+const MAX_COUNTED_PATHS: usize = 4096;
+
+/// Wraps the handler of a back-end so that the raw events it receives are counted and
+/// passed to the raw handler of `raw` before they reach the debouncer.
+pub(crate) fn counting(raw: &SharedRaw, mut event_handler: impl EventHandler) -> impl EventHandler {
+    let raw = raw.clone();
+    move |result: notify::Result<notify::Event>| {
+        {
+            let mut raw = lock(&raw);
+            if let Ok(event) = &result {
+                for path in &event.paths {
+                    *raw.counts.entry(path.clone()).or_default() += 1;
+                }
+            }
+            if let Some(handler) = &mut raw.handler {
+                let handled = panic::isolate(|| handler.handle_event(clone_result(&result)));
+                if let Err(error) = handled {
+                    raw.panics.push(error);
+                }
+            }
+        }
+        event_handler.handle_event(result);
+    }
+}
+
+impl State {
+    /// Collects the raw event counts of the paths in a batch.
+    pub(crate) fn take_raw_counts(&self, events: &[DebouncedEvent]) -> HashMap<PathBuf, usize> {
+        let mut raw = lock(&self.raw);
+        let taken = events
+            .iter()
+            .flat_map(|event| &event.paths)
+            .filter_map(|path| raw.counts.remove_entry(path))
+            .collect();
+        if raw.counts.len() > MAX_COUNTED_PATHS {
+            raw.counts.clear();
+        }
+        taken
+    }
+}
+
+/// Takes the panics of the raw handler since they were last taken.
+pub(crate) fn take_panics(raw: &SharedRaw) -> Vec<Error> {
+    std::mem::take(&mut lock(raw).panics)
+}
+
+/// Returns how many raw events were seen for the paths of `event`, and at least one.
+pub(crate) fn raw_count(counts: &HashMap<PathBuf, usize>, event: &DebouncedEvent) -> usize {
+    event
+        .paths
+        .iter()
+        .filter_map(|path| counts.get(path))
+        .sum::<usize>()
+        .max(1)
+}
+
+/// Copies a raw result for the raw handler. Errors cannot be cloned, so they are passed on
+/// with their kind described and their paths intact.
+fn clone_result(result: &notify::Result<notify::Event>) -> notify::Result<notify::Event> {
+    match result {
+        Ok(event) => Ok(event.clone()),
+        Err(error) => {
+            let mut copy = notify::Error::generic(&error.to_string());
+            copy.paths = error.paths.clone();
+            Err(copy)
+        }
+    }
+}
+
+/// Locks the raw state, recovering it if the raw handler panicked.
+pub(crate) fn lock(raw: &SharedRaw) -> MutexGuard<'_, Raw> {
+    raw.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
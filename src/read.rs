@@ -0,0 +1,76 @@
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    thread,
+    time::Duration,
+};
+
+use notify::EventKind;
+
+use crate::{DebounceEventHandler, DebounceEventResult, DebouncedEvent};
+
+/// How often reading a changed file is attempted before giving up.
+const READ_ATTEMPTS: u32 = 5;
+
+/// How long to wait before the first retry. Each retry waits twice as long as the last.
+const RETRY_DELAY: Duration = Duration::from_millis(10);
+
+/// Returns the handler of [`Watcher::watch_contents`](crate::Watcher::watch_contents),
+/// which reads `path` after each batch that changed it and hands the contents to `handler`.
+pub(crate) fn contents_handler(
+    path: &Path,
+    mut handler: impl FnMut(Vec<u8>) + Send + 'static,
+) -> impl DebounceEventHandler {
+    let path = path.to_path_buf();
+    move |result: DebounceEventResult| {
+        let Ok(events) = result else {
+            return;
+        };
+        if !changes(&events, &path) {
+            return;
+        }
+        match read_with_retry(&path) {
+            Ok(contents) => handler(contents),
+            Err(error) => tracing::warn!(
+                "Failed to read {} after it changed: {}",
+                path.display(),
+                error
+            ),
+        }
+    }
+}
+
+/// Returns `true` if `events` created or changed the file at `path`.
+pub(crate) fn changes(events: &[DebouncedEvent], path: &Path) -> bool {
+    events.iter().any(|event| {
+        !matches!(event.kind, EventKind::Remove(_) | EventKind::Access(_))
+            && event.paths.last().map(PathBuf::as_path) == Some(path)
+    })
+}
+
+/// Reads the file at `path`, retrying for a moment if it is missing or locked, as it is
+/// while an editor replaces it by renaming, or while another process holds it open on
+/// Windows.
+pub(crate) fn read_with_retry(path: &Path) -> io::Result<Vec<u8>> {
+    let mut delay = RETRY_DELAY;
+    let mut attempt = 1;
+    loop {
+        match fs::read(path) {
+            Err(error) if attempt < READ_ATTEMPTS && is_transient(&error) => {
+                tracing::trace!("Retrying to read {}: {}", path.display(), error);
+                thread::sleep(delay);
+                delay *= 2;
+                attempt += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
+fn is_transient(error: &io::Error) -> bool {
+    match error.kind() {
+        io::ErrorKind::NotFound | io::ErrorKind::PermissionDenied => true,
+        // ERROR_SHARING_VIOLATION and ERROR_LOCK_VIOLATION.
+        _ => cfg!(windows) && matches!(error.raw_os_error(), Some(32 | 33)),
+    }
+}
@@ -0,0 +1,284 @@
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    process::Command,
+    sync::{
+        mpsc::{self, RecvTimeoutError},
+        Arc, Mutex, MutexGuard,
+    },
+    thread,
+    time::{Duration, SystemTime},
+};
+
+use crate::{
+    hashpoll::{self, Stamp},
+    Error, EventSink, WatchBackend,
+};
+
+/// How often the remote paths are listed by default.
+const DEFAULT_INTERVAL: Duration = Duration::from_secs(5);
+
+/// A back-end that polls directories on a remote host over SSH, for servers that cannot
+/// run an agent of their own.
+///
+/// Every interval, the watched paths are listed on the remote host with `find`, and the
+/// differences to the previous listing are reported like the changes of a local path, so
+/// they are debounced, filtered and delivered as usual. The paths passed to
+/// [`Watcher::watch`](crate::Watcher::watch) and reported in events are paths on the remote
+/// host. The listing runs through the local `ssh` client in batch mode, so authentication
+/// must not need a prompt, such as with an SSH agent or a key without passphrase, and the
+/// remote `find` must support `-printf`, as GNU `find` does.
+///
+/// ```no_run
+/// use std::time::Duration;
+/// use watchit::{SshBackend, WatcherBuilder};
+///
+/// let (watcher, events) = WatcherBuilder::new()
+///     .custom_backend(|sink| {
+///         Ok(SshBackend::new(sink, "deploy@web1.example.com").interval(Duration::from_secs(10)))
+///     })
+///     .build_channel()
+///     .unwrap();
+/// watcher.watch("/srv/app/releases").unwrap();
+/// ```
+#[derive(Debug)]
+pub struct SshBackend {
+    sink: EventSink,
+    remote: Remote,
+    interval: Duration,
+    roots: SharedRoots,
+    /// Stops the listing thread when the back-end is dropped, once it was started.
+    stop: Option<mpsc::Sender<()>>,
+}
+
+type SharedRoots = Arc<Mutex<BTreeMap<PathBuf, Root>>>;
+
+#[derive(Debug)]
+struct Root {
+    recursive: bool,
+    snapshot: BTreeMap<PathBuf, Stamp>,
+}
+
+/// How to reach the remote host.
+#[derive(Debug, Clone)]
+struct Remote {
+    program: PathBuf,
+    destination: String,
+}
+
+impl SshBackend {
+    /// Creates a back-end listing paths on `destination`, which is passed to `ssh` as is,
+    /// such as `host`, `user@host` or the name of a host in `~/.ssh/config`.
+    pub fn new(sink: EventSink, destination: impl Into<String>) -> Self {
+        Self {
+            sink,
+            remote: Remote {
+                program: PathBuf::from("ssh"),
+                destination: destination.into(),
+            },
+            interval: DEFAULT_INTERVAL,
+            roots: SharedRoots::default(),
+            stop: None,
+        }
+    }
+
+    /// Sets how often the watched paths are listed. Defaults to five seconds.
+    ///
+    /// Each listing opens an SSH connection, unless connections are shared with the
+    /// `ControlMaster` option of `ssh`.
+    pub fn interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    /// Sets the SSH client to run. Defaults to `ssh` on the `PATH`.
+    ///
+    /// The client is called as `<program> -o BatchMode=yes <destination> <command>`.
+    pub fn program(mut self, program: impl Into<PathBuf>) -> Self {
+        self.remote.program = program.into();
+        self
+    }
+
+    /// Starts the thread listing the watched paths, unless it is running.
+    fn start(&mut self) -> Result<(), Error> {
+        if self.stop.is_some() {
+            return Ok(());
+        }
+        let (stop, stopped) = mpsc::channel();
+        let roots = Arc::clone(&self.roots);
+        let remote = self.remote.clone();
+        let sink = self.sink.clone();
+        let interval = self.interval;
+        thread::Builder::new()
+            .name("watchit-ssh".to_string())
+            .spawn(move || {
+                let mut failing = false;
+                while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(interval) {
+                    match scan(&roots, &remote) {
+                        Ok(events) => {
+                            failing = false;
+                            for event in events {
+                                sink.send(event);
+                            }
+                        }
+                        // Reported once, rather than every interval while the host is down.
+                        Err(error) if !failing => {
+                            failing = true;
+                            sink.error(error);
+                        }
+                        Err(error) => tracing::debug!("Listing remote paths failed: {}", error),
+                    }
+                }
+            })
+            .map_err(Error::io)?;
+        self.stop = Some(stop);
+        Ok(())
+    }
+}
+
+impl WatchBackend for SshBackend {
+    fn watch(&mut self, path: &Path, recursive: bool) -> Result<(), Error> {
+        let snapshot = self.remote.list(path, recursive)?;
+        if snapshot.is_empty() {
+            return Err(Error::path_not_found().add_path(path.to_path_buf()));
+        }
+        lock(&self.roots).insert(
+            path.to_path_buf(),
+            Root {
+                recursive,
+                snapshot,
+            },
+        );
+        self.start()
+    }
+
+    fn unwatch(&mut self, path: &Path) -> Result<(), Error> {
+        match lock(&self.roots).remove(path) {
+            Some(_) => Ok(()),
+            None => Err(Error::watch_not_found().add_path(path.to_path_buf())),
+        }
+    }
+}
+
+/// Lists every root again, returning the differences to its previous listing.
+///
+/// A root whose listing fails keeps its previous listing, so that a dropped connection
+/// is not mistaken for the removal of every path.
+fn scan(roots: &SharedRoots, remote: &Remote) -> Result<Vec<notify::Event>, Error> {
+    let watched: Vec<(PathBuf, bool)> = lock(roots)
+        .iter()
+        .map(|(path, root)| (path.clone(), root.recursive))
+        .collect();
+
+    let mut events = Vec::new();
+    for (path, recursive) in watched {
+        // The lock is not held while waiting for the remote host.
+        let snapshot = remote.list(&path, recursive)?;
+        if let Some(root) = lock(roots).get_mut(&path) {
+            hashpoll::compare(&root.snapshot, &snapshot, &mut events);
+            root.snapshot = snapshot;
+        }
+    }
+    Ok(events)
+}
+
+impl Remote {
+    /// Lists `path` and, for a directory, its entries, all its descendants if `recursive`
+    /// is set. A path that does not exist has an empty listing.
+    fn list(&self, path: &Path, recursive: bool) -> Result<BTreeMap<PathBuf, Stamp>, Error> {
+        let depth = if recursive { "" } else { " -maxdepth 1" };
+        let command = format!(
+            "find {}{} -printf '%y %s %T@ %p\\0'",
+            quote(&path.to_string_lossy()),
+            depth
+        );
+        let output = Command::new(&self.program)
+            .args(["-o", "BatchMode=yes", &self.destination, &command])
+            .output()
+            .map_err(Error::io)?;
+
+        // `find` fails for unreadable entries too, which leaves the rest of the listing.
+        if !output.status.success() && output.stdout.is_empty() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            if stderr.contains("No such file") {
+                return Ok(BTreeMap::new());
+            }
+            return Err(Error::generic(&format!(
+                "listing {} on {} failed: {}",
+                path.display(),
+                self.destination,
+                stderr.trim()
+            )));
+        }
+        Ok(parse(&output.stdout))
+    }
+}
+
+/// Parses the records of `find -printf '%y %s %T@ %p\0'`.
+fn parse(listing: &[u8]) -> BTreeMap<PathBuf, Stamp> {
+    listing
+        .split(|&byte| byte == 0)
+        .filter_map(|record| {
+            let record = String::from_utf8_lossy(record);
+            let mut fields = record.splitn(4, ' ');
+            let kind = fields.next()?;
+            let len = fields.next()?.parse().ok()?;
+            let modified = fields
+                .next()?
+                .parse::<f64>()
+                .ok()
+                .and_then(|seconds| Duration::try_from_secs_f64(seconds).ok())
+                .map(|since_epoch| SystemTime::UNIX_EPOCH + since_epoch);
+            let path = PathBuf::from(fields.next()?);
+            let stamp = Stamp {
+                is_dir: kind == "d",
+                len,
+                modified,
+                hash: None,
+            };
+            Some((path, stamp))
+        })
+        .collect()
+}
+
+/// Quotes `value` for the remote shell.
+fn quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', r"'\''"))
+}
+
+fn lock(roots: &SharedRoots) -> MutexGuard<'_, BTreeMap<PathBuf, Root>> {
+    roots
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+#[cfg(test)]
+/// This module contains tests for the SSH back-end.
+///
+/// The `parses_find_listing` test verifies that the records of the remote `find` are read
+/// into stamps, including paths with spaces and quotes.
+///
+/// The `quotes_single_quotes` test verifies that paths are quoted safely for the remote
+/// shell.
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_find_listing() {
+        let listing = b"d 4096 1700000000.5000000000 /srv/app\0f 12 1700000001.0000000000 /srv/app/it's here.txt\0";
+        let parsed = parse(listing);
+        assert_eq!(parsed.len(), 2);
+        assert!(parsed[Path::new("/srv/app")].is_dir);
+        let file = &parsed[Path::new("/srv/app/it's here.txt")];
+        assert_eq!(file.len, 12);
+        assert_eq!(
+            file.modified,
+            Some(SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_001))
+        );
+    }
+
+    #[test]
+    fn quotes_single_quotes() {
+        assert_eq!(quote("/srv/it's"), r"'/srv/it'\''s'");
+    }
+}
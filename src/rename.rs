@@ -0,0 +1,168 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+use notify::{
+    event::{EventKind, ModifyKind, RenameMode},
+    RecursiveMode,
+};
+use notify_debouncer_full::{
+    file_id::{get_file_id, FileId},
+    FileIdCache, FileIdMap,
+};
+
+use crate::{state::State, DebouncedEvent};
+
+/// How long the file ID of a removed path is remembered for matching it to a creation.
+const REMOVED_TTL: Duration = Duration::from_secs(60);
+
+/// The debouncer's file ID cache, extended to remember the IDs of removed paths.
+///
+/// Moving a file between two watched directories is reported as a removal and a creation on
+/// back-ends that watch each directory separately. By the time the debounced batch reaches
+/// the watch set the removed path is gone from the cache, so its ID is kept here until the
+/// batch has been processed.
+#[derive(Debug, Default)]
+pub(crate) struct FileIdTracker {
+    map: FileIdMap,
+    removed: HashMap<PathBuf, (FileId, Instant)>,
+}
+
+impl FileIdTracker {
+    /// See [`FileIdMap::add_root`].
+    pub(crate) fn add_root(&mut self, path: impl Into<PathBuf>, recursive_mode: RecursiveMode) {
+        self.map.add_root(path, recursive_mode);
+    }
+
+    /// See [`FileIdMap::remove_root`]. Paths removed this way are not remembered, since they
+    /// were unwatched rather than removed from disk.
+    pub(crate) fn remove_root(&mut self, path: impl AsRef<Path>) {
+        self.map.remove_root(path);
+    }
+
+    /// Returns the ID `path` had when it was removed.
+    fn removed_id(&self, path: &Path) -> Option<FileId> {
+        self.removed.get(path).map(|(id, _)| *id)
+    }
+
+    /// Returns the ID `path` had when it was removed, forgetting it.
+    fn take_removed(&mut self, path: &Path) -> Option<FileId> {
+        self.removed.remove(path).map(|(id, _)| id)
+    }
+
+    /// Forgets removed paths that were never matched to a creation.
+    fn prune_removed(&mut self) {
+        self.removed
+            .retain(|_, (_, removed)| removed.elapsed() < REMOVED_TTL);
+    }
+}
+
+impl FileIdCache for FileIdTracker {
+    fn cached_file_id(&self, path: &Path) -> Option<&FileId> {
+        self.map.cached_file_id(path)
+    }
+
+    fn add_path(&mut self, path: &Path) {
+        self.map.add_path(path);
+    }
+
+    fn remove_path(&mut self, path: &Path) {
+        if let Some(id) = self.map.cached_file_id(path) {
+            self.removed
+                .insert(path.to_path_buf(), (*id, Instant::now()));
+        }
+        self.map.remove_path(path);
+    }
+
+    fn rescan(&mut self) {
+        self.map.rescan();
+    }
+}
+
+impl State {
+    /// Merges the removal and creation of the same file within a batch into one rename event
+    /// carrying both the old and the new path.
+    pub(crate) fn correlate_renames(&mut self, events: &mut Vec<DebouncedEvent>) {
+        let is_removal = |event: &DebouncedEvent| {
+            matches!(
+                event.kind,
+                EventKind::Remove(_) | EventKind::Modify(ModifyKind::Name(RenameMode::From))
+            )
+        };
+        let is_creation = |event: &DebouncedEvent| {
+            matches!(
+                event.kind,
+                EventKind::Create(_) | EventKind::Modify(ModifyKind::Name(RenameMode::To))
+            )
+        };
+
+        let mut index = 0;
+        while index < events.len() {
+            if !is_removal(&events[index]) || events[index].paths.len() != 1 {
+                index += 1;
+                continue;
+            }
+
+            let from = events[index].paths[0].clone();
+            let Some(id) = self.removed_id(&from) else {
+                index += 1;
+                continue;
+            };
+
+            let to = events.iter().position(|event| {
+                is_creation(event)
+                    && event.paths.len() == 1
+                    && get_file_id(&event.paths[0]).is_ok_and(|created| created == id)
+            });
+            let Some(to) = to else {
+                index += 1;
+                continue;
+            };
+
+            self.take_removed(&from);
+            let target = events.remove(to).paths.remove(0);
+            if to < index {
+                index -= 1;
+            }
+
+            tracing::debug!(
+                "Correlated removal and creation as rename: {} -> {}",
+                from.display(),
+                target.display()
+            );
+
+            let event = &mut events[index];
+            event.kind = EventKind::Modify(ModifyKind::Name(RenameMode::Both));
+            event.paths.push(target);
+            index += 1;
+        }
+
+        self.debouncer.cache().prune_removed();
+        for debouncer in self.debouncers.values_mut() {
+            debouncer.cache().prune_removed();
+        }
+    }
+
+    /// Looks up the ID of a removed path in the caches of every debouncer.
+    pub(crate) fn removed_id(&mut self, path: &Path) -> Option<FileId> {
+        if let Some(id) = self.debouncer.cache().removed_id(path) {
+            return Some(id);
+        }
+        self.debouncers
+            .values_mut()
+            .find_map(|debouncer| debouncer.cache().removed_id(path))
+    }
+
+    /// Takes the ID of a removed path from the caches of every debouncer, so that it is not
+    /// matched to another creation.
+    fn take_removed(&mut self, path: &Path) -> Option<FileId> {
+        if let Some(id) = self.debouncer.cache().take_removed(path) {
+            return Some(id);
+        }
+        self.debouncers
+            .values_mut()
+            .find_map(|debouncer| debouncer.cache().take_removed(path))
+    }
+}
@@ -0,0 +1,193 @@
+use std::path::{Path, PathBuf};
+
+use notify::{
+    event::{DataChange, EventKind, ModifyKind, RenameMode},
+    RecursiveMode, Watcher as _,
+};
+use notify_debouncer_full::{
+    file_id::{get_file_id, FileId},
+    FileIdCache as _,
+};
+
+use crate::{state::State, DebouncedEvent};
+
+impl State {
+    /// Starts noticing when the watched file `filename` is replaced by a new file.
+    pub(crate) fn track_file(&mut self, filename: &Path) {
+        if filename.is_file() {
+            self.files
+                .insert(filename.to_path_buf(), get_file_id(filename).ok());
+        }
+    }
+
+    /// Stops noticing replacements of `filename`.
+    pub(crate) fn untrack_file(&mut self, filename: &Path) {
+        self.files.remove(filename);
+    }
+
+    /// Turns watches on directories that were removed into pending watches, so that they are
+    /// re-established with their original mode once the directory is created again.
+    ///
+    /// Build tools often delete and recreate their output directories, which leaves the old
+    /// OS watch pointing at a directory that no longer exists. This only applies if the
+    /// watcher was built with [`WatcherBuilder::rewatch_recreated`](crate::WatcherBuilder::rewatch_recreated).
+    pub(crate) fn resolve_removed_dirs(&mut self, events: &[DebouncedEvent]) {
+        if !self.rewatch_recreated {
+            return;
+        }
+
+        let removed: Vec<(PathBuf, RecursiveMode)> = self
+            .watches
+            .values()
+            .filter(|watch| {
+                !watch.pending
+                    && !watch.glob
+                    && !watch.polled
+                    && watch.extensions.is_empty()
+                    && watch.max_depth.is_none()
+                    && watch.symlink_target.is_none()
+                    && !self.files.contains_key(&watch.path)
+                    && events
+                        .iter()
+                        .any(|event| is_removal(event) && event.paths.first() == Some(&watch.path))
+                    && !watch.path.exists()
+            })
+            .map(|watch| {
+                let mode = if watch.recursive {
+                    RecursiveMode::Recursive
+                } else {
+                    RecursiveMode::NonRecursive
+                };
+                (watch.path.clone(), mode)
+            })
+            .collect();
+
+        for (dirname, mode) in removed {
+            let _ = self.backend(&dirname).watcher().unwatch(&dirname);
+            self.backend(&dirname).cache().remove_root(&dirname);
+            let tags = self
+                .watches
+                .remove(&dirname)
+                .map(|watch| watch.tags)
+                .unwrap_or_default();
+
+            match self.add_pending_watch(&dirname, mode) {
+                Ok(()) => {
+                    if let Some(watch) = self.watches.get_mut(&dirname) {
+                        watch.tags = tags;
+                    }
+                    tracing::debug!(
+                        "Waiting for removed directory to be recreated: {}",
+                        dirname.display()
+                    );
+                }
+                Err(error) => {
+                    tracing::debug!(
+                        "Stopped watching removed directory {}: {}",
+                        dirname.display(),
+                        error
+                    );
+                    self.report(error);
+                }
+            }
+        }
+    }
+
+    /// Re-establishes the watch on files that were replaced, and reports each replacement as
+    /// a single modification instead of a removal.
+    ///
+    /// Editors often save by writing a temporary file and renaming it over the original,
+    /// after which the original inode and its OS watch are gone and only a removal is
+    /// reported. If the path holds a new file by the time the removal is debounced, the watch
+    /// is moved to the new file. If the path is really gone, the watch becomes a pending
+    /// watch so that it is re-established once the path is created again.
+    pub(crate) fn resolve_replaced(&mut self, events: &mut Vec<DebouncedEvent>) {
+        if self.files.is_empty() {
+            return;
+        }
+
+        let removed: Vec<(PathBuf, Option<FileId>)> = self
+            .files
+            .iter()
+            .filter(|(filename, _)| {
+                events
+                    .iter()
+                    .any(|event| is_removal(event) && event.paths.first() == Some(filename))
+            })
+            .map(|(filename, id)| (filename.clone(), *id))
+            .collect();
+
+        for (filename, previous_id) in removed {
+            let mode = match self.watches.get(&filename) {
+                Some(watch) if watch.recursive => RecursiveMode::Recursive,
+                _ => RecursiveMode::NonRecursive,
+            };
+
+            // The old OS watch died with the old file either way.
+            let _ = self.backend(&filename).watcher().unwatch(&filename);
+
+            if !filename.is_file() {
+                self.backend(&filename).cache().remove_root(&filename);
+                self.files.remove(&filename);
+                self.watches.remove(&filename);
+                if let Err(error) = self.add_pending_watch(&filename, mode) {
+                    tracing::debug!(
+                        "Stopped watching removed file {}: {}",
+                        filename.display(),
+                        error
+                    );
+                    self.report(error);
+                }
+                continue;
+            }
+
+            if let Err(error) = self.backend(&filename).watcher().watch(&filename, mode) {
+                tracing::debug!(
+                    "Failed to re-watch replaced file {}: {}",
+                    filename.display(),
+                    error
+                );
+                self.report(error);
+                continue;
+            }
+            self.backend(&filename).cache().add_path(&filename);
+            let current_id = get_file_id(&filename).ok();
+            self.files.insert(filename.clone(), current_id);
+
+            tracing::debug!(
+                "Re-established watch on replaced file: {} ({:?} -> {:?})",
+                filename.display(),
+                previous_id,
+                current_id
+            );
+
+            let Some(first) = events
+                .iter()
+                .position(|event| is_removal(event) && event.paths.first() == Some(&filename))
+            else {
+                continue;
+            };
+            let time = events[first].time;
+            events.retain(|event| !(is_removal(event) && event.paths.first() == Some(&filename)));
+            if !events.iter().any(|event| event.paths.contains(&filename)) {
+                events.insert(
+                    first.min(events.len()),
+                    DebouncedEvent::new(
+                        notify::Event::new(EventKind::Modify(ModifyKind::Data(DataChange::Any)))
+                            .add_path(filename.clone()),
+                        time,
+                    ),
+                );
+            }
+        }
+    }
+}
+
+/// Returns `true` if the event reports that its first path is gone.
+fn is_removal(event: &DebouncedEvent) -> bool {
+    matches!(
+        event.kind,
+        EventKind::Remove(_)
+            | EventKind::Modify(ModifyKind::Name(RenameMode::From | RenameMode::Any))
+    )
+}
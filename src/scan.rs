@@ -0,0 +1,100 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::Instant,
+};
+
+use notify::{
+    event::{CreateKind, EventKind},
+    Event,
+};
+
+use crate::{event::Details, state::State, DebouncedEvent, WatchInfo};
+
+impl State {
+    /// Synthesizes an event for every path that the watch on `filename` already reports.
+    ///
+    /// A watched file yields itself, and a watched directory yields the entries below it
+    /// that its watch reports, in sorted order.
+    pub(crate) fn scan_existing(&mut self, filename: &Path) -> Vec<(DebouncedEvent, Details)> {
+        let Some(watch) = self.watches.get(filename) else {
+            return Vec::new();
+        };
+
+        let mut paths = Vec::new();
+        if filename.is_dir() {
+            scan_dir(watch, filename, &mut paths);
+        } else if filename.exists() {
+            paths.push(filename.to_path_buf());
+        }
+
+        let events: Vec<DebouncedEvent> = paths
+            .into_iter()
+            .map(|path| {
+                let event = Event::new(EventKind::Create(CreateKind::Any)).add_path(path);
+                DebouncedEvent::new(event, Instant::now())
+            })
+            .collect();
+
+        tracing::debug!(
+            "Found {} existing paths below {}",
+            events.len(),
+            filename.display()
+        );
+
+        self.synthesized(events, true)
+    }
+
+    /// Tags synthesized events and attaches their details, dropping the events that the
+    /// watches filter out.
+    ///
+    /// # Arguments
+    /// * `events` - The synthesized events.
+    /// * `existing` - Whether the events report paths that already existed, see
+    ///   [`EventKind::Existing`](crate::EventKind::Existing).
+    pub(crate) fn synthesized(
+        &mut self,
+        mut events: Vec<DebouncedEvent>,
+        existing: bool,
+    ) -> Vec<(DebouncedEvent, Details)> {
+        self.tag_events(&mut events);
+        let events = events
+            .into_iter()
+            .map(|event| {
+                let details = Details {
+                    root: self.root_of(&event),
+                    file_id: self.file_id_of(&event),
+                    existing,
+                    ..Details::default()
+                };
+                (event, details)
+            })
+            .collect();
+        self.filter_events(events)
+    }
+}
+
+/// Collects the entries below `dir` that `watch` reports, descending into the directories
+/// whose children it reports as well.
+fn scan_dir(watch: &WatchInfo, dir: &Path, paths: &mut Vec<PathBuf>) {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(error) => {
+            tracing::debug!("Failed to scan {}: {}", dir.display(), error);
+            return;
+        }
+    };
+
+    let mut entries: Vec<PathBuf> = entries.flatten().map(|entry| entry.path()).collect();
+    entries.sort();
+    for path in entries {
+        if !watch.reports(&path) {
+            continue;
+        }
+        let is_dir = fs::symlink_metadata(&path).is_ok_and(|metadata| metadata.is_dir());
+        paths.push(path.clone());
+        if is_dir && watch.reports_children_of(&path) {
+            scan_dir(watch, &path, paths);
+        }
+    }
+}
@@ -0,0 +1,174 @@
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+    time::{Duration, Instant, SystemTime},
+};
+
+use notify::{event::ModifyKind, EventKind};
+
+use crate::{dispatch, event::Details, flush, state::State, DebouncedEvent, Error};
+
+/// The shortest time between two checks of a settling file.
+const MIN_CHECK_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Holds back the events below settling watches until their file stops changing, see
+/// [`WatchOptions::settle`](crate::WatchOptions::settle).
+#[derive(Default)]
+pub(crate) struct Settler {
+    /// How long the files below each settling watch must stay unchanged.
+    roots: BTreeMap<PathBuf, Duration>,
+    held: BTreeMap<PathBuf, Settling>,
+}
+
+struct Settling {
+    event: DebouncedEvent,
+    details: Details,
+    settle: Duration,
+    /// The size and modification time of the file when it was last checked, or `None` if
+    /// it does not exist.
+    stamp: Option<(u64, Option<SystemTime>)>,
+    /// When the file was last seen changing.
+    changed: Instant,
+}
+
+impl Settler {
+    pub(crate) fn settle_root(&mut self, root: PathBuf, settle: Duration) {
+        self.roots.insert(root, settle);
+    }
+
+    /// Returns how long the files below the watch on `root` must stay unchanged, if it
+    /// settles.
+    pub(crate) fn root_settle(&self, root: &Path) -> Option<Duration> {
+        self.roots.get(root).copied()
+    }
+
+    pub(crate) fn forget_root(&mut self, root: &Path) {
+        self.roots.remove(root);
+        self.held
+            .retain(|_, settling| settling.details.root.as_deref() != Some(root));
+    }
+
+    /// Returns the events that are not below a settling watch, and holds back the rest.
+    ///
+    /// A newer event for a held path restarts the wait, and takes the held event's place
+    /// unless it only reports more writes, so that a held creation stays a creation.
+    pub(crate) fn admit(
+        &mut self,
+        events: Vec<(DebouncedEvent, Details)>,
+        now: Instant,
+    ) -> Vec<(DebouncedEvent, Details)> {
+        let mut admitted = Vec::new();
+        for (event, details) in events {
+            let settle = details
+                .root
+                .as_ref()
+                .and_then(|root| self.roots.get(root))
+                .copied();
+            let (Some(settle), Some(path)) = (settle, event.paths.last().cloned()) else {
+                admitted.push((event, details));
+                continue;
+            };
+
+            let stamp = stamp(&path);
+            match self.held.get_mut(&path) {
+                Some(settling) => {
+                    let raw_count = settling.details.raw_count + details.raw_count;
+                    if !is_follow_up(&settling.event.kind, &event.kind) {
+                        settling.event = event;
+                        settling.details = details;
+                    }
+                    settling.details.raw_count = raw_count;
+                    settling.stamp = stamp;
+                    settling.changed = now;
+                }
+                None => {
+                    self.held.insert(
+                        path,
+                        Settling {
+                            event,
+                            details,
+                            settle,
+                            stamp,
+                            changed: now,
+                        },
+                    );
+                }
+            }
+        }
+        admitted
+    }
+
+    /// Checks the held files again, returning the events of those that stayed unchanged
+    /// long enough.
+    pub(crate) fn take_settled(&mut self, now: Instant) -> Vec<(DebouncedEvent, Details)> {
+        let mut settled = Vec::new();
+        let held = std::mem::take(&mut self.held);
+        for (path, mut settling) in held {
+            let stamp = stamp(&path);
+            if stamp != settling.stamp {
+                settling.stamp = stamp;
+                settling.changed = now;
+            }
+            if now.duration_since(settling.changed) >= settling.settle {
+                settled.push((settling.event, settling.details));
+            } else {
+                self.held.insert(path, settling);
+            }
+        }
+        if !settled.is_empty() {
+            tracing::trace!("Released {} settled events", settled.len());
+        }
+        settled
+    }
+
+    /// Returns how long until the held files are checked again, if any is held.
+    pub(crate) fn next_check(&self, now: Instant) -> Option<Duration> {
+        self.held
+            .values()
+            .map(|settling| {
+                let remaining = (settling.changed + settling.settle).saturating_duration_since(now);
+                remaining.min(settling.settle / 4).max(MIN_CHECK_INTERVAL)
+            })
+            .min()
+    }
+}
+
+/// Returns `true` if an event of kind `new` only reports further writes to a file whose
+/// held event is of kind `held`.
+fn is_follow_up(held: &EventKind, new: &EventKind) -> bool {
+    !matches!(held, EventKind::Remove(_))
+        && matches!(
+            new,
+            EventKind::Modify(ModifyKind::Data(_) | ModifyKind::Metadata(_) | ModifyKind::Any)
+                | EventKind::Access(_)
+                | EventKind::Any
+                | EventKind::Other
+        )
+}
+
+/// Returns the size and modification time of the file at `path`, if it exists.
+fn stamp(path: &Path) -> Option<(u64, Option<SystemTime>)> {
+    let metadata = fs::metadata(path).ok()?;
+    Some((metadata.len(), metadata.modified().ok()))
+}
+
+impl State {
+    /// Holds back the events below the watch on `filename` until their file has kept its
+    /// size and modification time for `settle`.
+    pub(crate) fn add_settle(&mut self, filename: &Path, settle: Duration) -> Result<(), Error> {
+        let Some(watch) = self.watches.get_mut(filename) else {
+            return Err(Error::watch_not_found().add_path(filename.to_path_buf()));
+        };
+        watch.settle = Some(settle);
+
+        dispatch::lock(&self.dispatcher).settle_root(filename, settle);
+        flush::start_flusher(&self.dispatcher);
+
+        Ok(())
+    }
+
+    pub(crate) fn forget_settle(&mut self, filename: &Path) {
+        dispatch::lock(&self.dispatcher).unsettle_root(filename);
+    }
+}
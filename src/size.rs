@@ -0,0 +1,103 @@
+use std::{fs, path::Path};
+
+use crate::{json::Value, state::State, DebouncedEvent, Error, EventKind};
+
+/// How the size of a file changed with a modification.
+///
+/// Size changes are attached to events by watches registered with
+/// [`WatchOptions::track_size`](crate::WatchOptions::track_size).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SizeDelta {
+    /// The file grew by this many bytes, as when a log line is appended.
+    Grew(u64),
+    /// The file shrank by this many bytes, as when a log file is truncated or rotated.
+    Shrank(u64),
+    /// The file kept its size, as when it is rewritten in place.
+    Unchanged,
+}
+
+impl SizeDelta {
+    fn between(previous: u64, size: u64) -> Self {
+        match size.cmp(&previous) {
+            std::cmp::Ordering::Greater => Self::Grew(size - previous),
+            std::cmp::Ordering::Less => Self::Shrank(previous - size),
+            std::cmp::Ordering::Equal => Self::Unchanged,
+        }
+    }
+
+    pub(crate) fn to_json(self) -> Value {
+        match self {
+            Self::Grew(bytes) => Value::object([("grew", Value::number(bytes))]),
+            Self::Shrank(bytes) => Value::object([("shrank", Value::number(bytes))]),
+            Self::Unchanged => Value::string("unchanged"),
+        }
+    }
+
+    pub(crate) fn from_json(value: &Value) -> Result<Self, Error> {
+        if let Ok("unchanged") = value.as_str() {
+            return Ok(Self::Unchanged);
+        }
+        match (value.field("grew"), value.field("shrank")) {
+            (Ok(bytes), _) => Ok(Self::Grew(bytes.as_u64()?)),
+            (_, Ok(bytes)) => Ok(Self::Shrank(bytes.as_u64()?)),
+            (Err(error), _) => Err(error),
+        }
+    }
+}
+
+impl State {
+    /// Attaches the change in size to the modifications of files reported by the watch on
+    /// `filename`.
+    pub(crate) fn add_size_tracking(&mut self, filename: &Path) -> Result<(), Error> {
+        let Some(watch) = self.watches.get_mut(filename) else {
+            return Err(Error::watch_not_found().add_path(filename.to_path_buf()));
+        };
+        watch.track_size = true;
+
+        // Files below a directory are measured when they are first seen changing.
+        if let Some(size) = file_size(filename) {
+            self.sizes.insert(filename.to_path_buf(), size);
+        }
+
+        Ok(())
+    }
+
+    /// Forgets the remembered sizes of `filename` and everything below it.
+    pub(crate) fn forget_sizes(&mut self, filename: &Path) {
+        self.sizes.retain(|path, _| !path.starts_with(filename));
+    }
+
+    /// Computes how the size of the file an event reports changed since it was last seen,
+    /// and remembers the new size.
+    pub(crate) fn size_delta_for(&mut self, event: &DebouncedEvent) -> Option<SizeDelta> {
+        let path = event.paths.last()?;
+        let tracked = self
+            .watches
+            .values()
+            .any(|watch| watch.track_size && self.watch_reports(watch, path));
+        if !tracked {
+            return None;
+        }
+
+        let kind = EventKind::of(event);
+        let Some(size) = file_size(path).filter(|_| kind != EventKind::Removed) else {
+            self.sizes.remove(path);
+            return None;
+        };
+        let previous = self.sizes.insert(path.clone(), size)?;
+        (kind == EventKind::Modified).then(|| SizeDelta::between(previous, size))
+    }
+
+    /// Returns `true` if a watch attaches size changes to its events.
+    pub(crate) fn has_size_tracking(&self) -> bool {
+        self.watches.values().any(|watch| watch.track_size)
+    }
+}
+
+/// Returns the size of `path`, or `None` if it is not a file.
+fn file_size(path: &Path) -> Option<u64> {
+    fs::metadata(path)
+        .ok()
+        .filter(|metadata| metadata.is_file())
+        .map(|metadata| metadata.len())
+}
@@ -0,0 +1,197 @@
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use crate::{event::Details, state::State, DebouncedEvent, Error, EventKind};
+
+/// The subdirectory of a snapshot directory holding the copy of the current version of
+/// each file, which becomes a snapshot when the file is modified.
+const CURRENT_DIR: &str = ".current";
+
+/// Where the watch on a path keeps the previous versions of its files.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct SnapshotDir {
+    dir: PathBuf,
+    keep: usize,
+}
+
+/// A file of a batch whose versions are recorded once the state is unlocked.
+pub(crate) struct SnapshotJob {
+    /// The position of the event in the batch.
+    index: usize,
+    path: PathBuf,
+    /// The path of the file's versions below the snapshot directory.
+    relative: PathBuf,
+    snapshots: SnapshotDir,
+    /// Whether the event modified the file, so that the previous version is kept.
+    modified: bool,
+}
+
+impl State {
+    /// Keeps up to `keep` previous versions of the files reported by the watch on
+    /// `filename` in `dir`.
+    pub(crate) fn add_snapshots(
+        &mut self,
+        filename: &Path,
+        dir: PathBuf,
+        keep: usize,
+    ) -> Result<(), Error> {
+        let Some(watch) = self.watches.get_mut(filename) else {
+            return Err(Error::watch_not_found().add_path(filename.to_path_buf()));
+        };
+        fs::create_dir_all(dir.join(CURRENT_DIR))
+            .map_err(|error| Error::io(error).add_path(dir.clone()))?;
+        watch.snapshot_dir = Some(dir.clone());
+        watch.snapshot_versions = keep;
+
+        // Files below a directory are copied when they are created or first change.
+        let snapshots = SnapshotDir { dir, keep };
+        if filename.is_file() {
+            if let Some(name) = filename.file_name() {
+                keep_current(filename, &snapshots, Path::new(name))
+                    .map_err(|error| Error::io(error).add_path(filename.to_path_buf()))?;
+            }
+        }
+        self.snapshots.insert(filename.to_path_buf(), snapshots);
+
+        Ok(())
+    }
+
+    pub(crate) fn forget_snapshots(&mut self, filename: &Path) {
+        self.snapshots.remove(filename);
+    }
+
+    /// Returns the files of a batch whose versions must be recorded.
+    pub(crate) fn snapshot_jobs(&self, events: &[(DebouncedEvent, Details)]) -> Vec<SnapshotJob> {
+        if self.snapshots.is_empty() {
+            return Vec::new();
+        }
+        events
+            .iter()
+            .enumerate()
+            .filter_map(|(index, (event, details))| {
+                let kind = details.kind(event);
+                if !matches!(
+                    kind,
+                    EventKind::Created
+                        | EventKind::Existing
+                        | EventKind::Modified
+                        | EventKind::Renamed
+                ) {
+                    return None;
+                }
+                let path = event.paths.last()?;
+                let (relative, snapshots) = self.snapshots_of(path)?;
+                Some(SnapshotJob {
+                    index,
+                    path: path.clone(),
+                    relative,
+                    snapshots,
+                    modified: kind == EventKind::Modified,
+                })
+            })
+            .collect()
+    }
+
+    /// Returns where the most specific watch reporting `path` with snapshots keeps its
+    /// versions, and their path below the snapshot directory.
+    fn snapshots_of(&self, path: &Path) -> Option<(PathBuf, SnapshotDir)> {
+        let watch = self
+            .watches
+            .values()
+            .filter(|watch| watch.snapshot_dir.is_some() && self.watch_reports(watch, path))
+            .max_by_key(|watch| watch.path.components().count())?;
+        let relative = match path.strip_prefix(&watch.path) {
+            Ok(relative) if !relative.as_os_str().is_empty() => relative.to_path_buf(),
+            _ => PathBuf::from(path.file_name()?),
+        };
+        Some((relative, self.snapshots.get(&watch.path)?.clone()))
+    }
+}
+
+/// Records the versions of the files of `jobs`, attaching the snapshot of the previous
+/// version to the modifications of the batch.
+pub(crate) fn take(events: &mut [(DebouncedEvent, Details)], jobs: Vec<SnapshotJob>) {
+    for job in jobs {
+        if !job.path.is_file() {
+            continue;
+        }
+        if job.modified {
+            match keep_previous(&job.snapshots, &job.relative) {
+                Ok(snapshot) => events[job.index].1.snapshot = snapshot,
+                Err(error) => tracing::warn!(
+                    "Failed to keep the previous version of {}: {}",
+                    job.path.display(),
+                    error
+                ),
+            }
+        }
+        if let Err(error) = keep_current(&job.path, &job.snapshots, &job.relative) {
+            tracing::warn!(
+                "Failed to copy the current version of {}: {}",
+                job.path.display(),
+                error
+            );
+        }
+    }
+}
+
+/// Copies the file at `path` as the current version of the file at `relative`.
+fn keep_current(path: &Path, snapshots: &SnapshotDir, relative: &Path) -> io::Result<()> {
+    let current = snapshots.dir.join(CURRENT_DIR).join(relative);
+    if let Some(parent) = current.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::copy(path, current).map(drop)
+}
+
+/// Turns the current version of the file at `relative` into its newest snapshot, removing
+/// the oldest snapshots beyond those kept, and returns the path of the snapshot.
+///
+/// Snapshots are numbered like the backups of GNU tools, so the versions of `app.conf` are
+/// `app.conf.~1~`, `app.conf.~2~` and so on, the highest being the newest.
+fn keep_previous(snapshots: &SnapshotDir, relative: &Path) -> io::Result<Option<PathBuf>> {
+    let current = snapshots.dir.join(CURRENT_DIR).join(relative);
+    if snapshots.keep == 0 || !current.is_file() {
+        return Ok(None);
+    }
+    let Some(name) = relative.file_name().and_then(|name| name.to_str()) else {
+        return Ok(None);
+    };
+    let target = snapshots.dir.join(relative);
+    let parent = target.parent().unwrap_or(&snapshots.dir);
+    fs::create_dir_all(parent)?;
+
+    let mut versions = versions(parent, name)?;
+    let next = versions.last().map_or(1, |(number, _)| number + 1);
+    let snapshot = parent.join(format!("{name}.~{next}~"));
+    fs::rename(current, &snapshot)?;
+    versions.push((next, snapshot.clone()));
+    let excess = versions.len().saturating_sub(snapshots.keep);
+    for (_, old) in versions.drain(..excess) {
+        fs::remove_file(old)?;
+    }
+    Ok(Some(snapshot))
+}
+
+/// Returns the snapshots of the file `name` in `dir`, oldest first.
+fn versions(dir: &Path, name: &str) -> io::Result<Vec<(u64, PathBuf)>> {
+    let mut versions = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let file_name = entry.file_name();
+        let Some(number) = file_name
+            .to_str()
+            .and_then(|file_name| file_name.strip_prefix(name))
+            .and_then(|rest| rest.strip_prefix(".~"))
+            .and_then(|rest| rest.strip_suffix('~'))
+            .and_then(|number| number.parse().ok())
+        else {
+            continue;
+        };
+        versions.push((number, entry.path()));
+    }
+    versions.sort();
+    Ok(versions)
+}
@@ -0,0 +1,572 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, MutexGuard, OnceLock, Weak},
+    time::Duration,
+};
+
+use notify::{RecursiveMode, Watcher as _};
+use notify_debouncer_full::file_id::FileId;
+
+use crate::{
+    backend::{AnyWatcher, Source},
+    change::Fingerprint,
+    checksum::{self, Digest},
+    depth::DepthWatch,
+    dispatch::{self, SharedDispatcher},
+    event::{Batch, Details},
+    glob::GlobWatch,
+    helpers::HelperDir,
+    json::Value,
+    panic,
+    pending::PendingWatch,
+    raw::{self, SharedRaw},
+    rename::FileIdTracker,
+    snapshot::{self, SnapshotDir},
+    strategy::LeadingWatch,
+    symlink::LinkWatch,
+    tree::SharedIndex,
+    validate::{self, Validator},
+    verify::Verifier,
+    DebounceEventResult, DebouncedEvent, Encoding, Error, EventKindMask, FilterSet, WatchInfo,
+};
+
+/// The watch set of a [`Watcher`](crate::Watcher) together with the backend that serves it.
+///
+/// The state lives behind a shared lock so that handles such as
+/// [`WatchGuard`](crate::WatchGuard) can remove their watch without borrowing the watcher.
+pub(crate) struct State {
+    pub(crate) debouncer: Debouncer,
+    /// The back-end of `debouncer` and of the additional debouncers.
+    pub(crate) backend: Source,
+    /// The debounce duration of `debouncer`.
+    pub(crate) debounce: Duration,
+    /// Additional debouncers serving watches that override the debounce duration.
+    pub(crate) debouncers: BTreeMap<Duration, Debouncer>,
+    pub(crate) mode: RecursiveMode,
+    /// Whether watched directories are watched again after being removed and recreated.
+    pub(crate) rewatch_recreated: bool,
+    pub(crate) watches: BTreeMap<PathBuf, WatchInfo>,
+    pub(crate) pending: BTreeMap<PathBuf, PendingWatch>,
+    pub(crate) links: BTreeMap<PathBuf, LinkWatch>,
+    pub(crate) files: BTreeMap<PathBuf, Option<FileId>>,
+    pub(crate) helpers: BTreeMap<PathBuf, HelperDir>,
+    pub(crate) globs: BTreeMap<PathBuf, GlobWatch>,
+    pub(crate) depths: BTreeMap<PathBuf, DepthWatch>,
+    /// The content hashes of files whose unchanged rewrites are not reported.
+    pub(crate) hashes: BTreeMap<PathBuf, u64>,
+    /// The text of files whose events carry a diff against their previous version.
+    pub(crate) contents: BTreeMap<PathBuf, String>,
+    /// The sizes of files whose modifications carry the change in size.
+    pub(crate) sizes: BTreeMap<PathBuf, u64>,
+    /// The checksums of files whose modifications carry the old and the new checksum.
+    pub(crate) checksums: BTreeMap<PathBuf, Digest>,
+    /// The sizes and content hashes of files whose modifications carry a change summary.
+    pub(crate) fingerprints: BTreeMap<PathBuf, Fingerprint>,
+    /// The last versions that parsed of the files whose modifications carry the changed keys.
+    pub(crate) documents: BTreeMap<PathBuf, Value>,
+    /// The validators of watches whose files are validated when they change.
+    pub(crate) validators: BTreeMap<PathBuf, Validator>,
+    /// Where watches keeping snapshots keep the previous versions of their files.
+    pub(crate) snapshots: BTreeMap<PathBuf, SnapshotDir>,
+    /// The largest file whose content the watcher reads, if it reads contents.
+    pub(crate) contents_limit: Option<u64>,
+    /// The encodings of the files whose content the watcher reads, as they were last seen.
+    pub(crate) encodings: BTreeMap<PathBuf, Encoding>,
+    /// The filter sets of watches that deliver only some of their events.
+    pub(crate) filters: BTreeMap<PathBuf, FilterSet>,
+    /// The kinds of events delivered by watches that deliver only some kinds.
+    pub(crate) kinds: BTreeMap<PathBuf, EventKindMask>,
+    /// The watches whose modifications are delivered when the writer closes the file.
+    pub(crate) close_write: BTreeSet<PathBuf>,
+    /// The watches delivering only the first event of a burst.
+    pub(crate) leading: BTreeMap<PathBuf, LeadingWatch>,
+    /// The indexes of the trees kept by [`TreeWatcher`](crate::TreeWatcher)s, by their root.
+    pub(crate) trees: BTreeMap<PathBuf, SharedIndex>,
+    /// How often paths are polled once the OS runs out of file watches, if at all.
+    pub(crate) poll_interval: Option<Duration>,
+    /// The debouncer polling paths that did not fit within the OS limit on file watches.
+    pub(crate) poller: Option<Debouncer>,
+    /// The largest file whose content the pollers compare, if they compare contents.
+    pub(crate) poll_hashing: Option<u64>,
+    /// How often paths on network file systems are polled instead of watched natively, if
+    /// they are detected at all.
+    pub(crate) network_poll: Option<Duration>,
+    /// How often the watched paths are swept for changes the back-end missed, if at all.
+    pub(crate) verify_interval: Option<Duration>,
+    /// The sweep over the paths served by the back-end.
+    pub(crate) verifier: Option<Verifier>,
+    pub(crate) dispatcher: SharedDispatcher,
+    pub(crate) cell: StateCell,
+    pub(crate) raw: SharedRaw,
+    /// Errors that happened while reacting to events, to be delivered after the batch.
+    pub(crate) errors: Vec<Error>,
+}
+
+/// How [`State::serve`] registered a path.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Served {
+    /// A debouncer watches the path natively, and its cache still needs the path as a root.
+    Native,
+    /// The poller watches the path.
+    Polled,
+}
+
+impl Served {
+    /// Marks the [`WatchInfo`] of a path served this way.
+    pub(crate) fn mark(self, info: WatchInfo) -> WatchInfo {
+        match self {
+            Self::Native => info,
+            Self::Polled => info.polled(),
+        }
+    }
+}
+
+/// The backend registering paths with the OS and debouncing their events.
+pub(crate) type Debouncer = notify_debouncer_full::Debouncer<AnyWatcher, FileIdTracker>;
+
+/// A handle to the [`State`] of a watcher.
+pub(crate) type SharedState = Arc<Mutex<State>>;
+
+/// Gives the debouncer thread access to the [`State`] once the watcher has been created.
+pub(crate) type StateCell = Arc<OnceLock<Weak<Mutex<State>>>>;
+
+impl State {
+    /// Registers `filename` with both the file watcher and the debouncer's cache using `mode`.
+    pub(crate) fn add_watch(&mut self, filename: &Path, mode: RecursiveMode) -> Result<(), Error> {
+        if self.serve_watch(filename, mode)? {
+            self.debouncer.cache().add_root(filename, mode);
+            self.track_file(filename);
+            self.refresh_helpers();
+        }
+
+        Ok(())
+    }
+
+    /// Serves `filename` like [`State::serve`], and records its [`WatchInfo`], but leaves the
+    /// debouncer's cache alone.
+    ///
+    /// Returns `true` if the debouncer watches `filename`, in which case the caller still has to
+    /// add it to the cache, track it and refresh the helpers, see [`State::add_watch`].
+    pub(crate) fn serve_watch(
+        &mut self,
+        filename: &Path,
+        mode: RecursiveMode,
+    ) -> Result<bool, Error> {
+        let served = self.serve(filename, mode, None)?;
+        let info = WatchInfo::new(filename.to_path_buf(), mode);
+        self.watches
+            .insert(filename.to_path_buf(), served.mark(info));
+
+        if served == Served::Native {
+            tracing::debug!("Watching {:?} for changes: {}", mode, filename.display());
+        }
+
+        Ok(served == Served::Native)
+    }
+
+    /// Registers `filename` like [`State::add_watch`], but with a debouncer that waits for
+    /// `debounce` instead of the watcher's debounce duration.
+    pub(crate) fn add_debounced_watch(
+        &mut self,
+        filename: &Path,
+        mode: RecursiveMode,
+        debounce: Duration,
+    ) -> Result<(), Error> {
+        if debounce == self.debounce {
+            return self.add_watch(filename, mode);
+        }
+
+        let served = self.serve(filename, mode, Some(debounce))?;
+        if served == Served::Polled {
+            let info = WatchInfo::new(filename.to_path_buf(), mode);
+            self.watches
+                .insert(filename.to_path_buf(), served.mark(info));
+            return Ok(());
+        }
+        self.debouncer_with(debounce)?
+            .cache()
+            .add_root(filename, mode);
+
+        self.watches.insert(
+            filename.to_path_buf(),
+            WatchInfo::new(filename.to_path_buf(), mode).debounce(debounce),
+        );
+
+        self.track_file(filename);
+
+        self.refresh_helpers();
+
+        tracing::debug!(
+            "Watching {:?} for changes with {:?} debounce: {}",
+            mode,
+            debounce,
+            filename.display()
+        );
+
+        Ok(())
+    }
+
+    /// Registers `filename` with the debouncer waiting for `debounce`, or the watcher's
+    /// debouncer if it is `None`, and lets the verification sweep scan it.
+    ///
+    /// The path is polled instead if it lives on a network file system, see
+    /// [`State::network_poll_interval`], or if the OS ran out of file watches, see
+    /// [`State::poll_fallback`]. Every path registered with the back-end goes through here,
+    /// while recording the watch and adding it to the debouncer's cache is up to the caller.
+    pub(crate) fn serve(
+        &mut self,
+        filename: &Path,
+        mode: RecursiveMode,
+        debounce: Option<Duration>,
+    ) -> Result<Served, Error> {
+        if let Some(interval) = self.network_poll_interval(filename) {
+            self.poll(filename, mode, interval)?;
+            return Ok(Served::Polled);
+        }
+
+        let debouncer = match debounce {
+            Some(debounce) => self.debouncer_with(debounce)?,
+            None => &mut self.debouncer,
+        };
+        if let Err(error) = debouncer.watcher().watch(filename, mode) {
+            self.poll_fallback(filename, mode, error)?;
+            return Ok(Served::Polled);
+        }
+
+        self.verify_watch(filename, mode);
+
+        Ok(Served::Native)
+    }
+
+    /// Returns the additional debouncer waiting for `debounce`, creating it if no watch uses
+    /// it yet.
+    fn debouncer_with(&mut self, debounce: Duration) -> Result<&mut Debouncer, Error> {
+        if !self.debouncers.contains_key(&debounce) {
+            let debouncer = new_debouncer(
+                debounce,
+                &self.backend,
+                &self.dispatcher,
+                &self.cell,
+                &self.raw,
+            )?;
+            self.debouncers.insert(debounce, debouncer);
+            tracing::debug!("Created additional debouncer with {:?} debounce", debounce);
+        }
+        Ok(self
+            .debouncers
+            .get_mut(&debounce)
+            .expect("debouncer was just inserted"))
+    }
+
+    /// Returns the debouncer that serves the direct watch on `filename`.
+    pub(crate) fn backend(&mut self, filename: &Path) -> &mut Debouncer {
+        let debounce = self.watches.get(filename).and_then(|watch| watch.debounce);
+        match debounce.and_then(|debounce| self.debouncers.get_mut(&debounce)) {
+            Some(debouncer) => debouncer,
+            None => &mut self.debouncer,
+        }
+    }
+
+    /// Removes `filename` from the file watcher, the debouncer's cache and the routing table.
+    pub(crate) fn remove_watch(&mut self, filename: &Path) -> Result<(), Error> {
+        if !self.remove_pending_watch(filename)
+            && !self.remove_link_watch(filename)
+            && !self.remove_glob_watch(filename)
+            && !self.remove_depth_watch(filename)
+            && !self.remove_extension_watch(filename)
+            && !self.remove_polled_watch(filename)
+        {
+            let backend = self.backend(filename);
+            backend.watcher().unwatch(filename)?;
+
+            backend.cache().remove_root(filename);
+
+            self.watches.remove(filename);
+
+            self.untrack_file(filename);
+
+            self.refresh_helpers();
+        }
+
+        dispatch::lock(&self.dispatcher).remove_route(filename);
+        self.forget_hashes(filename);
+        self.forget_contents(filename);
+        self.forget_sizes(filename);
+        self.forget_checksums(filename);
+        self.forget_fingerprints(filename);
+        self.forget_documents(filename);
+        self.forget_validator(filename);
+        self.forget_snapshots(filename);
+        self.forget_encodings(filename);
+        self.forget_filters(filename);
+        self.forget_strategy(filename);
+        self.forget_settle(filename);
+        self.forget_close_write(filename);
+        self.forget_verify(filename);
+
+        tracing::debug!("Stopped watching file: {}", filename.display());
+
+        Ok(())
+    }
+}
+
+impl State {
+    /// Removes every watch, keeping the backend alive for new ones.
+    pub(crate) fn clear(&mut self) {
+        let filenames: Vec<PathBuf> = self.watches.keys().cloned().collect();
+        for filename in &filenames {
+            if let Err(error) = self.remove_watch(filename) {
+                tracing::debug!("Failed to unwatch {}: {}", filename.display(), error);
+                self.watches.remove(filename);
+            }
+        }
+
+        // Anything left over, such as files that were replaced after their watch was removed.
+        self.files.clear();
+
+        tracing::debug!("Cleared {} watches", filenames.len());
+    }
+}
+
+impl State {
+    /// Updates the watch set from a debounced batch before it is dispatched.
+    fn process(&mut self, mut events: Vec<DebouncedEvent>) -> Vec<(DebouncedEvent, Details)> {
+        self.report_overflows(&mut events);
+        self.resolve_links(&mut events);
+        self.resolve_replaced(&mut events);
+        self.resolve_removed_dirs(&events);
+        self.correlate_renames(&mut events);
+        self.resolve_depths(&events);
+
+        let noise: Vec<bool> = events
+            .iter()
+            .map(|event| self.is_helper_noise(event))
+            .collect();
+
+        self.resolve_pending(&events);
+
+        let mut noise = noise.into_iter();
+        events.retain(|_| !noise.next().unwrap_or_default());
+
+        self.close_events(&mut events);
+        self.suppress_unchanged(&mut events);
+
+        self.tag_events(&mut events);
+        let events = self.describe(events);
+        let events = self.filter_events(events);
+        let events = self.lead_events(events);
+        self.summarize(events)
+    }
+
+    /// Attaches the details that only the watch set knows to each event.
+    fn describe(&mut self, events: Vec<DebouncedEvent>) -> Vec<(DebouncedEvent, Details)> {
+        let diffs = self.has_diffs();
+        let sizes = self.has_size_tracking();
+        let summaries = self.has_change_summaries();
+        let keys = self.has_key_tracking();
+        let counts = self.take_raw_counts(&events);
+        events
+            .into_iter()
+            .map(|event| {
+                let details = Details {
+                    root: self.root_of(&event),
+                    raw_count: raw::raw_count(&counts, &event),
+                    diff: if diffs { self.diff_for(&event) } else { None },
+                    size_delta: if sizes {
+                        self.size_delta_for(&event)
+                    } else {
+                        None
+                    },
+                    checksum: None,
+                    change: if summaries {
+                        self.change_summary_for(&event)
+                    } else {
+                        None
+                    },
+                    changed_keys: if keys {
+                        self.changed_keys_for(&event)
+                    } else {
+                        Vec::new()
+                    },
+                    validation: None,
+                    snapshot: None,
+                    encoding: self.encoding_for(&event),
+                    changed: Vec::new(),
+                    file_id: self.file_id_of(&event),
+                    existing: false,
+                    processed: None,
+                };
+                (event, details)
+            })
+            .collect()
+    }
+
+    /// Replaces the back-end's notices that events were dropped with a single
+    /// [`Error::Overflow`].
+    fn report_overflows(&mut self, events: &mut Vec<DebouncedEvent>) {
+        let count = events.len();
+        events.retain(|event| !event.need_rescan());
+        if events.len() < count {
+            tracing::warn!("The back-end's event queue overflowed");
+            self.report(Error::Overflow);
+        }
+    }
+
+    /// Records an error that happened in the background, so that it reaches the handler
+    /// instead of vanishing.
+    pub(crate) fn report(&mut self, error: impl Into<Error>) {
+        self.errors.push(error.into());
+    }
+
+    /// Returns the path of the most specific watch reporting an event.
+    pub(crate) fn root_of(&self, event: &DebouncedEvent) -> Option<PathBuf> {
+        let path = event.paths.last()?;
+        self.watches
+            .values()
+            .filter(|watch| self.watch_reports(watch, path))
+            .max_by_key(|watch| watch.path.components().count())
+            .map(|watch| watch.path.clone())
+    }
+
+    /// Returns `true` if a watch expects events for `path` through a helper directory.
+    pub(crate) fn is_expected(&self, path: &Path) -> bool {
+        self.is_pending_path(path) || self.is_link_path(path)
+    }
+}
+
+/// Creates a debouncer that hands its batches to the watch set and then to the dispatcher.
+pub(crate) fn new_debouncer(
+    debounce: Duration,
+    backend: &Source,
+    dispatcher: &SharedDispatcher,
+    cell: &StateCell,
+    raw: &SharedRaw,
+) -> Result<Debouncer, Error> {
+    spawn_debouncer(debounce, backend, dispatcher, cell, raw, false)
+}
+
+/// Creates a debouncer like [`new_debouncer`] for the verification sweep, whose batches
+/// only deliver the changes the other debouncers missed.
+pub(crate) fn new_sweep_debouncer(
+    debounce: Duration,
+    backend: &Source,
+    dispatcher: &SharedDispatcher,
+    cell: &StateCell,
+    raw: &SharedRaw,
+) -> Result<Debouncer, Error> {
+    spawn_debouncer(debounce, backend, dispatcher, cell, raw, true)
+}
+
+fn spawn_debouncer(
+    debounce: Duration,
+    backend: &Source,
+    dispatcher: &SharedDispatcher,
+    cell: &StateCell,
+    raw: &SharedRaw,
+    sweep: bool,
+) -> Result<Debouncer, Error> {
+    let dispatcher = dispatcher.clone();
+    let cell = cell.clone();
+    let panics = raw.clone();
+    let mut debouncer: Debouncer = notify_debouncer_full::new_debouncer_opt(
+        debounce,
+        None,
+        move |result: DebounceEventResult| {
+            // Handlers are isolated by the dispatcher, which leaves the filter and the
+            // watch set, so a panic below never stops the debouncer thread. It is
+            // delivered as an error instead of the batch.
+            let handled = panic::isolate(|| {
+                let (batch, mut errors) = process(&cell, result, sweep);
+                errors.extend(raw::take_panics(&panics));
+                dispatch::dispatch(&dispatcher, batch);
+                if !errors.is_empty() {
+                    dispatch::dispatch(&dispatcher, Err(errors));
+                }
+                let expired = dispatch::drain(&dispatcher).take_expired();
+                expire(&cell, &expired);
+            });
+            if let Err(error) = handled {
+                dispatch::dispatch(&dispatcher, Err(vec![error]));
+                drop(dispatch::drain(&dispatcher));
+            }
+        },
+        FileIdTracker::default(),
+        notify::Config::default(),
+    )?;
+    debouncer.watcher().start(backend, raw)?;
+    Ok(debouncer)
+}
+
+/// Lets the watch set react to a debounced batch on the debouncer thread.
+///
+/// Returns the batch to dispatch, and the errors that happened while reacting to it.
+pub(crate) fn process(
+    cell: &StateCell,
+    result: DebounceEventResult,
+    sweep: bool,
+) -> (Batch, Vec<Error>) {
+    match (result, cell.get().and_then(Weak::upgrade)) {
+        (Ok(events), Some(shared)) => {
+            let mut state = lock(&shared);
+            let events = state.verify_events(events, sweep);
+            let mut events = state.process(events);
+            let jobs = state.checksum_jobs(&events);
+            let validations = state.validation_jobs(&events);
+            let snapshots = state.snapshot_jobs(&events);
+            if !jobs.is_empty() || !validations.is_empty() || !snapshots.is_empty() {
+                // Files are hashed, validated and copied without the lock, so that large
+                // files and slow validators do not hold up registering and removing watches.
+                drop(state);
+                let digests = checksum::compute(&jobs);
+                validate::validate(&mut events, validations);
+                snapshot::take(&mut events, snapshots);
+                state = lock(&shared);
+                state.attach_checksums(&mut events, jobs, digests);
+            }
+            (Ok(events), std::mem::take(&mut state.errors))
+        }
+        (Ok(events), None) => {
+            let events = events
+                .into_iter()
+                .map(|event| (event, Details::default()))
+                .collect();
+            (Ok(events), Vec::new())
+        }
+        (Err(errors), _) => (
+            Err(errors.into_iter().map(Error::from).collect()),
+            Vec::new(),
+        ),
+    }
+}
+
+/// Removes the watches of one-shot handlers that have fired.
+///
+/// This runs after the dispatcher lock is released, as removing a watch locks it again.
+pub(crate) fn expire(cell: &StateCell, expired: &[PathBuf]) {
+    if expired.is_empty() {
+        return;
+    }
+    let Some(state) = cell.get().and_then(Weak::upgrade) else {
+        return;
+    };
+    let mut state = lock(&state);
+    for filename in expired {
+        if let Err(error) = state.remove_watch(filename) {
+            tracing::debug!(
+                "Failed to remove one-shot watch {}: {}",
+                filename.display(),
+                error
+            );
+        }
+    }
+    // Removing a one-shot watch is requested by the user, so its errors are not reported.
+    state.errors.clear();
+}
+
+/// Locks the state, recovering it if a previous holder panicked.
+pub(crate) fn lock(state: &SharedState) -> MutexGuard<'_, State> {
+    state
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
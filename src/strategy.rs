@@ -0,0 +1,143 @@
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    dispatch, event::Details, flush, json::Value, rate::RateLimit, state::State, DebouncedEvent,
+    Error,
+};
+
+/// When the events of a watch are delivered relative to the changes that cause them, see
+/// [`WatchOptions::strategy`](crate::WatchOptions::strategy).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DebounceStrategy {
+    /// Events are delivered once their path has been quiet for the debounce duration.
+    #[default]
+    Trailing,
+    /// The first event of a path is delivered right away, and the events following it are
+    /// dropped until the path has been quiet for the debounce duration.
+    Leading,
+    /// The first event of a path is delivered right away, and then at most one event per
+    /// debounce duration, the newest of those that arrived in between.
+    Throttle,
+}
+
+impl DebounceStrategy {
+    pub(crate) fn to_json(self) -> Value {
+        Value::string(match self {
+            Self::Trailing => "trailing",
+            Self::Leading => "leading",
+            Self::Throttle => "throttle",
+        })
+    }
+
+    pub(crate) fn from_json(value: &Value) -> Result<Self, Error> {
+        match value.as_str()? {
+            "trailing" => Ok(Self::Trailing),
+            "leading" => Ok(Self::Leading),
+            "throttle" => Ok(Self::Throttle),
+            other => Err(Error::generic(&format!(
+                "unknown debounce strategy {other:?}"
+            ))),
+        }
+    }
+}
+
+/// How long the debouncer of a leading or throttled watch waits, so that events are
+/// delivered shortly after the change instead of a whole debounce duration later.
+pub(crate) const EDGE_DEBOUNCE: Duration = Duration::from_millis(20);
+
+/// The paths below a [`DebounceStrategy::Leading`] watch that were reported recently.
+#[derive(Clone)]
+pub(crate) struct LeadingWatch {
+    interval: Duration,
+    /// When each path last had an event, whether it was delivered or dropped.
+    last: BTreeMap<PathBuf, Instant>,
+}
+
+impl State {
+    /// Delivers the events below the watch on `filename` according to `strategy`, with
+    /// `interval` taking the place of the debounce duration.
+    pub(crate) fn add_strategy(
+        &mut self,
+        filename: &Path,
+        strategy: DebounceStrategy,
+        interval: Duration,
+    ) -> Result<(), Error> {
+        let Some(watch) = self.watches.get_mut(filename) else {
+            return Err(Error::watch_not_found().add_path(filename.to_path_buf()));
+        };
+        watch.strategy = strategy;
+
+        match strategy {
+            DebounceStrategy::Trailing => {}
+            DebounceStrategy::Leading => {
+                self.leading.insert(
+                    filename.to_path_buf(),
+                    LeadingWatch {
+                        interval,
+                        last: BTreeMap::new(),
+                    },
+                );
+            }
+            DebounceStrategy::Throttle => {
+                let limit = RateLimit {
+                    max_events: 1,
+                    per: interval,
+                };
+                dispatch::lock(&self.dispatcher).limit_root(filename, limit);
+                flush::start_flusher(&self.dispatcher);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Returns the interval that takes the place of the debounce duration of the leading or
+    /// throttled watch on `filename`.
+    pub(crate) fn strategy_interval(&self, filename: &Path) -> Option<Duration> {
+        match self.leading.get(filename) {
+            Some(watch) => Some(watch.interval),
+            None => dispatch::lock(&self.dispatcher)
+                .root_limit(filename)
+                .map(|limit| limit.per),
+        }
+    }
+
+    pub(crate) fn forget_strategy(&mut self, filename: &Path) {
+        if self.leading.remove(filename).is_none() {
+            dispatch::lock(&self.dispatcher).unlimit_root(filename);
+        }
+    }
+
+    /// Drops the events below leading watches whose path had another event within the
+    /// interval of its watch.
+    pub(crate) fn lead_events(
+        &mut self,
+        mut events: Vec<(DebouncedEvent, Details)>,
+    ) -> Vec<(DebouncedEvent, Details)> {
+        if self.leading.is_empty() {
+            return events;
+        }
+        let now = Instant::now();
+        events.retain(|(event, details)| {
+            let (Some(watch), Some(path)) = (
+                details
+                    .root
+                    .as_ref()
+                    .and_then(|root| self.leading.get_mut(root)),
+                event.paths.last(),
+            ) else {
+                return true;
+            };
+            let interval = watch.interval;
+            watch
+                .last
+                .retain(|_, last| now.duration_since(*last) < interval);
+            watch.last.insert(path.clone(), now).is_none()
+        });
+        events
+    }
+}
@@ -0,0 +1,907 @@
+use std::{
+    collections::VecDeque,
+    future::Future,
+    ops::{Deref, DerefMut},
+    pin::Pin,
+    sync::{Arc, Condvar, Mutex, MutexGuard},
+    task::{Context, Poll, Wake, Waker},
+    thread::{self, Thread},
+    time::{Duration, Instant},
+};
+
+use crate::{CancellationToken, Error, Event, EventHandler, EventResult, Watcher, WatcherBuilder};
+
+/// A [`Watcher`] whose events are awaited instead of passed to a callback.
+///
+/// The watcher dereferences to [`Watcher`], so paths are registered as usual, and its events
+/// are consumed one at a time with [`AsyncWatcher::next`]:
+///
+/// ```no_run
+/// # async fn run() -> Result<(), watchit::Error> {
+/// let mut watcher = watchit::AsyncWatcher::new()?;
+/// watcher.watch("Cargo.toml")?;
+/// while let Some(event) = watcher.next().await {
+///     println!("{}", event?);
+/// }
+/// # Ok(())
+/// # }
+/// ```
+///
+/// Events are queued by the debouncer thread and wake the task awaiting them. Nothing but
+/// the standard library's [`Waker`] is involved, so the watcher works the same on Tokio,
+/// async-std, smol or any other executor, and pulls in none of them. By default, the queue
+/// is unbounded, so events that are never awaited accumulate; see [`WatcherBuilder::queue`]
+/// to bound it.
+pub struct AsyncWatcher {
+    watcher: Watcher,
+    stream: EventStream,
+}
+
+impl AsyncWatcher {
+    /// Creates a new asynchronous file watcher.
+    ///
+    /// Events are debounced for 2 seconds, like with [`Watcher::new`]. Use
+    /// [`WatcherBuilder::build_async`] to configure the watcher further.
+    ///
+    /// # Returns
+    /// A `Result` containing either a new instance of the file watcher, or an `Error` if the
+    /// underlying OS watcher could not be created.
+    pub fn new() -> Result<Self, Error> {
+        WatcherBuilder::new().build_async()
+    }
+
+    pub(crate) fn from_parts(watcher: Watcher, stream: EventStream) -> Self {
+        Self { watcher, stream }
+    }
+
+    /// Waits for the next event.
+    ///
+    /// # Returns
+    /// The next event, an error the watcher reported instead, or `None` once the watcher can
+    /// no longer deliver events.
+    #[allow(clippy::should_implement_trait)]
+    pub fn next(&mut self) -> Next<'_> {
+        self.stream.next()
+    }
+
+    /// Polls for the next event, like `futures::Stream::poll_next`.
+    ///
+    /// If no event is queued, the task of `cx` is woken once one arrives.
+    pub fn poll_next(&mut self, cx: &mut Context<'_>) -> Poll<Option<Result<Event, Error>>> {
+        self.stream.poll_next(cx)
+    }
+
+    /// Separates the watcher from its events, so that the events can be awaited by a task of
+    /// their own while the watch set is changed elsewhere.
+    ///
+    /// The stream ends once the returned [`Watcher`] and its clones are dropped and the events
+    /// queued before have been awaited.
+    pub fn split(self) -> (Watcher, EventStream) {
+        (self.watcher, self.stream)
+    }
+}
+
+impl Deref for AsyncWatcher {
+    type Target = Watcher;
+
+    fn deref(&self) -> &Watcher {
+        &self.watcher
+    }
+}
+
+impl DerefMut for AsyncWatcher {
+    fn deref_mut(&mut self) -> &mut Watcher {
+        &mut self.watcher
+    }
+}
+
+/// The events of an [`AsyncWatcher`], separated from it with [`AsyncWatcher::split`], or of
+/// a subscription, see [`Watcher::subscribe`].
+///
+/// The stream can be moved to another thread or task, and is awaited with any executor.
+pub struct EventStream {
+    shared: Arc<Shared>,
+}
+
+/// The sending half of an [`EventStream`], handed to the dispatcher as the event handler.
+pub(crate) struct StreamSender {
+    shared: Arc<Shared>,
+}
+
+/// What happens to an event that arrives while a bounded queue is full, see
+/// [`WatcherBuilder::queue`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Overflow {
+    /// The oldest queued event is dropped to make room.
+    DropOldest,
+    /// The new event is dropped.
+    DropNewest,
+    /// The new event replaces the queued event of the same path, if there is one. Otherwise
+    /// the oldest queued event is dropped.
+    Coalesce,
+    /// The debouncer thread waits until the consumer makes room, which slows down the
+    /// watcher instead of losing events. The consumer must not run on the thread that
+    /// registers watches with [`WatchOptions::initial_scan`](crate::WatchOptions::initial_scan)
+    /// or resumes the watcher, as those deliver events on the calling thread.
+    Block,
+}
+
+/// The capacity of a bounded queue, and what happens once it is full.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct QueueLimit {
+    pub(crate) capacity: usize,
+    pub(crate) overflow: Overflow,
+}
+
+struct Shared {
+    queue: Mutex<Queue>,
+    /// Signalled whenever the consumer takes an item or goes away.
+    space: Condvar,
+    limit: Option<QueueLimit>,
+}
+
+/// The events delivered but not yet awaited, and the task waiting for them.
+#[derive(Default)]
+struct Queue {
+    items: VecDeque<Result<Event, Error>>,
+    waker: Option<Waker>,
+    /// Whether the sender is gone, so that no more items arrive.
+    closed: bool,
+    /// Whether the stream is gone, so that items are no longer awaited.
+    abandoned: bool,
+}
+
+pub(crate) fn event_stream(limit: Option<QueueLimit>) -> (StreamSender, EventStream) {
+    let shared = Arc::new(Shared {
+        queue: Mutex::new(Queue::default()),
+        space: Condvar::new(),
+        limit: limit.map(|limit| QueueLimit {
+            capacity: limit.capacity.max(1),
+            ..limit
+        }),
+    });
+    (
+        StreamSender {
+            shared: shared.clone(),
+        },
+        EventStream { shared },
+    )
+}
+
+impl EventStream {
+    /// Waits for the next event, see [`AsyncWatcher::next`].
+    #[allow(clippy::should_implement_trait)]
+    pub fn next(&mut self) -> Next<'_> {
+        Next { stream: self }
+    }
+
+    /// Polls for the next event, see [`AsyncWatcher::poll_next`].
+    pub fn poll_next(&mut self, cx: &mut Context<'_>) -> Poll<Option<Result<Event, Error>>> {
+        let mut queue = self.shared.lock();
+        if let Some(item) = queue.items.pop_front() {
+            self.shared.space.notify_all();
+            return Poll::Ready(Some(item));
+        }
+        if queue.closed {
+            return Poll::Ready(None);
+        }
+        queue.waker = Some(cx.waker().clone());
+        Poll::Pending
+    }
+}
+
+impl EventStream {
+    /// Blocks the current thread until the next item arrives, or until `deadline` if there
+    /// is one.
+    pub(crate) fn wait(&mut self, deadline: Option<Instant>) -> Option<Result<Event, Error>> {
+        let waker = Waker::from(Arc::new(Unpark(thread::current())));
+        let mut cx = Context::from_waker(&waker);
+        loop {
+            if let Poll::Ready(item) = self.poll_next(&mut cx) {
+                return item;
+            }
+            match deadline {
+                Some(deadline) => {
+                    let now = Instant::now();
+                    if now >= deadline {
+                        return None;
+                    }
+                    thread::park_timeout(deadline - now);
+                }
+                None => thread::park(),
+            }
+        }
+    }
+
+    /// Ends the stream once `token` is cancelled, dropping the events still queued.
+    pub(crate) fn cancel_on(&self, token: &CancellationToken) {
+        let shared = Arc::downgrade(&self.shared);
+        token.on_cancel(move || {
+            if let Some(shared) = shared.upgrade() {
+                shared.cancel();
+            }
+        });
+    }
+}
+
+impl Drop for EventStream {
+    fn drop(&mut self) {
+        self.shared.lock().abandoned = true;
+        self.shared.space.notify_all();
+    }
+}
+
+impl StreamSender {
+    /// Returns `true` if the stream was dropped.
+    pub(crate) fn is_closed(&self) -> bool {
+        self.shared.lock().abandoned
+    }
+}
+
+impl Shared {
+    fn lock(&self) -> MutexGuard<'_, Queue> {
+        self.queue
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Ends the stream right away, and releases a sender waiting for room.
+    fn cancel(&self) {
+        let mut queue = self.lock();
+        queue.items.clear();
+        queue.closed = true;
+        queue.abandoned = true;
+        if let Some(waker) = queue.waker.take() {
+            waker.wake();
+        }
+        self.space.notify_all();
+    }
+
+    /// Queues `item`, applying the overflow policy if the queue is full.
+    fn push<'a>(
+        &'a self,
+        mut queue: MutexGuard<'a, Queue>,
+        item: Result<Event, Error>,
+    ) -> MutexGuard<'a, Queue> {
+        let Some(limit) = self.limit else {
+            queue.items.push_back(item);
+            return queue;
+        };
+
+        while queue.items.len() >= limit.capacity {
+            if queue.abandoned {
+                return queue;
+            }
+            match limit.overflow {
+                Overflow::DropOldest => {
+                    queue.items.pop_front();
+                }
+                Overflow::DropNewest => {
+                    tracing::trace!("Dropped the newest event of a full queue");
+                    return queue;
+                }
+                Overflow::Coalesce => {
+                    let same_path = item.as_ref().ok().and_then(|event| {
+                        queue.items.iter().position(|queued| {
+                            queued
+                                .as_ref()
+                                .is_ok_and(|queued| queued.path == event.path)
+                        })
+                    });
+                    match same_path {
+                        Some(index) => {
+                            queue.items[index] = item;
+                            return queue;
+                        }
+                        None => {
+                            queue.items.pop_front();
+                        }
+                    }
+                }
+                Overflow::Block => {
+                    if let Some(waker) = queue.waker.take() {
+                        waker.wake();
+                    }
+                    queue = self
+                        .space
+                        .wait(queue)
+                        .unwrap_or_else(|poisoned| poisoned.into_inner());
+                }
+            }
+        }
+        queue.items.push_back(item);
+        queue
+    }
+}
+
+impl EventHandler for StreamSender {
+    fn handle_event(&mut self, result: EventResult) {
+        let items: Vec<Result<Event, Error>> = match result {
+            Ok(events) => events.into_iter().map(Ok).collect(),
+            Err(errors) => errors.into_iter().map(Err).collect(),
+        };
+        let mut queue = self.shared.lock();
+        for item in items {
+            queue = self.shared.push(queue, item);
+        }
+        if let Some(waker) = queue.waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+impl Drop for StreamSender {
+    fn drop(&mut self) {
+        let mut queue = self.shared.lock();
+        queue.closed = true;
+        if let Some(waker) = queue.waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+/// A blocking iterator over the events of a [`Watcher`], see [`Watcher::events`].
+pub struct Events {
+    stream: EventStream,
+}
+
+impl Events {
+    pub(crate) fn new(stream: EventStream) -> Self {
+        Self { stream }
+    }
+
+    /// Waits up to `timeout` for the next event.
+    ///
+    /// This suits polling loops that have other periodic work to do, and tests that must
+    /// not hang when an expected event never arrives:
+    ///
+    /// ```no_run
+    /// use std::time::Duration;
+    /// use watchit::{DebounceEventResult, Watcher};
+    ///
+    /// let watcher = Watcher::new(|_: DebounceEventResult| {}).unwrap();
+    /// watcher.watch("src").unwrap();
+    /// let mut events = watcher.events();
+    /// loop {
+    ///     match events.next_event_timeout(Duration::from_secs(1)) {
+    ///         Some(event) => println!("{event}"),
+    ///         None => println!("still waiting"),
+    ///     }
+    /// }
+    /// ```
+    ///
+    /// # Returns
+    /// The next event, or `None` if none arrived in time or the watcher was dropped.
+    pub fn next_event_timeout(&mut self, timeout: Duration) -> Option<Event> {
+        self.next_before(Instant::now().checked_add(timeout))
+    }
+
+    /// Blocks until the next event arrives, or until `deadline` if there is one.
+    fn next_before(&mut self, deadline: Option<Instant>) -> Option<Event> {
+        loop {
+            match self.stream.wait(deadline)? {
+                Ok(event) => return Some(event),
+                Err(error) => {
+                    tracing::debug!("Skipped error in blocking iterator: {}", error);
+                }
+            }
+        }
+    }
+}
+
+impl Iterator for Events {
+    type Item = Event;
+
+    /// Blocks until the next event arrives, or returns `None` once the watcher is dropped.
+    fn next(&mut self) -> Option<Event> {
+        self.next_before(None)
+    }
+}
+
+/// Wakes a thread blocked on a stream.
+struct Unpark(Thread);
+
+impl Wake for Unpark {
+    fn wake(self: Arc<Self>) {
+        self.0.unpark();
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        self.0.unpark();
+    }
+}
+
+/// A future spawned for an event by a handler of [`Watcher::new_async`].
+pub type HandlerFuture = Pin<Box<dyn Future<Output = ()> + Send + 'static>>;
+
+/// Runs the futures of asynchronous handlers, see [`Watcher::new_async`].
+///
+/// The trait is implemented for closures taking a [`HandlerFuture`], so that any executor can
+/// be plugged in, such as `|future| { tokio::spawn(future); }` or
+/// `|future| smol::spawn(future).detach()`.
+pub trait Spawn: Send + 'static {
+    /// Starts running `future` in the background.
+    fn spawn(&self, future: HandlerFuture);
+}
+
+impl<F> Spawn for F
+where
+    F: Fn(HandlerFuture) + Send + 'static,
+{
+    fn spawn(&self, future: HandlerFuture) {
+        (self)(future);
+    }
+}
+
+/// Spawns a future of an asynchronous handler for each event or error.
+pub(crate) struct AsyncHandler<S, H> {
+    pub(crate) spawn: S,
+    pub(crate) handler: H,
+    pub(crate) in_flight: InFlight,
+}
+
+impl<S, H, F> EventHandler for AsyncHandler<S, H>
+where
+    S: Spawn,
+    H: FnMut(Result<Event, Error>) -> F + Send + 'static,
+    F: Future<Output = ()> + Send + 'static,
+{
+    fn handle_event(&mut self, result: EventResult) {
+        let items: Vec<Result<Event, Error>> = match result {
+            Ok(events) => events.into_iter().map(Ok).collect(),
+            Err(errors) => errors.into_iter().map(Err).collect(),
+        };
+        for item in items {
+            let future = self.in_flight.track((self.handler)(item));
+            self.spawn.spawn(future);
+        }
+    }
+}
+
+/// The future returned by [`AsyncWatcher::next`] and [`EventStream::next`].
+pub struct Next<'a> {
+    stream: &'a mut EventStream,
+}
+
+impl Future for Next<'_> {
+    type Output = Option<Result<Event, Error>>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        self.stream.poll_next(cx)
+    }
+}
+
+/// Counts the futures of an asynchronous handler that have not finished yet, so that
+/// [`Watcher::shutdown_async`] can wait for them.
+#[derive(Clone, Default)]
+pub(crate) struct InFlight {
+    shared: Arc<Mutex<Running>>,
+}
+
+#[derive(Default)]
+struct Running {
+    count: usize,
+    /// When the watcher stopped delivering events, once it has.
+    stopped: Option<Instant>,
+    /// The tasks waiting for the count to drop to zero.
+    wakers: Vec<Waker>,
+}
+
+impl InFlight {
+    /// Wraps `future` so that it is counted until it finishes or is dropped unfinished.
+    fn track(&self, future: impl Future<Output = ()> + Send + 'static) -> HandlerFuture {
+        self.lock().count += 1;
+        let finished = Finished(self.clone());
+        Box::pin(async move {
+            let _finished = finished;
+            future.await;
+        })
+    }
+
+    /// Records that the watcher stopped, so that no more futures are started.
+    pub(crate) fn stopped(&self) {
+        self.lock().stopped = Some(Instant::now());
+        self.wake_all();
+    }
+
+    fn wake_all(&self) {
+        for waker in std::mem::take(&mut self.lock().wakers) {
+            waker.wake();
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Running> {
+        self.shared
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+/// Marks a tracked future as finished when it is dropped.
+struct Finished(InFlight);
+
+impl Drop for Finished {
+    fn drop(&mut self) {
+        let done = {
+            let mut running = self.0.lock();
+            running.count -= 1;
+            running.count == 0
+        };
+        if done {
+            self.0.wake_all();
+        }
+    }
+}
+
+/// The future returned by [`Watcher::shutdown_async`].
+///
+/// It resolves to `true` once the watcher has stopped and every future of its asynchronous
+/// handler has finished, or to `false` if some were still running when the grace period
+/// ran out.
+pub struct Shutdown {
+    in_flight: InFlight,
+    grace: Duration,
+    timer: bool,
+}
+
+impl Shutdown {
+    pub(crate) fn new(in_flight: InFlight, grace: Duration) -> Self {
+        Self {
+            in_flight,
+            grace,
+            timer: false,
+        }
+    }
+}
+
+impl Future for Shutdown {
+    type Output = bool;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<bool> {
+        let deadline = {
+            let mut running = self.in_flight.lock();
+            if !running
+                .wakers
+                .iter()
+                .any(|waker| waker.will_wake(cx.waker()))
+            {
+                running.wakers.push(cx.waker().clone());
+            }
+            let Some(stopped) = running.stopped else {
+                return Poll::Pending;
+            };
+            if running.count == 0 {
+                return Poll::Ready(true);
+            }
+            let deadline = stopped.checked_add(self.grace);
+            if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                tracing::warn!(
+                    "{} handler futures were still running after the shutdown grace period",
+                    running.count
+                );
+                return Poll::Ready(false);
+            }
+            deadline
+        };
+
+        // Executors don't share a timer, so the grace period is timed on a thread of its own.
+        if let (false, Some(deadline)) = (self.timer, deadline) {
+            self.timer = true;
+            let in_flight = self.in_flight.clone();
+            thread::spawn(move || {
+                thread::sleep(deadline.saturating_duration_since(Instant::now()));
+                in_flight.wake_all();
+            });
+        }
+        Poll::Pending
+    }
+}
+
+#[cfg(test)]
+/// This module contains tests for the asynchronous watcher.
+///
+/// The `awaits_events` test verifies that a change is delivered to a task awaiting
+/// [`AsyncWatcher::next`] on a minimal executor.
+///
+/// The `async_handler_runs_on_spawner` test verifies that the futures of an asynchronous
+/// handler are handed to its spawner and run there.
+///
+/// The `subscriptions_receive_every_event` test verifies that each subscription receives its
+/// own copy of an event that the watcher's handler receives as well.
+///
+/// The `split_stream_ends_with_watcher` test verifies that a stream separated from its watcher
+/// is awaited on another thread and ends once the watcher is dropped.
+///
+/// The `overflow_bounds_the_queue` test verifies that a full queue drops the oldest event,
+/// the newest event, or the queued event of the same path, depending on its policy.
+///
+/// The `overflow_blocks_until_consumed` test verifies that a full blocking queue holds the
+/// sender back until the stream takes an event, and releases it once the stream is dropped.
+///
+/// The `cancellation_ends_pending_streams` test verifies that cancelling a token ends the
+/// awaited stream of an asynchronous watcher and the receiver of a channel watcher.
+///
+/// The `next_event_timeout_gives_up` test verifies that waiting for an event with a timeout
+/// returns `None` once the timeout elapses, and the event if one is queued.
+///
+/// The `shutdown_waits_for_handler_futures` test verifies that shutting an asynchronous
+/// watcher down waits for the futures of its handler, unless they outlast the grace period.
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::EventKind;
+
+    /// Runs `future` to completion on the current thread.
+    fn block_on<F: Future>(future: F) -> F::Output {
+        let waker = Waker::from(Arc::new(Unpark(thread::current())));
+        let mut cx = Context::from_waker(&waker);
+        let mut future = std::pin::pin!(future);
+        loop {
+            if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+                return output;
+            }
+            thread::park();
+        }
+    }
+
+    #[test]
+    fn awaits_events() {
+        let root = std::env::temp_dir().join("watchit_async_test");
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(&root).unwrap();
+        let mut watcher = Watcher::builder()
+            .debounce(Duration::from_millis(200))
+            .build_async()
+            .unwrap();
+        watcher.watch(&root).unwrap();
+        let filename = root.join("async.txt");
+        let writer = {
+            let filename = filename.clone();
+            thread::spawn(move || {
+                thread::sleep(Duration::from_millis(100));
+                fs::write(filename, b"").unwrap();
+            })
+        };
+        let event = block_on(watcher.next()).unwrap().unwrap();
+        writer.join().unwrap();
+        assert_eq!(event.path, filename);
+        assert_eq!(event.kind, EventKind::Created);
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn split_stream_ends_with_watcher() {
+        let root = std::env::temp_dir().join("watchit_split_stream_test");
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(&root).unwrap();
+        let (watcher, mut stream) = Watcher::builder()
+            .debounce(Duration::from_millis(200))
+            .build_async()
+            .unwrap()
+            .split();
+        watcher.watch(&root).unwrap();
+        let consumer = thread::spawn(move || {
+            let mut paths = Vec::new();
+            while let Some(event) = block_on(stream.next()) {
+                paths.push(event.unwrap().path);
+            }
+            paths
+        });
+        fs::write(root.join("split.txt"), b"").unwrap();
+        thread::sleep(Duration::from_secs(1));
+        drop(watcher);
+        let paths = consumer.join().unwrap();
+        assert!(paths.contains(&root.join("split.txt")));
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn async_handler_runs_on_spawner() {
+        let root = std::env::temp_dir().join("watchit_async_handler_test");
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(&root).unwrap();
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let watcher = Watcher::builder()
+            .debounce(Duration::from_millis(200))
+            .build_async_handler(
+                |future: HandlerFuture| {
+                    thread::spawn(move || block_on(future));
+                },
+                move |event: Result<Event, Error>| {
+                    let sender = sender.clone();
+                    async move {
+                        let _ = sender.send(event.unwrap().path);
+                    }
+                },
+            )
+            .unwrap();
+        watcher.watch(&root).unwrap();
+        fs::write(root.join("spawned.txt"), b"").unwrap();
+        let path = receiver.recv_timeout(Duration::from_secs(2)).unwrap();
+        assert_eq!(path, root.join("spawned.txt"));
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn subscriptions_receive_every_event() {
+        let root = std::env::temp_dir().join("watchit_subscribe_test");
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(&root).unwrap();
+        let (sender, receiver) = std::sync::mpsc::channel::<Event>();
+        let watcher = Watcher::builder()
+            .debounce(Duration::from_millis(200))
+            .build_with_event_handler(sender)
+            .unwrap();
+        let mut first = watcher.subscribe();
+        let mut second = watcher.subscribe();
+        watcher.watch(&root).unwrap();
+        fs::write(root.join("shared.txt"), b"").unwrap();
+        let path = root.join("shared.txt");
+        assert_eq!(block_on(first.next()).unwrap().unwrap().path, path);
+        assert_eq!(block_on(second.next()).unwrap().unwrap().path, path);
+        assert_eq!(
+            receiver.recv_timeout(Duration::from_secs(1)).unwrap().path,
+            path
+        );
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    /// Returns a modification of `path`, numbered `sequence`.
+    fn modified(path: &str, sequence: u64) -> Event {
+        let time = std::time::SystemTime::now();
+        Event {
+            sequence,
+            path: std::path::PathBuf::from(path),
+            from: None,
+            kind: EventKind::Modified,
+            root: None,
+            raw_events: 1,
+            time,
+            delivered: time,
+            tags: Vec::new(),
+            metadata: None,
+            diff: None,
+            size_delta: None,
+            checksum: None,
+            change: None,
+            changed_keys: Vec::new(),
+            validation: None,
+            snapshot: None,
+            changed: Vec::new(),
+            file_id: None,
+            contents: None,
+            encoding: None,
+            previous_encoding: None,
+        }
+    }
+
+    /// Delivers `events` to a queue of two items and returns the sequence numbers it holds.
+    fn queued(overflow: Overflow, events: Vec<Event>) -> Vec<u64> {
+        let limit = QueueLimit {
+            capacity: 2,
+            overflow,
+        };
+        let (mut sender, mut stream) = event_stream(Some(limit));
+        sender.handle_event(Ok(events));
+        drop(sender);
+        let mut sequences = Vec::new();
+        while let Some(event) = block_on(stream.next()) {
+            sequences.push(event.unwrap().sequence);
+        }
+        sequences
+    }
+
+    #[test]
+    fn overflow_bounds_the_queue() {
+        let events = || vec![modified("a", 0), modified("b", 1), modified("a", 2)];
+        assert_eq!(queued(Overflow::DropOldest, events()), [1, 2]);
+        assert_eq!(queued(Overflow::DropNewest, events()), [0, 1]);
+        assert_eq!(queued(Overflow::Coalesce, events()), [2, 1]);
+
+        let events = vec![modified("a", 0), modified("b", 1), modified("c", 2)];
+        assert_eq!(queued(Overflow::Coalesce, events), [1, 2]);
+    }
+
+    #[test]
+    fn overflow_blocks_until_consumed() {
+        let limit = QueueLimit {
+            capacity: 1,
+            overflow: Overflow::Block,
+        };
+        let (mut sender, mut stream) = event_stream(Some(limit));
+        let producer = thread::spawn(move || {
+            sender.handle_event(Ok(vec![modified("a", 0), modified("b", 1)]));
+            sender.handle_event(Ok(vec![modified("c", 2), modified("d", 3)]));
+        });
+        thread::sleep(Duration::from_millis(100));
+        assert!(!producer.is_finished());
+        assert_eq!(block_on(stream.next()).unwrap().unwrap().sequence, 0);
+        assert_eq!(block_on(stream.next()).unwrap().unwrap().sequence, 1);
+        drop(stream);
+        producer.join().unwrap();
+    }
+
+    #[test]
+    fn cancellation_ends_pending_streams() {
+        let token = CancellationToken::new();
+        let mut watcher = Watcher::builder()
+            .cancellation(token.clone())
+            .build_async()
+            .unwrap();
+        let (_channel, receiver) = Watcher::builder()
+            .cancellation(token.clone())
+            .build_channel()
+            .unwrap();
+        let canceller = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(100));
+            token.cancel();
+        });
+        assert!(block_on(watcher.next()).is_none());
+        assert!(matches!(
+            receiver.recv_timeout(Duration::from_secs(1)),
+            Err(std::sync::mpsc::RecvTimeoutError::Disconnected)
+        ));
+        canceller.join().unwrap();
+        assert!(watcher
+            .subscribe()
+            .poll_next(&mut Context::from_waker(Waker::noop()))
+            .is_ready());
+    }
+
+    #[test]
+    fn next_event_timeout_gives_up() {
+        let (mut sender, stream) = event_stream(None);
+        let mut events = Events::new(stream);
+        let start = Instant::now();
+        assert!(events
+            .next_event_timeout(Duration::from_millis(100))
+            .is_none());
+        assert!(start.elapsed() >= Duration::from_millis(100));
+
+        sender.handle_event(Ok(vec![modified("a", 0)]));
+        let event = events.next_event_timeout(Duration::from_millis(100));
+        assert_eq!(event.unwrap().sequence, 0);
+    }
+
+    #[test]
+    fn shutdown_waits_for_handler_futures() {
+        let root = std::env::temp_dir().join("watchit_shutdown_async_test");
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(&root).unwrap();
+        let watcher = |handled: Arc<Mutex<Vec<std::path::PathBuf>>>, delay: Duration| {
+            Watcher::builder()
+                .debounce(Duration::from_millis(200))
+                .build_async_handler(
+                    |future: HandlerFuture| {
+                        thread::spawn(move || block_on(future));
+                    },
+                    move |event: Result<Event, Error>| {
+                        let handled = handled.clone();
+                        async move {
+                            thread::sleep(delay);
+                            handled.lock().unwrap().push(event.unwrap().path);
+                        }
+                    },
+                )
+                .unwrap()
+        };
+
+        let handled = Arc::new(Mutex::new(Vec::new()));
+        let patient = watcher(handled.clone(), Duration::from_millis(300));
+        patient.watch(&root).unwrap();
+        fs::write(root.join("patient.txt"), b"").unwrap();
+        thread::sleep(Duration::from_millis(100));
+        assert!(block_on(patient.shutdown_async(Duration::from_secs(2))));
+        assert!(handled.lock().unwrap().contains(&root.join("patient.txt")));
+
+        let handled = Arc::new(Mutex::new(Vec::new()));
+        let hasty = watcher(handled.clone(), Duration::from_secs(2));
+        hasty.watch(&root).unwrap();
+        fs::write(root.join("hasty.txt"), b"").unwrap();
+        thread::sleep(Duration::from_millis(100));
+        assert!(!block_on(hasty.shutdown_async(Duration::from_millis(100))));
+        assert!(handled.lock().unwrap().is_empty());
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
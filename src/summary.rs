@@ -0,0 +1,78 @@
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+
+use notify::event::{EventKind, ModifyKind};
+
+use crate::{event::Details, state::State, DebouncedEvent, Error};
+
+impl State {
+    /// Reports the changes below the watch on `filename` as one event per batch.
+    pub(crate) fn add_summary(&mut self, filename: &Path) -> Result<(), Error> {
+        let Some(watch) = self.watches.get_mut(filename) else {
+            return Err(Error::watch_not_found().add_path(filename.to_path_buf()));
+        };
+        watch.summarize = true;
+
+        Ok(())
+    }
+
+    /// Replaces the events of summarizing watches with one event for each watched path,
+    /// listing the paths that changed below it.
+    ///
+    /// The summary takes the place of the first event it replaces, so that the order of the
+    /// batch is kept as far as possible.
+    pub(crate) fn summarize(
+        &self,
+        events: Vec<(DebouncedEvent, Details)>,
+    ) -> Vec<(DebouncedEvent, Details)> {
+        if self.watches.values().all(|watch| !watch.summarize) {
+            return events;
+        }
+
+        let mut batch: Vec<(DebouncedEvent, Details)> = Vec::with_capacity(events.len());
+        let mut summaries: BTreeMap<PathBuf, usize> = BTreeMap::new();
+        for (event, details) in events {
+            let Some(root) = details.root.clone().filter(|root| self.summarizes(root)) else {
+                batch.push((event, details));
+                continue;
+            };
+
+            let index = *summaries.entry(root.clone()).or_insert_with(|| {
+                let kind = EventKind::Modify(ModifyKind::Any);
+                let summary = notify::Event::new(kind).add_path(root.clone());
+                let mut summary = DebouncedEvent::new(summary, event.time);
+                self.tag_events(std::slice::from_mut(&mut summary));
+                let details = Details {
+                    root: Some(root),
+                    ..Details::default()
+                };
+                batch.push((summary, details));
+                batch.len() - 1
+            });
+            let (summary, summary_details) = &mut batch[index];
+
+            summary.time = summary.time.min(event.time);
+            // Events of the same path share their raw count.
+            if event
+                .paths
+                .last()
+                .is_some_and(|path| !summary_details.changed.contains(path))
+            {
+                summary_details.raw_count += details.raw_count;
+            }
+            for path in &event.paths {
+                if !summary_details.changed.contains(path) {
+                    summary_details.changed.push(path.clone());
+                }
+            }
+        }
+
+        batch
+    }
+
+    fn summarizes(&self, root: &Path) -> bool {
+        self.watches.get(root).is_some_and(|watch| watch.summarize)
+    }
+}
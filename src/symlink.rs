@@ -0,0 +1,184 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use notify::{RecursiveMode, Watcher as _};
+
+use crate::{
+    state::{Served, State},
+    DebouncedEvent, Error, WatchInfo,
+};
+
+/// A watch on a symbolic link that follows the link to its target.
+#[derive(Debug, Clone)]
+pub(crate) struct LinkWatch {
+    /// The mode the target is watched with.
+    mode: RecursiveMode,
+    /// The resolved target currently watched in place of the link, if the link resolves.
+    target: Option<PathBuf>,
+    /// The directory holding the link, watched to notice when the link is replaced.
+    parent: PathBuf,
+}
+
+impl State {
+    /// Watches the target of the symbolic link `link`, re-resolving it when the link changes.
+    ///
+    /// If `link` is not a symbolic link it is watched directly.
+    pub(crate) fn add_link_watch(&mut self, link: &Path, mode: RecursiveMode) -> Result<(), Error> {
+        let is_symlink = fs::symlink_metadata(link)
+            .map_err(|error| Error::io(error).add_path(link.to_path_buf()))?
+            .file_type()
+            .is_symlink();
+        if !is_symlink {
+            return self.add_watch(link, mode);
+        }
+
+        let parent = match link.parent() {
+            Some(parent) if parent.as_os_str().is_empty() => Path::new("."),
+            Some(parent) => parent,
+            None => return Err(Error::path_not_found().add_path(link.to_path_buf())),
+        };
+
+        let target = fs::canonicalize(link)
+            .map_err(|error| Error::io(error).add_path(link.to_path_buf()))?;
+        self.watch_target(&target, mode)?;
+
+        if let Err(error) = self.acquire_helper(parent, RecursiveMode::NonRecursive) {
+            self.unwatch_target(&target);
+            return Err(error);
+        }
+
+        self.links.insert(
+            link.to_path_buf(),
+            LinkWatch {
+                mode,
+                target: Some(target.clone()),
+                parent: parent.to_path_buf(),
+            },
+        );
+        self.watches.insert(
+            link.to_path_buf(),
+            WatchInfo::new(link.to_path_buf(), mode).following(target.clone()),
+        );
+
+        tracing::debug!(
+            "Watching {:?} for changes: {} -> {}",
+            mode,
+            link.display(),
+            target.display()
+        );
+
+        Ok(())
+    }
+
+    /// Removes a symbolic link watch together with the watch on its target.
+    ///
+    /// Returns `false` if `link` is not a followed symbolic link.
+    pub(crate) fn remove_link_watch(&mut self, link: &Path) -> bool {
+        let Some(watch) = self.links.remove(link) else {
+            return false;
+        };
+
+        if let Some(target) = &watch.target {
+            self.unwatch_target(target);
+        }
+        self.release_helper(&watch.parent, RecursiveMode::NonRecursive);
+        self.watches.remove(link);
+
+        true
+    }
+
+    /// Rewrites event paths inside link targets so they are reported under the link, and
+    /// re-resolves links that were replaced by one of the events.
+    pub(crate) fn resolve_links(&mut self, events: &mut [DebouncedEvent]) {
+        if self.links.is_empty() {
+            return;
+        }
+
+        let changed: Vec<PathBuf> = self
+            .links
+            .keys()
+            .filter(|link| events.iter().any(|event| event.paths.contains(link)))
+            .cloned()
+            .collect();
+        for link in changed {
+            self.retarget_link(&link);
+        }
+
+        for event in events.iter_mut() {
+            for path in &mut event.paths {
+                let rewritten = self.links.iter().find_map(|(link, watch)| {
+                    let target = watch.target.as_ref()?;
+                    let rest = path.strip_prefix(target).ok()?;
+                    Some(if rest.as_os_str().is_empty() {
+                        link.clone()
+                    } else {
+                        link.join(rest)
+                    })
+                });
+                if let Some(rewritten) = rewritten {
+                    *path = rewritten;
+                }
+            }
+        }
+    }
+
+    /// Returns `true` if `path` is a followed symbolic link.
+    pub(crate) fn is_link_path(&self, path: &Path) -> bool {
+        self.links.contains_key(path)
+    }
+
+    /// Moves the watch of `link` to wherever the link points now.
+    fn retarget_link(&mut self, link: &Path) {
+        let Some(watch) = self.links.get(link).cloned() else {
+            return;
+        };
+
+        let target = fs::canonicalize(link).ok();
+        if target == watch.target {
+            return;
+        }
+
+        if let Some(old) = &watch.target {
+            self.unwatch_target(old);
+        }
+        let target = target.filter(|target| match self.watch_target(target, watch.mode) {
+            Ok(()) => true,
+            Err(error) => {
+                tracing::debug!("Failed to follow {}: {}", link.display(), error);
+                self.report(error);
+                false
+            }
+        });
+
+        tracing::debug!(
+            "Symbolic link {} now points to {:?}",
+            link.display(),
+            target
+        );
+
+        if let Some(info) = self.watches.get_mut(link) {
+            info.symlink_target = target.clone();
+        }
+        if let Some(watch) = self.links.get_mut(link) {
+            watch.target = target;
+        }
+    }
+
+    fn watch_target(&mut self, target: &Path, mode: RecursiveMode) -> Result<(), Error> {
+        if self.serve(target, mode, None)? == Served::Native {
+            self.debouncer.cache().add_root(target, mode);
+        }
+        Ok(())
+    }
+
+    /// Stops watching the target of a link, whichever way it was served.
+    fn unwatch_target(&mut self, target: &Path) {
+        if self.debouncer.watcher().unwatch(target).is_err() {
+            self.unpoll(target);
+        }
+        self.debouncer.cache().remove_root(target);
+        self.forget_verify(target);
+    }
+}
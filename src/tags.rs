@@ -0,0 +1,97 @@
+use std::path::Path;
+
+use crate::{state::State, DebouncedEvent, Error};
+
+/// Separates the tags stored in an event's `info` attribute.
+const SEPARATOR: char = ',';
+
+/// Gives access to the tags of the watches that reported an event.
+///
+/// Tags are attached with [`Watcher::watch_tagged`](crate::Watcher::watch_tagged). Because
+/// [`DebouncedEvent`] dereferences to [`notify::Event`], the tags can be read directly from
+/// the events passed to a handler:
+///
+/// ```no_run
+/// use watchit::{DebounceEventResult, EventTags, Watcher};
+///
+/// let watcher = Watcher::new(|result: DebounceEventResult| {
+///     for event in result.unwrap_or_default() {
+///         if event.tags().any(|tag| tag == "templates") {
+///             println!("reloading templates after {:?}", event.kind);
+///         }
+///     }
+/// })
+/// .unwrap();
+/// watcher.watch_tagged("templates", "templates").unwrap();
+/// ```
+pub trait EventTags {
+    /// Returns the tags of every watch that reported the event, in sorted order.
+    fn tags(&self) -> impl Iterator<Item = &str>;
+}
+
+impl EventTags for notify::Event {
+    fn tags(&self) -> impl Iterator<Item = &str> {
+        self.attrs
+            .info()
+            .unwrap_or_default()
+            .split(SEPARATOR)
+            .filter(|tag| !tag.is_empty())
+    }
+}
+
+/// Checks that `tag` can be stored in an event's attributes.
+pub(crate) fn validate_tag(tag: &str) -> Result<(), Error> {
+    if tag.is_empty() || tag.contains(SEPARATOR) {
+        return Err(Error::generic(&format!(
+            "invalid watch tag {tag:?}: tags must be non-empty and must not contain '{SEPARATOR}'"
+        )));
+    }
+    Ok(())
+}
+
+impl State {
+    /// Attaches `tag` to the watch on `filename`.
+    pub(crate) fn add_tag(&mut self, filename: &Path, tag: &str) -> Result<(), Error> {
+        validate_tag(tag)?;
+
+        let Some(watch) = self.watches.get_mut(filename) else {
+            return Err(Error::watch_not_found().add_path(filename.to_path_buf()));
+        };
+        if !watch.tags.iter().any(|existing| existing == tag) {
+            watch.tags.push(tag.to_string());
+            watch.tags.sort();
+        }
+
+        Ok(())
+    }
+
+    /// Records the tags of the watches reporting each event in the event's attributes.
+    pub(crate) fn tag_events(&self, events: &mut [DebouncedEvent]) {
+        if self.watches.values().all(|watch| watch.tags.is_empty()) {
+            return;
+        }
+
+        for event in events {
+            let mut tags: Vec<&str> = self
+                .watches
+                .values()
+                .filter(|watch| {
+                    !watch.tags.is_empty()
+                        && event
+                            .paths
+                            .iter()
+                            .any(|path| self.watch_reports(watch, path))
+                })
+                .flat_map(|watch| watch.tags.iter().map(String::as_str))
+                .collect();
+            if tags.is_empty() {
+                continue;
+            }
+            tags.sort_unstable();
+            tags.dedup();
+
+            let tags = tags.join(&SEPARATOR.to_string());
+            event.attrs.set_info(&tags);
+        }
+    }
+}
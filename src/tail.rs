@@ -0,0 +1,264 @@
+use std::{
+    fs::File,
+    io::{self, Read as _, Seek as _, SeekFrom},
+    mem,
+    path::{Path, PathBuf},
+};
+
+use notify_debouncer_full::file_id::{get_file_id, FileId};
+
+use crate::{read, DebounceEventHandler, DebounceEventResult};
+
+/// How many of the bytes that were read last are compared to notice truncation.
+const LAST_LEN: usize = 64;
+
+/// Follows a file that is appended to, such as a log, returning the lines added since it
+/// was last read.
+///
+/// The tailer remembers how far the file was read, starting at its end when the tailer is
+/// created, so that only lines appended afterwards are returned. A line is returned once its
+/// line break was written, with the line break removed; an incomplete last line is kept
+/// until the rest of it arrives. Invalid UTF-8 is replaced, see [`String::from_utf8_lossy`].
+///
+/// Log rotation is followed. When the file shrinks, or the last bytes that were read
+/// changed, it was truncated and is read again from the start. When the path names a new
+/// file, the old one was renamed or removed; the lines still written to the old file are
+/// returned first, unless [`drain_rotated`](Tailer::drain_rotated) is turned off, and the new
+/// file is then read from its start. While the path names no file, as between the rename and
+/// the creation of the new file, nothing is read.
+///
+/// [`Watcher::tail`](crate::Watcher::tail) reads a tailer after each change of its file.
+#[derive(Debug)]
+pub struct Tailer {
+    path: PathBuf,
+    /// The open file, which keeps the rotated file readable after it was renamed.
+    file: File,
+    id: Option<FileId>,
+    offset: u64,
+    /// The last bytes before the offset, which differ when the file was truncated and
+    /// written again before it was read.
+    last: Vec<u8>,
+    /// The start of a line whose line break has not been written yet.
+    partial: Vec<u8>,
+    drain_rotated: bool,
+}
+
+impl Tailer {
+    /// Creates a tailer for the file at `path`, starting at its current end.
+    ///
+    /// # Errors
+    /// Returns an error if the file cannot be opened.
+    pub fn new(path: impl Into<PathBuf>) -> io::Result<Self> {
+        let path = path.into();
+        let file = File::open(&path)?;
+        let mut tailer = Self {
+            id: get_file_id(&path).ok(),
+            path,
+            file,
+            offset: 0,
+            last: Vec::new(),
+            partial: Vec::new(),
+            drain_rotated: true,
+        };
+        let len = tailer.file.metadata()?.len();
+        tailer.offset = len.saturating_sub(LAST_LEN as u64);
+        tailer.read_appended()?;
+        tailer.partial = tailer.unfinished_line()?;
+        Ok(tailer)
+    }
+
+    /// Sets whether the lines written to the file after the last read are returned when it
+    /// is rotated, before the lines of the new file. Defaults to `true`.
+    ///
+    /// The last line of the rotated file is returned even without a line break, as nothing
+    /// is appended to it anymore.
+    pub fn drain_rotated(mut self, drain: bool) -> Self {
+        self.drain_rotated = drain;
+        self
+    }
+
+    /// Returns the path of the file that is followed.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Returns how many bytes of the current file have been read.
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    /// Reads what was appended to the file since the last call, returning the complete
+    /// lines.
+    ///
+    /// # Errors
+    /// Returns an error if the file cannot be opened or read. The position is kept, so the
+    /// lines are returned by a later call.
+    pub fn read_lines(&mut self) -> io::Result<Vec<String>> {
+        let Ok(id) = get_file_id(&self.path) else {
+            return Ok(Vec::new());
+        };
+        let mut lines = Vec::new();
+        if self.id != Some(id) {
+            let file = File::open(&self.path)?;
+            tracing::debug!(
+                "{} was rotated, following the new file",
+                self.path.display()
+            );
+            if self.drain_rotated {
+                let rest = self.read_appended()?;
+                lines = self.split(&rest);
+                if !self.partial.is_empty() {
+                    lines.push(String::from_utf8_lossy(&self.partial).into_owned());
+                }
+            }
+            self.file = file;
+            self.id = Some(id);
+            self.rewind();
+        } else if self.was_truncated()? {
+            tracing::debug!(
+                "{} was truncated, reading it from the start",
+                self.path.display()
+            );
+            self.rewind();
+        }
+        let appended = self.read_appended()?;
+        lines.extend(self.split(&appended));
+        Ok(lines)
+    }
+
+    /// Reads the open file from the offset to its end.
+    fn read_appended(&mut self) -> io::Result<Vec<u8>> {
+        self.file.seek(SeekFrom::Start(self.offset))?;
+        let mut appended = Vec::new();
+        self.file.read_to_end(&mut appended)?;
+        self.offset += appended.len() as u64;
+        self.last.extend_from_slice(&appended);
+        let excess = self.last.len().saturating_sub(LAST_LEN);
+        self.last.drain(..excess);
+        Ok(appended)
+    }
+
+    /// Returns the bytes between the last line break before the offset and the offset, the
+    /// start of the line the next read completes.
+    fn unfinished_line(&mut self) -> io::Result<Vec<u8>> {
+        let mut line = Vec::new();
+        let mut end = self.offset;
+        while end > 0 {
+            let start = end.saturating_sub(LAST_LEN as u64);
+            let mut chunk = vec![0; (end - start) as usize];
+            self.file.seek(SeekFrom::Start(start))?;
+            self.file.read_exact(&mut chunk)?;
+            let line_start = chunk.iter().rposition(|&byte| byte == b'\n');
+            line.splice(0..0, chunk.drain(line_start.map_or(0, |index| index + 1)..));
+            if line_start.is_some() {
+                break;
+            }
+            end = start;
+        }
+        Ok(line)
+    }
+
+    /// Returns `true` if the file is shorter than the offset, or if the bytes before the
+    /// offset changed.
+    fn was_truncated(&mut self) -> io::Result<bool> {
+        if self.file.metadata()?.len() < self.offset {
+            return Ok(true);
+        }
+        let mut last = vec![0; self.last.len()];
+        self.file
+            .seek(SeekFrom::Start(self.offset - last.len() as u64))?;
+        self.file.read_exact(&mut last)?;
+        Ok(last != self.last)
+    }
+
+    /// Starts reading the open file from its start.
+    fn rewind(&mut self) {
+        self.offset = 0;
+        self.last.clear();
+        self.partial.clear();
+    }
+
+    /// Splits `appended` into lines, completing the partial line of the last read.
+    fn split(&mut self, appended: &[u8]) -> Vec<String> {
+        let mut lines = Vec::new();
+        let mut rest = appended;
+        while let Some(end) = rest.iter().position(|&byte| byte == b'\n') {
+            self.partial.extend_from_slice(&rest[..end]);
+            if self.partial.last() == Some(&b'\r') {
+                self.partial.pop();
+            }
+            lines.push(String::from_utf8_lossy(&mem::take(&mut self.partial)).into_owned());
+            rest = &rest[end + 1..];
+        }
+        self.partial.extend_from_slice(rest);
+        lines
+    }
+}
+
+/// Returns the handler of [`Watcher::tail`](crate::Watcher::tail), which reads `tailer` after
+/// each batch that changed its file and passes the new lines to `handler` one by one.
+pub(crate) fn tail_handler(
+    mut tailer: Tailer,
+    mut handler: impl FnMut(String) + Send + 'static,
+) -> impl DebounceEventHandler {
+    move |result: DebounceEventResult| {
+        let Ok(events) = result else {
+            return;
+        };
+        if !read::changes(&events, tailer.path()) {
+            return;
+        }
+        match tailer.read_lines() {
+            Ok(lines) => lines.into_iter().for_each(&mut handler),
+            Err(error) => tracing::warn!(
+                "Failed to read the lines appended to {}: {}",
+                tailer.path().display(),
+                error
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+/// This module contains tests for the tailer.
+///
+/// The `keeps_partial_lines` test verifies that a line is only returned once its line break
+/// arrives, without the line break.
+///
+/// The `completes_the_line_it_starts_in` test verifies that a tailer created while the last
+/// line of its file is incomplete returns that line whole once it is finished.
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_partial_lines() {
+        let path = std::env::temp_dir().join("watchit_tail_split_test");
+        File::create(&path).unwrap();
+        let mut tailer = Tailer::new(&path).unwrap();
+        assert_eq!(tailer.split(b"one\r\ntw"), ["one"]);
+        assert_eq!(tailer.split(b"o\n\nthree"), ["two", ""]);
+        assert!(tailer.split(b"").is_empty());
+        assert_eq!(tailer.split(b"\n"), ["three"]);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn completes_the_line_it_starts_in() {
+        let path = std::env::temp_dir().join("watchit_tail_unfinished_test");
+        let start = "x".repeat(LAST_LEN * 2);
+        std::fs::write(&path, format!("first\n{start}")).unwrap();
+        let mut tailer = Tailer::new(&path).unwrap();
+        assert!(tailer.read_lines().unwrap().is_empty());
+
+        let mut file = std::fs::OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .unwrap();
+        std::io::Write::write_all(&mut file, b" end\nnext\n").unwrap();
+        assert_eq!(
+            tailer.read_lines().unwrap(),
+            [format!("{start} end"), "next".into()]
+        );
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,532 @@
+use std::collections::{btree_map::Entry, BTreeMap};
+
+use crate::{
+    json::{Value, MAX_DEPTH},
+    Error,
+};
+
+/// Parses a TOML document into the JSON value it corresponds to, with tables as objects.
+///
+/// Numbers are kept as written, without digit separators, and dates and times as strings,
+/// which is all that comparing versions of a document needs.
+pub(crate) fn parse(text: &str) -> Result<Value, Error> {
+    let mut parser = Parser {
+        chars: text.chars().collect(),
+        pos: 0,
+        depth: 0,
+    };
+    let mut root = BTreeMap::new();
+    let mut current = Vec::new();
+    loop {
+        parser.skip_blank_lines();
+        match parser.peek() {
+            None => break,
+            Some('[') => {
+                parser.pos += 1;
+                let array = parser.eat('[');
+                let path = parser.key()?;
+                if path.len() > MAX_DEPTH {
+                    return Err(too_deep());
+                }
+                parser.expect(']')?;
+                if array {
+                    parser.expect(']')?;
+                    let (last, parent) = path.split_last().ok_or_else(|| invalid("empty key"))?;
+                    match table(&mut root, parent)?.entry(last.clone()) {
+                        Entry::Vacant(entry) => {
+                            entry.insert(Value::Array(vec![Value::Object(BTreeMap::new())]));
+                        }
+                        Entry::Occupied(mut entry) => match entry.get_mut() {
+                            Value::Array(tables) => tables.push(Value::Object(BTreeMap::new())),
+                            _ => return Err(invalid(&format!("{last} is not an array"))),
+                        },
+                    }
+                } else {
+                    table(&mut root, &path)?;
+                }
+                current = path;
+            }
+            Some(_) => {
+                let key = parser.key()?;
+                parser.skip_spaces();
+                parser.expect('=')?;
+                let (last, parent) = key.split_last().ok_or_else(|| invalid("empty key"))?;
+                let path: Vec<String> = current.iter().chain(parent).cloned().collect();
+                let value = parser.below(path.len(), Parser::value)?;
+                insert(table(&mut root, &path)?, last, value)?;
+            }
+        }
+        parser.end_of_line()?;
+    }
+    Ok(Value::Object(root))
+}
+
+/// Returns the table at `path` below `root`, creating the missing ones. Arrays of tables
+/// lead to their last table.
+fn table<'a>(
+    root: &'a mut BTreeMap<String, Value>,
+    path: &[String],
+) -> Result<&'a mut BTreeMap<String, Value>, Error> {
+    let mut table = root;
+    for key in path {
+        let value = table
+            .entry(key.clone())
+            .or_insert_with(|| Value::Object(BTreeMap::new()));
+        let value = match value {
+            Value::Array(tables) => tables
+                .last_mut()
+                .ok_or_else(|| invalid(&format!("{key} is not a table")))?,
+            value => value,
+        };
+        table = match value {
+            Value::Object(fields) => fields,
+            _ => return Err(invalid(&format!("{key} is not a table"))),
+        };
+    }
+    Ok(table)
+}
+
+fn insert(table: &mut BTreeMap<String, Value>, key: &str, value: Value) -> Result<(), Error> {
+    match table.entry(key.to_string()) {
+        Entry::Vacant(entry) => {
+            entry.insert(value);
+            Ok(())
+        }
+        Entry::Occupied(_) => Err(invalid(&format!("duplicate key {key}"))),
+    }
+}
+
+fn invalid(message: &str) -> Error {
+    Error::generic(&format!("invalid TOML: {message}"))
+}
+
+fn too_deep() -> Error {
+    invalid(&format!("nested deeper than {MAX_DEPTH} levels"))
+}
+
+struct Parser {
+    chars: Vec<char>,
+    pos: usize,
+    /// How many tables and arrays contain the value being parsed.
+    depth: usize,
+}
+
+impl Parser {
+    /// Parses a value with `parse` as if it were nested `levels` deeper, unless that is too
+    /// deep.
+    fn below(
+        &mut self,
+        levels: usize,
+        parse: fn(&mut Self) -> Result<Value, Error>,
+    ) -> Result<Value, Error> {
+        if self.depth + levels > MAX_DEPTH {
+            return Err(too_deep());
+        }
+        self.depth += levels;
+        let value = parse(self);
+        self.depth -= levels;
+        value
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.pos += 1;
+        Some(c)
+    }
+
+    fn eat(&mut self, expected: char) -> bool {
+        let matches = self.peek() == Some(expected);
+        if matches {
+            self.pos += 1;
+        }
+        matches
+    }
+
+    fn starts_with(&self, prefix: &str) -> bool {
+        prefix
+            .chars()
+            .enumerate()
+            .all(|(offset, c)| self.chars.get(self.pos + offset) == Some(&c))
+    }
+
+    fn expect(&mut self, expected: char) -> Result<(), Error> {
+        self.skip_spaces();
+        if self.eat(expected) {
+            Ok(())
+        } else {
+            Err(invalid(&format!("expected {expected:?}")))
+        }
+    }
+
+    fn skip_spaces(&mut self) {
+        while matches!(self.peek(), Some(' ' | '\t')) {
+            self.pos += 1;
+        }
+    }
+
+    fn skip_comment(&mut self) {
+        if self.peek() == Some('#') {
+            while !matches!(self.peek(), None | Some('\n')) {
+                self.pos += 1;
+            }
+        }
+    }
+
+    /// Skips whitespace, comments and line breaks, as between the values of an array.
+    fn skip_blank_lines(&mut self) {
+        loop {
+            self.skip_spaces();
+            self.skip_comment();
+            if !(self.eat('\n') || self.eat('\r')) {
+                return;
+            }
+        }
+    }
+
+    fn end_of_line(&mut self) -> Result<(), Error> {
+        self.skip_spaces();
+        self.skip_comment();
+        self.eat('\r');
+        if self.eat('\n') || self.peek().is_none() {
+            Ok(())
+        } else {
+            Err(invalid("expected the end of the line"))
+        }
+    }
+
+    /// Parses a dotted key, such as `server.port` or `"a b".c`.
+    fn key(&mut self) -> Result<Vec<String>, Error> {
+        let mut key = Vec::new();
+        loop {
+            self.skip_spaces();
+            let part = match self.peek() {
+                Some('"') => {
+                    self.pos += 1;
+                    self.basic_string()?
+                }
+                Some('\'') => {
+                    self.pos += 1;
+                    self.literal_string()?
+                }
+                _ => {
+                    let start = self.pos;
+                    while self
+                        .peek()
+                        .is_some_and(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+                    {
+                        self.pos += 1;
+                    }
+                    if self.pos == start {
+                        return Err(invalid("expected a key"));
+                    }
+                    self.chars[start..self.pos].iter().collect()
+                }
+            };
+            key.push(part);
+            self.skip_spaces();
+            if !self.eat('.') {
+                return Ok(key);
+            }
+        }
+    }
+
+    fn value(&mut self) -> Result<Value, Error> {
+        self.skip_spaces();
+        if self.starts_with("\"\"\"") {
+            self.pos += 3;
+            return self.multiline_basic_string().map(Value::String);
+        }
+        if self.starts_with("'''") {
+            self.pos += 3;
+            return self.multiline_literal_string().map(Value::String);
+        }
+        match self.peek() {
+            Some('"') => {
+                self.pos += 1;
+                self.basic_string().map(Value::String)
+            }
+            Some('\'') => {
+                self.pos += 1;
+                self.literal_string().map(Value::String)
+            }
+            Some('[') => self.below(1, Self::array),
+            Some('{') => self.below(1, Self::inline_table),
+            Some(_) => self.scalar(),
+            None => Err(invalid("unexpected end of input")),
+        }
+    }
+
+    fn array(&mut self) -> Result<Value, Error> {
+        self.pos += 1;
+        let mut values = Vec::new();
+        loop {
+            self.skip_blank_lines();
+            if self.eat(']') {
+                return Ok(Value::Array(values));
+            }
+            values.push(self.value()?);
+            self.skip_blank_lines();
+            if !self.eat(',') {
+                self.skip_blank_lines();
+                return if self.eat(']') {
+                    Ok(Value::Array(values))
+                } else {
+                    Err(invalid("expected ',' or ']'"))
+                };
+            }
+        }
+    }
+
+    fn inline_table(&mut self) -> Result<Value, Error> {
+        self.pos += 1;
+        let mut fields = BTreeMap::new();
+        self.skip_spaces();
+        if self.eat('}') {
+            return Ok(Value::Object(fields));
+        }
+        loop {
+            let key = self.key()?;
+            self.expect('=')?;
+            let (last, parent) = key.split_last().ok_or_else(|| invalid("empty key"))?;
+            // The value is nested in the tables its dotted key leads through.
+            let value = self.below(parent.len(), Self::value)?;
+            insert(table(&mut fields, parent)?, last, value)?;
+            self.skip_spaces();
+            match self.next() {
+                Some(',') => continue,
+                Some('}') => return Ok(Value::Object(fields)),
+                _ => return Err(invalid("expected ',' or '}'")),
+            }
+        }
+    }
+
+    /// Parses a boolean, number, date or time.
+    fn scalar(&mut self) -> Result<Value, Error> {
+        let start = self.pos;
+        while !matches!(
+            self.peek(),
+            None | Some(',' | ']' | '}' | '#' | '\n' | '\r')
+        ) {
+            self.pos += 1;
+        }
+        let token: String = self.chars[start..self.pos].iter().collect();
+        let token = token.trim_end();
+        // Only the spaces of a date and time followed by a comment are given back.
+        self.pos = start + token.chars().count();
+
+        match token {
+            "true" => return Ok(Value::Bool(true)),
+            "false" => return Ok(Value::Bool(false)),
+            "inf" | "+inf" | "-inf" | "nan" | "+nan" | "-nan" => {
+                return Ok(Value::Number(token.to_string()))
+            }
+            _ => {}
+        }
+        let number = token.replace('_', "");
+        let radix = ["0x", "0o", "0b"]
+            .into_iter()
+            .zip([16, 8, 2])
+            .find(|(prefix, _)| number.starts_with(prefix));
+        let is_number = match radix {
+            Some((prefix, radix)) => u64::from_str_radix(&number[prefix.len()..], radix).is_ok(),
+            None => {
+                number.parse::<f64>().is_ok()
+                    && number
+                        .chars()
+                        .all(|c| c.is_ascii_digit() || "+-.eE".contains(c))
+            }
+        };
+        if is_number {
+            return Ok(Value::Number(number));
+        }
+        let is_date_time = token.starts_with(|c: char| c.is_ascii_digit())
+            && token.contains(['-', ':'])
+            && token
+                .chars()
+                .all(|c| c.is_ascii_digit() || "-:.+TZtz ".contains(c));
+        if is_date_time {
+            return Ok(Value::String(token.to_string()));
+        }
+        Err(invalid(&format!("unexpected value {token}")))
+    }
+
+    fn basic_string(&mut self) -> Result<String, Error> {
+        let mut string = String::new();
+        loop {
+            match self.next() {
+                Some('"') => return Ok(string),
+                Some('\\') => string.push(self.escape()?),
+                Some('\n') | None => return Err(invalid("unterminated string")),
+                Some(c) => string.push(c),
+            }
+        }
+    }
+
+    fn multiline_basic_string(&mut self) -> Result<String, Error> {
+        self.skip_first_line_break();
+        let mut string = String::new();
+        loop {
+            if self.starts_with("\"\"\"") && !self.starts_with("\"\"\"\"") {
+                self.pos += 3;
+                return Ok(string);
+            }
+            match self.next() {
+                Some('\\') if matches!(self.peek(), Some(' ' | '\t' | '\r' | '\n')) => {
+                    // A line-ending backslash trims the whitespace up to the next text.
+                    while matches!(self.peek(), Some(' ' | '\t' | '\r' | '\n')) {
+                        self.pos += 1;
+                    }
+                }
+                Some('\\') => string.push(self.escape()?),
+                Some(c) => string.push(c),
+                None => return Err(invalid("unterminated string")),
+            }
+        }
+    }
+
+    fn literal_string(&mut self) -> Result<String, Error> {
+        let mut string = String::new();
+        loop {
+            match self.next() {
+                Some('\'') => return Ok(string),
+                Some('\n') | None => return Err(invalid("unterminated string")),
+                Some(c) => string.push(c),
+            }
+        }
+    }
+
+    fn multiline_literal_string(&mut self) -> Result<String, Error> {
+        self.skip_first_line_break();
+        let mut string = String::new();
+        loop {
+            if self.starts_with("'''") && !self.starts_with("''''") {
+                self.pos += 3;
+                return Ok(string);
+            }
+            match self.next() {
+                Some(c) => string.push(c),
+                None => return Err(invalid("unterminated string")),
+            }
+        }
+    }
+
+    /// Skips the line break right after the opening quotes of a multi-line string.
+    fn skip_first_line_break(&mut self) {
+        if self.starts_with("\r\n") {
+            self.pos += 2;
+        } else {
+            self.eat('\n');
+        }
+    }
+
+    fn escape(&mut self) -> Result<char, Error> {
+        let digits = match self.next() {
+            Some('b') => return Ok('\u{8}'),
+            Some('t') => return Ok('\t'),
+            Some('n') => return Ok('\n'),
+            Some('f') => return Ok('\u{c}'),
+            Some('r') => return Ok('\r'),
+            Some('e') => return Ok('\u{1b}'),
+            Some('"') => return Ok('"'),
+            Some('\\') => return Ok('\\'),
+            Some('u') => 4,
+            Some('U') => 8,
+            _ => return Err(invalid("malformed escape sequence")),
+        };
+        let mut code = 0;
+        for _ in 0..digits {
+            let digit = self
+                .next()
+                .and_then(|c| c.to_digit(16))
+                .ok_or_else(|| invalid("malformed unicode escape"))?;
+            code = code * 16 + digit;
+        }
+        char::from_u32(code).ok_or_else(|| invalid("invalid unicode escape"))
+    }
+}
+
+#[cfg(test)]
+/// This module contains tests for the TOML parser.
+///
+/// The `parses_tables_and_values` test verifies that tables, arrays of tables, dotted keys
+/// and the kinds of values end up where they belong.
+///
+/// The `rejects_malformed_documents` test verifies that unterminated strings, duplicate
+/// keys and stray text are reported as errors.
+///
+/// The `rejects_deep_nesting` test verifies that arrays, inline tables and dotted keys
+/// nested beyond the limit are reported as errors instead of overflowing the stack.
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_tables_and_values() {
+        let document = parse(
+            r#"
+# A comment
+title = "watch \"it\"" # trailing comment
+owner.name = 'Tom'
+
+[server]
+port = 8_080
+ratio = 0.5
+enabled = true
+started = 1979-05-27T07:32:00Z
+hosts = [
+  "alpha", # first
+  "beta",
+]
+limits = { cpu = 2, memory.max = "1G" }
+motd = """
+Hello \
+  there"""
+
+[[plugins]]
+name = "a"
+
+[[plugins]]
+name = "b"
+"#,
+        )
+        .unwrap();
+        let expected = Value::parse(
+            r#"{
+                "title": "watch \"it\"",
+                "owner": {"name": "Tom"},
+                "server": {
+                    "port": 8080,
+                    "ratio": 0.5,
+                    "enabled": true,
+                    "started": "1979-05-27T07:32:00Z",
+                    "hosts": ["alpha", "beta"],
+                    "limits": {"cpu": 2, "memory": {"max": "1G"}},
+                    "motd": "Hello there"
+                },
+                "plugins": [{"name": "a"}, {"name": "b"}]
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(document, expected);
+    }
+
+    #[test]
+    fn rejects_malformed_documents() {
+        assert!(parse("title = \"open").is_err());
+        assert!(parse("a = 1\na = 2").is_err());
+        assert!(parse("a = 1 b").is_err());
+        assert!(parse("a = [1, 2").is_err());
+        assert!(parse("= 1").is_err());
+    }
+
+    #[test]
+    fn rejects_deep_nesting() {
+        assert!(parse(&format!("a = {}", "[".repeat(100_000))).is_err());
+        assert!(parse(&format!("a = {}", "{b = ".repeat(100_000))).is_err());
+        assert!(parse(&format!("{} = 1", vec!["a"; 100_000].join("."))).is_err());
+        assert!(parse(&format!("[{}]", vec!["a"; 100_000].join("."))).is_err());
+    }
+}
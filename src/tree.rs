@@ -0,0 +1,689 @@
+use std::{
+    collections::BTreeMap,
+    fs, io,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, MutexGuard},
+    time::{Duration, Instant, SystemTime},
+};
+
+use notify::event::{
+    CreateKind, DataChange, EventKind as NotifyKind, ModifyKind, RemoveKind, RenameMode,
+};
+use notify_debouncer_full::file_id;
+
+use crate::{
+    event::{path_from_json, path_to_json, time_from_json, time_to_json},
+    json::Value,
+    persist, state, ChecksumAlgorithm, DebouncedEvent, Digest, Error, Event, EventHandler,
+    EventKind, EventResult, FileId, FileType, Metadata, Watcher, WatcherBuilder, DEFAULT_DEBOUNCE,
+};
+
+/// Keeps an index of everything below a watched directory, so that its current state can be
+/// queried instead of being rebuilt from events.
+///
+/// The tree is scanned when the watcher is created and the index is updated from every
+/// batch of events before the batch is passed to the handler, so a handler looking up the
+/// paths of its events sees their new state. Each entry records the size, modification
+/// time and file ID of a path, and the FNV-1a checksum of files, see [`IndexEntry`].
+/// An [`Error::Overflow`] of the back-end makes the watcher rescan the tree, since events
+/// were lost, while other errors, such as a panicking handler, leave the index as it is.
+///
+/// The index can be saved with [`TreeWatcher::save_index`], and a later run started from
+/// it with [`TreeWatcher::with_baseline`] reports the changes made while no watcher was
+/// running as events, as sync and indexing tools need. [`Watcher::save_state`] saves the
+/// index together with the other watches of the watcher.
+///
+/// ```no_run
+/// use std::time::SystemTime;
+/// use watchit::TreeWatcher;
+///
+/// let started = SystemTime::now();
+/// let tree = TreeWatcher::new("assets", |_| {}).unwrap();
+/// for (path, entry) in tree.changed_since(started) {
+///     match entry {
+///         Some(entry) => println!("{} is now {} bytes", path.display(), entry.size),
+///         None => println!("{} was removed", path.display()),
+///     }
+/// }
+/// ```
+pub struct TreeWatcher {
+    watcher: Watcher,
+    root: PathBuf,
+    index: SharedIndex,
+}
+
+pub(crate) type SharedIndex = Arc<Mutex<TreeIndex>>;
+
+impl TreeWatcher {
+    /// Watches the tree below `root` with the default options of [`WatcherBuilder::new`],
+    /// see [`WatcherBuilder::build_tree`].
+    ///
+    /// # Errors
+    /// Returns an error if the watcher cannot be created or `root` cannot be watched.
+    pub fn new(root: impl AsRef<Path>, handler: impl EventHandler) -> Result<Self, Error> {
+        WatcherBuilder::new().build_tree(root, handler)
+    }
+
+    /// Watches the tree below `root` with the default options of [`WatcherBuilder::new`],
+    /// reporting what changed since the index was saved at `baseline`, see
+    /// [`WatcherBuilder::build_tree_with_baseline`].
+    ///
+    /// # Errors
+    /// Returns an error if the watcher cannot be created, `root` cannot be watched, or the
+    /// saved index cannot be read or is of another tree.
+    pub fn with_baseline(
+        root: impl AsRef<Path>,
+        baseline: impl AsRef<Path>,
+        handler: impl EventHandler,
+    ) -> Result<Self, Error> {
+        WatcherBuilder::new().build_tree_with_baseline(root, baseline, handler)
+    }
+
+    /// Returns the directory whose tree is indexed.
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Returns a copy of the whole index.
+    pub fn snapshot(&self) -> BTreeMap<PathBuf, IndexEntry> {
+        lock(&self.index).entries.clone()
+    }
+
+    /// Returns the entry of `path`, if it is in the tree.
+    pub fn get(&self, path: impl AsRef<Path>) -> Option<IndexEntry> {
+        lock(&self.index).entries.get(path.as_ref()).cloned()
+    }
+
+    /// Returns how many paths the tree holds, the root included.
+    pub fn len(&self) -> usize {
+        lock(&self.index).entries.len()
+    }
+
+    /// Returns `true` if the tree holds no paths, which is the case if the root is missing.
+    pub fn is_empty(&self) -> bool {
+        lock(&self.index).entries.is_empty()
+    }
+
+    /// Returns the paths that changed at or after `since`, with their current entries, or
+    /// `None` for paths that were removed.
+    ///
+    /// Removed paths are forgotten once they are older than twice the debounce duration of
+    /// the watcher, which outlasts the batch that reports them, so a consumer polling the
+    /// tree less often learns about older removals from the missing entries.
+    pub fn changed_since(&self, since: SystemTime) -> BTreeMap<PathBuf, Option<IndexEntry>> {
+        let index = lock(&self.index);
+        let removed = index
+            .removed
+            .iter()
+            .filter(|&(_, &removed)| removed >= since)
+            .map(|(path, _)| (path.clone(), None));
+        let changed = index
+            .entries
+            .iter()
+            .filter(|(_, entry)| entry.changed >= since)
+            .map(|(path, entry)| (path.clone(), Some(entry.clone())));
+        removed.chain(changed).collect()
+    }
+
+    /// Saves the index to `path`, so that a later run can report what changed in the
+    /// meantime, see [`WatcherBuilder::build_tree_with_baseline`].
+    ///
+    /// The index is written to a temporary file next to `path` first, which then replaces
+    /// `path`, so that a crash while saving does not leave a partial index behind.
+    ///
+    /// # Errors
+    /// Returns an error if the index cannot be written.
+    pub fn save_index(&self, path: impl AsRef<Path>) -> Result<(), Error> {
+        let path = path.as_ref();
+        let json = lock(&self.index).to_json(&self.root).to_string();
+        persist::write_atomically(path, &json)
+    }
+
+    /// Returns the watcher that watches the tree, which can watch other paths as well.
+    pub fn watcher(&self) -> &Watcher {
+        &self.watcher
+    }
+}
+
+impl WatcherBuilder {
+    /// Creates the configured file watcher and keeps an index of the tree below `root`, see
+    /// [`TreeWatcher`].
+    ///
+    /// # Arguments
+    /// * `root` - The directory whose tree is indexed.
+    /// * `handler` - The event handler to call with the events of the tree, once the index
+    ///   reflects them.
+    ///
+    /// # Returns
+    /// A `Result` containing either the tree watcher, or an `Error` if the watcher cannot be
+    /// created or `root` cannot be watched.
+    pub fn build_tree(
+        self,
+        root: impl AsRef<Path>,
+        handler: impl EventHandler,
+    ) -> Result<TreeWatcher, Error> {
+        self.build_tree_from(root.as_ref().to_path_buf(), None, handler)
+    }
+
+    /// Creates the configured file watcher and keeps an index of the tree below `root`,
+    /// starting from the index saved at `baseline` by [`TreeWatcher::save_index`], or as
+    /// part of a state saved by [`Watcher::save_state`].
+    ///
+    /// The tree is compared to the saved index once it is watched, and the handler
+    /// receives events for the paths created, modified, renamed and removed in the meantime,
+    /// such as while the process was not running, before or among the first live events.
+    /// Files removed and created with the same file ID and content are reported as renamed.
+    /// If no index was saved at `baseline` yet, the tree is indexed without reporting
+    /// events, like [`WatcherBuilder::build_tree`] does.
+    ///
+    /// # Arguments
+    /// * `root` - The directory whose tree is indexed.
+    /// * `baseline` - The file the index was saved to.
+    /// * `handler` - The event handler to call with the events of the tree, once the index
+    ///   reflects them.
+    ///
+    /// # Returns
+    /// A `Result` containing either the tree watcher, or an `Error` if the watcher cannot be
+    /// created, `root` cannot be watched, or the saved index cannot be read or is of another
+    /// tree.
+    pub fn build_tree_with_baseline(
+        self,
+        root: impl AsRef<Path>,
+        baseline: impl AsRef<Path>,
+        handler: impl EventHandler,
+    ) -> Result<TreeWatcher, Error> {
+        let root = root.as_ref().to_path_buf();
+        let baseline = TreeIndex::load(baseline.as_ref(), &root)?;
+        self.build_tree_from(root, baseline, handler)
+    }
+
+    fn build_tree_from(
+        self,
+        root: PathBuf,
+        baseline: Option<TreeIndex>,
+        mut handler: impl EventHandler,
+    ) -> Result<TreeWatcher, Error> {
+        let compare = baseline.is_some();
+        let mut baseline = baseline.unwrap_or_default();
+        baseline.window = self.debounce * 2;
+        let index = Arc::new(Mutex::new(baseline));
+
+        let watcher = {
+            let index = Arc::clone(&index);
+            let root = root.clone();
+            self.build_with_event_handler(move |result: EventResult| {
+                lock(&index).update(&root, &result);
+                handler.handle_event(result);
+            })?
+        };
+        // The tree is scanned once it is watched, so that no change between the two is missed.
+        watcher.watch_recursive(&root)?;
+        state::lock(&watcher.state)
+            .trees
+            .insert(root.clone(), Arc::clone(&index));
+        watcher.rescan_tree(&root, &index, compare);
+
+        Ok(TreeWatcher {
+            watcher,
+            root,
+            index,
+        })
+    }
+}
+
+impl Watcher {
+    /// Brings the index of the tree below `root` up to date, and reports the differences
+    /// to the handler if `report` is set.
+    pub(crate) fn rescan_tree(&self, root: &Path, index: &SharedIndex, report: bool) {
+        let events = {
+            let mut index = lock(index);
+            let changes = index.rescan(root, SystemTime::now());
+            changes.into_events(&index)
+        };
+        if !report {
+            return;
+        }
+        let ids: BTreeMap<PathBuf, FileId> = events
+            .iter()
+            .filter_map(|(event, id)| Some((event.paths.last()?.clone(), (*id)?)))
+            .collect();
+        let events = events.into_iter().map(|(event, _)| event).collect();
+        let mut events = state::lock(&self.state).synthesized(events, false);
+        for (event, details) in &mut events {
+            // Removed paths are identified by the IDs they had in the saved index.
+            if let Some(path) = event.paths.last() {
+                details.file_id = details.file_id.or_else(|| ids.get(path).copied());
+            }
+        }
+        self.dispatch_synthesized(events);
+    }
+}
+
+/// What the index of a [`TreeWatcher`] knows about a path.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct IndexEntry {
+    /// The type of the entry. Symbolic links are indexed themselves, not their targets.
+    pub file_type: FileType,
+    /// The size of the entry in bytes.
+    pub size: u64,
+    /// When the entry was last modified, if the platform records it.
+    pub modified: Option<SystemTime>,
+    /// The FNV-1a checksum of the content, if the entry is a file that could be read.
+    pub hash: Option<Digest>,
+    /// The platform's identifier of the entry, if it could be read.
+    pub file_id: Option<FileId>,
+    /// When the index last saw the entry change, or first saw it.
+    pub changed: SystemTime,
+}
+
+impl IndexEntry {
+    /// Reads the entry of `path` without following symbolic links.
+    fn read(path: &Path, changed: SystemTime) -> Option<Self> {
+        let metadata = Metadata::read(path)?;
+        let hash = (metadata.file_type == FileType::File)
+            .then(|| Digest::of_file(ChecksumAlgorithm::Fnv1a64, path).ok())
+            .flatten();
+        Some(Self {
+            file_type: metadata.file_type,
+            size: metadata.size,
+            modified: metadata.modified,
+            hash,
+            file_id: file_id::get_file_id(path).ok().map(FileId::from),
+            changed,
+        })
+    }
+
+    fn to_json(&self, path: &Path) -> Value {
+        Value::object([
+            ("path", path_to_json(path)),
+            ("file_type", self.file_type.to_json()),
+            ("size", Value::number(self.size)),
+            ("modified", Value::optional(self.modified, time_to_json)),
+            ("hash", Value::optional(self.hash.as_ref(), Digest::to_json)),
+            ("file_id", Value::optional(self.file_id, FileId::to_json)),
+            ("changed", time_to_json(self.changed)),
+        ])
+    }
+
+    fn from_json(value: &Value) -> Result<(PathBuf, Self), Error> {
+        let entry = Self {
+            file_type: FileType::from_json(value.field("file_type")?)?,
+            size: value.field("size")?.as_u64()?,
+            modified: value.field("modified")?.as_optional(time_from_json)?,
+            hash: value.field("hash")?.as_optional(Digest::from_json)?,
+            file_id: value.field("file_id")?.as_optional(FileId::from_json)?,
+            changed: time_from_json(value.field("changed")?)?,
+        };
+        Ok((path_from_json(value.field("path")?)?, entry))
+    }
+
+    /// Returns `true` if `other` describes the same version of the entry.
+    fn same_as(&self, other: &Self) -> bool {
+        self.file_type == other.file_type
+            && self.size == other.size
+            && self.modified == other.modified
+            && self.hash == other.hash
+            && self.file_id == other.file_id
+    }
+}
+
+/// The entries of a tree and the paths removed from it.
+#[derive(Debug)]
+pub(crate) struct TreeIndex {
+    pub(crate) entries: BTreeMap<PathBuf, IndexEntry>,
+    /// When each removed path was removed, for as long as `window`.
+    pub(crate) removed: BTreeMap<PathBuf, SystemTime>,
+    /// How long removed paths are remembered, twice the debounce duration so that they
+    /// outlive the batch that reports them.
+    pub(crate) window: Duration,
+}
+
+impl Default for TreeIndex {
+    fn default() -> Self {
+        Self {
+            entries: BTreeMap::new(),
+            removed: BTreeMap::new(),
+            window: DEFAULT_DEBOUNCE * 2,
+        }
+    }
+}
+
+impl TreeIndex {
+    /// Updates the index from a batch of events of the tree below `root`.
+    fn update(&mut self, root: &Path, result: &EventResult) {
+        match result {
+            Ok(events) => self.apply(events),
+            Err(errors) => {
+                if errors.iter().any(|error| matches!(error, Error::Overflow)) {
+                    self.rescan(root, SystemTime::now());
+                }
+            }
+        }
+        self.prune(SystemTime::now());
+    }
+
+    /// Forgets the paths removed longer than `window` before `now`.
+    fn prune(&mut self, now: SystemTime) {
+        let Some(oldest) = now.checked_sub(self.window) else {
+            return;
+        };
+        self.removed.retain(|_, &mut removed| removed >= oldest);
+    }
+
+    /// Updates the index from the events of a batch.
+    fn apply(&mut self, events: &[Event]) {
+        for event in events {
+            match event.kind {
+                EventKind::Removed => self.remove(&event.path, event.time),
+                EventKind::Renamed => {
+                    if let Some(from) = &event.from {
+                        self.remove(from, event.time);
+                    }
+                    self.refresh(&event.path, event.time, true);
+                }
+                EventKind::Created => self.refresh(&event.path, event.time, true),
+                _ => self.refresh(&event.path, event.time, false),
+            }
+        }
+    }
+
+    /// Reads the tree below `root` again, recording the entries that differ from the index
+    /// as changed at `time`, and returns the differences.
+    pub(crate) fn rescan(&mut self, root: &Path, time: SystemTime) -> TreeChanges {
+        let mut changes = TreeChanges::default();
+        let mut seen = Vec::new();
+        self.refresh_tree(root, time, &mut seen, &mut changes);
+        seen.sort();
+        let gone: Vec<PathBuf> = self
+            .entries
+            .keys()
+            .filter(|path| seen.binary_search(path).is_err())
+            .cloned()
+            .collect();
+        for path in gone {
+            if let Some(entry) = self.entries.remove(&path) {
+                changes.removed.push((path.clone(), entry));
+            }
+            self.removed.insert(path, time);
+        }
+        changes
+    }
+
+    /// Reads the entry of `path`, and of everything below it if `recurse` is set.
+    fn refresh(&mut self, path: &Path, time: SystemTime, recurse: bool) {
+        if recurse {
+            self.refresh_tree(path, time, &mut Vec::new(), &mut TreeChanges::default());
+        } else if self.refresh_entry(path, time) == Refresh::Missing {
+            self.remove(path, time);
+        }
+    }
+
+    /// Reads the entries of `path` and everything below it, adding their paths to `seen`
+    /// and the entries that differ from the index to `changes`.
+    fn refresh_tree(
+        &mut self,
+        path: &Path,
+        time: SystemTime,
+        seen: &mut Vec<PathBuf>,
+        changes: &mut TreeChanges,
+    ) {
+        match self.refresh_entry(path, time) {
+            Refresh::Missing => {
+                self.remove(path, time);
+                return;
+            }
+            Refresh::New => changes.created.push(path.to_path_buf()),
+            Refresh::Changed => changes.modified.push(path.to_path_buf()),
+            Refresh::Same => {}
+        }
+        seen.push(path.to_path_buf());
+        if self.entries[path].file_type != FileType::Dir {
+            return;
+        }
+        let Ok(children) = fs::read_dir(path) else {
+            return;
+        };
+        let children: Vec<PathBuf> = children
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .collect();
+        for child in children {
+            self.refresh_tree(&child, time, seen, changes);
+        }
+    }
+
+    /// Reads the entry of `path`, keeping when it last changed if it is the same as before.
+    fn refresh_entry(&mut self, path: &Path, time: SystemTime) -> Refresh {
+        let Some(mut entry) = IndexEntry::read(path, time) else {
+            return Refresh::Missing;
+        };
+        let refresh = match self.entries.get(path) {
+            Some(previous) if previous.same_as(&entry) => {
+                entry.changed = previous.changed;
+                Refresh::Same
+            }
+            Some(_) => Refresh::Changed,
+            None => Refresh::New,
+        };
+        self.entries.insert(path.to_path_buf(), entry);
+        self.removed.remove(path);
+        refresh
+    }
+
+    /// Removes `path` and everything below it from the index.
+    fn remove(&mut self, path: &Path, time: SystemTime) {
+        let below: Vec<PathBuf> = self
+            .entries
+            .range(path.to_path_buf()..)
+            .map(|(entry, _)| entry)
+            .take_while(|entry| entry.starts_with(path))
+            .cloned()
+            .collect();
+        for entry in below {
+            self.entries.remove(&entry);
+            self.removed.insert(entry, time);
+        }
+    }
+}
+
+impl TreeIndex {
+    /// Writes the entries of the tree below `root`, without the removed paths.
+    pub(crate) fn to_json(&self, root: &Path) -> Value {
+        let entries = self
+            .entries
+            .iter()
+            .map(|(path, entry)| entry.to_json(path))
+            .collect();
+        Value::object([
+            ("root", path_to_json(root)),
+            ("entries", Value::Array(entries)),
+        ])
+    }
+
+    /// Reads the entries written by [`TreeIndex::to_json`], with the root of their tree.
+    pub(crate) fn from_json(value: &Value) -> Result<(PathBuf, Self), Error> {
+        let root = path_from_json(value.field("root")?)?;
+        let entries = value
+            .field("entries")?
+            .as_array()?
+            .iter()
+            .map(IndexEntry::from_json)
+            .collect::<Result<_, _>>()?;
+        let index = Self {
+            entries,
+            ..Self::default()
+        };
+        Ok((root, index))
+    }
+
+    /// Reads the index saved at `path` for the tree below `root`, or returns `None` if no
+    /// index was saved there yet.
+    ///
+    /// The file may also be a state saved by [`Watcher::save_state`], which holds the
+    /// indexes of all the trees of its watcher, in which case the one of `root` is read.
+    fn load(path: &Path, root: &Path) -> Result<Option<Self>, Error> {
+        let text = match fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(error) => return Err(Error::io(error).add_path(path.to_path_buf())),
+        };
+        let value = Value::parse(&text).map_err(|error| error.add_path(path.to_path_buf()))?;
+        if let Ok(trees) = value.field("trees") {
+            return Self::find(trees, root).map_err(|error| error.add_path(path.to_path_buf()));
+        }
+        let (saved_root, index) =
+            Self::from_json(&value).map_err(|error| error.add_path(path.to_path_buf()))?;
+        if saved_root != root {
+            let message = format!("the index is of the tree below {}", saved_root.display());
+            return Err(Error::generic(&message).add_path(path.to_path_buf()));
+        }
+        Ok(Some(index))
+    }
+
+    /// Reads the index of the tree below `root` from the indexes of a saved state, or
+    /// returns `None` if the state does not hold it.
+    fn find(trees: &Value, root: &Path) -> Result<Option<Self>, Error> {
+        for tree in trees.as_array()? {
+            let (saved_root, index) = Self::from_json(tree)?;
+            if saved_root == root {
+                return Ok(Some(index));
+            }
+        }
+        Ok(None)
+    }
+}
+
+/// How the entry of a path compares to the index.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Refresh {
+    Missing,
+    New,
+    Changed,
+    Same,
+}
+
+/// The differences a rescan found between the tree and its index.
+#[derive(Debug, Default)]
+pub(crate) struct TreeChanges {
+    created: Vec<PathBuf>,
+    modified: Vec<PathBuf>,
+    removed: Vec<(PathBuf, IndexEntry)>,
+}
+
+impl TreeChanges {
+    /// Synthesizes the events reporting the changes, as of `index`.
+    ///
+    /// A removed file and a created one with the same file ID and content are reported as a
+    /// rename, since file IDs of removed files may be reused, and
+    /// the modifications of directories, which only mean their children changed, are not
+    /// reported.
+    fn into_events(mut self, index: &TreeIndex) -> Vec<(DebouncedEvent, Option<FileId>)> {
+        let now = Instant::now();
+        let event = |kind, paths: Vec<PathBuf>| {
+            let event = paths
+                .into_iter()
+                .fold(notify::Event::new(kind), notify::Event::add_path);
+            DebouncedEvent::new(event, now)
+        };
+        let entry = |path: &Path| index.entries.get(path);
+
+        let mut events = Vec::new();
+        self.removed.retain(|(from, removed)| {
+            let renamed = self.created.iter().position(|to| {
+                entry(to).is_some_and(|created| {
+                    created.file_type == FileType::File
+                        && removed.file_type == FileType::File
+                        && created.file_id.is_some()
+                        && created.file_id == removed.file_id
+                        && created.hash == removed.hash
+                })
+            });
+            let Some(position) = renamed else {
+                return true;
+            };
+            let to = self.created.remove(position);
+            let kind = NotifyKind::Modify(ModifyKind::Name(RenameMode::Both));
+            events.push((event(kind, vec![from.clone(), to]), removed.file_id));
+            false
+        });
+        for (path, removed) in self.removed {
+            let kind = if removed.file_type == FileType::Dir {
+                RemoveKind::Folder
+            } else {
+                RemoveKind::File
+            };
+            events.push((event(NotifyKind::Remove(kind), vec![path]), removed.file_id));
+        }
+        for path in self.created {
+            let kind = if entry(&path).is_some_and(|entry| entry.file_type == FileType::Dir) {
+                CreateKind::Folder
+            } else {
+                CreateKind::File
+            };
+            events.push((event(NotifyKind::Create(kind), vec![path]), None));
+        }
+        for path in self.modified {
+            if entry(&path).is_some_and(|entry| entry.file_type != FileType::Dir) {
+                let kind = NotifyKind::Modify(ModifyKind::Data(DataChange::Any));
+                events.push((event(kind, vec![path]), None));
+            }
+        }
+        events
+    }
+}
+
+pub(crate) fn lock(index: &SharedIndex) -> MutexGuard<'_, TreeIndex> {
+    index
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+#[cfg(test)]
+/// This module contains tests for the index of a tree.
+///
+/// The `rescans_only_after_lost_events` test verifies that the index is read again after an
+/// overflow of the back-end, but not after errors that lose no events.
+///
+/// The `forgets_old_removals` test verifies that removed paths are only remembered for the
+/// window of the index.
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rescans_only_after_lost_events() {
+        let root = std::env::temp_dir().join("watchit_tree_rescan_test");
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(&root).unwrap();
+        std::fs::write(root.join("unseen.txt"), "written without an event").unwrap();
+        let mut index = TreeIndex::default();
+
+        let panicked = Error::HandlerPanic {
+            message: "handler failed".to_string(),
+        };
+        index.update(&root, &Err(vec![panicked]));
+        assert!(index.entries.is_empty());
+
+        index.update(&root, &Err(vec![Error::Overflow]));
+        assert!(index.entries.contains_key(&root.join("unseen.txt")));
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn forgets_old_removals() {
+        let now = SystemTime::now();
+        let mut index = TreeIndex {
+            window: Duration::from_secs(2),
+            ..TreeIndex::default()
+        };
+        index
+            .removed
+            .insert(PathBuf::from("old"), now - Duration::from_secs(3));
+        index.removed.insert(PathBuf::from("recent"), now);
+
+        index.prune(now);
+        assert_eq!(
+            index.removed.keys().collect::<Vec<_>>(),
+            [&PathBuf::from("recent")]
+        );
+    }
+}
@@ -0,0 +1,160 @@
+use std::{
+    fmt,
+    panic::{self, AssertUnwindSafe},
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use crate::{event::Details, json::Value, state::State, DebouncedEvent, Error, EventKind};
+
+/// The outcome of the validator of a watch for the file an event reports, see
+/// [`WatchOptions::validate`](crate::WatchOptions::validate).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Validation {
+    /// The validator accepted the file.
+    Valid,
+    /// The validator rejected the file, with the message of its error.
+    Invalid(String),
+}
+
+impl Validation {
+    /// Returns `true` if the validator accepted the file.
+    pub fn is_valid(&self) -> bool {
+        *self == Self::Valid
+    }
+
+    /// Returns the message of the validator's error, if it rejected the file.
+    pub fn error(&self) -> Option<&str> {
+        match self {
+            Self::Valid => None,
+            Self::Invalid(message) => Some(message),
+        }
+    }
+
+    pub(crate) fn to_json(&self) -> Value {
+        match self {
+            Self::Valid => Value::string("valid"),
+            Self::Invalid(message) => Value::object([("invalid", Value::string(message))]),
+        }
+    }
+
+    pub(crate) fn from_json(value: &Value) -> Result<Self, Error> {
+        if let Ok("valid") = value.as_str() {
+            return Ok(Self::Valid);
+        }
+        Ok(Self::Invalid(value.field("invalid")?.as_str()?.to_string()))
+    }
+}
+
+type ValidateFn = Arc<dyn Fn(&Path) -> Result<(), String> + Send + Sync + 'static>;
+
+/// A validator registered with [`WatchOptions::validate`](crate::WatchOptions::validate).
+#[derive(Clone)]
+pub(crate) struct Validator(ValidateFn);
+
+impl Validator {
+    pub(crate) fn new<E: fmt::Display>(
+        validator: impl Fn(&Path) -> Result<(), E> + Send + Sync + 'static,
+    ) -> Self {
+        Self(Arc::new(move |path| {
+            validator(path).map_err(|error| error.to_string())
+        }))
+    }
+
+    /// Validates the file at `path`, turning a panic of the validator into a rejection.
+    fn run(&self, path: &Path) -> Validation {
+        match panic::catch_unwind(AssertUnwindSafe(|| (self.0)(path))) {
+            Ok(Ok(())) => Validation::Valid,
+            Ok(Err(message)) => Validation::Invalid(message),
+            Err(_) => {
+                tracing::error!("Validator panicked for {}", path.display());
+                Validation::Invalid("the validator panicked".to_string())
+            }
+        }
+    }
+}
+
+impl fmt::Debug for Validator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Validator")
+    }
+}
+
+/// A file of a batch that is validated once the state is unlocked.
+pub(crate) struct ValidationJob {
+    /// The position of the event in the batch.
+    index: usize,
+    path: PathBuf,
+    validator: Validator,
+}
+
+impl State {
+    /// Validates the files that the watch on `filename` reports created or changed.
+    pub(crate) fn add_validator(
+        &mut self,
+        filename: &Path,
+        validator: Validator,
+    ) -> Result<(), Error> {
+        let Some(watch) = self.watches.get_mut(filename) else {
+            return Err(Error::watch_not_found().add_path(filename.to_path_buf()));
+        };
+        watch.validated = true;
+
+        self.validators.insert(filename.to_path_buf(), validator);
+
+        Ok(())
+    }
+
+    pub(crate) fn forget_validator(&mut self, filename: &Path) {
+        self.validators.remove(filename);
+    }
+
+    /// Returns the files of a batch that must be validated.
+    pub(crate) fn validation_jobs(
+        &self,
+        events: &[(DebouncedEvent, Details)],
+    ) -> Vec<ValidationJob> {
+        if self.validators.is_empty() {
+            return Vec::new();
+        }
+        events
+            .iter()
+            .enumerate()
+            .filter(|(_, (event, details))| {
+                matches!(
+                    details.kind(event),
+                    EventKind::Created
+                        | EventKind::Existing
+                        | EventKind::Modified
+                        | EventKind::Renamed
+                )
+            })
+            .filter_map(|(index, (event, _))| {
+                let path = event.paths.last()?;
+                let validator = self.validator_of(path)?;
+                Some(ValidationJob {
+                    index,
+                    path: path.clone(),
+                    validator,
+                })
+            })
+            .collect()
+    }
+
+    /// Returns the validator of the most specific watch reporting `path` with a validator.
+    fn validator_of(&self, path: &Path) -> Option<Validator> {
+        self.watches
+            .values()
+            .filter(|watch| watch.validated && self.watch_reports(watch, path))
+            .max_by_key(|watch| watch.path.components().count())
+            .and_then(|watch| self.validators.get(&watch.path))
+            .cloned()
+    }
+}
+
+/// Runs the validators of `jobs` and attaches their outcomes to the events of the batch.
+pub(crate) fn validate(events: &mut [(DebouncedEvent, Details)], jobs: Vec<ValidationJob>) {
+    for job in jobs {
+        events[job.index].1.validation = Some(job.validator.run(&job.path));
+    }
+}
@@ -0,0 +1,119 @@
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+use notify::{RecursiveMode, Watcher as _};
+
+use crate::{
+    state::{self, Debouncer, State},
+    DebouncedEvent,
+};
+
+/// The polling sweep that catches the changes the back-end missed, see
+/// [`WatcherBuilder::verify`](crate::WatcherBuilder::verify).
+pub(crate) struct Verifier {
+    pub(crate) debouncer: Debouncer,
+    /// How long after an event of the back-end the sweep may report the same change.
+    window: Duration,
+    /// When each path last had an event of the back-end.
+    reported: BTreeMap<PathBuf, Instant>,
+}
+
+impl State {
+    /// Lets the verification sweep scan `filename` as well, if the watcher verifies its
+    /// back-end.
+    ///
+    /// A watch the sweep cannot scan is still served by the back-end, so failures are only
+    /// logged.
+    pub(crate) fn verify_watch(&mut self, filename: &Path, mode: RecursiveMode) {
+        let Some(interval) = self.verify_interval else {
+            return;
+        };
+
+        if self.verifier.is_none() {
+            let debouncer = match state::new_sweep_debouncer(
+                self.debounce,
+                &self.poll_source(interval),
+                &self.dispatcher,
+                &self.cell,
+                &self.raw,
+            ) {
+                Ok(debouncer) => debouncer,
+                Err(error) => {
+                    tracing::warn!("Failed to create verification sweep: {}", error);
+                    return;
+                }
+            };
+            self.verifier = Some(Verifier {
+                debouncer,
+                window: interval + self.debounce,
+                reported: BTreeMap::new(),
+            });
+            tracing::debug!("Created verification sweep with {:?} interval", interval);
+        }
+        let verifier = self.verifier.as_mut().expect("verifier was just created");
+
+        if let Err(error) = verifier.debouncer.watcher().watch(filename, mode) {
+            tracing::warn!(
+                "Failed to verify the events of {}: {}",
+                filename.display(),
+                error
+            );
+            return;
+        }
+        verifier.debouncer.cache().add_root(filename, mode);
+    }
+
+    pub(crate) fn forget_verify(&mut self, filename: &Path) {
+        let Some(verifier) = &mut self.verifier else {
+            return;
+        };
+        if verifier.debouncer.watcher().unwatch(filename).is_ok() {
+            verifier.debouncer.cache().remove_root(filename);
+        }
+        verifier
+            .reported
+            .retain(|path, _| !path.starts_with(filename));
+    }
+
+    /// Records the paths of a batch of the back-end, or drops the events of a sweep batch
+    /// that the back-end reported already.
+    pub(crate) fn verify_events(
+        &mut self,
+        mut events: Vec<DebouncedEvent>,
+        sweep: bool,
+    ) -> Vec<DebouncedEvent> {
+        let Some(verifier) = &mut self.verifier else {
+            return events;
+        };
+        let now = Instant::now();
+        let window = verifier.window;
+        verifier
+            .reported
+            .retain(|_, reported| now.duration_since(*reported) < window);
+
+        if !sweep {
+            for path in events.iter().flat_map(|event| &event.paths) {
+                verifier.reported.insert(path.clone(), now);
+            }
+            return events;
+        }
+
+        events.retain(|event| {
+            let missed = event
+                .paths
+                .iter()
+                .all(|path| !verifier.reported.contains_key(path));
+            if missed {
+                tracing::debug!(
+                    "Verification sweep found a missed change: {:?}",
+                    event.paths
+                );
+            }
+            missed
+        });
+        events
+    }
+}
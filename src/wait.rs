@@ -0,0 +1,78 @@
+use std::{
+    path::Path,
+    time::{Duration, Instant},
+};
+
+use crate::{Error, Event, WatcherBuilder};
+
+impl WatcherBuilder {
+    /// Watches `path` until it changes, then tears the watcher down again.
+    ///
+    /// This is the "wait until the config file is updated" pattern in one call. Only
+    /// changes after the call are reported.
+    ///
+    /// # Arguments
+    /// * `path` - The file or directory to wait for.
+    /// * `timeout` - How long to wait at most, or `None` to wait until a change arrives.
+    ///
+    /// # Returns
+    /// A `Result` containing either the first change, or `None` if the timeout elapsed or
+    /// the watcher was [cancelled](WatcherBuilder::cancellation) first. An `Error` is
+    /// returned if the path cannot be watched, or if the watcher reports an error before
+    /// the first change.
+    pub fn wait_for_change(
+        self,
+        path: impl AsRef<Path>,
+        timeout: Option<Duration>,
+    ) -> Result<Option<Event>, Error> {
+        let deadline = timeout.and_then(|timeout| Instant::now().checked_add(timeout));
+        let (watcher, mut stream) = self.build_async()?.split();
+        watcher.watch(path)?;
+        stream.wait(deadline).transpose()
+    }
+
+    /// Watches `path` until it changes, then tears the watcher down again, see
+    /// [`WatcherBuilder::wait_for_change`].
+    ///
+    /// The future does not time out by itself; wrap it in the timeout of the executor
+    /// instead. Dropping the future tears the watcher down.
+    ///
+    /// # Returns
+    /// A `Result` containing either the first change, or `None` if the watcher was
+    /// cancelled first. An `Error` is returned if the path cannot be watched, or if the
+    /// watcher reports an error before the first change.
+    pub async fn wait_for_change_async(
+        self,
+        path: impl AsRef<Path>,
+    ) -> Result<Option<Event>, Error> {
+        let mut watcher = self.build_async()?;
+        watcher.watch(path)?;
+        watcher.next().await.transpose()
+    }
+}
+
+/// Waits until `path` changes, with the default options of [`WatcherBuilder::new`].
+///
+/// ```no_run
+/// use std::time::Duration;
+///
+/// match watchit::wait_for_change("config.toml", Some(Duration::from_secs(60))).unwrap() {
+///     Some(event) => println!("{event}"),
+///     None => println!("config.toml did not change within a minute"),
+/// }
+/// ```
+///
+/// See [`WatcherBuilder::wait_for_change`] for the arguments and the returned value, and to
+/// change the debounce duration.
+pub fn wait_for_change(
+    path: impl AsRef<Path>,
+    timeout: Option<Duration>,
+) -> Result<Option<Event>, Error> {
+    WatcherBuilder::new().wait_for_change(path, timeout)
+}
+
+/// Waits until `path` changes without blocking a thread, with the default options of
+/// [`WatcherBuilder::new`], see [`WatcherBuilder::wait_for_change_async`].
+pub async fn wait_for_change_async(path: impl AsRef<Path>) -> Result<Option<Event>, Error> {
+    WatcherBuilder::new().wait_for_change_async(path).await
+}
@@ -0,0 +1,668 @@
+use std::{
+    fmt,
+    path::{Path, PathBuf},
+    sync::{Mutex, Weak},
+    time::{Duration, SystemTime},
+};
+
+use notify::RecursiveMode;
+
+use crate::{
+    state::{self, State},
+    strategy::EDGE_DEBOUNCE,
+    validate::Validator,
+    ChecksumAlgorithm, DebounceStrategy, Error, EventKindMask, FilterSet,
+};
+
+/// How a path registered with [`Watcher::watch_with_mode`](crate::Watcher::watch_with_mode)
+/// is watched.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum WatchMode {
+    /// Only the path itself is watched. For a directory, this includes changes to its direct
+    /// children but nothing deeper.
+    NonRecursive,
+    /// The path and everything below it are watched, including files and directories that
+    /// are created later.
+    Recursive,
+}
+
+impl From<WatchMode> for RecursiveMode {
+    fn from(mode: WatchMode) -> Self {
+        match mode {
+            WatchMode::NonRecursive => RecursiveMode::NonRecursive,
+            WatchMode::Recursive => RecursiveMode::Recursive,
+        }
+    }
+}
+
+/// Describes a path registered with a [`Watcher`](crate::Watcher).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WatchInfo {
+    /// The path being watched, exactly as it was registered.
+    pub path: PathBuf,
+    /// Whether everything below the path is watched as well.
+    pub recursive: bool,
+    /// When the path was registered.
+    pub added: SystemTime,
+    /// Whether the path is still waiting to be created, see
+    /// [`Watcher::watch_pending`](crate::Watcher::watch_pending).
+    pub pending: bool,
+    /// The resolved target of the symbolic link, if the watch follows a symbolic link, see
+    /// [`WatchOptions::follow_symlinks`].
+    pub symlink_target: Option<PathBuf>,
+    /// Whether the path is a glob pattern, see [`Watcher::watch_glob`](crate::Watcher::watch_glob).
+    pub glob: bool,
+    /// How many levels below the path are watched, if a recursive watch is limited, see
+    /// [`WatchOptions::max_depth`].
+    pub max_depth: Option<usize>,
+    /// The debounce duration of the watch, if it overrides the watcher's, see
+    /// [`WatchOptions::debounce`].
+    pub debounce: Option<Duration>,
+    /// The file extensions events are reported for, if the watch only reports some files,
+    /// see [`Watcher::watch_dir_ext`](crate::Watcher::watch_dir_ext).
+    pub extensions: Vec<String>,
+    /// The tags attached to the watch, in sorted order, see
+    /// [`Watcher::watch_tagged`](crate::Watcher::watch_tagged).
+    pub tags: Vec<String>,
+    /// Whether the path is polled because the OS ran out of file watches, see
+    /// [`WatcherBuilder::poll_fallback`](crate::WatcherBuilder::poll_fallback), or because it
+    /// lives on a network file system, see
+    /// [`WatcherBuilder::network_poll`](crate::WatcherBuilder::network_poll).
+    pub polled: bool,
+    /// Whether modifications that leave a file's content unchanged are dropped, see
+    /// [`WatchOptions::ignore_unchanged`].
+    pub ignore_unchanged: bool,
+    /// The largest text file events carry a diff for, if the watch computes diffs, see
+    /// [`WatchOptions::diff`].
+    pub diff_limit: Option<u64>,
+    /// Whether modifications carry the change in size of their file, see
+    /// [`WatchOptions::track_size`].
+    pub track_size: bool,
+    /// How the checksums that modifications carry are computed, if the watch tracks
+    /// checksums, see [`WatchOptions::checksum`].
+    pub checksum: Option<ChecksumAlgorithm>,
+    /// Whether modifications carry a summary of the change, see
+    /// [`WatchOptions::change_summary`].
+    pub change_summary: bool,
+    /// Whether modifications of JSON and TOML files carry the keys that changed, see
+    /// [`WatchOptions::key_changes`].
+    pub key_changes: bool,
+    /// Whether the files the watch reports created or changed are validated, see
+    /// [`WatchOptions::validate`].
+    pub validated: bool,
+    /// The directory the previous versions of the files the watch reports are kept in, if
+    /// it keeps snapshots, see [`WatchOptions::snapshots`].
+    pub snapshot_dir: Option<PathBuf>,
+    /// How many previous versions of each file are kept, see [`WatchOptions::snapshots`].
+    pub snapshot_versions: usize,
+    /// Whether the changes below the path are reported as one event per batch, see
+    /// [`WatchOptions::summarize`].
+    pub summarize: bool,
+    /// Whether only the events that a filter set allows are delivered, see
+    /// [`WatchOptions::filter_set`].
+    pub filtered: bool,
+    /// When the events of the watch are delivered, see [`WatchOptions::strategy`].
+    pub strategy: DebounceStrategy,
+    /// How long files must stay unchanged before their events are delivered, if the watch
+    /// waits for them to settle, see [`WatchOptions::settle`].
+    pub settle: Option<Duration>,
+    /// The kinds of events the watch delivers, see [`WatchOptions::kinds`].
+    pub kinds: EventKindMask,
+    /// Whether modifications are delivered when the writer closes the file, see
+    /// [`WatchOptions::close_write`].
+    pub close_write: bool,
+}
+
+impl WatchInfo {
+    pub(crate) fn new(path: PathBuf, mode: RecursiveMode) -> Self {
+        Self {
+            path,
+            recursive: mode == RecursiveMode::Recursive,
+            added: SystemTime::now(),
+            pending: false,
+            symlink_target: None,
+            glob: false,
+            max_depth: None,
+            debounce: None,
+            extensions: Vec::new(),
+            tags: Vec::new(),
+            polled: false,
+            ignore_unchanged: false,
+            diff_limit: None,
+            track_size: false,
+            checksum: None,
+            change_summary: false,
+            key_changes: false,
+            validated: false,
+            snapshot_dir: None,
+            snapshot_versions: 0,
+            summarize: false,
+            filtered: false,
+            strategy: DebounceStrategy::Trailing,
+            settle: None,
+            kinds: EventKindMask::ALL,
+            close_write: false,
+        }
+    }
+
+    /// Marks the watch as waiting for its path to be created.
+    pub(crate) fn pending(mut self) -> Self {
+        self.pending = true;
+        self
+    }
+
+    /// Marks the watch as a glob pattern.
+    pub(crate) fn glob(mut self) -> Self {
+        self.glob = true;
+        self
+    }
+
+    /// Marks the watch as served by the polling fallback.
+    pub(crate) fn polled(mut self) -> Self {
+        self.polled = true;
+        self
+    }
+
+    /// Limits a recursive watch to `max_depth` levels below its path.
+    pub(crate) fn max_depth(mut self, max_depth: usize) -> Self {
+        self.max_depth = Some(max_depth);
+        self
+    }
+
+    /// Records that the watch is debounced for `debounce`.
+    pub(crate) fn debounce(mut self, debounce: Duration) -> Self {
+        self.debounce = Some(debounce);
+        self
+    }
+
+    /// Returns `true` if the watch reports events for `path`.
+    pub(crate) fn reports(&self, path: &Path) -> bool {
+        if !self.extensions.is_empty() {
+            return self.has_extension(path) && path.starts_with(&self.path);
+        }
+        if !self.is_direct() {
+            return false;
+        }
+        self.path == path
+            || path.parent() == Some(self.path.as_path())
+            || (self.recursive && self.depth_of(path).is_some_and(|depth| self.within(depth)))
+    }
+
+    /// Returns `true` if the watch reports events for the children of `dir`.
+    pub(crate) fn reports_children_of(&self, dir: &Path) -> bool {
+        if !self.is_direct() {
+            return false;
+        }
+        self.path == dir
+            || (self.recursive
+                && self
+                    .depth_of(dir)
+                    .is_some_and(|depth| self.within(depth + 1)))
+    }
+
+    /// Returns `true` if the watch reports events for everything below `dir`.
+    pub(crate) fn reports_everything_below(&self, dir: &Path) -> bool {
+        self.is_direct()
+            && self.recursive
+            && self.max_depth.is_none()
+            && dir.starts_with(&self.path)
+    }
+
+    /// Returns `true` if the path is registered with the backend and every event it
+    /// produces is reported.
+    fn is_direct(&self) -> bool {
+        !self.pending && !self.glob && self.extensions.is_empty()
+    }
+
+    fn has_extension(&self, path: &Path) -> bool {
+        path.extension().is_some_and(|extension| {
+            self.extensions
+                .iter()
+                .any(|wanted| extension.eq_ignore_ascii_case(wanted.as_str()))
+        })
+    }
+
+    fn depth_of(&self, path: &Path) -> Option<usize> {
+        path.strip_prefix(&self.path)
+            .ok()
+            .map(|rest| rest.components().count())
+    }
+
+    fn within(&self, depth: usize) -> bool {
+        self.max_depth.is_none_or(|max_depth| depth <= max_depth)
+    }
+
+    /// Records that the watch follows a symbolic link to `target`.
+    pub(crate) fn following(mut self, target: PathBuf) -> Self {
+        self.symlink_target = Some(target);
+        self
+    }
+}
+
+/// Options for a single watch registered with
+/// [`Watcher::watch_with_options`](crate::Watcher::watch_with_options).
+///
+/// Options that are not set fall back to the watcher's configuration.
+#[derive(Debug, Clone, Default)]
+pub struct WatchOptions {
+    pub(crate) recursive: Option<bool>,
+    pub(crate) follow_symlinks: bool,
+    pub(crate) max_depth: Option<usize>,
+    pub(crate) debounce: Option<Duration>,
+    pub(crate) ignore_unchanged: bool,
+    pub(crate) diff: Option<u64>,
+    pub(crate) initial_scan: bool,
+    pub(crate) track_size: bool,
+    pub(crate) checksum: Option<ChecksumAlgorithm>,
+    pub(crate) change_summary: bool,
+    pub(crate) key_changes: bool,
+    pub(crate) validator: Option<Validator>,
+    pub(crate) snapshots: Option<(PathBuf, usize)>,
+    pub(crate) summarize: bool,
+    pub(crate) filters: Option<FilterSet>,
+    pub(crate) strategy: DebounceStrategy,
+    pub(crate) settle: Option<Duration>,
+    pub(crate) kinds: Option<EventKindMask>,
+    pub(crate) close_write: bool,
+}
+
+impl WatchOptions {
+    /// Creates a new set of options with the watcher's defaults.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets whether everything below the path is watched as well.
+    ///
+    /// # Arguments
+    /// * `recursive` - `true` to watch the path recursively. Defaults to the watcher's
+    ///   [`WatcherBuilder::recursive`](crate::WatcherBuilder::recursive) setting.
+    pub fn recursive(mut self, recursive: bool) -> Self {
+        self.recursive = Some(recursive);
+        self
+    }
+
+    /// Sets whether a symbolic link is followed to its target.
+    ///
+    /// When enabled and the path is a symbolic link, the resolved target is watched instead
+    /// of the link node, and events inside the target are reported under the link's path.
+    /// The directory holding the link is watched as well, so that the target is re-resolved
+    /// when the link itself is replaced. Such a watch is served by the watcher's debouncer,
+    /// so it cannot have a [debounce duration](WatchOptions::debounce) or
+    /// [strategy](WatchOptions::strategy) of its own.
+    ///
+    /// # Arguments
+    /// * `follow` - `true` to follow symbolic links. Defaults to `false`.
+    pub fn follow_symlinks(mut self, follow: bool) -> Self {
+        self.follow_symlinks = follow;
+        self
+    }
+
+    /// Limits a recursive watch to a number of levels below the path.
+    ///
+    /// With a limit of `2`, a project root is watched together with its sub-directories and
+    /// theirs, but nothing deeper, so large trees such as `node_modules` are never descended
+    /// into. Each directory within the limit is registered separately, and directories
+    /// created within the limit later on are watched as well. Setting a limit implies
+    /// [`WatchOptions::recursive`]. The directories are served by the watcher's debouncer, so
+    /// the watch cannot have a [debounce duration](WatchOptions::debounce) or
+    /// [strategy](WatchOptions::strategy) of its own.
+    ///
+    /// # Arguments
+    /// * `max_depth` - How many levels below the path are watched. A limit of `1` watches the
+    ///   children of the path only, like a non-recursive watch.
+    pub fn max_depth(mut self, max_depth: usize) -> Self {
+        self.recursive = Some(true);
+        self.max_depth = Some(max_depth);
+        self
+    }
+
+    /// Overrides how long events for this path are debounced.
+    ///
+    /// Watches with the same debounce duration share one debouncer, and each distinct
+    /// duration adds another, so a certificate can settle for ten seconds while templates
+    /// are reloaded after a hundred milliseconds. Only plain watches can override the
+    /// duration. It is ignored in combination with [`WatchOptions::follow_symlinks`] and
+    /// [`WatchOptions::max_depth`].
+    ///
+    /// # Arguments
+    /// * `debounce` - How long to wait for the path to settle before triggering the handler.
+    ///   Defaults to the watcher's debounce duration.
+    pub fn debounce(mut self, debounce: Duration) -> Self {
+        self.debounce = Some(debounce);
+        self
+    }
+
+    /// Sets whether modifications that leave a file's content unchanged are dropped.
+    ///
+    /// Many tools rewrite files with identical content. With this option, the content of
+    /// each file reported by the watch is hashed whenever it is modified, and the event is
+    /// only delivered if the hash differs from the previous one. A watched file is hashed
+    /// when the watch is registered; files below a watched directory are hashed when they
+    /// first change, so their first modification is always delivered.
+    ///
+    /// # Arguments
+    /// * `ignore` - `true` to drop unchanged rewrites. Defaults to `false`.
+    pub fn ignore_unchanged(mut self, ignore: bool) -> Self {
+        self.ignore_unchanged = ignore;
+        self
+    }
+
+    /// Attaches a line diff against the previous version to the events of text files.
+    ///
+    /// The content of each UTF-8 file reported by the watch is remembered, and every event
+    /// for it carries the lines removed and added since, see
+    /// [`Event::diff`](crate::Event::diff). A watched file is read when the watch is
+    /// registered; files below a watched directory are read when they are created or first
+    /// change, so the first modification of an existing file carries no diff. Diffs are only
+    /// delivered to handlers of the crate's own [`Event`](crate::Event)s.
+    ///
+    /// # Arguments
+    /// * `max_size` - The largest file, in bytes, that is diffed. Larger files and files
+    ///   that are not valid UTF-8 carry no diff.
+    pub fn diff(mut self, max_size: u64) -> Self {
+        self.diff = Some(max_size);
+        self
+    }
+
+    /// Sets whether modifications carry how much their file grew or shrank.
+    ///
+    /// The size of each file reported by the watch is remembered, and every modification of
+    /// it carries a [`SizeDelta`](crate::SizeDelta) against the previous size, so that log
+    /// tailers can tell appends from truncation and rotation, see
+    /// [`Event::size_delta`](crate::Event::size_delta). A watched file is measured when the
+    /// watch is registered; files below a watched directory are measured when they are
+    /// created or first change, so the first modification of an existing file carries no
+    /// change. Size changes are only delivered to handlers of the crate's own
+    /// [`Event`](crate::Event)s.
+    ///
+    /// # Arguments
+    /// * `track` - `true` to track file sizes. Defaults to `false`.
+    pub fn track_size(mut self, track: bool) -> Self {
+        self.track_size = track;
+        self
+    }
+
+    /// Sets whether modifications carry the checksum of their file before and after.
+    ///
+    /// The checksum of each file reported by the watch is remembered, and every modification
+    /// of it carries the previous and the new one, see
+    /// [`Event::checksum`](crate::Event::checksum), for integrity monitoring and cache
+    /// invalidation. A watched file is hashed when the watch is registered; files below a
+    /// watched directory are hashed when they are created or first change, so the first
+    /// modification of an existing file carries no previous checksum. The files of a batch
+    /// are hashed on the debouncer thread before the batch is delivered, spread across a few
+    /// scoped threads and with the watch set unlocked. Hashing large files thus delays the
+    /// delivery of the batch and of those after it, but not the registration of other watches.
+    /// Checksums are only delivered to handlers of the crate's own
+    /// [`Event`](crate::Event)s.
+    ///
+    /// # Arguments
+    /// * `algorithm` - How checksums are computed, such as
+    ///   [`ChecksumAlgorithm::Sha256`](crate::ChecksumAlgorithm::Sha256).
+    pub fn checksum(mut self, algorithm: ChecksumAlgorithm) -> Self {
+        self.checksum = Some(algorithm);
+        self
+    }
+
+    /// Sets whether modifications carry a summary of how their file changed.
+    ///
+    /// The size and a hash of the content of each file reported by the watch are
+    /// remembered, and every modification of it carries the previous and the new size, how
+    /// many bytes were added or removed, and whether the previous content was kept and only
+    /// appended to, see [`Event::change`](crate::Event::change). Each modified file is read
+    /// once to tell appends from rewrites. A watched file is read when the watch is
+    /// registered; files below a watched directory are read when they are created or first
+    /// change, so the first modification of an existing file carries no summary. Summaries
+    /// are only delivered to handlers of the crate's own [`Event`](crate::Event)s.
+    ///
+    /// # Arguments
+    /// * `summary` - `true` to summarize changes. Defaults to `false`.
+    pub fn change_summary(mut self, summary: bool) -> Self {
+        self.change_summary = summary;
+        self
+    }
+
+    /// Sets whether modifications of structured files carry the keys that changed.
+    ///
+    /// Each file reported by the watch is parsed, as TOML if its extension is `toml` and as
+    /// JSON otherwise, and every modification of it carries the JSON pointers of the values
+    /// that differ from the previous version, such as `/server/port`, see
+    /// [`Event::changed_keys`](crate::Event::changed_keys). Applications can then reload
+    /// only the subsystems whose settings changed. Tables and objects are compared key by
+    /// key, while arrays are compared as a whole. A version that does not parse carries no
+    /// keys, and the next one is compared to the last version that parsed. A watched file
+    /// is parsed when the watch is registered; files below a watched directory are parsed
+    /// when they are created or first change. Changed keys are only delivered to handlers
+    /// of the crate's own [`Event`](crate::Event)s.
+    ///
+    /// # Arguments
+    /// * `track` - `true` to report the keys that changed. Defaults to `false`.
+    pub fn key_changes(mut self, track: bool) -> Self {
+        self.key_changes = track;
+        self
+    }
+
+    /// Validates the files this watch reports created, changed or renamed.
+    ///
+    /// The validator is called with the path of each such file before its event is
+    /// delivered, and the outcome travels with the event, see
+    /// [`Event::validation`](crate::Event::validation), so that a handler can alert when an
+    /// edit breaks a configuration file instead of reloading it. Validators run after the
+    /// watch set is unlocked, so a slow one, such as one running `nginx -t`, only delays
+    /// the delivery of the batch. A validator that panics rejects the file. Outcomes are
+    /// only delivered to handlers of the crate's own [`Event`](crate::Event)s.
+    ///
+    /// # Arguments
+    /// * `validator` - The function returning an error for a file that is not valid. The
+    ///   error's message is delivered with the event.
+    pub fn validate<E: fmt::Display>(
+        mut self,
+        validator: impl Fn(&Path) -> Result<(), E> + Send + Sync + 'static,
+    ) -> Self {
+        self.validator = Some(Validator::new(validator));
+        self
+    }
+
+    /// Keeps the previous versions of the files this watch reports in a directory.
+    ///
+    /// Before a modification is delivered, the version of the file it replaced is kept in
+    /// `dir` as `<name>.~<n>~`, numbered like the backups of GNU tools with the highest
+    /// number being the newest, see [`Event::snapshot`](crate::Event::snapshot). Files below
+    /// a watched directory keep their relative path below `dir`. Only the newest `keep`
+    /// snapshots of each file are retained, so that a configuration management tool can undo
+    /// or diff the last few edits. A copy of the current version of each file is kept in
+    /// the `.current` directory below `dir`; a watched file is copied when the watch is
+    /// registered, and files below a watched directory when they are created or first
+    /// change, so that their first modification has no snapshot. Files are copied after the
+    /// watch set is unlocked. The snapshot directory must not be below the watched path.
+    ///
+    /// # Arguments
+    /// * `dir` - The directory to keep the snapshots in. It is created if it does not exist.
+    /// * `keep` - How many previous versions of each file to keep.
+    pub fn snapshots(mut self, dir: impl Into<PathBuf>, keep: usize) -> Self {
+        self.snapshots = Some((dir.into(), keep));
+        self
+    }
+
+    /// Sets whether the changes below the path are reported as one event per batch.
+    ///
+    /// Instead of one event per changed file, the handler receives a single modification of
+    /// the watched path for each debounced batch, listing every path that changed below it,
+    /// see [`Event::changed`](crate::Event::changed). This suits consumers that rebuild a
+    /// whole project regardless of which file changed. Handlers of the back-end's
+    /// [`DebouncedEvent`](crate::DebouncedEvent)s only see the watched path.
+    ///
+    /// # Arguments
+    /// * `summarize` - `true` to summarize changes. Defaults to `false`.
+    pub fn summarize(mut self, summarize: bool) -> Self {
+        self.summarize = summarize;
+        self
+    }
+
+    /// Sets whether the paths that already exist are reported when the watch is registered.
+    ///
+    /// With this option, registering a directory hands the handler one event per file and
+    /// directory already below it that the watch reports, as if each had just been created,
+    /// so that startup code and later changes can share one code path. Registering a file
+    /// reports the file itself. The events are delivered before
+    /// [`Watcher::watch_with_options`](crate::Watcher::watch_with_options) returns, and
+    /// handlers of the crate's own [`Event`](crate::Event)s see them as
+    /// [`EventKind::Existing`](crate::EventKind::Existing).
+    ///
+    /// # Arguments
+    /// * `scan` - `true` to report existing paths. Defaults to `false`.
+    pub fn initial_scan(mut self, scan: bool) -> Self {
+        self.initial_scan = scan;
+        self
+    }
+
+    /// Sets glob patterns and predicates deciding which events of this watch are delivered.
+    ///
+    /// The set applies only to the events below this path, on top of the watcher's
+    /// [`WatcherBuilder::filter_set`](crate::WatcherBuilder::filter_set). Patterns relative
+    /// to the watched path, such as `*.log`, match the paths directly below it.
+    ///
+    /// # Arguments
+    /// * `filters` - The patterns and predicates to apply, see [`FilterSet`].
+    pub fn filter_set(mut self, filters: FilterSet) -> Self {
+        self.filters = Some(filters);
+        self
+    }
+
+    /// Sets when the events of this path are delivered relative to the changes causing them.
+    ///
+    /// By default, events are delivered once the path has been quiet for the debounce
+    /// duration, so every change is reported that much later. A
+    /// [leading](DebounceStrategy::Leading) watch reports the first change of a burst
+    /// right away instead, and a [throttled](DebounceStrategy::Throttle) one reports changes
+    /// right away but at most once per debounce duration. Both are served by a debouncer
+    /// that only waits a few milliseconds, which the `debounce` of [`WatchInfo`] reports,
+    /// and use [`WatchOptions::debounce`], or the watcher's debounce duration, as their
+    /// interval.
+    ///
+    /// # Arguments
+    /// * `strategy` - When events are delivered. Defaults to
+    ///   [`DebounceStrategy::Trailing`].
+    pub fn strategy(mut self, strategy: DebounceStrategy) -> Self {
+        self.strategy = strategy;
+        self
+    }
+
+    /// Sets which kinds of events of this watch are delivered.
+    ///
+    /// The mask applies only to the events below this path, on top of the watcher's
+    /// [`WatcherBuilder::kinds`](crate::WatcherBuilder::kinds), so one watcher can report
+    /// only creations in an inbox directory and only modifications of a configuration file.
+    ///
+    /// # Arguments
+    /// * `kinds` - The kinds to deliver, see [`EventKind`](crate::EventKind). Defaults to
+    ///   [`EventKindMask::ALL`].
+    pub fn kinds(mut self, kinds: EventKindMask) -> Self {
+        self.kinds = Some(kinds);
+        self
+    }
+
+    /// Delays the events of this path until their file stops changing.
+    ///
+    /// Debouncing only waits for the events to stop, but a large upload or copy can pause
+    /// for longer than the debounce duration and still be incomplete. With this option,
+    /// each debounced event is held back, and the size and modification time of its file
+    /// are checked repeatedly, until both have stayed the same for `settle`. Every later
+    /// event for the file restarts the wait. Handlers therefore never see a half-written
+    /// file, at the cost of learning about every change `settle` later.
+    ///
+    /// # Arguments
+    /// * `settle` - How long a file must stay unchanged before its event is delivered.
+    pub fn settle(mut self, settle: Duration) -> Self {
+        self.settle = Some(settle);
+        self
+    }
+
+    /// Delivers the modifications of a file only once the writer closes it.
+    ///
+    /// A tool that reads a file as soon as it is reported modified can see a partial write,
+    /// if the writer is still busy. With this option, the writes themselves are not
+    /// delivered, and a single [`Modified`](crate::EventKind::Modified) event follows when
+    /// the file is closed after writing. Creations are still delivered when the file
+    /// appears. This relies on inotify's `IN_CLOSE_WRITE`, so it only applies on Linux with
+    /// the native back-end. Elsewhere, and for polled paths, modifications are delivered as
+    /// usual and [`WatchInfo::close_write`] stays `false`.
+    ///
+    /// # Arguments
+    /// * `close_write` - `true` to deliver modifications when the file is closed. Defaults
+    ///   to `false`.
+    pub fn close_write(mut self, close_write: bool) -> Self {
+        self.close_write = close_write;
+        self
+    }
+
+    /// Returns the duration of the debouncer serving the watch, if it is not the watcher's.
+    pub(crate) fn debouncer_duration(&self) -> Option<Duration> {
+        match self.strategy {
+            DebounceStrategy::Trailing => self.debounce,
+            DebounceStrategy::Leading | DebounceStrategy::Throttle => Some(EDGE_DEBOUNCE),
+        }
+    }
+
+    /// Resolves the recursive mode of the watch against the watcher's default.
+    pub(crate) fn mode(&self, default: RecursiveMode) -> RecursiveMode {
+        match self.recursive {
+            Some(true) => RecursiveMode::Recursive,
+            Some(false) => RecursiveMode::NonRecursive,
+            None => default,
+        }
+    }
+}
+
+/// The outcome of registering many paths at once with [`Watcher::watch_all`](crate::Watcher::watch_all).
+#[derive(Debug, Default)]
+pub struct WatchReport {
+    /// The paths that are now being watched.
+    pub watched: Vec<PathBuf>,
+    /// The paths that could not be watched, together with the reason.
+    pub failed: Vec<(PathBuf, Error)>,
+}
+
+impl WatchReport {
+    /// Returns `true` if every path was watched successfully.
+    pub fn is_ok(&self) -> bool {
+        self.failed.is_empty()
+    }
+}
+
+/// Keeps a path watched for as long as it is alive.
+///
+/// A `WatchGuard` is returned by [`Watcher::watch_scoped`](crate::Watcher::watch_scoped).
+/// When the guard is dropped, its path is unwatched. If the watcher itself has already been
+/// dropped, dropping the guard does nothing.
+#[must_use = "the path is unwatched as soon as the guard is dropped"]
+pub struct WatchGuard {
+    state: Weak<Mutex<State>>,
+    path: PathBuf,
+}
+
+impl WatchGuard {
+    pub(crate) fn new(state: Weak<Mutex<State>>, path: PathBuf) -> Self {
+        Self { state, path }
+    }
+
+    /// Returns the path kept watched by this guard.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for WatchGuard {
+    fn drop(&mut self) {
+        if let Some(state) = self.state.upgrade() {
+            if let Err(error) = state::lock(&state).remove_watch(&self.path) {
+                tracing::debug!(
+                    "Failed to unwatch {} on guard drop: {}",
+                    self.path.display(),
+                    error
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,4612 @@
+use std::{
+    collections::BTreeMap,
+    future::Future,
+    path::{Path, PathBuf},
+    sync::{mpsc, Arc, MutexGuard},
+    time::Duration,
+};
+
+use notify::RecursiveMode;
+
+use crate::{
+    dispatch::{self, SharedDispatcher},
+    event::Details,
+    rate::RateLimit,
+    read,
+    snapshot::SnapshotDir,
+    state::{self, SharedState, State},
+    strategy::LeadingWatch,
+    tags, tail,
+    validate::Validator,
+    DebounceEventHandler, DebounceEventResult, DebounceStrategy, DebouncedEvent, Error, Event,
+    EventHandler, EventKindMask, EventStream, Events, FilterSet, PauseMode, Shutdown, Spawn,
+    Tailer, WatchGuard, WatchInfo, WatchMode, WatchOptions, WatchReport, WatcherBuilder,
+    DEFAULT_DEBOUNCE,
+};
+
+/// A watcher that monitors files for changes and debounces events.
+///
+/// The `Watcher` struct is responsible for setting up a file watcher and debouncing
+/// file change events. It uses the `notify` crate to watch for file changes, and the
+/// `notify-debouncer-full` crate to debounce those events.
+///
+/// A `Watcher` is `Send` and `Sync`, and cloning it is cheap: clones share the same watch
+/// set, backend and handlers, so watches can be registered from several threads without
+/// wrapping the watcher in a mutex. Every method takes `&self` and locks the watch set for
+/// its own duration only, so calls from different threads are applied one after another
+/// and each sees the effects of the calls that completed before it. The backend stops once
+/// the last clone is dropped.
+#[derive(Clone)]
+pub struct Watcher {
+    pub(crate) state: SharedState,
+    pub(crate) dispatcher: SharedDispatcher,
+}
+
+impl Watcher {
+    /// Creates a new file watcher with the provided debounce event handler.
+    ///
+    /// The file watcher will debounce events for 2 seconds before triggering the provided handler.
+    /// This helps to reduce the number of events that need to be processed, especially when
+    /// many files are being watched and modified in quick succession.
+    ///
+    /// # Arguments
+    /// * `handler` - The debounce event handler to call when a file change is detected.
+    ///
+    /// # Returns
+    /// A `Result` containing either a new instance of the file watcher, or an `Error` if the
+    /// underlying OS watcher could not be created.
+    pub fn new(handler: impl DebounceEventHandler) -> Result<Self, Error> {
+        Self::with_debounce(DEFAULT_DEBOUNCE, handler)
+    }
+
+    /// Creates a new file watcher that debounces events for the given duration.
+    ///
+    /// Shorter durations deliver changes sooner at the cost of more handler invocations,
+    /// which suits latency-sensitive tools such as hot reloaders and test runners.
+    ///
+    /// # Arguments
+    /// * `debounce` - How long to wait for a file to settle before triggering the handler.
+    /// * `handler` - The debounce event handler to call when a file change is detected.
+    ///
+    /// # Returns
+    /// A `Result` containing either a new instance of the file watcher, or an `Error` if the
+    /// underlying OS watcher could not be created.
+    pub fn with_debounce(
+        debounce: Duration,
+        handler: impl DebounceEventHandler,
+    ) -> Result<Self, Error> {
+        WatcherBuilder::new().debounce(debounce).build(handler)
+    }
+
+    /// Creates a new file watcher that delivers the crate's own [`Event`](crate::Event)s.
+    ///
+    /// Events are debounced for 2 seconds, like with [`Watcher::new`]. Use
+    /// [`WatcherBuilder::build_with_event_handler`] to configure the watcher further.
+    ///
+    /// ```no_run
+    /// use watchit::{EventKind, EventResult, Watcher};
+    ///
+    /// let watcher = Watcher::with_event_handler(|result: EventResult| {
+    ///     for event in result.unwrap_or_default() {
+    ///         if event.kind == EventKind::Modified {
+    ///             println!("{} changed", event.path.display());
+    ///         }
+    ///     }
+    /// })
+    /// .unwrap();
+    /// watcher.watch("Cargo.toml").unwrap();
+    /// ```
+    ///
+    /// # Arguments
+    /// * `handler` - The event handler to call when a file change is detected.
+    ///
+    /// # Returns
+    /// A `Result` containing either a new instance of the file watcher, or an `Error` if the
+    /// underlying OS watcher could not be created.
+    pub fn with_event_handler(handler: impl EventHandler) -> Result<Self, Error> {
+        WatcherBuilder::new().build_with_event_handler(handler)
+    }
+
+    /// Creates a new file watcher together with a channel receiving its events.
+    ///
+    /// Instead of writing a callback, events are pulled from the receiver in the caller's own
+    /// loop, which fits existing event loops and makes tests straightforward:
+    ///
+    /// ```no_run
+    /// let (watcher, events) = watchit::Watcher::channel().unwrap();
+    /// watcher.watch("Cargo.toml").unwrap();
+    /// for event in events {
+    ///     println!("{event}");
+    /// }
+    /// ```
+    ///
+    /// Events are debounced for 2 seconds, like with [`Watcher::new`]; use
+    /// [`WatcherBuilder::build_channel`] to configure the watcher further. Errors are not sent
+    /// on the channel. They are logged, unless they are received with [`Watcher::errors`].
+    ///
+    /// # Returns
+    /// A `Result` containing either the file watcher and the receiver of its events, or an
+    /// `Error` if the underlying OS watcher could not be created.
+    pub fn channel() -> Result<(Self, mpsc::Receiver<Event>), Error> {
+        WatcherBuilder::new().build_channel()
+    }
+
+    /// Creates a new file watcher whose handler is asynchronous.
+    ///
+    /// For every event, `handler` is called on the debouncer thread and the future it returns
+    /// is handed to `spawn`, so the handler can await file reads and network calls without
+    /// holding up the delivery of further events. Futures of consecutive events may run
+    /// concurrently. Events are debounced for 2 seconds, like with [`Watcher::new`]. Use
+    /// [`WatcherBuilder::build_async_handler`] to configure the watcher further.
+    ///
+    /// ```ignore
+    /// let watcher = Watcher::new_async(
+    ///     |future| { tokio::spawn(future); },
+    ///     |event| async move {
+    ///         if let Ok(event) = event {
+    ///             let content = tokio::fs::read(&event.path).await;
+    ///         }
+    ///     },
+    /// )?;
+    /// ```
+    ///
+    /// # Arguments
+    /// * `spawn` - The executor running the futures of the handler, see [`Spawn`].
+    /// * `handler` - The handler to call with each event, or with each error reported
+    ///   instead.
+    ///
+    /// # Returns
+    /// A `Result` containing either a new instance of the file watcher, or an `Error` if the
+    /// underlying OS watcher could not be created.
+    pub fn new_async<F>(
+        spawn: impl Spawn,
+        handler: impl FnMut(Result<Event, Error>) -> F + Send + 'static,
+    ) -> Result<Self, Error>
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        WatcherBuilder::new().build_async_handler(spawn, handler)
+    }
+
+    /// Creates a new file watcher that delivers raw, undebounced events.
+    ///
+    /// Every event of the back-end is passed to `handler` as soon as it arrives, see
+    /// [`WatcherBuilder::raw`]. Use the builder to receive debounced events as well.
+    ///
+    /// # Arguments
+    /// * `handler` - The handler to call with each raw event.
+    ///
+    /// # Returns
+    /// A `Result` containing either a new instance of the file watcher, or an `Error` if the
+    /// underlying OS watcher could not be created.
+    pub fn raw(handler: impl notify::EventHandler) -> Result<Self, Error> {
+        WatcherBuilder::new()
+            .raw(handler)
+            .build(|_: DebounceEventResult| {})
+    }
+
+    /// Creates a builder for configuring a new file watcher.
+    ///
+    /// # Returns
+    /// A new [`WatcherBuilder`] with the default options.
+    pub fn builder() -> WatcherBuilder {
+        WatcherBuilder::new()
+    }
+
+    /// Watches the specified file for changes.
+    ///
+    /// This function sets up a file watcher to monitor the specified file for any changes.
+    /// When a change is detected, the file is added to the debouncer's cache to be processed later.
+    /// The function returns a `Result` indicating whether the file watcher was successfully set up.
+    ///
+    /// If the watcher was built with [`WatcherBuilder::recursive`], directories are watched
+    /// recursively just like [`Watcher::watch_recursive`].
+    ///
+    /// # Arguments
+    /// * `filename` - The path to the file to be watched.
+    ///
+    /// # Returns
+    /// A `Result` containing either an empty `()` value on success, or an `Error` on failure.
+    pub fn watch(&self, filename: impl AsRef<Path>) -> Result<(), Error> {
+        let mut state = self.lock();
+        let mode = state.mode;
+        state.add_watch(filename.as_ref(), mode)
+    }
+
+    /// Watches the specified file for changes using the given mode.
+    ///
+    /// Unlike [`Watcher::watch`], the mode does not depend on how the watcher was built.
+    /// The path is registered with the debouncer's cache using the same mode, so renames are
+    /// tracked throughout the watched tree in recursive mode and only among the direct
+    /// children otherwise.
+    ///
+    /// # Arguments
+    /// * `filename` - The path to the file to be watched.
+    /// * `mode` - Whether everything below the path is watched as well.
+    ///
+    /// # Returns
+    /// A `Result` containing either an empty `()` value on success, or an `Error` on failure.
+    pub fn watch_with_mode(
+        &self,
+        filename: impl AsRef<Path>,
+        mode: WatchMode,
+    ) -> Result<(), Error> {
+        self.lock().add_watch(filename.as_ref(), mode.into())
+    }
+
+    /// Watches the specified file for changes, delivering its events to a dedicated handler.
+    ///
+    /// Events for `filename`, or anything below it if it is watched recursively, are passed
+    /// to `handler` instead of the handler the watcher was created with. When paths registered
+    /// this way are nested, each event goes to the handler of the most specific path.
+    ///
+    /// # Arguments
+    /// * `filename` - The path to the file to be watched.
+    /// * `handler` - The debounce event handler to call when a change to this path is detected.
+    ///
+    /// # Returns
+    /// A `Result` containing either an empty `()` value on success, or an `Error` on failure.
+    pub fn watch_with(
+        &self,
+        filename: impl AsRef<Path>,
+        handler: impl DebounceEventHandler,
+    ) -> Result<(), Error> {
+        let filename = filename.as_ref();
+
+        let mut state = self.lock();
+        let mode = state.mode;
+        state.add_watch(filename, mode)?;
+
+        dispatch::lock(&self.dispatcher).add_route(filename, handler, false);
+
+        Ok(())
+    }
+
+    /// Watches the specified file, handing its contents to `handler` after each change.
+    ///
+    /// The file is read right after each debounced batch that created or changed it, and
+    /// removals are not passed on. If the file is missing or locked for a moment, as while
+    /// an editor replaces it by renaming, reading is retried a few times before the change
+    /// is skipped with a warning. Like with [`Watcher::watch_with`], the events of
+    /// `filename` no longer reach the watcher's own handler.
+    ///
+    /// # Arguments
+    /// * `filename` - The path to the file to be watched.
+    /// * `handler` - The function to call with the contents of the file after it changed.
+    ///
+    /// # Returns
+    /// A `Result` containing either an empty `()` value on success, or an `Error` on failure.
+    pub fn watch_contents(
+        &self,
+        filename: impl AsRef<Path>,
+        handler: impl FnMut(Vec<u8>) + Send + 'static,
+    ) -> Result<(), Error> {
+        let filename = filename.as_ref();
+        self.watch_with(filename, read::contents_handler(filename, handler))
+    }
+
+    /// Follows the specified file, passing each line appended to it to `handler`.
+    ///
+    /// Lines that are in the file already are skipped. After each debounced change the file
+    /// is read from where the last read stopped, and every complete line is passed on without
+    /// its line break, see [`Tailer`]. Like with [`Watcher::watch_with`], the events of
+    /// `filename` no longer reach the watcher's own handler.
+    ///
+    /// # Arguments
+    /// * `filename` - The path to the file to be followed.
+    /// * `handler` - The function to call with each line appended to the file.
+    ///
+    /// # Returns
+    /// A `Result` containing either an empty `()` value on success, or an `Error` if the file
+    /// cannot be opened or watched.
+    pub fn tail(
+        &self,
+        filename: impl AsRef<Path>,
+        handler: impl FnMut(String) + Send + 'static,
+    ) -> Result<(), Error> {
+        let filename = filename.as_ref();
+        let tailer = Tailer::new(filename)
+            .map_err(|error| Error::io(error).add_path(filename.to_path_buf()))?;
+        self.tail_with(tailer, handler)
+    }
+
+    /// Follows the file of a configured [`Tailer`], passing each line it returns to `handler`.
+    ///
+    /// Works like [`Watcher::tail`], for tailers that handle rotation differently or that
+    /// were read before.
+    ///
+    /// # Arguments
+    /// * `tailer` - The tailer to read after each change of its file.
+    /// * `handler` - The function to call with each line appended to the file.
+    ///
+    /// # Returns
+    /// A `Result` containing either an empty `()` value on success, or an `Error` on failure.
+    pub fn tail_with(
+        &self,
+        tailer: Tailer,
+        handler: impl FnMut(String) + Send + 'static,
+    ) -> Result<(), Error> {
+        let filename = tailer.path().to_path_buf();
+        self.watch_with(&filename, tail::tail_handler(tailer, handler))
+    }
+
+    /// Watches the specified file until the first change, then removes the watch.
+    ///
+    /// The first debounced batch of events for `filename` is passed to `handler`, after which
+    /// the path is unwatched automatically. Combined with a path that does not exist yet, this
+    /// waits for the file to appear, see [`Watcher::watch_pending`].
+    ///
+    /// # Arguments
+    /// * `filename` - The path to the file to be watched.
+    /// * `handler` - The debounce event handler to call for the first change to this path.
+    ///
+    /// # Returns
+    /// A `Result` containing either an empty `()` value on success, or an `Error` if neither
+    /// the file nor its parent directory can be watched.
+    pub fn watch_once(
+        &self,
+        filename: impl AsRef<Path>,
+        handler: impl DebounceEventHandler,
+    ) -> Result<(), Error> {
+        let filename = filename.as_ref();
+
+        let mut state = self.lock();
+        let mode = state.mode;
+        if filename.exists() {
+            state.add_watch(filename, mode)?;
+        } else {
+            state.add_pending_watch(filename, mode)?;
+        }
+
+        dispatch::lock(&self.dispatcher).add_route(filename, handler, true);
+
+        Ok(())
+    }
+
+    /// Watches the specified file for changes, labelling its events with `tag`.
+    ///
+    /// Every event reported by this watch carries the tag, which the handler reads with
+    /// [`EventTags::tags`](crate::EventTags::tags). An event reported by several tagged
+    /// watches, such as a file below two nested directories, carries all of their tags. If
+    /// the path is already watched the tag is added to the existing watch, so one path can
+    /// have several tags.
+    ///
+    /// # Arguments
+    /// * `filename` - The path to the file to be watched.
+    /// * `tag` - The label to attach. It must not be empty or contain a `,`.
+    ///
+    /// # Returns
+    /// A `Result` containing either an empty `()` value on success, or an `Error` if the tag
+    /// is invalid or the path cannot be watched.
+    pub fn watch_tagged(&self, filename: impl AsRef<Path>, tag: &str) -> Result<(), Error> {
+        let filename = filename.as_ref();
+        tags::validate_tag(tag)?;
+        let mut state = self.lock();
+
+        if !state.watches.contains_key(filename) {
+            let mode = state.mode;
+            state.add_watch(filename, mode)?;
+        }
+
+        state.add_tag(filename, tag)
+    }
+
+    /// Watches the specified file for changes using per-watch options.
+    ///
+    /// Options that are not set in `options` fall back to the watcher's configuration, so
+    /// `watch_with_options(path, WatchOptions::new())` is equivalent to `watch(path)`.
+    ///
+    /// # Arguments
+    /// * `filename` - The path to the file to be watched.
+    /// * `options` - The options for this watch.
+    ///
+    /// # Returns
+    /// A `Result` containing either an empty `()` value on success, or an `Error` on failure,
+    /// including when [`WatchOptions::max_depth`] or [`WatchOptions::follow_symlinks`] is
+    /// combined with a debounce duration or strategy of its own, which those watches cannot
+    /// have.
+    pub fn watch_with_options(
+        &self,
+        filename: impl AsRef<Path>,
+        options: WatchOptions,
+    ) -> Result<(), Error> {
+        let filename = filename.as_ref();
+        if (options.max_depth.is_some() || options.follow_symlinks)
+            && options.debouncer_duration().is_some()
+        {
+            return Err(Error::generic(
+                "max_depth and follow_symlinks cannot be combined with a debounce duration or \
+                 strategy",
+            )
+            .add_path(filename.to_path_buf()));
+        }
+        let mut state = self.lock();
+        let mode = options.mode(state.mode);
+        let previous = PreviousWatch::save(&state, filename);
+
+        if let Some(max_depth) = options.max_depth {
+            state.add_depth_watch(filename, max_depth)?;
+        } else if options.follow_symlinks {
+            state.add_link_watch(filename, mode)?;
+        } else if let Some(debounce) = options.debouncer_duration() {
+            state.add_debounced_watch(filename, mode, debounce)?;
+        } else {
+            state.add_watch(filename, mode)?;
+        }
+
+        let initial_scan = options.initial_scan;
+        if let Err(error) = configure_watch(&mut state, filename, options) {
+            // The watch is not kept without the options it was registered with, and a watch
+            // that was registered before gets its previous options back.
+            match previous {
+                Some(previous) => previous.restore(&mut state, filename),
+                None => {
+                    let _ = state.remove_watch(filename);
+                }
+            }
+            return Err(error);
+        }
+
+        if initial_scan {
+            let existing = state.scan_existing(filename);
+            drop(state);
+            self.dispatch_synthesized(existing);
+        }
+
+        Ok(())
+    }
+
+    /// Watches the specified file for changes, even if it does not exist yet.
+    ///
+    /// If the file exists, this behaves exactly like [`Watcher::watch`]. Otherwise its parent
+    /// directory is watched until the file appears; the handler then receives the `Create`
+    /// event for the file and the watch is upgraded to a direct watch on the file itself.
+    /// Other changes in the parent directory are not reported unless that directory is
+    /// watched as well. This is useful for log files and sockets created after startup.
+    ///
+    /// # Arguments
+    /// * `filename` - The path to the file to be watched.
+    ///
+    /// # Returns
+    /// A `Result` containing either an empty `()` value on success, or an `Error` if neither
+    /// the file nor its parent directory can be watched.
+    pub fn watch_pending(&self, filename: impl AsRef<Path>) -> Result<(), Error> {
+        let filename = filename.as_ref();
+        let mut state = self.lock();
+        let mode = state.mode;
+
+        if filename.exists() {
+            state.add_watch(filename, mode)
+        } else {
+            state.add_pending_watch(filename, mode)
+        }
+    }
+
+    /// Watches the specified file for changes for as long as the returned guard is alive.
+    ///
+    /// The path is registered exactly like [`Watcher::watch`]. Dropping the returned
+    /// [`WatchGuard`] unwatches the path again, releasing the OS watch descriptor, which lets
+    /// temporary watches be tied to the lifetime of another object.
+    ///
+    /// # Arguments
+    /// * `filename` - The path to the file to be watched.
+    ///
+    /// # Returns
+    /// A `Result` containing either the [`WatchGuard`] for the path on success, or an `Error`
+    /// on failure.
+    pub fn watch_scoped(&self, filename: impl AsRef<Path>) -> Result<WatchGuard, Error> {
+        let filename = filename.as_ref();
+
+        self.watch(filename)?;
+
+        Ok(WatchGuard::new(
+            Arc::downgrade(&self.state),
+            filename.to_path_buf(),
+        ))
+    }
+
+    /// Watches the specified directory and everything below it for changes.
+    ///
+    /// Files and sub-directories created after the watch was set up are watched as well.
+    /// The directory is registered recursively with the debouncer's cache so that renames
+    /// anywhere in the tree can be tracked.
+    ///
+    /// # Arguments
+    /// * `dirname` - The path to the directory to be watched.
+    ///
+    /// # Returns
+    /// A `Result` containing either an empty `()` value on success, or an `Error` on failure.
+    pub fn watch_recursive(&self, dirname: impl AsRef<Path>) -> Result<(), Error> {
+        self.lock()
+            .add_watch(dirname.as_ref(), RecursiveMode::Recursive)
+    }
+
+    /// Watches every path matching the glob pattern `pattern` for changes.
+    ///
+    /// The directory holding everything the pattern can match, such as `src` for
+    /// `src/**/*.rs`, is watched in its place, so paths created after the watch was set up
+    /// are picked up as soon as they match. Events for paths that do not match are not
+    /// reported unless they are covered by another watch. See [`Glob`](crate::Glob) for the supported
+    /// syntax. The pattern itself is registered as the watched path, so it is also what
+    /// [`Watcher::unwatch`] expects.
+    ///
+    /// # Arguments
+    /// * `pattern` - The glob pattern to be watched.
+    ///
+    /// # Returns
+    /// A `Result` containing either an empty `()` value on success, or an `Error` if the
+    /// pattern is malformed or its base directory cannot be watched.
+    pub fn watch_glob(&self, pattern: &str) -> Result<(), Error> {
+        self.lock().add_glob_watch(pattern)
+    }
+
+    /// Watches the specified directory and everything below it, reporting only changes to
+    /// files with one of the given extensions.
+    ///
+    /// Extensions are compared without regard to ASCII case and may be given with or
+    /// without the leading dot, so `["rs", ".toml"]` reports `main.rs` and `Cargo.TOML`.
+    /// Files created after the watch was set up are picked up as well. Events for other
+    /// paths below the directory, including the directories themselves, are not reported
+    /// unless they are covered by another watch.
+    ///
+    /// # Arguments
+    /// * `dirname` - The path to the directory to be watched.
+    /// * `extensions` - The extensions of the files to report changes for. If empty, this
+    ///   behaves like [`Watcher::watch_recursive`].
+    ///
+    /// # Returns
+    /// A `Result` containing either an empty `()` value on success, or an `Error` if the
+    /// directory cannot be watched.
+    pub fn watch_dir_ext<S: AsRef<str>>(
+        &self,
+        dirname: impl AsRef<Path>,
+        extensions: impl IntoIterator<Item = S>,
+    ) -> Result<(), Error> {
+        let extensions = extensions
+            .into_iter()
+            .map(|extension| extension.as_ref().trim_start_matches('.').to_string())
+            .collect();
+
+        self.lock()
+            .add_extension_watch(dirname.as_ref(), extensions)
+    }
+
+    /// Watches every path yielded by `filenames` for changes.
+    ///
+    /// Each path is registered exactly like [`Watcher::watch`] registers it, including the
+    /// network and fallback polling and the verification sweep. The debouncer's cache is only locked once for the whole batch,
+    /// which makes this considerably faster than calling `watch` in a loop for thousands of
+    /// paths. A failure to watch one path does not stop the remaining paths from being watched.
+    ///
+    /// # Arguments
+    /// * `filenames` - The paths to be watched.
+    ///
+    /// # Returns
+    /// A [`WatchReport`] listing which paths are now watched and which failed, and why.
+    pub fn watch_all<P: AsRef<Path>>(&self, filenames: impl IntoIterator<Item = P>) -> WatchReport {
+        let mut report = WatchReport::default();
+        let mut state = self.lock();
+        let state = &mut *state;
+        let mode = state.mode;
+
+        let mut direct = Vec::new();
+        for filename in filenames {
+            let filename = filename.as_ref().to_path_buf();
+            match state.serve_watch(&filename, mode) {
+                Ok(true) => direct.push(filename),
+                Ok(false) => report.watched.push(filename),
+                Err(error) => report.failed.push((filename, error)),
+            }
+        }
+
+        let mut cache = state.debouncer.cache();
+        for filename in &direct {
+            cache.add_root(filename, mode);
+        }
+        drop(cache);
+
+        for filename in &direct {
+            state.track_file(filename);
+        }
+        state.refresh_helpers();
+        report.watched.extend(direct);
+
+        tracing::debug!(
+            "Watching {} paths for changes, {} failed",
+            report.watched.len(),
+            report.failed.len()
+        );
+
+        report
+    }
+
+    /// Stops watching the specified file for changes.
+    ///
+    /// This function removes the file from the underlying file watcher and from the
+    /// debouncer's cache, so no further events will be reported for it.
+    ///
+    /// # Arguments
+    /// * `filename` - The path to the file to stop watching.
+    ///
+    /// # Returns
+    /// A `Result` containing either an empty `()` value on success, or an `Error` if the file
+    /// was never watched.
+    pub fn unwatch(&self, filename: impl AsRef<Path>) -> Result<(), Error> {
+        self.lock().remove_watch(filename.as_ref())
+    }
+
+    /// Stops watching every path at once.
+    ///
+    /// All paths are removed from the file watcher and the debouncer's cache, and their
+    /// per-path handlers are dropped, but the watcher and its backend stay alive, so new paths
+    /// can be watched right away. This suits services that rebuild their watch set when
+    /// their configuration changes.
+    pub fn clear(&self) {
+        self.lock().clear();
+    }
+
+    /// Suspends delivery of events to the handler.
+    ///
+    /// The paths stay registered with the file watcher while paused, so no watches need to be
+    /// rebuilt afterwards. This is useful while the application performs its own bulk writes
+    /// to watched files, such as during code generation.
+    ///
+    /// # Arguments
+    /// * `mode` - Whether events arriving while paused are dropped or queued until
+    ///   [`Watcher::resume`] is called.
+    pub fn pause(&self, mode: PauseMode) {
+        dispatch::lock(&self.dispatcher).pause(mode);
+
+        tracing::debug!("Paused event delivery ({:?})", mode);
+    }
+
+    /// Resumes delivery of events to the handler after [`Watcher::pause`].
+    ///
+    /// If the watcher was paused with [`PauseMode::Queue`], the queued events are delivered to
+    /// the handler before this function returns.
+    pub fn resume(&self) {
+        dispatch::lock(&self.dispatcher).resume();
+        let expired = dispatch::drain(&self.dispatcher).take_expired();
+        self.remove_expired(expired);
+
+        tracing::debug!("Resumed event delivery");
+    }
+
+    /// Hands events the watcher synthesized to its handlers, as if the back-end had
+    /// reported them.
+    pub(crate) fn dispatch_synthesized(&self, events: Vec<(DebouncedEvent, Details)>) {
+        if events.is_empty() {
+            return;
+        }
+        dispatch::dispatch(&self.dispatcher, Ok(events));
+        let expired = dispatch::drain(&self.dispatcher).take_expired();
+        self.remove_expired(expired);
+    }
+
+    /// Removes the watches of one-shot handlers that fired outside of the debouncer thread.
+    fn remove_expired(&self, expired: Vec<PathBuf>) {
+        for filename in expired {
+            if let Err(error) = self.lock().remove_watch(&filename) {
+                tracing::debug!(
+                    "Failed to remove one-shot watch {}: {}",
+                    filename.display(),
+                    error
+                );
+            }
+        }
+    }
+
+    /// Subscribes to every event of the watcher.
+    ///
+    /// Each subscription receives its own copy of every event delivered from now on, in
+    /// addition to the watcher's handler and to per-path handlers, so that independent tasks
+    /// such as a live-reload server, a cache invalidator and a logger don't have to fan out
+    /// events themselves. Events are converted as configured on the builder, for example with
+    /// [`WatcherBuilder::metadata`], and numbered in the order they are handed to the
+    /// subscriptions. Errors are not sent to subscriptions. A subscription is forgotten once
+    /// its stream is dropped.
+    ///
+    /// # Returns
+    /// An [`EventStream`](crate::EventStream) yielding the events, which ends once the
+    /// watcher is dropped.
+    pub fn subscribe(&self) -> EventStream {
+        dispatch::lock(&self.dispatcher).subscribe()
+    }
+
+    /// Returns an iterator that blocks until the next event arrives.
+    ///
+    /// This suits simple command-line tools that have no use for callbacks or async code:
+    ///
+    /// ```no_run
+    /// use watchit::{DebounceEventResult, Watcher};
+    ///
+    /// let watcher = Watcher::new(|_: DebounceEventResult| {}).unwrap();
+    /// watcher.watch("src").unwrap();
+    /// for event in watcher.events() {
+    ///     println!("{event}");
+    /// }
+    /// ```
+    ///
+    /// The iterator is a [subscription](Watcher::subscribe), so it yields every event from
+    /// the moment it is created, in addition to the watcher's handler. Errors are not
+    /// yielded. The iterator ends once the watcher is dropped. To wait for an event with a
+    /// timeout, use [`Events::next_event_timeout`].
+    pub fn events(&self) -> Events {
+        Events::new(self.subscribe())
+    }
+
+    /// Returns `true` if event delivery is currently paused.
+    pub fn is_paused(&self) -> bool {
+        dispatch::lock(&self.dispatcher).is_paused()
+    }
+
+    /// Sends the watcher's errors to the returned receiver instead of the handler.
+    ///
+    /// Errors include those reported by the back-end, such as [`Error::Overflow`], and
+    /// those that happen while the watcher reacts to events in the background, such as a
+    /// replaced file or a recreated directory that can no longer be watched. Calling this
+    /// again replaces the previous receiver. Once the receiver is dropped, errors go to the
+    /// handler again.
+    ///
+    /// # Returns
+    /// The receiving half of a channel carrying every subsequent error.
+    pub fn errors(&self) -> mpsc::Receiver<Error> {
+        dispatch::lock(&self.dispatcher).subscribe_errors()
+    }
+
+    /// Returns the paths that are currently being watched, in sorted order.
+    ///
+    /// # Returns
+    /// A `Vec` containing every registered path.
+    pub fn watched_paths(&self) -> Vec<PathBuf> {
+        self.lock().watches.keys().cloned().collect()
+    }
+
+    /// Returns details about every path that is currently being watched, in sorted order.
+    ///
+    /// # Returns
+    /// A `Vec` containing the [`WatchInfo`] of each registered path.
+    pub fn watches(&self) -> Vec<WatchInfo> {
+        self.lock().watches.values().cloned().collect()
+    }
+
+    /// Returns details about a single watched path.
+    ///
+    /// # Arguments
+    /// * `filename` - The path, exactly as it was registered.
+    ///
+    /// # Returns
+    /// The [`WatchInfo`] for the path, or `None` if it is not being watched.
+    pub fn watch_info(&self, filename: impl AsRef<Path>) -> Option<WatchInfo> {
+        self.lock().watches.get(filename.as_ref()).cloned()
+    }
+
+    /// Returns `true` if changes to the specified path are reported by any watch.
+    ///
+    /// This is the case if the path itself is registered, if it is a direct child of a
+    /// watched directory, if it lies below a recursively watched directory, or if it matches
+    /// a glob watch. Use [`Watcher::watch_info`] to check whether the path itself is
+    /// registered.
+    ///
+    /// # Arguments
+    /// * `filename` - The path to check.
+    pub fn is_watching(&self, filename: impl AsRef<Path>) -> bool {
+        let filename = filename.as_ref();
+        let state = self.lock();
+        state.watches.contains_key(filename) || state.covers(filename)
+    }
+
+    /// Stops the watcher after delivering the events that are still being debounced.
+    ///
+    /// Dropping a `Watcher` discards events whose debounce duration has not elapsed yet.
+    /// This function instead waits until every event that arrived before the call has been
+    /// debounced and handed to its handler, then stops the background threads. Events queued
+    /// while paused with [`PauseMode::Queue`] are delivered as well. When this function
+    /// returns, the final handler call has completed and no handler will be called again.
+    ///
+    /// Clones of the watcher, such as one captured by a handler, are shut down as well: they
+    /// no longer watch any path and deliver no events, though their background thread only
+    /// exits once the last clone is dropped. This blocks for slightly longer than the
+    /// longest debounce duration in use, or until a running handler returns, if that takes
+    /// longer.
+    pub fn shutdown(self) {
+        let settle = {
+            let state = self.lock();
+            let longest = state
+                .debouncers
+                .keys()
+                .copied()
+                .fold(state.debounce, Duration::max);
+            longest + longest / 4
+        };
+
+        tracing::debug!("Shutting down file watcher within {:?}", settle);
+
+        std::thread::sleep(settle);
+
+        // The debouncers are stopped without the state locked, which their threads need
+        // to finish the batch they may be processing.
+        let (debouncers, poller, verifier) = {
+            let mut state = self.lock();
+            (
+                std::mem::take(&mut state.debouncers),
+                state.poller.take(),
+                state.verifier.take(),
+            )
+        };
+        for debouncer in debouncers.into_values() {
+            debouncer.stop();
+        }
+        if let Some(poller) = poller {
+            poller.stop();
+        }
+        if let Some(verifier) = verifier {
+            verifier.debouncer.stop();
+        }
+
+        dispatch::lock(&self.dispatcher).resume();
+        drop(dispatch::drain(&self.dispatcher));
+
+        // The main debouncer lives as long as the state, which clones of the watcher share,
+        // so it is left without paths instead of being stopped.
+        self.lock().clear();
+        let mut dispatcher = dispatch::lock(&self.dispatcher);
+        dispatcher.shut_down();
+        while dispatcher.is_draining_elsewhere() {
+            // A handler is still running on the debouncer thread.
+            drop(dispatcher);
+            std::thread::sleep(Duration::from_millis(1));
+            dispatcher = dispatch::lock(&self.dispatcher);
+        }
+
+        tracing::debug!("File watcher shut down");
+    }
+
+    /// Stops the watcher like [`Watcher::shutdown`] without blocking, then waits for the
+    /// futures of the asynchronous handler to finish, see [`Watcher::new_async`].
+    ///
+    /// The futures already handed to the executor keep running, including those of the
+    /// events delivered while shutting down, and the returned future waits until they have
+    /// finished, so that no event is left half-processed when the process exits:
+    ///
+    /// ```ignore
+    /// if !watcher.shutdown_async(Duration::from_secs(5)).await {
+    ///     eprintln!("some events were still being handled");
+    /// }
+    /// ```
+    ///
+    /// The watcher is stopped on a thread of its own, so the returned future may be awaited
+    /// on any executor. For watchers without an asynchronous handler, the future is ready
+    /// once the watcher has stopped.
+    ///
+    /// # Arguments
+    /// * `grace` - How long to wait for the futures at most, once the watcher has stopped.
+    ///
+    /// # Returns
+    /// A future resolving to `true` if every future finished within `grace`.
+    pub fn shutdown_async(self, grace: Duration) -> Shutdown {
+        let in_flight = dispatch::lock(&self.dispatcher)
+            .in_flight()
+            .unwrap_or_default();
+        let stopped = in_flight.clone();
+        std::thread::spawn(move || {
+            self.shutdown();
+            stopped.stopped();
+        });
+        Shutdown::new(in_flight, grace)
+    }
+
+    fn lock(&self) -> MutexGuard<'_, State> {
+        state::lock(&self.state)
+    }
+}
+
+/// Applies the per-watch `options` to the watch of `filename` that was just registered.
+fn configure_watch(state: &mut State, filename: &Path, options: WatchOptions) -> Result<(), Error> {
+    if options.ignore_unchanged {
+        state.add_content_check(filename)?;
+    }
+    if let Some(max_size) = options.diff {
+        state.add_diff(filename, max_size)?;
+    }
+    if options.track_size {
+        state.add_size_tracking(filename)?;
+    }
+    if let Some(algorithm) = options.checksum {
+        state.add_checksum(filename, algorithm)?;
+    }
+    if options.change_summary {
+        state.add_change_summary(filename)?;
+    }
+    if options.key_changes {
+        state.add_key_tracking(filename)?;
+    }
+    if let Some(validator) = options.validator {
+        state.add_validator(filename, validator)?;
+    }
+    if let Some((dir, keep)) = options.snapshots {
+        state.add_snapshots(filename, dir, keep)?;
+    }
+    if options.summarize {
+        state.add_summary(filename)?;
+    }
+    if let Some(filters) = options.filters {
+        state.add_filter_set(filename, filters)?;
+    }
+    if let Some(kinds) = options.kinds {
+        state.add_kind_mask(filename, kinds)?;
+    }
+    if options.strategy != DebounceStrategy::Trailing {
+        let interval = options.debounce.unwrap_or(state.debounce);
+        state.add_strategy(filename, options.strategy, interval)?;
+    }
+    if let Some(settle) = options.settle {
+        state.add_settle(filename, settle)?;
+    }
+    if options.close_write {
+        state.add_close_write(filename)?;
+    }
+
+    Ok(())
+}
+
+/// The options of a watch that is registered again, kept in case the new options fail to
+/// apply.
+struct PreviousWatch {
+    info: WatchInfo,
+    validator: Option<Validator>,
+    snapshots: Option<SnapshotDir>,
+    filters: Option<FilterSet>,
+    kinds: Option<EventKindMask>,
+    close_write: bool,
+    leading: Option<LeadingWatch>,
+    limit: Option<RateLimit>,
+    settle: Option<Duration>,
+}
+
+impl PreviousWatch {
+    /// Copies the options of the watch on `filename`, if it is watched.
+    fn save(state: &State, filename: &Path) -> Option<Self> {
+        let info = state.watches.get(filename)?.clone();
+        let dispatcher = dispatch::lock(&state.dispatcher);
+        Some(Self {
+            info,
+            validator: state.validators.get(filename).cloned(),
+            snapshots: state.snapshots.get(filename).cloned(),
+            filters: state.filters.get(filename).cloned(),
+            kinds: state.kinds.get(filename).copied(),
+            close_write: state.close_write.contains(filename),
+            leading: state.leading.get(filename).cloned(),
+            limit: dispatcher.root_limit(filename),
+            settle: dispatcher.root_settle(filename),
+        })
+    }
+
+    /// Puts the options back on the watch on `filename`.
+    fn restore(self, state: &mut State, filename: &Path) {
+        fn put<T>(map: &mut BTreeMap<PathBuf, T>, filename: &Path, value: Option<T>) {
+            match value {
+                Some(value) => map.insert(filename.to_path_buf(), value),
+                None => map.remove(filename),
+            };
+        }
+
+        state.watches.insert(filename.to_path_buf(), self.info);
+        put(&mut state.validators, filename, self.validator);
+        put(&mut state.snapshots, filename, self.snapshots);
+        put(&mut state.filters, filename, self.filters);
+        put(&mut state.kinds, filename, self.kinds);
+        put(&mut state.leading, filename, self.leading);
+        if self.close_write {
+            state.close_write.insert(filename.to_path_buf());
+        } else {
+            state.close_write.remove(filename);
+        }
+
+        let mut dispatcher = dispatch::lock(&state.dispatcher);
+        match self.limit {
+            Some(limit) => dispatcher.limit_root(filename, limit),
+            None => dispatcher.unlimit_root(filename),
+        }
+        match self.settle {
+            Some(settle) => dispatcher.settle_root(filename, settle),
+            None => dispatcher.unsettle_root(filename),
+        }
+    }
+}
+
+#[cfg(test)]
+/// This module contains tests for the functionality of the `Watcher` struct.
+///
+/// The `it_works` test verifies that the `Watcher` correctly detects changes to a file.
+/// It creates a file, sets up a `Watcher` to monitor the file, and then checks that the
+/// `Watcher` correctly reports the file change event.
+///
+/// The `unwatch_unknown_path_fails` test verifies that removing a path that was never
+/// watched is reported as an error.
+///
+/// The `watch_recursive_sees_nested_changes` test verifies that a recursive watch reports
+/// changes to files in sub-directories created after the watch was set up.
+///
+/// The `builder_filter_drops_events` test verifies that events rejected by a builder filter
+/// never reach the handler.
+///
+/// The `watch_all_reports_failures` test verifies that a bulk registration reports missing
+/// paths individually without affecting the paths that exist.
+///
+/// The `watched_paths_tracks_registrations` test verifies that the introspection API reflects
+/// paths being watched and unwatched.
+///
+/// The `pause_queues_until_resume` test verifies that events arriving while paused in queue
+/// mode are held back and delivered on resume.
+///
+/// The `watch_with_routes_to_path_handler` test verifies that events for a path registered
+/// with its own handler bypass the watcher's handler.
+///
+/// The `watch_guard_unwatches_on_drop` test verifies that dropping a scoped watch's guard
+/// removes the watch.
+///
+/// The `watch_pending_upgrades_on_create` test verifies that a pending watch reports the
+/// creation of its file, ignores its siblings and becomes a direct watch.
+///
+/// The `follow_symlinks_reports_target_changes` test verifies that edits to the target of a
+/// followed symbolic link are reported under the link's path.
+///
+/// The `atomic_save_keeps_watching` test verifies that replacing a watched file by renaming
+/// a new file over it is reported as a modification and that the new file stays watched.
+///
+/// The `watch_glob_matches_new_files` test verifies that a glob watch reports files created
+/// after it was set up when they match, and ignores those that do not.
+///
+/// The `watch_once_removes_watch_after_first_change` test verifies that a one-shot handler
+/// is called for the first change only and that its path is unwatched afterwards.
+///
+/// The `max_depth_limits_recursion` test verifies that a depth-limited watch reports changes
+/// within the limit, including in directories created later, and ignores deeper changes.
+///
+/// The `debounce_override_delays_events` test verifies that a watch with a longer debounce
+/// duration is reported later than one using the watcher's duration.
+///
+/// The `watch_dir_ext_filters_by_extension` test verifies that only files with one of the
+/// requested extensions are reported, including in directories created later.
+///
+/// The `shutdown_flushes_pending_events` test verifies that events still being debounced
+/// when the watcher is shut down are delivered before `shutdown` returns.
+///
+/// The `clear_removes_every_watch` test verifies that clearing the watcher unregisters paths
+/// of every kind and that the watcher keeps working for paths watched afterwards.
+///
+/// The `cross_directory_move_is_one_rename` test verifies that the removal and creation of
+/// the same file in two watched directories are merged into a single rename event.
+///
+/// The `watch_tagged_labels_events` test verifies that events carry the tags of every watch
+/// that reported them.
+///
+/// The `is_watching_checks_coverage` test verifies that paths are reported as watched when
+/// they are registered or covered by another watch, and not otherwise.
+///
+/// The `watch_limit_falls_back_to_polling` test verifies that running out of OS file
+/// watches is reported as a dedicated error, or falls back to polling the path if the
+/// watcher was configured to.
+///
+/// The `watch_with_mode_overrides_default` test verifies that the mode passed to
+/// `watch_with_mode` takes precedence over the watcher's default mode.
+///
+/// The `rewatch_recreated_directory` test verifies that a watched directory that is removed
+/// and created again keeps reporting changes.
+///
+/// The `event_handler_receives_crate_events` test verifies that a watcher created with an
+/// event handler delivers the crate's own event type.
+///
+/// The `rename_event_carries_both_paths` test verifies that renames are delivered as a single
+/// event with the old and the new path.
+///
+/// The `metadata_snapshot_is_attached` test verifies that events carry the metadata of their
+/// path when snapshots are enabled.
+///
+/// The `event_latency_covers_debounce` test verifies that events record when their change was
+/// detected and when they were delivered.
+///
+/// The `kind_mask_drops_other_kinds` test verifies that events outside the watcher's kind mask
+/// never reach the handler.
+///
+/// The `ignore_unchanged_drops_identical_rewrites` test verifies that rewriting a file with the
+/// same content is not reported when content checks are enabled.
+///
+/// The `diff_reports_changed_lines` test verifies that events for a diffed file carry the lines
+/// that changed since its previous version.
+///
+/// The `event_carries_watch_root` test verifies that events name the most specific watch that
+/// reported them.
+///
+/// The `raw_event_count_reflects_bursts` test verifies that events report how many raw events
+/// were debounced into them.
+///
+/// The `raw_handler_sees_events_immediately` test verifies that raw events arrive well before
+/// the debounce duration has passed.
+///
+/// The `permission_change_is_attributes_changed` test verifies that changing only the
+/// permissions of a file is reported as an attribute change.
+///
+/// The `background_errors_reach_error_receiver` test verifies that a watch that can no longer be
+/// re-established is reported through the error receiver.
+///
+/// The `initial_scan_reports_existing_files` test verifies that registering a directory with
+/// `WatchOptions::initial_scan` reports the files already inside it as `EventKind::Existing`
+/// before the call returns.
+///
+/// The `contents_snapshot_small_files` test verifies that events of small files carry the
+/// content written, and that larger files carry none.
+///
+/// The `sequence_numbers_increase_across_batches` test verifies that delivered events are
+/// numbered consecutively from zero, across batches.
+///
+/// The `track_size_reports_growth_and_truncation` test verifies that appending to a watched
+/// file reports `SizeDelta::Grew` and truncating it `SizeDelta::Shrank`.
+///
+/// The `summarize_reports_one_event_per_batch` test verifies that changes below a summarizing
+/// watch arrive as a single event listing every changed path.
+///
+/// The `file_id_follows_renames` test verifies that a file keeps its reported ID when it is
+/// renamed, and that its removal reports the same ID.
+///
+/// The `channel_receives_events` test verifies that the receiver returned by
+/// `Watcher::channel` yields the watcher's events one by one.
+///
+/// The `crossbeam_channel_receives_events` test verifies that a bounded sender of
+/// `crossbeam-channel` receives the watcher's events. It only runs with the
+/// `crossbeam-channel` feature.
+///
+/// The `clones_share_watches_across_threads` test verifies that the watcher is `Send` and
+/// `Sync`, and that a watch registered through a clone on another thread is seen by the
+/// original.
+///
+/// The `events_iterator_blocks_for_changes` test verifies that the iterator returned by
+/// `Watcher::events` blocks until a change arrives and ends once the watcher is dropped.
+///
+/// The `workers_keep_slow_handlers_apart` test verifies that with a worker pool, a slow
+/// per-path handler does not delay the delivery of later events to the watcher's handler.
+///
+/// The `wait_for_change` test verifies that waiting for a change times out without one, and
+/// returns the first change of the path otherwise.
+///
+/// The `handler_panics_are_isolated` test verifies that a panicking handler is reported
+/// through the error receiver and keeps receiving later changes.
+///
+/// The `filter_set_applies_globs` test verifies that a filter set delivers included paths
+/// below a recursive watch and drops excluded and unmatched ones.
+///
+/// The `gitignore_drops_ignored_paths` test verifies that the rules of a `.gitignore` file,
+/// including negated rules and ignored directories, apply below a recursive watch.
+///
+/// The `watch_filter_set_applies_predicates` test verifies that the predicate of a watch's
+/// filter set decides which of its events are delivered, without affecting other watches.
+///
+/// The `filter_event_sees_kind_and_metadata` test verifies that the event predicate sees the
+/// kind and metadata of each event and decides which events are delivered.
+///
+/// The `filter_set_limits_file_size` test verifies that files outside the size limits of a
+/// filter set are dropped, while directories are delivered regardless of size.
+///
+/// The `rate_limit_coalesces_excess_events` test verifies that the events of a path beyond the
+/// rate limit are coalesced into one event, delivered when the window ends.
+///
+/// The `debounce_strategies_deliver_edges` test verifies that leading watches report the first
+/// change of a burst right away and drop the rest, while throttled watches also report the
+/// newest change once the interval ends.
+///
+/// The `settle_waits_for_complete_files` test verifies that the events of a file being written
+/// in pieces are held back until the file stops changing, and arrive as one creation.
+///
+/// The `middleware_drops_and_enriches_events` test verifies that middlewares run in order,
+/// dropping events and changing the events they pass on.
+///
+/// The `watchitignore_rereads_rules` test verifies that a `.watchitignore` file at the root of
+/// a watch drops the paths it ignores, and that edits to it apply to later events.
+///
+/// The `watch_kinds_mask_each_watch` test verifies that each watch only delivers the kinds of
+/// events its options ask for.
+///
+/// The `poll_backend_reports_changes` test verifies that a watcher polling its paths reports
+/// changes like a watcher using native notifications.
+///
+/// The `backend_selection_checks_platform` test verifies that the available native back-ends
+/// can be forced, and that building a watcher with an unavailable one fails.
+///
+/// The `custom_backend_drives_watcher` test verifies that a custom back-end is told which paths
+/// to watch, and that the changes it reports reach the handler.
+///
+/// The `verify_sweep_catches_missed_changes` test verifies that the verification sweep reports a
+/// change the back-end missed, but not one the back-end reported already.
+///
+/// The `network_filesystems_are_polled` test verifies that a path is polled exactly when it lives
+/// on a network file system, and never when detection is turned off.
+///
+/// The `poll_hashing_detects_preserved_mtime` test verifies that polling with hashing reports a
+/// rewrite that keeps the file's size and modification time.
+///
+/// The `overflow_reports_error` test verifies that the back-end's notice of dropped events
+/// reaches the handler as an [`Error::Overflow`].
+///
+/// The `close_write_waits_for_writer` test verifies that a close-write watch reports a file being
+/// written only once the writer closes it.
+///
+/// The `fanotify_reports_writes` test verifies that the fanotify back-end reports the files
+/// written below a watched path. It is ignored by default, as it marks the whole mount of the
+/// temporary directory, and only runs its checks with the privileges fanotify needs.
+///
+/// The `ssh_backend_reports_remote_changes` test verifies that the SSH back-end reports the
+/// changes of a listed directory, using a stand-in for `ssh` that runs the listing locally.
+///
+/// The `watch_contents_reads_changed_file` test verifies that the contents of a watched file
+/// are handed over after it changed, including when it is replaced by renaming.
+///
+/// The `tail_delivers_appended_lines` test verifies that following a file delivers only the
+/// lines appended after it started, once each line is complete.
+///
+/// The `tail_follows_rotation` test verifies that following a file survives truncation and
+/// rename-based rotation, draining the rotated file before reading the new one.
+///
+/// The `config_watcher_keeps_last_good_value` test verifies that configuration files are
+/// parsed again after each change, and that a broken edit keeps the last value that parsed.
+///
+/// The `checksum_reports_old_and_new_digests` test verifies that modifications of a watch
+/// tracking checksums carry the digests of the file before and after the change, including
+/// for files created below a watched directory.
+///
+/// The `change_summary_tells_appends_from_rewrites` test verifies that modifications of a
+/// watch summarizing changes carry the sizes before and after, and whether the file was only
+/// appended to.
+///
+/// The `key_changes_report_changed_pointers` test verifies that modifications of a TOML file
+/// carry the JSON pointers of the keys that changed, and that a broken version is skipped.
+///
+/// The `validate_reports_outcome` test verifies that modifications of a validated watch carry
+/// whether the validator accepted the file, with the message of its error otherwise.
+///
+/// The `snapshots_keep_previous_versions` test verifies that modifications of a watch keeping
+/// snapshots carry a copy of the version they replaced, and that only the newest versions
+/// are retained.
+///
+/// The `env_watcher_reports_changed_variables` test verifies that changes of a `.env` file
+/// report the variables that were added, removed and changed, and are applied to the
+/// environment of the process.
+///
+/// The `contents_report_encoding_flips` test verifies that events of a watcher reading
+/// contents carry the encoding of the file, and the previous one when it flips from text to
+/// binary.
+///
+/// The `tree_watcher_indexes_changes` test verifies that the index of a tree watcher holds the
+/// files of the tree when it is created, and reflects created, modified and removed files by
+/// the time their events are handled.
+///
+/// The `tree_watcher_reports_offline_changes` test verifies that a tree watcher started from
+/// a saved index reports the files created, modified, renamed and removed since the index
+/// was saved.
+///
+/// The `save_state_restores_watches` test verifies that a restarted watcher restores the
+/// saved watches with their options and tags, and reports the changes made to its tree
+/// while it was stopped.
+///
+/// The `handler_can_unwatch_its_path` test verifies that handlers run without the dispatcher
+/// locked, so that a handler unwatching its own path does not deadlock the debouncer thread.
+///
+/// The `shutdown_stops_clones` test verifies that shutting a watcher down returns while a
+/// clone of it is still alive, and that the clone no longer watches any path.
+///
+/// The `fsevents_flags_reach_stream` test verifies that the FSEvents back-end reports changes
+/// below the watched path, and that the flags it was given reach the event stream.
+///
+/// The `windows_buffer_overflow_reports_error` test verifies that changes overflowing the
+/// buffer of the `ReadDirectoryChangesW` back-end reach the handler as an
+/// [`Error::Overflow`].
+///
+/// The `restore_state_rejects_invalid_durations` test verifies that a saved state whose
+/// durations do not fit a `Duration` is reported as an error, and that saving leaves no
+/// temporary file behind.
+///
+/// The `failing_option_leaves_watches_unchanged` test verifies that a watch whose options
+/// cannot be applied is not kept, and that the paths watched before are still watched.
+///
+/// /// The `watch_all_registers_like_watch` test verifies that a path watched in bulk gets the same
+/// /// [`WatchInfo`] as one watched on its own, and that the verification sweep scans it as well.
+///
+/// /// The `panicking_filter_reports_an_error` test verifies that a filter panicking on the debouncer
+/// /// thread is delivered to the handler as an error.
+///
+/// /// The `middleware_may_use_the_watcher` test verifies that a middleware can call the watcher
+/// /// it belongs to, and that handlers of the back-end's events receive the events as the
+/// /// middleware passed them on.
+///
+/// /// The `max_depth_reregistration_unwatches_deeper_levels` test verifies that registering a
+/// /// depth-limited watch again with a smaller limit stops reporting the levels below it.
+///
+/// /// The `limited_watches_reject_their_own_debouncing` test verifies that depth-limited and
+/// /// link-following watches with a strategy or debounce duration of their own are rejected
+/// /// rather than registered without it.
+///
+/// /// The `failed_rewatch_keeps_previous_options` test verifies that registering a watched path
+/// /// again with options that fail to apply leaves the watch with its previous options.
+///
+/// /// The `depth_levels_are_verified` test verifies that the verification sweep scans every
+/// /// directory of a depth-limited watch, like it scans a plain watch.
+mod tests {
+    use std::{
+        fs::File,
+        io::Write as _,
+        sync::atomic::{AtomicUsize, Ordering},
+        thread::sleep,
+    };
+
+    use super::*;
+    use crate::{DebounceEventResult, EventResult, EventTags, FilterSet};
+
+    #[test]
+    fn it_works() {
+        static mut FILE_CHANGED: bool = false;
+        let mut file = File::create(Path::new("test.testfile")).unwrap();
+        let watcher = Watcher::new(move |event| {
+            tracing::debug!("Event: {:#?}", event);
+            unsafe { FILE_CHANGED = true };
+        })
+        .unwrap();
+        watcher.watch("test.testfile").unwrap();
+        assert!(!unsafe { FILE_CHANGED });
+        file.write_all(b"test").unwrap();
+        file.flush().unwrap();
+        drop(file);
+        sleep(Duration::from_secs(4));
+        assert!(unsafe { FILE_CHANGED });
+        std::fs::remove_file(Path::new("test.testfile")).unwrap();
+    }
+
+    #[test]
+    fn unwatch_unknown_path_fails() {
+        let watcher = Watcher::new(|_| {}).unwrap();
+        assert!(watcher.unwatch("never.watched").is_err());
+    }
+
+    #[test]
+    fn watch_recursive_sees_nested_changes() {
+        static COUNT: AtomicUsize = AtomicUsize::new(0);
+        let root = std::env::temp_dir().join("watchit_recursive_test");
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(&root).unwrap();
+        let watcher =
+            Watcher::with_debounce(Duration::from_millis(200), |result: DebounceEventResult| {
+                if let Ok(events) = result {
+                    COUNT.fetch_add(events.len(), Ordering::SeqCst);
+                }
+            })
+            .unwrap();
+        watcher.watch_recursive(&root).unwrap();
+        std::fs::create_dir_all(root.join("a/b")).unwrap();
+        sleep(Duration::from_millis(500));
+        std::fs::write(root.join("a/b/nested.txt"), b"test").unwrap();
+        sleep(Duration::from_secs(1));
+        assert!(COUNT.load(Ordering::SeqCst) > 0);
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn builder_filter_drops_events() {
+        static KEPT: AtomicUsize = AtomicUsize::new(0);
+        let root = std::env::temp_dir().join("watchit_filter_test");
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(&root).unwrap();
+        let watcher = Watcher::builder()
+            .debounce(Duration::from_millis(200))
+            .filter(|event| event.paths.iter().all(|path| path.ends_with("kept.txt")))
+            .build(|result: DebounceEventResult| {
+                if let Ok(events) = result {
+                    for event in events {
+                        assert!(event.paths[0].ends_with("kept.txt"));
+                        KEPT.fetch_add(1, Ordering::SeqCst);
+                    }
+                }
+            })
+            .unwrap();
+        watcher.watch(&root).unwrap();
+        std::fs::write(root.join("dropped.txt"), b"test").unwrap();
+        std::fs::write(root.join("kept.txt"), b"test").unwrap();
+        sleep(Duration::from_secs(1));
+        assert!(KEPT.load(Ordering::SeqCst) > 0);
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn watch_all_reports_failures() {
+        let root = std::env::temp_dir().join("watchit_watch_all_test");
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(&root).unwrap();
+        std::fs::write(root.join("one.txt"), b"test").unwrap();
+        std::fs::write(root.join("two.txt"), b"test").unwrap();
+        let watcher = Watcher::new(|_| {}).unwrap();
+        let report = watcher.watch_all([
+            root.join("one.txt"),
+            root.join("missing.txt"),
+            root.join("two.txt"),
+        ]);
+        assert!(!report.is_ok());
+        assert_eq!(report.watched, [root.join("one.txt"), root.join("two.txt")]);
+        assert_eq!(report.failed.len(), 1);
+        assert_eq!(report.failed[0].0, root.join("missing.txt"));
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn watched_paths_tracks_registrations() {
+        let root = std::env::temp_dir().join("watchit_watched_paths_test");
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(&root).unwrap();
+        let watcher = Watcher::new(|_| {}).unwrap();
+        watcher.watch_recursive(&root).unwrap();
+        assert_eq!(watcher.watched_paths(), vec![root.clone()]);
+        assert!(watcher.watch_info(&root).unwrap().recursive);
+        watcher.unwatch(&root).unwrap();
+        assert!(watcher.watched_paths().is_empty());
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn pause_queues_until_resume() {
+        static DELIVERED: AtomicUsize = AtomicUsize::new(0);
+        let root = std::env::temp_dir().join("watchit_pause_test");
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(&root).unwrap();
+        let watcher =
+            Watcher::with_debounce(Duration::from_millis(200), |result: DebounceEventResult| {
+                if let Ok(events) = result {
+                    DELIVERED.fetch_add(events.len(), Ordering::SeqCst);
+                }
+            })
+            .unwrap();
+        watcher.watch(&root).unwrap();
+        watcher.pause(PauseMode::Queue);
+        assert!(watcher.is_paused());
+        std::fs::write(root.join("paused.txt"), b"test").unwrap();
+        sleep(Duration::from_secs(1));
+        assert_eq!(DELIVERED.load(Ordering::SeqCst), 0);
+        watcher.resume();
+        assert!(DELIVERED.load(Ordering::SeqCst) > 0);
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn watch_with_routes_to_path_handler() {
+        static DEFAULT: AtomicUsize = AtomicUsize::new(0);
+        static ROUTED: AtomicUsize = AtomicUsize::new(0);
+        static MISROUTED: AtomicUsize = AtomicUsize::new(0);
+        let root = std::env::temp_dir().join("watchit_watch_with_test");
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(root.join("routed")).unwrap();
+        std::fs::create_dir_all(root.join("default")).unwrap();
+        let watcher =
+            Watcher::with_debounce(Duration::from_millis(200), |result: DebounceEventResult| {
+                if let Ok(events) = result {
+                    for event in events {
+                        if event.paths[0].starts_with(
+                            std::env::temp_dir().join("watchit_watch_with_test/routed"),
+                        ) {
+                            MISROUTED.fetch_add(1, Ordering::SeqCst);
+                        }
+                        DEFAULT.fetch_add(1, Ordering::SeqCst);
+                    }
+                }
+            })
+            .unwrap();
+        watcher.watch(root.join("default")).unwrap();
+        watcher
+            .watch_with(root.join("routed"), |result: DebounceEventResult| {
+                if let Ok(events) = result {
+                    ROUTED.fetch_add(events.len(), Ordering::SeqCst);
+                }
+            })
+            .unwrap();
+        std::fs::write(root.join("routed/file.txt"), b"test").unwrap();
+        std::fs::write(root.join("default/file.txt"), b"test").unwrap();
+        sleep(Duration::from_secs(1));
+        assert!(ROUTED.load(Ordering::SeqCst) > 0);
+        assert!(DEFAULT.load(Ordering::SeqCst) > 0);
+        assert_eq!(MISROUTED.load(Ordering::SeqCst), 0);
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn watch_guard_unwatches_on_drop() {
+        let root = std::env::temp_dir().join("watchit_guard_test");
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(&root).unwrap();
+        let watcher = Watcher::new(|_| {}).unwrap();
+        let guard = watcher.watch_scoped(&root).unwrap();
+        assert_eq!(guard.path(), root);
+        assert_eq!(watcher.watched_paths(), vec![root.clone()]);
+        drop(guard);
+        assert!(watcher.watched_paths().is_empty());
+        assert!(watcher.unwatch(&root).is_err());
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn watch_pending_upgrades_on_create() {
+        static TARGET: AtomicUsize = AtomicUsize::new(0);
+        static OTHER: AtomicUsize = AtomicUsize::new(0);
+        let root = std::env::temp_dir().join("watchit_pending_test");
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(&root).unwrap();
+        let target = root.join("later.log");
+        let watcher =
+            Watcher::with_debounce(Duration::from_millis(200), |result: DebounceEventResult| {
+                if let Ok(events) = result {
+                    for event in events {
+                        if event.paths[0].ends_with("later.log") {
+                            TARGET.fetch_add(1, Ordering::SeqCst);
+                        } else {
+                            OTHER.fetch_add(1, Ordering::SeqCst);
+                        }
+                    }
+                }
+            })
+            .unwrap();
+        watcher.watch_pending(&target).unwrap();
+        assert!(watcher.watch_info(&target).unwrap().pending);
+        std::fs::write(root.join("sibling.txt"), b"test").unwrap();
+        std::fs::write(&target, b"test").unwrap();
+        sleep(Duration::from_secs(1));
+        assert!(TARGET.load(Ordering::SeqCst) > 0);
+        assert_eq!(OTHER.load(Ordering::SeqCst), 0);
+        assert!(!watcher.watch_info(&target).unwrap().pending);
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn follow_symlinks_reports_target_changes() {
+        static LINKED: AtomicUsize = AtomicUsize::new(0);
+        let root = std::env::temp_dir().join("watchit_symlink_test");
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(root.join("real")).unwrap();
+        std::fs::create_dir_all(root.join("links")).unwrap();
+        std::fs::write(root.join("real/config.toml"), b"a = 1").unwrap();
+        std::os::unix::fs::symlink(
+            root.join("real/config.toml"),
+            root.join("links/config.toml"),
+        )
+        .unwrap();
+        let link = root.join("links/config.toml");
+        let watcher =
+            Watcher::with_debounce(Duration::from_millis(200), |result: DebounceEventResult| {
+                if let Ok(events) = result {
+                    for event in events {
+                        if event
+                            .paths
+                            .iter()
+                            .any(|path| path.ends_with("links/config.toml"))
+                        {
+                            LINKED.fetch_add(1, Ordering::SeqCst);
+                        }
+                    }
+                }
+            })
+            .unwrap();
+        watcher
+            .watch_with_options(&link, WatchOptions::new().follow_symlinks(true))
+            .unwrap();
+        assert_eq!(
+            watcher.watch_info(&link).unwrap().symlink_target,
+            Some(std::fs::canonicalize(root.join("real/config.toml")).unwrap())
+        );
+        std::fs::write(root.join("real/config.toml"), b"a = 2").unwrap();
+        sleep(Duration::from_secs(1));
+        assert!(LINKED.load(Ordering::SeqCst) > 0);
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn atomic_save_keeps_watching() {
+        static MODIFIED: AtomicUsize = AtomicUsize::new(0);
+        static REMOVED: AtomicUsize = AtomicUsize::new(0);
+        let root = std::env::temp_dir().join("watchit_atomic_save_test");
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(&root).unwrap();
+        let file = root.join("saved.txt");
+        std::fs::write(&file, b"one").unwrap();
+        let watcher =
+            Watcher::with_debounce(Duration::from_millis(200), |result: DebounceEventResult| {
+                if let Ok(events) = result {
+                    for event in events {
+                        match event.kind {
+                            notify::EventKind::Modify(_) => MODIFIED.fetch_add(1, Ordering::SeqCst),
+                            notify::EventKind::Remove(_) => REMOVED.fetch_add(1, Ordering::SeqCst),
+                            _ => 0,
+                        };
+                    }
+                }
+            })
+            .unwrap();
+        watcher.watch(&file).unwrap();
+        std::fs::write(root.join(".saved.txt.tmp"), b"two").unwrap();
+        std::fs::rename(root.join(".saved.txt.tmp"), &file).unwrap();
+        sleep(Duration::from_secs(1));
+        let after_save = MODIFIED.load(Ordering::SeqCst);
+        assert!(after_save > 0);
+        std::fs::write(&file, b"three").unwrap();
+        sleep(Duration::from_secs(1));
+        assert!(MODIFIED.load(Ordering::SeqCst) > after_save);
+        assert_eq!(REMOVED.load(Ordering::SeqCst), 0);
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn watch_glob_matches_new_files() {
+        static MATCHED: AtomicUsize = AtomicUsize::new(0);
+        static UNMATCHED: AtomicUsize = AtomicUsize::new(0);
+        let root = std::env::temp_dir().join("watchit_watch_glob_test");
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(root.join("src")).unwrap();
+        let watcher =
+            Watcher::with_debounce(Duration::from_millis(200), |result: DebounceEventResult| {
+                if let Ok(events) = result {
+                    for event in events {
+                        if event
+                            .paths
+                            .iter()
+                            .all(|path| path.ends_with("nested/new.rs"))
+                        {
+                            MATCHED.fetch_add(1, Ordering::SeqCst);
+                        } else {
+                            UNMATCHED.fetch_add(1, Ordering::SeqCst);
+                        }
+                    }
+                }
+            })
+            .unwrap();
+        let pattern = format!("{}/src/**/*.rs", root.display());
+        watcher.watch_glob(&pattern).unwrap();
+        assert!(watcher.watch_info(&pattern).unwrap().glob);
+        std::fs::create_dir_all(root.join("src/nested")).unwrap();
+        sleep(Duration::from_millis(500));
+        std::fs::write(root.join("src/nested/new.rs"), b"fn main() {}").unwrap();
+        std::fs::write(root.join("src/nested/notes.txt"), b"ignored").unwrap();
+        sleep(Duration::from_secs(1));
+        assert!(MATCHED.load(Ordering::SeqCst) > 0);
+        assert_eq!(UNMATCHED.load(Ordering::SeqCst), 0);
+        watcher.unwatch(&pattern).unwrap();
+        assert!(watcher.watched_paths().is_empty());
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn watch_once_removes_watch_after_first_change() {
+        static ONCE: AtomicUsize = AtomicUsize::new(0);
+        let root = std::env::temp_dir().join("watchit_watch_once_test");
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(&root).unwrap();
+        let file = root.join("ready.flag");
+        let watcher =
+            Watcher::with_debounce(Duration::from_millis(200), |_: DebounceEventResult| {})
+                .unwrap();
+        watcher
+            .watch_once(&file, |result: DebounceEventResult| {
+                if result.is_ok() {
+                    ONCE.fetch_add(1, Ordering::SeqCst);
+                }
+            })
+            .unwrap();
+        std::fs::write(&file, b"ready").unwrap();
+        sleep(Duration::from_secs(1));
+        assert_eq!(ONCE.load(Ordering::SeqCst), 1);
+        assert!(watcher.watched_paths().is_empty());
+        std::fs::write(&file, b"again").unwrap();
+        sleep(Duration::from_secs(1));
+        assert_eq!(ONCE.load(Ordering::SeqCst), 1);
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn max_depth_limits_recursion() {
+        static SHALLOW: AtomicUsize = AtomicUsize::new(0);
+        static CREATED: AtomicUsize = AtomicUsize::new(0);
+        static DEEP: AtomicUsize = AtomicUsize::new(0);
+        let root = std::env::temp_dir().join("watchit_max_depth_test");
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(root.join("a/b/c")).unwrap();
+        let watcher =
+            Watcher::with_debounce(Duration::from_millis(200), |result: DebounceEventResult| {
+                if let Ok(events) = result {
+                    for event in events {
+                        if event.paths.iter().any(|path| path.ends_with("deep.txt")) {
+                            DEEP.fetch_add(1, Ordering::SeqCst);
+                        } else if event
+                            .paths
+                            .iter()
+                            .any(|path| path.ends_with("new/shallow.txt"))
+                        {
+                            CREATED.fetch_add(1, Ordering::SeqCst);
+                        } else if event
+                            .paths
+                            .iter()
+                            .any(|path| path.ends_with("a/shallow.txt"))
+                        {
+                            SHALLOW.fetch_add(1, Ordering::SeqCst);
+                        }
+                    }
+                }
+            })
+            .unwrap();
+        watcher
+            .watch_with_options(&root, WatchOptions::new().max_depth(2))
+            .unwrap();
+        assert_eq!(watcher.watch_info(&root).unwrap().max_depth, Some(2));
+        std::fs::write(root.join("a/shallow.txt"), b"1").unwrap();
+        std::fs::write(root.join("a/b/deep.txt"), b"1").unwrap();
+        std::fs::create_dir(root.join("new")).unwrap();
+        sleep(Duration::from_secs(1));
+        std::fs::write(root.join("new/shallow.txt"), b"1").unwrap();
+        sleep(Duration::from_secs(1));
+        assert!(SHALLOW.load(Ordering::SeqCst) > 0);
+        assert!(CREATED.load(Ordering::SeqCst) > 0);
+        assert_eq!(DEEP.load(Ordering::SeqCst), 0);
+        watcher.unwatch(&root).unwrap();
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn debounce_override_delays_events() {
+        static FAST: AtomicUsize = AtomicUsize::new(0);
+        static SLOW: AtomicUsize = AtomicUsize::new(0);
+        let root = std::env::temp_dir().join("watchit_debounce_override_test");
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(&root).unwrap();
+        std::fs::write(root.join("fast.txt"), b"").unwrap();
+        std::fs::write(root.join("slow.txt"), b"").unwrap();
+        let watcher =
+            Watcher::with_debounce(Duration::from_millis(200), |result: DebounceEventResult| {
+                if let Ok(events) = result {
+                    for event in events {
+                        if event.paths.iter().any(|path| path.ends_with("slow.txt")) {
+                            SLOW.fetch_add(1, Ordering::SeqCst);
+                        } else {
+                            FAST.fetch_add(1, Ordering::SeqCst);
+                        }
+                    }
+                }
+            })
+            .unwrap();
+        watcher.watch(root.join("fast.txt")).unwrap();
+        watcher
+            .watch_with_options(
+                root.join("slow.txt"),
+                WatchOptions::new().debounce(Duration::from_secs(3)),
+            )
+            .unwrap();
+        assert_eq!(
+            watcher.watch_info(root.join("slow.txt")).unwrap().debounce,
+            Some(Duration::from_secs(3))
+        );
+        std::fs::write(root.join("fast.txt"), b"1").unwrap();
+        std::fs::write(root.join("slow.txt"), b"1").unwrap();
+        sleep(Duration::from_secs(1));
+        assert!(FAST.load(Ordering::SeqCst) > 0);
+        assert_eq!(SLOW.load(Ordering::SeqCst), 0);
+        sleep(Duration::from_secs(4));
+        assert!(SLOW.load(Ordering::SeqCst) > 0);
+        watcher.unwatch(root.join("slow.txt")).unwrap();
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn watch_dir_ext_filters_by_extension() {
+        static MATCHED: AtomicUsize = AtomicUsize::new(0);
+        static UNMATCHED: AtomicUsize = AtomicUsize::new(0);
+        let root = std::env::temp_dir().join("watchit_watch_dir_ext_test");
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(&root).unwrap();
+        let watcher =
+            Watcher::with_debounce(Duration::from_millis(200), |result: DebounceEventResult| {
+                if let Ok(events) = result {
+                    for event in events {
+                        let matched = event.paths.iter().all(|path| {
+                            path.extension()
+                                .is_some_and(|extension| extension == "rs" || extension == "TOML")
+                        });
+                        if matched {
+                            MATCHED.fetch_add(1, Ordering::SeqCst);
+                        } else {
+                            UNMATCHED.fetch_add(1, Ordering::SeqCst);
+                        }
+                    }
+                }
+            })
+            .unwrap();
+        watcher.watch_dir_ext(&root, ["rs", ".toml"]).unwrap();
+        std::fs::create_dir(root.join("nested")).unwrap();
+        sleep(Duration::from_millis(500));
+        std::fs::write(root.join("nested/lib.rs"), b"").unwrap();
+        std::fs::write(root.join("Cargo.TOML"), b"").unwrap();
+        std::fs::write(root.join("notes.md"), b"").unwrap();
+        sleep(Duration::from_secs(1));
+        assert!(MATCHED.load(Ordering::SeqCst) > 0);
+        assert_eq!(UNMATCHED.load(Ordering::SeqCst), 0);
+        watcher.unwatch(&root).unwrap();
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn shutdown_flushes_pending_events() {
+        static FLUSHED: AtomicUsize = AtomicUsize::new(0);
+        let root = std::env::temp_dir().join("watchit_shutdown_test");
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(&root).unwrap();
+        let file = root.join("pending.txt");
+        std::fs::write(&file, b"").unwrap();
+        let watcher =
+            Watcher::with_debounce(Duration::from_millis(500), |result: DebounceEventResult| {
+                if result.is_ok() {
+                    FLUSHED.fetch_add(1, Ordering::SeqCst);
+                }
+            })
+            .unwrap();
+        watcher.watch(&file).unwrap();
+        std::fs::write(&file, b"changed").unwrap();
+        sleep(Duration::from_millis(50));
+        watcher.shutdown();
+        let flushed = FLUSHED.load(Ordering::SeqCst);
+        assert!(flushed > 0);
+        std::fs::write(&file, b"after").unwrap();
+        sleep(Duration::from_secs(1));
+        assert_eq!(FLUSHED.load(Ordering::SeqCst), flushed);
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn clear_removes_every_watch() {
+        static CHANGED: AtomicUsize = AtomicUsize::new(0);
+        let root = std::env::temp_dir().join("watchit_clear_test");
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(root.join("dir")).unwrap();
+        std::fs::write(root.join("file.txt"), b"").unwrap();
+        let watcher =
+            Watcher::with_debounce(Duration::from_millis(200), |result: DebounceEventResult| {
+                if result.is_ok() {
+                    CHANGED.fetch_add(1, Ordering::SeqCst);
+                }
+            })
+            .unwrap();
+        watcher.watch(root.join("file.txt")).unwrap();
+        watcher.watch_recursive(root.join("dir")).unwrap();
+        watcher.watch_pending(root.join("missing.txt")).unwrap();
+        watcher.watch_dir_ext(&root, ["rs"]).unwrap();
+        watcher.clear();
+        assert!(watcher.watched_paths().is_empty());
+        std::fs::write(root.join("file.txt"), b"1").unwrap();
+        std::fs::write(root.join("dir/nested.txt"), b"1").unwrap();
+        std::fs::write(root.join("missing.txt"), b"1").unwrap();
+        sleep(Duration::from_secs(1));
+        assert_eq!(CHANGED.load(Ordering::SeqCst), 0);
+        watcher.watch(root.join("file.txt")).unwrap();
+        std::fs::write(root.join("file.txt"), b"2").unwrap();
+        sleep(Duration::from_secs(1));
+        assert!(CHANGED.load(Ordering::SeqCst) > 0);
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn cross_directory_move_is_one_rename() {
+        use notify::event::{CreateKind, EventKind, ModifyKind, RemoveKind, RenameMode};
+        use notify_debouncer_full::FileIdCache as _;
+
+        use crate::DebouncedEvent;
+
+        let root = std::env::temp_dir().join("watchit_cross_directory_move_test");
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(root.join("a")).unwrap();
+        std::fs::create_dir_all(root.join("b")).unwrap();
+        let from = root.join("a/moved.txt");
+        let to = root.join("b/moved.txt");
+        std::fs::write(&from, b"").unwrap();
+        let watcher =
+            Watcher::with_debounce(Duration::from_millis(200), |_: DebounceEventResult| {})
+                .unwrap();
+        watcher.watch(root.join("a")).unwrap();
+        watcher.watch(root.join("b")).unwrap();
+
+        // Back-ends watching each directory separately report the move as a removal and a
+        // creation, and the debouncer drops the removed path from its cache.
+        let mut state = watcher.lock();
+        state.debouncer.cache().remove_path(&from);
+        std::fs::rename(&from, &to).unwrap();
+        let now = std::time::Instant::now();
+        let mut events = vec![
+            DebouncedEvent::new(
+                notify::Event::new(EventKind::Remove(RemoveKind::File)).add_path(from.clone()),
+                now,
+            ),
+            DebouncedEvent::new(
+                notify::Event::new(EventKind::Create(CreateKind::File)).add_path(to.clone()),
+                now,
+            ),
+        ];
+        state.correlate_renames(&mut events);
+        drop(state);
+
+        assert_eq!(events.len(), 1);
+        assert_eq!(
+            events[0].kind,
+            EventKind::Modify(ModifyKind::Name(RenameMode::Both))
+        );
+        assert_eq!(events[0].paths, vec![from, to]);
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn watch_tagged_labels_events() {
+        static TAGGED: AtomicUsize = AtomicUsize::new(0);
+        let root = std::env::temp_dir().join("watchit_watch_tagged_test");
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(root.join("templates")).unwrap();
+        let watcher =
+            Watcher::with_debounce(Duration::from_millis(200), |result: DebounceEventResult| {
+                if let Ok(events) = result {
+                    for event in events {
+                        let tags: Vec<&str> = event.tags().collect();
+                        if tags == ["html", "templates"] {
+                            TAGGED.fetch_add(1, Ordering::SeqCst);
+                        }
+                    }
+                }
+            })
+            .unwrap();
+        watcher
+            .watch_tagged(root.join("templates"), "templates")
+            .unwrap();
+        watcher
+            .watch_tagged(root.join("templates"), "html")
+            .unwrap();
+        assert!(watcher.watch_tagged(&root, "a,b").is_err());
+        std::fs::write(root.join("templates/index.html"), b"").unwrap();
+        sleep(Duration::from_secs(1));
+        assert!(TAGGED.load(Ordering::SeqCst) > 0);
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn is_watching_checks_coverage() {
+        let root = std::env::temp_dir().join("watchit_is_watching_test");
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(root.join("tree/deep")).unwrap();
+        std::fs::create_dir_all(root.join("flat/sub")).unwrap();
+        let watcher =
+            Watcher::with_debounce(Duration::from_millis(200), |_: DebounceEventResult| {})
+                .unwrap();
+        watcher.watch_recursive(root.join("tree")).unwrap();
+        watcher.watch(root.join("flat")).unwrap();
+        watcher
+            .watch_pending(root.join("flat/sub/later.txt"))
+            .unwrap();
+        assert!(watcher.is_watching(root.join("tree")));
+        assert!(watcher.is_watching(root.join("tree/deep/file.rs")));
+        assert!(watcher.is_watching(root.join("flat/file.rs")));
+        assert!(watcher.is_watching(root.join("flat/sub/later.txt")));
+        assert!(!watcher.is_watching(root.join("flat/sub/other.txt")));
+        assert!(!watcher.is_watching(&root));
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn watch_limit_falls_back_to_polling() {
+        static POLLED: AtomicUsize = AtomicUsize::new(0);
+        let root = std::env::temp_dir().join("watchit_watch_limit_test");
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(&root).unwrap();
+        let file = root.join("polled.txt");
+        std::fs::write(&file, b"").unwrap();
+        let limit = || notify::Error::new(notify::ErrorKind::MaxFilesWatch);
+
+        let watcher =
+            Watcher::with_debounce(Duration::from_millis(200), |_: DebounceEventResult| {})
+                .unwrap();
+        watcher.watch(&root).unwrap();
+        let error = watcher
+            .lock()
+            .poll_fallback(&file, RecursiveMode::NonRecursive, limit())
+            .unwrap_err();
+        assert!(matches!(error, Error::WatchLimit { registered: 1, .. }));
+
+        let watcher = Watcher::builder()
+            .debounce(Duration::from_millis(200))
+            .poll_fallback(Duration::from_millis(100))
+            .build(|result: DebounceEventResult| {
+                if result.is_ok() {
+                    POLLED.fetch_add(1, Ordering::SeqCst);
+                }
+            })
+            .unwrap();
+        let mut state = watcher.lock();
+        state
+            .poll_fallback(&file, RecursiveMode::NonRecursive, limit())
+            .unwrap();
+        let info =
+            state::Served::Polled.mark(WatchInfo::new(file.clone(), RecursiveMode::NonRecursive));
+        state.watches.insert(file.clone(), info);
+        drop(state);
+        assert!(watcher.watch_info(&file).unwrap().polled);
+        sleep(Duration::from_millis(200));
+        // Polling compares modification times in whole seconds.
+        std::fs::File::options()
+            .write(true)
+            .open(&file)
+            .unwrap()
+            .set_modified(std::time::SystemTime::now() + Duration::from_secs(5))
+            .unwrap();
+        sleep(Duration::from_secs(1));
+        assert!(POLLED.load(Ordering::SeqCst) > 0);
+        watcher.unwatch(&file).unwrap();
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn watch_with_mode_overrides_default() {
+        let root = std::env::temp_dir().join("watchit_watch_with_mode_test");
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(root.join("tree/deep")).unwrap();
+        std::fs::create_dir_all(root.join("flat/deep")).unwrap();
+        let watcher = Watcher::builder()
+            .recursive(true)
+            .build(|_: DebounceEventResult| {})
+            .unwrap();
+        watcher
+            .watch_with_mode(root.join("tree"), WatchMode::Recursive)
+            .unwrap();
+        watcher
+            .watch_with_mode(root.join("flat"), WatchMode::NonRecursive)
+            .unwrap();
+        assert!(watcher.watch_info(root.join("tree")).unwrap().recursive);
+        assert!(!watcher.watch_info(root.join("flat")).unwrap().recursive);
+        assert!(watcher.is_watching(root.join("tree/deep/file.txt")));
+        assert!(!watcher.is_watching(root.join("flat/deep/file.txt")));
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn rewatch_recreated_directory() {
+        static REBUILT: AtomicUsize = AtomicUsize::new(0);
+        let root = std::env::temp_dir().join("watchit_rewatch_recreated_test");
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(root.join("target/debug")).unwrap();
+        let watcher = Watcher::builder()
+            .debounce(Duration::from_millis(200))
+            .rewatch_recreated(true)
+            .build(|result: DebounceEventResult| {
+                if let Ok(events) = result {
+                    for event in events {
+                        if event.paths.iter().any(|path| path.ends_with("rebuilt.txt")) {
+                            REBUILT.fetch_add(1, Ordering::SeqCst);
+                        }
+                    }
+                }
+            })
+            .unwrap();
+        watcher.watch_recursive(root.join("target")).unwrap();
+        std::fs::remove_dir_all(root.join("target")).unwrap();
+        sleep(Duration::from_secs(1));
+        assert!(watcher.watch_info(root.join("target")).unwrap().pending);
+        std::fs::create_dir_all(root.join("target/debug")).unwrap();
+        sleep(Duration::from_secs(1));
+        let info = watcher.watch_info(root.join("target")).unwrap();
+        assert!(!info.pending && info.recursive);
+        std::fs::write(root.join("target/debug/rebuilt.txt"), b"").unwrap();
+        sleep(Duration::from_secs(1));
+        assert!(REBUILT.load(Ordering::SeqCst) > 0);
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn event_handler_receives_crate_events() {
+        use crate::{EventKind, EventResult};
+
+        let root = std::env::temp_dir().join("watchit_event_handler_test");
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(&root).unwrap();
+        let (sender, receiver) = std::sync::mpsc::channel::<EventResult>();
+        let watcher = Watcher::builder()
+            .debounce(Duration::from_millis(200))
+            .build_with_event_handler(sender)
+            .unwrap();
+        watcher.watch(&root).unwrap();
+        std::fs::write(root.join("created.txt"), b"").unwrap();
+        let events = receiver
+            .recv_timeout(Duration::from_secs(5))
+            .unwrap()
+            .unwrap();
+        assert!(events.iter().any(
+            |event| event.path == root.join("created.txt") && event.kind == EventKind::Created
+        ));
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn rename_event_carries_both_paths() {
+        use crate::{EventKind, EventResult};
+
+        let root = std::env::temp_dir().join("watchit_rename_paths_test");
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(&root).unwrap();
+        std::fs::write(root.join("old.txt"), b"").unwrap();
+        let (sender, receiver) = std::sync::mpsc::channel::<EventResult>();
+        let watcher = Watcher::builder()
+            .debounce(Duration::from_millis(200))
+            .build_with_event_handler(sender)
+            .unwrap();
+        watcher.watch(&root).unwrap();
+        std::fs::rename(root.join("old.txt"), root.join("new.txt")).unwrap();
+        let events = receiver
+            .recv_timeout(Duration::from_secs(5))
+            .unwrap()
+            .unwrap();
+        let rename = events
+            .iter()
+            .find(|event| event.kind == EventKind::Renamed)
+            .unwrap();
+        assert_eq!(
+            rename.rename(),
+            Some((
+                root.join("old.txt").as_path(),
+                root.join("new.txt").as_path()
+            ))
+        );
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn metadata_snapshot_is_attached() {
+        use crate::{EventResult, FileType};
+
+        let root = std::env::temp_dir().join("watchit_metadata_snapshot_test");
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(&root).unwrap();
+        let (sender, receiver) = std::sync::mpsc::channel::<EventResult>();
+        let watcher = Watcher::builder()
+            .debounce(Duration::from_millis(200))
+            .metadata(true)
+            .build_with_event_handler(sender)
+            .unwrap();
+        watcher.watch(&root).unwrap();
+        std::fs::write(root.join("sized.txt"), b"12345").unwrap();
+        let events = receiver
+            .recv_timeout(Duration::from_secs(5))
+            .unwrap()
+            .unwrap();
+        let metadata = events
+            .iter()
+            .find(|event| event.path == root.join("sized.txt"))
+            .and_then(|event| event.metadata.clone())
+            .unwrap();
+        assert_eq!(metadata.file_type, FileType::File);
+        assert_eq!(metadata.size, 5);
+        assert!(metadata.modified.is_some());
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn event_latency_covers_debounce() {
+        use crate::EventResult;
+
+        let root = std::env::temp_dir().join("watchit_event_latency_test");
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(&root).unwrap();
+        let (sender, receiver) = std::sync::mpsc::channel::<EventResult>();
+        let watcher = Watcher::builder()
+            .debounce(Duration::from_millis(200))
+            .build_with_event_handler(sender)
+            .unwrap();
+        watcher.watch(&root).unwrap();
+        std::fs::write(root.join("timed.txt"), b"").unwrap();
+        let events = receiver
+            .recv_timeout(Duration::from_secs(5))
+            .unwrap()
+            .unwrap();
+        let event = &events[0];
+        assert!(event.delivered >= event.time);
+        assert!(event.latency() >= Duration::from_millis(150));
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn kind_mask_drops_other_kinds() {
+        use crate::{EventKind, EventKindMask, EventResult};
+
+        let root = std::env::temp_dir().join("watchit_kind_mask_test");
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(&root).unwrap();
+        std::fs::write(root.join("doomed.txt"), b"").unwrap();
+        let (sender, receiver) = std::sync::mpsc::channel::<EventResult>();
+        let watcher = Watcher::builder()
+            .debounce(Duration::from_millis(200))
+            .kinds(EventKindMask::CREATED)
+            .build_with_event_handler(sender)
+            .unwrap();
+        watcher.watch(&root).unwrap();
+        std::fs::write(root.join("born.txt"), b"").unwrap();
+        std::fs::remove_file(root.join("doomed.txt")).unwrap();
+        sleep(Duration::from_secs(1));
+        let events: Vec<_> = receiver.try_iter().flat_map(Result::unwrap).collect();
+        assert!(events
+            .iter()
+            .any(|event| event.path == root.join("born.txt")));
+        assert!(events.iter().all(|event| event.kind == EventKind::Created));
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn ignore_unchanged_drops_identical_rewrites() {
+        static CHANGES: AtomicUsize = AtomicUsize::new(0);
+        let root = std::env::temp_dir().join("watchit_ignore_unchanged_test");
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(&root).unwrap();
+        let filename = root.join("same.txt");
+        std::fs::write(&filename, b"content").unwrap();
+        let watcher =
+            Watcher::with_debounce(Duration::from_millis(200), |result: DebounceEventResult| {
+                if let Ok(events) = result {
+                    CHANGES.fetch_add(events.len(), Ordering::SeqCst);
+                }
+            })
+            .unwrap();
+        watcher
+            .watch_with_options(&filename, WatchOptions::new().ignore_unchanged(true))
+            .unwrap();
+        assert!(watcher.watch_info(&filename).unwrap().ignore_unchanged);
+        std::fs::write(&filename, b"content").unwrap();
+        sleep(Duration::from_secs(1));
+        assert_eq!(CHANGES.load(Ordering::SeqCst), 0);
+        std::fs::write(&filename, b"changed").unwrap();
+        sleep(Duration::from_secs(1));
+        assert!(CHANGES.load(Ordering::SeqCst) > 0);
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn diff_reports_changed_lines() {
+        use crate::{DiffLine, EventResult};
+
+        let root = std::env::temp_dir().join("watchit_diff_test");
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(&root).unwrap();
+        let filename = root.join("config.ini");
+        std::fs::write(&filename, "port = 80\nhost = a\n").unwrap();
+        let (sender, receiver) = std::sync::mpsc::channel::<EventResult>();
+        let watcher = Watcher::builder()
+            .debounce(Duration::from_millis(200))
+            .build_with_event_handler(sender)
+            .unwrap();
+        watcher
+            .watch_with_options(&filename, WatchOptions::new().diff(1024))
+            .unwrap();
+        std::fs::write(&filename, "port = 8080\nhost = a\n").unwrap();
+        let events = receiver
+            .recv_timeout(Duration::from_secs(5))
+            .unwrap()
+            .unwrap();
+        let diff = events
+            .iter()
+            .find_map(|event| event.diff.clone().filter(|diff| !diff.is_empty()))
+            .unwrap();
+        let line = |number: usize, text: &str| DiffLine {
+            number,
+            text: text.to_string(),
+        };
+        assert_eq!(diff.removed, vec![line(1, "port = 80")]);
+        assert_eq!(diff.added, vec![line(1, "port = 8080")]);
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn event_carries_watch_root() {
+        use crate::EventResult;
+
+        let root = std::env::temp_dir().join("watchit_event_root_test");
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(root.join("src/nested")).unwrap();
+        let (sender, receiver) = std::sync::mpsc::channel::<EventResult>();
+        let watcher = Watcher::builder()
+            .debounce(Duration::from_millis(200))
+            .build_with_event_handler(sender)
+            .unwrap();
+        watcher.watch_recursive(&root).unwrap();
+        watcher
+            .watch_tagged(root.join("src/nested"), "nested")
+            .unwrap();
+        std::fs::write(root.join("top.txt"), b"").unwrap();
+        std::fs::write(root.join("src/nested/deep.txt"), b"").unwrap();
+        sleep(Duration::from_secs(1));
+        let events: Vec<_> = receiver.try_iter().flat_map(Result::unwrap).collect();
+        let root_of = |name: &str| {
+            events
+                .iter()
+                .find(|event| event.path.ends_with(name))
+                .and_then(|event| event.root.clone())
+        };
+        assert_eq!(root_of("top.txt"), Some(root.clone()));
+        assert_eq!(root_of("deep.txt"), Some(root.join("src/nested")));
+        assert!(events
+            .iter()
+            .any(|event| event.path.ends_with("deep.txt") && event.tags == ["nested"]));
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn raw_event_count_reflects_bursts() {
+        use crate::EventResult;
+
+        let root = std::env::temp_dir().join("watchit_raw_count_test");
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(&root).unwrap();
+        let filename = root.join("burst.txt");
+        std::fs::write(&filename, b"").unwrap();
+        let (sender, receiver) = std::sync::mpsc::channel::<EventResult>();
+        let watcher = Watcher::builder()
+            .debounce(Duration::from_millis(300))
+            .build_with_event_handler(sender)
+            .unwrap();
+        watcher.watch(&filename).unwrap();
+        for round in 0..10 {
+            std::fs::write(&filename, format!("round {round}")).unwrap();
+        }
+        let events = receiver
+            .recv_timeout(Duration::from_secs(5))
+            .unwrap()
+            .unwrap();
+        assert!(events.iter().all(|event| event.raw_events >= 10));
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn raw_handler_sees_events_immediately() {
+        let root = std::env::temp_dir().join("watchit_raw_handler_test");
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(&root).unwrap();
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let watcher = Watcher::raw(sender).unwrap();
+        watcher.watch(&root).unwrap();
+        std::fs::write(root.join("raw.txt"), b"").unwrap();
+        let event: notify::Event = receiver
+            .recv_timeout(Duration::from_millis(500))
+            .unwrap()
+            .unwrap();
+        assert!(event.paths.contains(&root.join("raw.txt")));
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn permission_change_is_attributes_changed() {
+        use crate::{EventKind, EventResult};
+
+        let root = std::env::temp_dir().join("watchit_attributes_changed_test");
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(&root).unwrap();
+        let filename = root.join("secret.txt");
+        std::fs::write(&filename, b"").unwrap();
+        let (sender, receiver) = std::sync::mpsc::channel::<EventResult>();
+        let watcher = Watcher::builder()
+            .debounce(Duration::from_millis(200))
+            .build_with_event_handler(sender)
+            .unwrap();
+        watcher.watch(&filename).unwrap();
+        let mut permissions = std::fs::metadata(&filename).unwrap().permissions();
+        permissions.set_readonly(true);
+        std::fs::set_permissions(&filename, permissions).unwrap();
+        let events = receiver
+            .recv_timeout(Duration::from_secs(5))
+            .unwrap()
+            .unwrap();
+        assert!(events
+            .iter()
+            .all(|event| event.kind == EventKind::AttributesChanged));
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn background_errors_reach_error_receiver() {
+        let root = std::env::temp_dir().join("watchit_error_receiver_test");
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(root.join("sub")).unwrap();
+        let filename = root.join("sub/file.txt");
+        std::fs::write(&filename, b"").unwrap();
+        let watcher =
+            Watcher::with_debounce(Duration::from_millis(200), |_: DebounceEventResult| {})
+                .unwrap();
+        let errors = watcher.errors();
+        watcher.watch(&filename).unwrap();
+        std::fs::remove_dir_all(root.join("sub")).unwrap();
+        let error = errors.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert!(matches!(error, Error::Notify(_)));
+        assert!(!watcher.is_watching(&filename));
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn initial_scan_reports_existing_files() {
+        use crate::{EventKind, EventResult};
+
+        let root = std::env::temp_dir().join("watchit_initial_scan_test");
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(root.join("nested")).unwrap();
+        std::fs::write(root.join("a.txt"), b"").unwrap();
+        std::fs::write(root.join("nested").join("b.txt"), b"").unwrap();
+        let (sender, receiver) = std::sync::mpsc::channel::<EventResult>();
+        let watcher = Watcher::builder()
+            .debounce(Duration::from_millis(200))
+            .build_with_event_handler(sender)
+            .unwrap();
+        watcher
+            .watch_with_options(
+                &root,
+                WatchOptions::new().recursive(true).initial_scan(true),
+            )
+            .unwrap();
+        let events: Vec<_> = receiver.try_iter().flat_map(Result::unwrap).collect();
+        let paths: Vec<_> = events.iter().map(|event| event.path.clone()).collect();
+        assert_eq!(
+            paths,
+            [
+                root.join("a.txt"),
+                root.join("nested"),
+                root.join("nested").join("b.txt"),
+            ]
+        );
+        assert!(events.iter().all(|event| event.kind == EventKind::Existing));
+        assert!(events
+            .iter()
+            .all(|event| event.root.as_ref() == Some(&root)));
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn contents_snapshot_small_files() {
+        use crate::EventResult;
+
+        let root = std::env::temp_dir().join("watchit_contents_test");
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(&root).unwrap();
+        let (sender, receiver) = std::sync::mpsc::channel::<EventResult>();
+        let watcher = Watcher::builder()
+            .debounce(Duration::from_millis(200))
+            .contents(16)
+            .build_with_event_handler(sender)
+            .unwrap();
+        watcher.watch(&root).unwrap();
+        std::fs::write(root.join("small.txt"), b"tiny").unwrap();
+        std::fs::write(root.join("large.txt"), [b'x'; 64]).unwrap();
+        sleep(Duration::from_secs(1));
+        let events: Vec<_> = receiver.try_iter().flat_map(Result::unwrap).collect();
+        let small = events
+            .iter()
+            .find(|event| event.path == root.join("small.txt"))
+            .unwrap();
+        assert_eq!(small.contents.as_deref(), Some(&b"tiny"[..]));
+        assert!(events
+            .iter()
+            .filter(|event| event.path == root.join("large.txt"))
+            .all(|event| event.contents.is_none()));
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn sequence_numbers_increase_across_batches() {
+        use crate::EventResult;
+
+        let root = std::env::temp_dir().join("watchit_sequence_test");
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(&root).unwrap();
+        std::fs::write(root.join("a.txt"), b"").unwrap();
+        let (sender, receiver) = std::sync::mpsc::channel::<EventResult>();
+        let watcher = Watcher::builder()
+            .debounce(Duration::from_millis(200))
+            .build_with_event_handler(sender)
+            .unwrap();
+        watcher
+            .watch_with_options(&root, WatchOptions::new().initial_scan(true))
+            .unwrap();
+        std::fs::write(root.join("b.txt"), b"").unwrap();
+        sleep(Duration::from_secs(1));
+        std::fs::write(root.join("c.txt"), b"").unwrap();
+        sleep(Duration::from_secs(1));
+        let sequence: Vec<u64> = receiver
+            .try_iter()
+            .flat_map(Result::unwrap)
+            .map(|event| event.sequence)
+            .collect();
+        assert!(sequence.len() >= 3);
+        assert!(sequence.iter().copied().eq(0..sequence.len() as u64));
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn track_size_reports_growth_and_truncation() {
+        use crate::{EventKind, EventResult, SizeDelta};
+        use std::io::Write;
+
+        let root = std::env::temp_dir().join("watchit_track_size_test");
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(&root).unwrap();
+        let filename = root.join("app.log");
+        std::fs::write(&filename, b"first\n").unwrap();
+        let (sender, receiver) = std::sync::mpsc::channel::<EventResult>();
+        let watcher = Watcher::builder()
+            .debounce(Duration::from_millis(200))
+            .build_with_event_handler(sender)
+            .unwrap();
+        watcher
+            .watch_with_options(&filename, WatchOptions::new().track_size(true))
+            .unwrap();
+        let deltas = || -> Vec<SizeDelta> {
+            receiver
+                .try_iter()
+                .flat_map(Result::unwrap)
+                .filter(|event| event.kind == EventKind::Modified)
+                .filter_map(|event| event.size_delta)
+                .collect()
+        };
+        std::fs::OpenOptions::new()
+            .append(true)
+            .open(&filename)
+            .unwrap()
+            .write_all(b"second\n")
+            .unwrap();
+        sleep(Duration::from_secs(1));
+        assert!(deltas().contains(&SizeDelta::Grew(7)));
+        std::fs::File::create(&filename).unwrap();
+        sleep(Duration::from_secs(1));
+        assert!(deltas().contains(&SizeDelta::Shrank(13)));
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn summarize_reports_one_event_per_batch() {
+        use crate::{EventKind, EventResult};
+
+        let root = std::env::temp_dir().join("watchit_summarize_test");
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(root.join("src")).unwrap();
+        let (sender, receiver) = std::sync::mpsc::channel::<EventResult>();
+        let watcher = Watcher::builder()
+            .debounce(Duration::from_millis(200))
+            .build_with_event_handler(sender)
+            .unwrap();
+        watcher
+            .watch_with_options(&root, WatchOptions::new().recursive(true).summarize(true))
+            .unwrap();
+        std::fs::write(root.join("src").join("main.rs"), b"fn main() {}").unwrap();
+        std::fs::write(root.join("src").join("lib.rs"), b"").unwrap();
+        sleep(Duration::from_secs(1));
+        let events: Vec<_> = receiver.try_iter().flat_map(Result::unwrap).collect();
+        assert!(!events.is_empty());
+        assert!(events
+            .iter()
+            .all(|event| event.path == root && event.kind == EventKind::Modified));
+        let changed: Vec<_> = events.iter().flat_map(|event| &event.changed).collect();
+        assert!(changed.contains(&&root.join("src").join("main.rs")));
+        assert!(changed.contains(&&root.join("src").join("lib.rs")));
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn file_id_follows_renames() {
+        use crate::{EventKind, EventResult};
+
+        let root = std::env::temp_dir().join("watchit_file_id_test");
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(&root).unwrap();
+        let (sender, receiver) = std::sync::mpsc::channel::<EventResult>();
+        let watcher = Watcher::builder()
+            .debounce(Duration::from_millis(200))
+            .build_with_event_handler(sender)
+            .unwrap();
+        watcher.watch(&root).unwrap();
+        let events =
+            || -> Vec<crate::Event> { receiver.try_iter().flat_map(Result::unwrap).collect() };
+        std::fs::write(root.join("draft.txt"), b"").unwrap();
+        sleep(Duration::from_secs(1));
+        let created = events()
+            .into_iter()
+            .find_map(|event| event.file_id)
+            .unwrap();
+        std::fs::rename(root.join("draft.txt"), root.join("final.txt")).unwrap();
+        sleep(Duration::from_secs(1));
+        let renamed = events()
+            .into_iter()
+            .find(|event| event.kind == EventKind::Renamed)
+            .unwrap();
+        assert_eq!(renamed.file_id, Some(created));
+        std::fs::remove_file(root.join("final.txt")).unwrap();
+        sleep(Duration::from_secs(1));
+        let removed = events()
+            .into_iter()
+            .find(|event| event.kind == EventKind::Removed)
+            .unwrap();
+        assert_eq!(removed.file_id, Some(created));
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn channel_receives_events() {
+        let root = std::env::temp_dir().join("watchit_channel_test");
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(&root).unwrap();
+        let (watcher, events) = Watcher::builder()
+            .debounce(Duration::from_millis(200))
+            .build_channel()
+            .unwrap();
+        watcher.watch(&root).unwrap();
+        std::fs::write(root.join("pulled.txt"), b"").unwrap();
+        let event = events.recv_timeout(Duration::from_secs(2)).unwrap();
+        assert_eq!(event.path, root.join("pulled.txt"));
+        drop(watcher);
+        assert!(events
+            .iter()
+            .all(|event| event.path == root.join("pulled.txt")));
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    #[cfg(feature = "crossbeam-channel")]
+    fn crossbeam_channel_receives_events() {
+        let root = std::env::temp_dir().join("watchit_crossbeam_test");
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(&root).unwrap();
+        let (sender, receiver) = crossbeam_channel::bounded::<crate::Event>(16);
+        let watcher = Watcher::builder()
+            .debounce(Duration::from_millis(200))
+            .build_with_event_handler(sender)
+            .unwrap();
+        watcher.watch(&root).unwrap();
+        std::fs::write(root.join("selected.txt"), b"").unwrap();
+        let event = crossbeam_channel::select! {
+            recv(receiver) -> event => event.unwrap(),
+            default(Duration::from_secs(2)) => panic!("no event received"),
+        };
+        assert_eq!(event.path, root.join("selected.txt"));
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn clones_share_watches_across_threads() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<Watcher>();
+
+        let root = std::env::temp_dir().join("watchit_shared_test");
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(&root).unwrap();
+        let watcher = Watcher::new(|_: DebounceEventResult| {}).unwrap();
+        let clone = watcher.clone();
+        let path = root.clone();
+        std::thread::spawn(move || clone.watch(path).unwrap())
+            .join()
+            .unwrap();
+        assert!(watcher.is_watching(&root));
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn events_iterator_blocks_for_changes() {
+        let root = std::env::temp_dir().join("watchit_events_iterator_test");
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(&root).unwrap();
+        let watcher =
+            Watcher::with_debounce(Duration::from_millis(200), |_: DebounceEventResult| {})
+                .unwrap();
+        watcher.watch(&root).unwrap();
+        let mut events = watcher.events();
+        let filename = root.join("iterated.txt");
+        let writer = {
+            let filename = filename.clone();
+            std::thread::spawn(move || {
+                sleep(Duration::from_millis(100));
+                std::fs::write(filename, b"").unwrap();
+            })
+        };
+        assert_eq!(events.next().unwrap().path, filename);
+        writer.join().unwrap();
+        drop(watcher);
+        assert!(events.all(|event| event.path == filename));
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn workers_keep_slow_handlers_apart() {
+        let root = std::env::temp_dir().join("watchit_workers_test");
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(root.join("slow")).unwrap();
+        std::fs::create_dir_all(root.join("fast")).unwrap();
+        let (sender, receiver) = std::sync::mpsc::channel::<crate::Event>();
+        let watcher = Watcher::builder()
+            .debounce(Duration::from_millis(200))
+            .workers(2)
+            .build_with_event_handler(sender)
+            .unwrap();
+        watcher
+            .watch_with(root.join("slow"), |_: DebounceEventResult| {
+                sleep(Duration::from_secs(3));
+            })
+            .unwrap();
+        watcher.watch(root.join("fast")).unwrap();
+        std::fs::write(root.join("slow").join("a.txt"), b"").unwrap();
+        sleep(Duration::from_millis(500));
+        std::fs::write(root.join("fast").join("b.txt"), b"").unwrap();
+        let event = receiver.recv_timeout(Duration::from_secs(1)).unwrap();
+        assert_eq!(event.path, root.join("fast").join("b.txt"));
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn wait_for_change_returns_first_change() {
+        let root = std::env::temp_dir().join("watchit_wait_for_change_test");
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(&root).unwrap();
+        let filename = root.join("config.toml");
+        std::fs::write(&filename, b"old").unwrap();
+        let builder = || Watcher::builder().debounce(Duration::from_millis(200));
+
+        let event = builder()
+            .wait_for_change(&filename, Some(Duration::from_millis(500)))
+            .unwrap();
+        assert!(event.is_none());
+
+        let writer = {
+            let filename = filename.clone();
+            std::thread::spawn(move || {
+                sleep(Duration::from_millis(100));
+                std::fs::write(filename, b"new").unwrap();
+            })
+        };
+        let event = builder()
+            .wait_for_change(&filename, Some(Duration::from_secs(2)))
+            .unwrap()
+            .unwrap();
+        writer.join().unwrap();
+        assert_eq!(event.path, filename);
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn handler_panics_are_isolated() {
+        let root = std::env::temp_dir().join("watchit_panic_test");
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(&root).unwrap();
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let calls = Arc::new(AtomicUsize::new(0));
+        let watcher = {
+            let calls = calls.clone();
+            Watcher::builder()
+                .debounce(Duration::from_millis(200))
+                .build_with_event_handler(move |result: EventResult| {
+                    if calls.fetch_add(1, Ordering::SeqCst) == 0 {
+                        panic!("first batch");
+                    }
+                    for event in result.unwrap() {
+                        let _ = sender.send(event.path);
+                    }
+                })
+                .unwrap()
+        };
+        let errors = watcher.errors();
+        watcher.watch(&root).unwrap();
+
+        std::fs::write(root.join("first.txt"), b"").unwrap();
+        let error = errors.recv_timeout(Duration::from_secs(2)).unwrap();
+        assert!(matches!(error, Error::HandlerPanic { message } if message == "first batch"));
+
+        std::fs::write(root.join("second.txt"), b"").unwrap();
+        let path = receiver.recv_timeout(Duration::from_secs(2)).unwrap();
+        assert_eq!(path, root.join("second.txt"));
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn filter_set_applies_globs() {
+        let root = std::env::temp_dir().join("watchit_filter_set_test");
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(root.join("target")).unwrap();
+        let filters = FilterSet::new()
+            .include("**/*.rs")
+            .unwrap()
+            .exclude("target/**")
+            .unwrap();
+        let (watcher, events) = Watcher::builder()
+            .debounce(Duration::from_millis(200))
+            .recursive(true)
+            .filter_set(filters)
+            .build_channel()
+            .unwrap();
+        watcher.watch(&root).unwrap();
+        std::fs::write(root.join("target/build.rs"), b"").unwrap();
+        std::fs::write(root.join("README.md"), b"").unwrap();
+        std::fs::write(root.join("main.rs"), b"").unwrap();
+        sleep(Duration::from_secs(1));
+        let paths: Vec<PathBuf> = events.try_iter().map(|event| event.path).collect();
+        assert!(!paths.is_empty());
+        assert!(paths.iter().all(|path| *path == root.join("main.rs")));
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn gitignore_drops_ignored_paths() {
+        let root = std::env::temp_dir().join("watchit_gitignore_test");
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(root.join("target/debug")).unwrap();
+        std::fs::write(root.join(".gitignore"), "target/\n*.log\n!keep.log\n").unwrap();
+        let (watcher, events) = Watcher::builder()
+            .debounce(Duration::from_millis(200))
+            .recursive(true)
+            .filter_set(FilterSet::new().gitignore(true))
+            .build_channel()
+            .unwrap();
+        watcher.watch(&root).unwrap();
+        std::fs::write(root.join("target/debug/app"), b"").unwrap();
+        std::fs::write(root.join("debug.log"), b"").unwrap();
+        std::fs::write(root.join("keep.log"), b"").unwrap();
+        std::fs::write(root.join("main.rs"), b"").unwrap();
+        sleep(Duration::from_secs(1));
+        let mut paths: Vec<PathBuf> = events.try_iter().map(|event| event.path).collect();
+        paths.sort();
+        paths.dedup();
+        assert_eq!(paths, [root.join("keep.log"), root.join("main.rs")]);
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn watch_filter_set_applies_predicates() {
+        let root = std::env::temp_dir().join("watchit_watch_filter_test");
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(root.join("logs")).unwrap();
+        std::fs::create_dir_all(root.join("other")).unwrap();
+        let (watcher, events) = Watcher::builder()
+            .debounce(Duration::from_millis(200))
+            .build_channel()
+            .unwrap();
+        let dated = FilterSet::new().matching(|path| {
+            path.file_stem()
+                .and_then(|stem| stem.to_str())
+                .is_some_and(|stem| !stem.is_empty() && stem.bytes().all(|b| b.is_ascii_digit()))
+        });
+        watcher
+            .watch_with_options(root.join("logs"), WatchOptions::new().filter_set(dated))
+            .unwrap();
+        watcher.watch(root.join("other")).unwrap();
+        assert!(watcher.watch_info(root.join("logs")).unwrap().filtered);
+        std::fs::write(root.join("logs/20240101.log"), b"").unwrap();
+        std::fs::write(root.join("logs/latest.log"), b"").unwrap();
+        std::fs::write(root.join("other/latest.log"), b"").unwrap();
+        sleep(Duration::from_secs(1));
+        let mut paths: Vec<PathBuf> = events.try_iter().map(|event| event.path).collect();
+        paths.sort();
+        paths.dedup();
+        assert_eq!(
+            paths,
+            [
+                root.join("logs/20240101.log"),
+                root.join("other/latest.log")
+            ]
+        );
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn filter_event_sees_kind_and_metadata() {
+        let root = std::env::temp_dir().join("watchit_filter_event_test");
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(&root).unwrap();
+        let (watcher, events) = Watcher::builder()
+            .debounce(Duration::from_millis(200))
+            .filter_event(|event| {
+                event.kind == crate::EventKind::Created
+                    && event
+                        .metadata
+                        .as_ref()
+                        .is_some_and(|metadata| metadata.file_type == crate::FileType::File)
+            })
+            .build_channel()
+            .unwrap();
+        watcher.watch(&root).unwrap();
+        std::fs::create_dir(root.join("dir")).unwrap();
+        std::fs::write(root.join("file.txt"), b"").unwrap();
+        sleep(Duration::from_secs(1));
+        let events: Vec<Event> = events.try_iter().collect();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].path, root.join("file.txt"));
+        assert_eq!(events[0].sequence, 0);
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn filter_set_limits_file_size() {
+        let root = std::env::temp_dir().join("watchit_size_filter_test");
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(&root).unwrap();
+        let (watcher, events) = Watcher::builder()
+            .debounce(Duration::from_millis(200))
+            .filter_set(FilterSet::new().min_size(1).max_size(1024))
+            .build_channel()
+            .unwrap();
+        watcher.watch(&root).unwrap();
+        std::fs::write(root.join("empty.txt"), b"").unwrap();
+        std::fs::write(root.join("small.txt"), b"small").unwrap();
+        std::fs::write(root.join("large.bin"), vec![0; 4096]).unwrap();
+        std::fs::create_dir(root.join("dir")).unwrap();
+        sleep(Duration::from_secs(1));
+        let mut paths: Vec<PathBuf> = events.try_iter().map(|event| event.path).collect();
+        paths.sort();
+        paths.dedup();
+        assert_eq!(paths, [root.join("dir"), root.join("small.txt")]);
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn rate_limit_coalesces_excess_events() {
+        let root = std::env::temp_dir().join("watchit_rate_limit_test");
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(&root).unwrap();
+        let file = root.join("app.log");
+        std::fs::write(&file, b"").unwrap();
+        let (watcher, events) = Watcher::builder()
+            .debounce(Duration::from_millis(100))
+            .rate_limit(1, Duration::from_secs(1))
+            .build_channel()
+            .unwrap();
+        watcher.watch(&root).unwrap();
+        for line in 0..6 {
+            std::fs::write(&file, format!("line {line}\n")).unwrap();
+            sleep(Duration::from_millis(300));
+        }
+        sleep(Duration::from_millis(2500));
+        let events: Vec<Event> = events
+            .try_iter()
+            .filter(|event| event.path == file)
+            .collect();
+        assert!(events.len() >= 2 && events.len() < 6, "{events:?}");
+        assert!(events.iter().any(|event| event.raw_events > 1));
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn debounce_strategies_deliver_edges() {
+        let root = std::env::temp_dir().join("watchit_strategy_test");
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(root.join("leading")).unwrap();
+        std::fs::create_dir_all(root.join("throttle")).unwrap();
+        let (watcher, events) = Watcher::builder()
+            .debounce(Duration::from_secs(5))
+            .build_channel()
+            .unwrap();
+        for (dir, strategy) in [
+            ("leading", DebounceStrategy::Leading),
+            ("throttle", DebounceStrategy::Throttle),
+        ] {
+            let options = WatchOptions::new()
+                .debounce(Duration::from_secs(1))
+                .strategy(strategy);
+            watcher.watch_with_options(root.join(dir), options).unwrap();
+            assert_eq!(
+                watcher.watch_info(root.join(dir)).unwrap().strategy,
+                strategy
+            );
+        }
+        let leading = root.join("leading").join("file.txt");
+        let throttled = root.join("throttle").join("file.txt");
+        for step in 0..3 {
+            std::fs::write(&leading, format!("{step}")).unwrap();
+            std::fs::write(&throttled, format!("{step}")).unwrap();
+            sleep(Duration::from_millis(150));
+            if step == 0 {
+                let paths: Vec<PathBuf> = events.try_iter().map(|event| event.path).collect();
+                assert!(paths.contains(&leading) && paths.contains(&throttled));
+            }
+        }
+        sleep(Duration::from_millis(1500));
+        let paths: Vec<PathBuf> = events.try_iter().map(|event| event.path).collect();
+        assert!(!paths.contains(&leading), "{paths:?}");
+        assert!(paths.contains(&throttled), "{paths:?}");
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn settle_waits_for_complete_files() {
+        use std::io::Write;
+
+        let root = std::env::temp_dir().join("watchit_settle_test");
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(&root).unwrap();
+        let (watcher, events) = Watcher::builder()
+            .debounce(Duration::from_millis(100))
+            .build_channel()
+            .unwrap();
+        let options = WatchOptions::new().settle(Duration::from_millis(600));
+        watcher.watch_with_options(&root, options).unwrap();
+        assert_eq!(
+            watcher.watch_info(&root).unwrap().settle,
+            Some(Duration::from_millis(600))
+        );
+        let upload = root.join("upload.bin");
+        let mut file = std::fs::File::create(&upload).unwrap();
+        for _ in 0..4 {
+            file.write_all(&[0; 1024]).unwrap();
+            file.flush().unwrap();
+            sleep(Duration::from_millis(250));
+        }
+        drop(file);
+        assert_eq!(events.try_iter().count(), 0);
+        sleep(Duration::from_millis(1200));
+        let events: Vec<Event> = events.try_iter().collect();
+        assert_eq!(events.len(), 1, "{events:?}");
+        assert_eq!(events[0].path, upload);
+        assert_eq!(events[0].kind, crate::EventKind::Created);
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn middleware_drops_and_enriches_events() {
+        let root = std::env::temp_dir().join("watchit_middleware_test");
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(&root).unwrap();
+        let (watcher, events) = Watcher::builder()
+            .debounce(Duration::from_millis(200))
+            .middleware(|event: Event| {
+                (event.path.extension() != Some("tmp".as_ref())).then_some(event)
+            })
+            .middleware(|mut event: Event| {
+                event.tags.push(format!("seen:{}", event.tags.len()));
+                Some(event)
+            })
+            .build_channel()
+            .unwrap();
+        watcher.watch(&root).unwrap();
+        std::fs::write(root.join("kept.txt"), b"kept").unwrap();
+        std::fs::write(root.join("dropped.tmp"), b"dropped").unwrap();
+        sleep(Duration::from_secs(1));
+        let events: Vec<Event> = events.try_iter().collect();
+        assert!(!events.is_empty());
+        assert!(events
+            .iter()
+            .all(|event| event.path == root.join("kept.txt")));
+        assert!(events.iter().all(|event| event.tags == ["seen:0"]));
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn watchitignore_rereads_rules() {
+        let root = std::env::temp_dir().join("watchit_watchitignore_test");
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(&root).unwrap();
+        let rules = root.join(".watchitignore");
+        std::fs::write(&rules, "*.log\n").unwrap();
+        let (watcher, events) = Watcher::builder()
+            .debounce(Duration::from_millis(200))
+            .filter_set(FilterSet::new().watchitignore(true))
+            .build_channel()
+            .unwrap();
+        watcher.watch(&root).unwrap();
+        let changed = |events: &mpsc::Receiver<Event>| {
+            let mut paths: Vec<PathBuf> = events
+                .try_iter()
+                .map(|event| event.path)
+                .filter(|path| *path != rules)
+                .collect();
+            paths.sort();
+            paths.dedup();
+            paths
+        };
+        std::fs::write(root.join("app.log"), b"").unwrap();
+        std::fs::write(root.join("notes.txt"), b"").unwrap();
+        sleep(Duration::from_secs(1));
+        assert_eq!(changed(&events), [root.join("notes.txt")]);
+        std::fs::write(&rules, "*.txt\n").unwrap();
+        sleep(Duration::from_millis(500));
+        std::fs::write(root.join("app.log"), b"more").unwrap();
+        std::fs::write(root.join("notes.txt"), b"more").unwrap();
+        sleep(Duration::from_secs(1));
+        assert_eq!(changed(&events), [root.join("app.log")]);
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn watch_kinds_mask_each_watch() {
+        use crate::{EventKind, EventKindMask};
+
+        let root = std::env::temp_dir().join("watchit_watch_kinds_test");
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(root.join("inbox")).unwrap();
+        std::fs::create_dir_all(root.join("config")).unwrap();
+        std::fs::write(root.join("config/app.toml"), b"a = 1").unwrap();
+        let (watcher, events) = Watcher::builder()
+            .debounce(Duration::from_millis(200))
+            .build_channel()
+            .unwrap();
+        let inbox = WatchOptions::new().kinds(EventKindMask::CREATED);
+        watcher
+            .watch_with_options(root.join("inbox"), inbox)
+            .unwrap();
+        let config = WatchOptions::new().kinds(EventKindMask::MODIFIED);
+        watcher
+            .watch_with_options(root.join("config"), config)
+            .unwrap();
+        assert_eq!(
+            watcher.watch_info(root.join("inbox")).unwrap().kinds,
+            EventKindMask::CREATED
+        );
+        std::fs::write(root.join("inbox/order.json"), b"{}").unwrap();
+        std::fs::write(root.join("config/new.toml"), b"").unwrap();
+        sleep(Duration::from_millis(500));
+        std::fs::write(root.join("inbox/order.json"), b"{\"id\": 1}").unwrap();
+        std::fs::write(root.join("config/app.toml"), b"a = 2").unwrap();
+        sleep(Duration::from_secs(1));
+        let events: Vec<Event> = events.try_iter().collect();
+        let kinds = |path: &str| {
+            let path = root.join(path);
+            events
+                .iter()
+                .filter(|event| event.path == path)
+                .map(|event| event.kind)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(kinds("inbox/order.json"), [EventKind::Created]);
+        assert_eq!(kinds("config/new.toml"), []);
+        assert!(!kinds("config/app.toml").is_empty());
+        assert!(kinds("config/app.toml")
+            .iter()
+            .all(|kind| *kind == EventKind::Modified));
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn poll_backend_reports_changes() {
+        let root = std::env::temp_dir().join("watchit_poll_backend_test");
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(&root).unwrap();
+        let (watcher, events) = Watcher::builder()
+            .debounce(Duration::from_millis(200))
+            .backend(crate::Backend::Poll {
+                interval: Duration::from_millis(100),
+            })
+            .build_channel()
+            .unwrap();
+        watcher.watch(&root).unwrap();
+        std::fs::write(root.join("share.txt"), b"remote change").unwrap();
+        sleep(Duration::from_secs(1));
+        let paths: Vec<PathBuf> = events.try_iter().map(|event| event.path).collect();
+        assert!(paths.contains(&root.join("share.txt")), "{paths:?}");
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn backend_selection_checks_platform() {
+        use crate::Backend;
+
+        for backend in [
+            Backend::Inotify,
+            Backend::Kqueue,
+            Backend::FsEvents {
+                latency: Duration::from_millis(100),
+                flags: crate::FsEventsFlags::default(),
+            },
+            Backend::Windows {
+                buffer_size: 64 * 1024,
+                network_drives: true,
+            },
+        ] {
+            let built = Watcher::builder()
+                .backend(backend)
+                .build(|_: DebounceEventResult| {});
+            assert_eq!(built.is_ok(), backend.is_available(), "{backend:?}");
+        }
+    }
+
+    #[test]
+    fn custom_backend_drives_watcher() {
+        use crate::{EventSink, WatchBackend};
+        use std::sync::{mpsc, Arc, Mutex};
+
+        struct Double {
+            sink: EventSink,
+            sinks: mpsc::Sender<EventSink>,
+            watched: Arc<Mutex<Vec<(PathBuf, bool)>>>,
+        }
+
+        impl WatchBackend for Double {
+            fn watch(&mut self, path: &Path, recursive: bool) -> Result<(), Error> {
+                self.watched
+                    .lock()
+                    .unwrap()
+                    .push((path.to_path_buf(), recursive));
+                self.sinks.send(self.sink.clone()).unwrap();
+                Ok(())
+            }
+
+            fn unwatch(&mut self, path: &Path) -> Result<(), Error> {
+                self.watched
+                    .lock()
+                    .unwrap()
+                    .retain(|(watched, _)| watched != path);
+                Ok(())
+            }
+        }
+
+        let dir = std::env::temp_dir().join("watchit_custom_backend_test");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let dir = dir.canonicalize().unwrap();
+
+        let watched = Arc::new(Mutex::new(Vec::new()));
+        let (sinks, sink) = mpsc::channel();
+        let recorded = Arc::clone(&watched);
+        let (tx, rx) = mpsc::channel();
+        let watcher = Watcher::builder()
+            .debounce(Duration::from_millis(200))
+            .custom_backend(move |sink| {
+                Ok(Double {
+                    sink,
+                    sinks: sinks.clone(),
+                    watched: Arc::clone(&recorded),
+                })
+            })
+            .build(move |result: DebounceEventResult| {
+                for event in result.unwrap_or_default() {
+                    tx.send(event).unwrap();
+                }
+            })
+            .unwrap();
+        watcher.watch(&dir).unwrap();
+        assert_eq!(*watched.lock().unwrap(), vec![(dir.clone(), false)]);
+
+        // Nothing changes on disk: only the back-end reports the file.
+        let file = dir.join("reported.txt");
+        let sink = sink.recv_timeout(Duration::from_secs(1)).unwrap();
+        sink.send(
+            notify::Event::new(notify::EventKind::Create(notify::event::CreateKind::File))
+                .add_path(file.clone()),
+        );
+        let event = rx.recv_timeout(Duration::from_secs(2)).unwrap();
+        assert_eq!(event.paths, vec![file]);
+        assert!(event.kind.is_create());
+
+        watcher.unwatch(&dir).unwrap();
+        assert!(watched.lock().unwrap().is_empty());
+        drop(watcher);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn verify_sweep_catches_missed_changes() {
+        use crate::{EventSink, WatchBackend};
+        use std::sync::mpsc;
+
+        // A back-end that only reports what the test tells it to.
+        struct Deaf {
+            sink: EventSink,
+            sinks: mpsc::Sender<EventSink>,
+        }
+
+        impl WatchBackend for Deaf {
+            fn watch(&mut self, _: &Path, _: bool) -> Result<(), Error> {
+                self.sinks.send(self.sink.clone()).unwrap();
+                Ok(())
+            }
+
+            fn unwatch(&mut self, _: &Path) -> Result<(), Error> {
+                Ok(())
+            }
+        }
+
+        let dir = std::env::temp_dir().join("watchit_verify_sweep_test");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let dir = dir.canonicalize().unwrap();
+
+        let (sinks, sink) = mpsc::channel();
+        let (tx, rx) = mpsc::channel();
+        let watcher = Watcher::builder()
+            .debounce(Duration::from_millis(100))
+            .custom_backend(move |sink| {
+                Ok(Deaf {
+                    sink,
+                    sinks: sinks.clone(),
+                })
+            })
+            .verify(Duration::from_millis(300))
+            .build(move |result: DebounceEventResult| {
+                for event in result.unwrap_or_default() {
+                    tx.send(event).unwrap();
+                }
+            })
+            .unwrap();
+        watcher.watch(&dir).unwrap();
+        let sink = sink.recv_timeout(Duration::from_secs(1)).unwrap();
+
+        // The sweep also reports the directory, whose modification time changes too.
+        let paths_within = |timeout: Duration| {
+            sleep(timeout);
+            rx.try_iter()
+                .flat_map(|event| event.event.paths)
+                .filter(|path| path != &dir)
+                .collect::<Vec<_>>()
+        };
+
+        let missed = dir.join("missed.txt");
+        std::fs::write(&missed, "missed").unwrap();
+        assert_eq!(paths_within(Duration::from_millis(1000)), vec![missed]);
+
+        let reported = dir.join("reported.txt");
+        std::fs::write(&reported, "reported").unwrap();
+        sink.send(
+            notify::Event::new(notify::EventKind::Create(notify::event::CreateKind::File))
+                .add_path(reported.clone()),
+        );
+        assert_eq!(paths_within(Duration::from_millis(1000)), vec![reported]);
+
+        drop(watcher);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn network_filesystems_are_polled() {
+        let dir = std::env::temp_dir().join("watchit_network_poll_test");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let network = crate::network::filesystem_of(&dir).is_some();
+        let watcher = Watcher::builder()
+            .network_poll(Some(Duration::from_millis(200)))
+            .build(|_: DebounceEventResult| {})
+            .unwrap();
+        watcher.watch(&dir).unwrap();
+        assert_eq!(watcher.watch_info(&dir).unwrap().polled, network);
+        watcher.unwatch(&dir).unwrap();
+
+        let watcher = Watcher::builder()
+            .network_poll(None)
+            .build(|_: DebounceEventResult| {})
+            .unwrap();
+        watcher.watch(&dir).unwrap();
+        assert!(!watcher.watch_info(&dir).unwrap().polled);
+
+        drop(watcher);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn poll_hashing_detects_preserved_mtime() {
+        use std::sync::mpsc;
+
+        let dir = std::env::temp_dir().join("watchit_poll_hashing_test");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let dir = dir.canonicalize().unwrap();
+        let file = dir.join("config.txt");
+        std::fs::write(&file, "before").unwrap();
+        let modified = std::fs::metadata(&file).unwrap().modified().unwrap();
+
+        let (tx, rx) = mpsc::channel();
+        let watcher = Watcher::builder()
+            .debounce(Duration::from_millis(100))
+            .backend(crate::Backend::Poll {
+                interval: Duration::from_millis(100),
+            })
+            .poll_hashing(Some(1024))
+            .build(move |result: DebounceEventResult| {
+                for event in result.unwrap_or_default() {
+                    tx.send(event).unwrap();
+                }
+            })
+            .unwrap();
+        watcher.watch(&file).unwrap();
+
+        std::fs::write(&file, "after!").unwrap();
+        File::options()
+            .write(true)
+            .open(&file)
+            .unwrap()
+            .set_modified(modified)
+            .unwrap();
+        let event = rx.recv_timeout(Duration::from_secs(2)).unwrap();
+        assert_eq!(event.paths, vec![file.clone()]);
+        assert!(event.kind.is_modify());
+
+        drop(watcher);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn overflow_reports_error() {
+        use crate::{EventSink, WatchBackend};
+        use std::sync::mpsc;
+
+        struct Overflowing(EventSink);
+
+        impl WatchBackend for Overflowing {
+            fn watch(&mut self, _: &Path, _: bool) -> Result<(), Error> {
+                self.0.send(
+                    notify::Event::new(notify::EventKind::Other)
+                        .set_flag(notify::event::Flag::Rescan),
+                );
+                Ok(())
+            }
+
+            fn unwatch(&mut self, _: &Path) -> Result<(), Error> {
+                Ok(())
+            }
+        }
+
+        let (tx, rx) = mpsc::channel();
+        let watcher = Watcher::builder()
+            .debounce(Duration::from_millis(100))
+            .custom_backend(|sink| Ok(Overflowing(sink)))
+            .build(move |result: DebounceEventResult| {
+                tx.send(result).unwrap();
+            })
+            .unwrap();
+        let errors = watcher.errors();
+        watcher.watch(std::env::temp_dir()).unwrap();
+
+        let error = errors.recv_timeout(Duration::from_secs(2)).unwrap();
+        assert!(matches!(error, Error::Overflow));
+        assert!(rx
+            .try_iter()
+            .flat_map(Result::unwrap_or_default)
+            .all(|event| !event.need_rescan()));
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn close_write_waits_for_writer() {
+        use std::sync::mpsc;
+
+        let dir = std::env::temp_dir().join("watchit_close_write_test");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let dir = dir.canonicalize().unwrap();
+        let file = dir.join("output.log");
+        std::fs::write(&file, "").unwrap();
+
+        let (tx, rx) = mpsc::channel();
+        let watcher = Watcher::builder()
+            .debounce(Duration::from_millis(200))
+            .build(move |result: DebounceEventResult| {
+                for event in result.unwrap_or_default() {
+                    tx.send(event).unwrap();
+                }
+            })
+            .unwrap();
+        watcher
+            .watch_with_options(&dir, WatchOptions::new().close_write(true))
+            .unwrap();
+        assert!(watcher.watch_info(&dir).unwrap().close_write);
+
+        let mut writer = File::options().append(true).open(&file).unwrap();
+        writer.write_all(b"first half ").unwrap();
+        sleep(Duration::from_millis(600));
+        assert!(rx.try_iter().all(|event| event.paths != vec![file.clone()]));
+
+        writer.write_all(b"second half").unwrap();
+        drop(writer);
+        let event = rx.recv_timeout(Duration::from_secs(2)).unwrap();
+        assert_eq!(event.paths, vec![file.clone()]);
+        assert!(event.kind.is_modify());
+        sleep(Duration::from_millis(400));
+        assert!(rx.try_recv().is_err());
+
+        drop(watcher);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    #[cfg(all(feature = "fanotify", target_os = "linux"))]
+    #[ignore = "marks the whole mount of the temporary directory and needs CAP_SYS_ADMIN"]
+    fn fanotify_reports_writes() {
+        use std::sync::mpsc;
+
+        let dir = std::env::temp_dir().join("watchit_fanotify_test");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let dir = dir.canonicalize().unwrap();
+        let file = dir.join("audited.txt");
+        std::fs::write(&file, "").unwrap();
+
+        let (tx, rx) = mpsc::channel();
+        let built = Watcher::builder()
+            .debounce(Duration::from_millis(200))
+            .backend(crate::Backend::Fanotify {
+                permission_events: true,
+            })
+            .build(move |result: DebounceEventResult| {
+                for event in result.unwrap_or_default() {
+                    tx.send(event).unwrap();
+                }
+            });
+        // Without CAP_SYS_ADMIN there is nothing to test.
+        let Ok(watcher) = built else {
+            return;
+        };
+        watcher.watch(&dir).unwrap();
+
+        std::fs::write(&file, "written").unwrap();
+        // Waits for the modification instead of guessing how long the events take.
+        loop {
+            let event = rx.recv_timeout(Duration::from_secs(30)).unwrap();
+            assert_eq!(event.paths, vec![file.clone()]);
+            if event.kind.is_modify() {
+                break;
+            }
+        }
+
+        drop(watcher);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    #[cfg(all(feature = "ssh", unix))]
+    fn ssh_backend_reports_remote_changes() {
+        use crate::SshBackend;
+        use std::{os::unix::fs::PermissionsExt as _, sync::mpsc};
+
+        let dir = std::env::temp_dir().join("watchit_ssh_backend_test");
+        let _ = std::fs::remove_dir_all(&dir);
+        let remote = dir.join("remote");
+        std::fs::create_dir_all(&remote).unwrap();
+        let remote = remote.canonicalize().unwrap();
+
+        // Called as `ssh -o BatchMode=yes <destination> <command>`.
+        let ssh = dir.join("ssh");
+        std::fs::write(&ssh, "#!/bin/sh\nexec sh -c \"$4\"\n").unwrap();
+        std::fs::set_permissions(&ssh, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+        let (tx, rx) = mpsc::channel();
+        let program = ssh.clone();
+        let watcher = Watcher::builder()
+            .debounce(Duration::from_millis(100))
+            .custom_backend(move |sink| {
+                Ok(SshBackend::new(sink, "deploy@server")
+                    .interval(Duration::from_millis(200))
+                    .program(&program))
+            })
+            .build(move |result: DebounceEventResult| {
+                for event in result.unwrap_or_default() {
+                    tx.send(event).unwrap();
+                }
+            })
+            .unwrap();
+        watcher.watch(&remote).unwrap();
+        assert!(watcher.watch(remote.join("missing")).is_err());
+
+        let release = remote.join("release.tar");
+        std::fs::write(&release, "v2").unwrap();
+        // The directory is reported as well, as its modification time changes.
+        sleep(Duration::from_millis(1000));
+        let events: Vec<_> = rx.try_iter().collect();
+        assert!(events
+            .iter()
+            .any(|event| event.paths == vec![release.clone()] && event.kind.is_create()));
+
+        drop(watcher);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn watch_contents_reads_changed_file() {
+        let root = std::env::temp_dir().join("watchit_watch_contents_test");
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(&root).unwrap();
+        let file = root.join("config.txt");
+        std::fs::write(&file, b"first").unwrap();
+
+        let (sender, receiver) = mpsc::channel();
+        let watcher = Watcher::with_debounce(Duration::from_millis(100), |_| {}).unwrap();
+        watcher
+            .watch_contents(&file, move |contents| {
+                let _ = sender.send(contents);
+            })
+            .unwrap();
+
+        std::fs::write(&file, b"second").unwrap();
+        let contents = receiver.recv_timeout(Duration::from_secs(2)).unwrap();
+        assert_eq!(contents, b"second");
+
+        std::fs::write(root.join("config.txt.tmp"), b"third").unwrap();
+        std::fs::rename(root.join("config.txt.tmp"), &file).unwrap();
+        let contents = receiver.recv_timeout(Duration::from_secs(2)).unwrap();
+        assert_eq!(contents, b"third");
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn tail_delivers_appended_lines() {
+        use std::io::Write as _;
+
+        let root = std::env::temp_dir().join("watchit_tail_test");
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(&root).unwrap();
+        let log = root.join("app.log");
+        std::fs::write(&log, b"old line\n").unwrap();
+
+        let (sender, receiver) = mpsc::channel();
+        let watcher = Watcher::with_debounce(Duration::from_millis(100), |_| {}).unwrap();
+        watcher
+            .tail(&log, move |line| {
+                let _ = sender.send(line);
+            })
+            .unwrap();
+
+        let mut file = std::fs::OpenOptions::new().append(true).open(&log).unwrap();
+        file.write_all(b"first\nsec").unwrap();
+        assert_eq!(
+            receiver.recv_timeout(Duration::from_secs(2)).unwrap(),
+            "first"
+        );
+        file.write_all(b"ond\n").unwrap();
+        assert_eq!(
+            receiver.recv_timeout(Duration::from_secs(2)).unwrap(),
+            "second"
+        );
+        assert!(receiver.recv_timeout(Duration::from_millis(300)).is_err());
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn tail_follows_rotation() {
+        use std::io::Write as _;
+
+        let root = std::env::temp_dir().join("watchit_tail_rotation_test");
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(&root).unwrap();
+        let log = root.join("app.log");
+        std::fs::write(&log, b"old line\n").unwrap();
+
+        let (sender, receiver) = mpsc::channel();
+        let watcher = Watcher::with_debounce(Duration::from_millis(100), |_| {}).unwrap();
+        watcher
+            .tail(&log, move |line| {
+                let _ = sender.send(line);
+            })
+            .unwrap();
+        let next = || receiver.recv_timeout(Duration::from_secs(2)).unwrap();
+
+        std::fs::write(&log, b"after truncation\n").unwrap();
+        assert_eq!(next(), "after truncation");
+
+        let mut old = std::fs::OpenOptions::new().append(true).open(&log).unwrap();
+        std::fs::rename(&log, root.join("app.log.1")).unwrap();
+        old.write_all(b"late line\nunfinished").unwrap();
+        std::fs::write(&log, b"new file\n").unwrap();
+        assert_eq!(next(), "late line");
+        assert_eq!(next(), "unfinished");
+        assert_eq!(next(), "new file");
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn config_watcher_keeps_last_good_value() {
+        use crate::{ConfigWatcher, WatcherBuilder};
+
+        let root = std::env::temp_dir().join("watchit_config_watcher_test");
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(&root).unwrap();
+        let file = root.join("port.conf");
+        std::fs::write(&file, b"8080").unwrap();
+
+        let (sender, receiver) = mpsc::channel();
+        let config: ConfigWatcher<u16> = WatcherBuilder::new()
+            .debounce(Duration::from_millis(100))
+            .build_config(
+                &file,
+                |bytes| String::from_utf8_lossy(bytes).trim().parse::<u16>(),
+                move |result| {
+                    let _ =
+                        sender.send(result.map(|port| *port).map_err(|error| error.to_string()));
+                },
+            )
+            .unwrap();
+        let next = || receiver.recv_timeout(Duration::from_secs(2)).unwrap();
+        assert_eq!(next(), Ok(8080));
+
+        std::fs::write(&file, b"not a port").unwrap();
+        assert!(next().unwrap_err().contains("port.conf"));
+        assert_eq!(config.current().as_deref(), Some(&8080));
+
+        std::fs::write(&file, b"9090").unwrap();
+        assert_eq!(next(), Ok(9090));
+        assert_eq!(config.current().as_deref(), Some(&9090));
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn checksum_reports_old_and_new_digests() {
+        use crate::{ChecksumAlgorithm, ChecksumChange, Digest, EventKind, EventResult};
+
+        let root = std::env::temp_dir().join("watchit_checksum_test");
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(&root).unwrap();
+        let (sender, receiver) = mpsc::channel::<EventResult>();
+        let watcher = Watcher::builder()
+            .debounce(Duration::from_millis(200))
+            .build_with_event_handler(sender)
+            .unwrap();
+        watcher
+            .watch_with_options(
+                &root,
+                WatchOptions::new().checksum(ChecksumAlgorithm::Sha256),
+            )
+            .unwrap();
+        assert_eq!(
+            watcher.watch_info(&root).unwrap().checksum,
+            Some(ChecksumAlgorithm::Sha256)
+        );
+        let checksums = || -> Vec<ChecksumChange> {
+            receiver
+                .try_iter()
+                .flat_map(Result::unwrap)
+                .filter(|event| event.kind == EventKind::Modified)
+                .filter_map(|event| event.checksum)
+                .collect()
+        };
+        let digest = |bytes: &[u8]| Digest::of(ChecksumAlgorithm::Sha256, bytes);
+
+        let filename = root.join("data.bin");
+        std::fs::write(&filename, b"first").unwrap();
+        sleep(Duration::from_secs(1));
+        assert!(checksums().is_empty());
+        std::fs::write(&filename, b"second").unwrap();
+        sleep(Duration::from_secs(1));
+        let changes = checksums();
+        assert!(changes.contains(&ChecksumChange {
+            old: Some(digest(b"first")),
+            new: digest(b"second"),
+        }));
+        assert!(changes.iter().all(ChecksumChange::changed));
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn change_summary_tells_appends_from_rewrites() {
+        use crate::{ChangeSummary, EventKind, EventResult};
+        use std::io::Write;
+
+        let root = std::env::temp_dir().join("watchit_change_summary_test");
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(&root).unwrap();
+        let filename = root.join("app.log");
+        std::fs::write(&filename, b"first\n").unwrap();
+        let (sender, receiver) = mpsc::channel::<EventResult>();
+        let watcher = Watcher::builder()
+            .debounce(Duration::from_millis(200))
+            .build_with_event_handler(sender)
+            .unwrap();
+        watcher
+            .watch_with_options(&filename, WatchOptions::new().change_summary(true))
+            .unwrap();
+        let summaries = || -> Vec<ChangeSummary> {
+            receiver
+                .try_iter()
+                .flat_map(Result::unwrap)
+                .filter(|event| event.kind == EventKind::Modified)
+                .filter_map(|event| event.change)
+                .collect()
+        };
+
+        std::fs::OpenOptions::new()
+            .append(true)
+            .open(&filename)
+            .unwrap()
+            .write_all(b"second\n")
+            .unwrap();
+        sleep(Duration::from_secs(1));
+        assert!(summaries().contains(&ChangeSummary {
+            previous_size: 6,
+            size: 13,
+            bytes_added: 7,
+            bytes_removed: 0,
+            pure_append: true,
+        }));
+
+        std::fs::write(&filename, b"rewritten, longer\n").unwrap();
+        sleep(Duration::from_secs(1));
+        let summaries = summaries();
+        assert!(summaries.iter().any(|summary| summary.size == 18));
+        assert!(summaries.iter().all(|summary| !summary.pure_append));
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn key_changes_report_changed_pointers() {
+        use crate::{EventKind, EventResult};
+
+        let root = std::env::temp_dir().join("watchit_key_changes_test");
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(&root).unwrap();
+        let filename = root.join("app.toml");
+        std::fs::write(&filename, "name = \"app\"\n[server]\nport = 80\n").unwrap();
+        let (sender, receiver) = mpsc::channel::<EventResult>();
+        let watcher = Watcher::builder()
+            .debounce(Duration::from_millis(200))
+            .build_with_event_handler(sender)
+            .unwrap();
+        watcher
+            .watch_with_options(&filename, WatchOptions::new().key_changes(true))
+            .unwrap();
+        let keys = || -> Vec<Vec<String>> {
+            receiver
+                .try_iter()
+                .flat_map(Result::unwrap)
+                .filter(|event| event.kind == EventKind::Modified)
+                .map(|event| event.changed_keys)
+                .collect()
+        };
+
+        std::fs::write(&filename, "name = \"app\"\n[server]\nport = 8080\n").unwrap();
+        sleep(Duration::from_secs(1));
+        assert!(keys().contains(&vec!["/server/port".to_string()]));
+
+        std::fs::write(&filename, "name = \"app\n").unwrap();
+        sleep(Duration::from_secs(1));
+        assert!(keys().iter().all(Vec::is_empty));
+
+        std::fs::write(&filename, "name = \"renamed\"\n[server]\nport = 8080\n").unwrap();
+        sleep(Duration::from_secs(1));
+        assert!(keys().contains(&vec!["/name".to_string()]));
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn validate_reports_outcome() {
+        use crate::{EventKind, EventResult, Validation};
+
+        let root = std::env::temp_dir().join("watchit_validate_test");
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(&root).unwrap();
+        let filename = root.join("app.conf");
+        std::fs::write(&filename, "valid\n").unwrap();
+        let (sender, receiver) = mpsc::channel::<EventResult>();
+        let watcher = Watcher::builder()
+            .debounce(Duration::from_millis(200))
+            .build_with_event_handler(sender)
+            .unwrap();
+        let options = WatchOptions::new().validate(|path| {
+            let text = std::fs::read_to_string(path).map_err(|error| error.to_string())?;
+            if text.starts_with("valid") {
+                Ok(())
+            } else {
+                Err(format!("unexpected {:?}", text.trim()))
+            }
+        });
+        watcher.watch_with_options(&filename, options).unwrap();
+        let outcomes = || -> Vec<Option<Validation>> {
+            receiver
+                .try_iter()
+                .flat_map(Result::unwrap)
+                .filter(|event| event.kind == EventKind::Modified)
+                .map(|event| event.validation)
+                .collect()
+        };
+
+        std::fs::write(&filename, "broken\n").unwrap();
+        sleep(Duration::from_secs(1));
+        let invalid = Validation::Invalid("unexpected \"broken\"".to_string());
+        assert!(outcomes().contains(&Some(invalid)));
+
+        std::fs::write(&filename, "valid again\n").unwrap();
+        sleep(Duration::from_secs(1));
+        assert!(outcomes().contains(&Some(Validation::Valid)));
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn snapshots_keep_previous_versions() {
+        use crate::{EventKind, EventResult};
+
+        let root = std::env::temp_dir().join("watchit_snapshots_test");
+        let _ = std::fs::remove_dir_all(&root);
+        let watched = root.join("watched");
+        let snapshots = root.join("snapshots");
+        std::fs::create_dir_all(&watched).unwrap();
+        let filename = watched.join("app.conf");
+        std::fs::write(&filename, "1").unwrap();
+        let (sender, receiver) = mpsc::channel::<EventResult>();
+        let watcher = Watcher::builder()
+            .debounce(Duration::from_millis(200))
+            .build_with_event_handler(sender)
+            .unwrap();
+        watcher
+            .watch_with_options(&filename, WatchOptions::new().snapshots(&snapshots, 2))
+            .unwrap();
+        let snapshot = || -> Option<PathBuf> {
+            receiver
+                .try_iter()
+                .flat_map(Result::unwrap)
+                .filter(|event| event.kind == EventKind::Modified)
+                .find_map(|event| event.snapshot)
+        };
+
+        for version in ["2", "3", "4"] {
+            std::fs::write(&filename, version).unwrap();
+            sleep(Duration::from_secs(1));
+            let previous = snapshot().unwrap();
+            let expected = (version.parse::<u32>().unwrap() - 1).to_string();
+            assert_eq!(std::fs::read_to_string(previous).unwrap(), expected);
+        }
+        assert!(!snapshots.join("app.conf.~1~").exists());
+        assert_eq!(
+            std::fs::read_to_string(snapshots.join("app.conf.~2~")).unwrap(),
+            "2"
+        );
+        assert_eq!(
+            std::fs::read_to_string(snapshots.join("app.conf.~3~")).unwrap(),
+            "3"
+        );
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn env_watcher_reports_changed_variables() {
+        use crate::{EnvChange, WatcherBuilder};
+
+        let root = std::env::temp_dir().join("watchit_env_watcher_test");
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(&root).unwrap();
+        let file = root.join(".env");
+        std::fs::write(&file, "WATCHIT_TEST_PORT=8080\nWATCHIT_TEST_GONE=1\n").unwrap();
+
+        let (sender, receiver) = mpsc::channel();
+        let env = WatcherBuilder::new()
+            .debounce(Duration::from_millis(100))
+            .build_env(&file, true, move |change| {
+                let _ = sender.send(change);
+            })
+            .unwrap();
+        let next = || receiver.recv_timeout(Duration::from_secs(2)).unwrap();
+        assert_eq!(next().added.len(), 2);
+        assert_eq!(std::env::var("WATCHIT_TEST_PORT").as_deref(), Ok("8080"));
+
+        std::fs::write(&file, "WATCHIT_TEST_PORT=9090\nWATCHIT_TEST_NEW=\"a b\"\n").unwrap();
+        let expected = EnvChange {
+            added: [("WATCHIT_TEST_NEW".to_string(), "a b".to_string())].into(),
+            removed: [("WATCHIT_TEST_GONE".to_string(), "1".to_string())].into(),
+            changed: [(
+                "WATCHIT_TEST_PORT".to_string(),
+                ("8080".to_string(), "9090".to_string()),
+            )]
+            .into(),
+        };
+        assert_eq!(next(), expected);
+        assert_eq!(env.get("WATCHIT_TEST_PORT").as_deref(), Some("9090"));
+        assert_eq!(std::env::var("WATCHIT_TEST_PORT").as_deref(), Ok("9090"));
+        assert!(std::env::var("WATCHIT_TEST_GONE").is_err());
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn contents_report_encoding_flips() {
+        use crate::{Encoding, EventKind, EventResult};
+
+        let root = std::env::temp_dir().join("watchit_encoding_test");
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(&root).unwrap();
+        let filename = root.join("data");
+        std::fs::write(&filename, "text\n").unwrap();
+        let (sender, receiver) = mpsc::channel::<EventResult>();
+        let watcher = Watcher::builder()
+            .debounce(Duration::from_millis(200))
+            .contents(1024)
+            .build_with_event_handler(sender)
+            .unwrap();
+        watcher.watch(&filename).unwrap();
+        let encodings = || -> Vec<(Option<Encoding>, Option<Encoding>)> {
+            receiver
+                .try_iter()
+                .flat_map(Result::unwrap)
+                .filter(|event| event.kind == EventKind::Modified)
+                .map(|event| (event.encoding, event.previous_encoding))
+                .collect()
+        };
+
+        std::fs::write(&filename, "more text\n").unwrap();
+        sleep(Duration::from_secs(1));
+        assert!(encodings().contains(&(Some(Encoding::Utf8), None)));
+
+        std::fs::write(&filename, b"\x7fELF\x02\x01\x01\0\0\0").unwrap();
+        sleep(Duration::from_secs(1));
+        assert!(encodings().contains(&(Some(Encoding::Binary), Some(Encoding::Utf8))));
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn tree_watcher_indexes_changes() {
+        use crate::{EventResult, FileType, WatcherBuilder};
+
+        let root = std::env::temp_dir().join("watchit_tree_watcher_test");
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(root.join("sub")).unwrap();
+        let old = root.join("sub").join("old.txt");
+        std::fs::write(&old, "old").unwrap();
+        let (sender, receiver) = mpsc::channel::<EventResult>();
+        let tree = WatcherBuilder::new()
+            .debounce(Duration::from_secs(1))
+            .build_tree(&root, sender)
+            .unwrap();
+        assert_eq!(tree.len(), 3);
+        assert_eq!(tree.get(root.join("sub")).unwrap().file_type, FileType::Dir);
+        assert_eq!(tree.get(&old).unwrap().size, 3);
+        let started = std::time::SystemTime::now();
+
+        let new = root.join("new.txt");
+        std::fs::write(&new, "brand new").unwrap();
+        std::fs::remove_file(&old).unwrap();
+        receiver
+            .recv_timeout(Duration::from_secs(3))
+            .unwrap()
+            .unwrap();
+        sleep(Duration::from_millis(500));
+
+        let entry = tree.get(&new).unwrap();
+        assert_eq!(entry.size, 9);
+        assert!(entry.hash.is_some());
+        assert!(tree.get(&old).is_none());
+        let changed = tree.changed_since(started);
+        assert_eq!(changed.get(&new), Some(&Some(entry)));
+        assert_eq!(changed.get(&old), Some(&None));
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn tree_watcher_reports_offline_changes() {
+        use crate::{EventKind, EventResult, WatcherBuilder};
+
+        let root = std::env::temp_dir().join("watchit_tree_baseline_test");
+        let _ = std::fs::remove_dir_all(&root);
+        let tree_root = root.join("tree");
+        std::fs::create_dir_all(&tree_root).unwrap();
+        let index = root.join("index.json");
+        for name in ["kept", "modified", "removed", "moved"] {
+            std::fs::write(tree_root.join(name), name).unwrap();
+        }
+        let tree = WatcherBuilder::new()
+            .build_tree_with_baseline(&tree_root, &index, |_| {})
+            .unwrap();
+        tree.save_index(&index).unwrap();
+        drop(tree);
+
+        std::fs::write(tree_root.join("modified"), "changed while stopped").unwrap();
+        std::fs::remove_file(tree_root.join("removed")).unwrap();
+        std::fs::rename(tree_root.join("moved"), tree_root.join("renamed")).unwrap();
+        std::fs::write(tree_root.join("created"), "new").unwrap();
+
+        let (sender, receiver) = mpsc::channel::<EventResult>();
+        let tree = WatcherBuilder::new()
+            .debounce(Duration::from_millis(200))
+            .build_tree_with_baseline(&tree_root, &index, sender)
+            .unwrap();
+        sleep(Duration::from_millis(500));
+        let mut events: Vec<(EventKind, String)> = receiver
+            .try_iter()
+            .flat_map(Result::unwrap)
+            .map(|event| {
+                let name = event
+                    .path
+                    .file_name()
+                    .unwrap()
+                    .to_string_lossy()
+                    .into_owned();
+                (event.kind, name)
+            })
+            .collect();
+        events.sort_by(|a, b| a.1.cmp(&b.1));
+        assert_eq!(
+            events,
+            [
+                (EventKind::Created, "created".to_string()),
+                (EventKind::Modified, "modified".to_string()),
+                (EventKind::Removed, "removed".to_string()),
+                (EventKind::Renamed, "renamed".to_string()),
+            ]
+        );
+        assert!(tree.get(tree_root.join("renamed")).is_some());
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn save_state_restores_watches() {
+        use crate::{ChecksumAlgorithm, EventKind, WatcherBuilder};
+
+        let root = std::env::temp_dir().join("watchit_save_state_test");
+        let _ = std::fs::remove_dir_all(&root);
+        let tree_root = root.join("tree");
+        let other = root.join("other");
+        std::fs::create_dir_all(&tree_root).unwrap();
+        std::fs::create_dir_all(&other).unwrap();
+        std::fs::write(tree_root.join("data.txt"), "before").unwrap();
+        std::fs::write(other.join("config.toml"), "").unwrap();
+        let state = root.join("state.json");
+
+        let options = WatchOptions::new()
+            .checksum(ChecksumAlgorithm::Sha256)
+            .filter_set(FilterSet::new().exclude("**/*.tmp").unwrap());
+        let tree = WatcherBuilder::new()
+            .build_tree(&tree_root, |_| {})
+            .unwrap();
+        tree.watcher().watch_with_options(&other, options).unwrap();
+        tree.watcher()
+            .watch_tagged(other.join("config.toml"), "config")
+            .unwrap();
+        tree.watcher().save_state(&state).unwrap();
+        let saved = tree.watcher().watches();
+        drop(tree);
+
+        std::fs::write(tree_root.join("data.txt"), "changed while stopped").unwrap();
+
+        let (sender, receiver) = mpsc::channel::<EventResult>();
+        let tree = WatcherBuilder::new()
+            .debounce(Duration::from_millis(200))
+            .build_tree(&tree_root, sender)
+            .unwrap();
+        let report = tree.watcher().restore_state(&state).unwrap();
+        assert!(report.is_ok());
+        assert_eq!(report.watched.len(), 3);
+        let restored = tree.watcher().watches();
+        assert_eq!(restored.len(), saved.len());
+        for (restored, saved) in restored.iter().zip(&saved) {
+            assert_eq!(restored.path, saved.path);
+            assert_eq!(restored.recursive, saved.recursive);
+            assert_eq!(restored.tags, saved.tags);
+            assert_eq!(restored.checksum, saved.checksum);
+            assert_eq!(restored.filtered, saved.filtered);
+        }
+
+        sleep(Duration::from_millis(500));
+        let events: Vec<(EventKind, PathBuf)> = receiver
+            .try_iter()
+            .flat_map(Result::unwrap)
+            .map(|event| (event.kind, event.path))
+            .collect();
+        assert_eq!(events, [(EventKind::Modified, tree_root.join("data.txt"))]);
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn handler_can_unwatch_its_path() {
+        let root = std::env::temp_dir().join("watchit_unwatch_from_handler_test");
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(&root).unwrap();
+        let watcher = Watcher::with_debounce(Duration::from_millis(200), |_| {}).unwrap();
+        let (sender, receiver) = mpsc::channel();
+        let handle = watcher.clone();
+        let path = root.clone();
+        watcher
+            .watch_with(&root, move |_: DebounceEventResult| {
+                let _ = sender.send(handle.unwatch(&path).is_ok());
+            })
+            .unwrap();
+        std::fs::write(root.join("file.txt"), b"test").unwrap();
+        assert_eq!(receiver.recv_timeout(Duration::from_secs(5)), Ok(true));
+        assert!(watcher.watched_paths().is_empty());
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn shutdown_stops_clones() {
+        let root = std::env::temp_dir().join("watchit_shutdown_clones_test");
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(&root).unwrap();
+        let watcher = Watcher::with_debounce(Duration::from_millis(200), |_| {}).unwrap();
+        watcher.watch(&root).unwrap();
+        let clone = watcher.clone();
+        let (sender, receiver) = mpsc::channel();
+        std::thread::spawn(move || {
+            watcher.shutdown();
+            let _ = sender.send(());
+        });
+        assert_eq!(receiver.recv_timeout(Duration::from_secs(5)), Ok(()));
+        assert!(clone.watched_paths().is_empty());
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    #[cfg(target_os = "macos")]
+    fn fsevents_flags_reach_stream() {
+        use crate::{Backend, FsEventsFlags};
+
+        let dir = std::env::temp_dir().join("watchit_fsevents_flags_test");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let build = |flags| {
+            Watcher::builder()
+                .debounce(Duration::from_millis(100))
+                .backend(Backend::FsEvents {
+                    latency: Duration::from_millis(50),
+                    flags,
+                })
+                .build_channel()
+                .unwrap()
+        };
+        let (watcher, events) = build(FsEventsFlags::default());
+        let (ignoring, ignored) = build(FsEventsFlags::default() | FsEventsFlags::IGNORE_SELF);
+        watcher.watch(&dir).unwrap();
+        ignoring.watch(&dir).unwrap();
+
+        std::fs::write(dir.join("flags.txt"), "written").unwrap();
+        let event = events.recv_timeout(Duration::from_secs(10)).unwrap();
+        assert_eq!(event.path, dir.join("flags.txt"));
+        // Both streams have the same latency, so the ignoring one had the time to report.
+        sleep(Duration::from_millis(500));
+        assert!(ignored.try_recv().is_err());
+
+        drop((watcher, ignoring));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    #[cfg(windows)]
+    fn windows_buffer_overflow_reports_error() {
+        let dir = std::env::temp_dir().join("watchit_windows_overflow_test");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let watcher = Watcher::builder()
+            .debounce(Duration::from_millis(100))
+            .backend(crate::Backend::Windows {
+                // Room for about one record, so that a burst of changes cannot fit.
+                buffer_size: 64,
+                network_drives: true,
+            })
+            .build(|_: DebounceEventResult| {})
+            .unwrap();
+        let errors = watcher.errors();
+        watcher.watch(&dir).unwrap();
+
+        for index in 0..1000 {
+            std::fs::write(dir.join(format!("churn-{index}.txt")), "churn").unwrap();
+        }
+        let error = errors.recv_timeout(Duration::from_secs(10)).unwrap();
+        assert!(matches!(error, Error::Overflow), "{error:?}");
+
+        drop(watcher);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn restore_state_rejects_invalid_durations() {
+        let root = std::env::temp_dir().join("watchit_restore_durations_test");
+        let _ = std::fs::remove_dir_all(&root);
+        let watched = root.join("watched");
+        std::fs::create_dir_all(&watched).unwrap();
+        let state = root.join("state.json");
+
+        let watcher = Watcher::new(|_| {}).unwrap();
+        watcher
+            .watch_with_options(
+                &watched,
+                WatchOptions::new().debounce(Duration::new(1, 500_000_000)),
+            )
+            .unwrap();
+        watcher.save_state(&state).unwrap();
+        let files: Vec<_> = std::fs::read_dir(&root)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect();
+        assert_eq!(files.len(), 2, "{files:?}");
+
+        let saved = std::fs::read_to_string(&state).unwrap();
+        for (from, to) in [
+            (r#""nanos":500000000"#, r#""nanos":1000000000"#),
+            (r#""nanos":500000000"#, r#""nanos":18446744073709551615"#),
+        ] {
+            assert!(saved.contains(from), "{saved}");
+            std::fs::write(&state, saved.replace(from, to)).unwrap();
+            let restorer = Watcher::new(|_| {}).unwrap();
+            assert!(restorer.restore_state(&state).is_err());
+        }
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn failing_option_leaves_watches_unchanged() {
+        use crate::ChecksumAlgorithm;
+
+        let root = std::env::temp_dir().join("watchit_failing_option_test");
+        let _ = std::fs::remove_dir_all(&root);
+        let watched = root.join("watched");
+        let added = root.join("added");
+        std::fs::create_dir_all(&watched).unwrap();
+        std::fs::create_dir_all(&added).unwrap();
+        // Snapshots cannot be kept below a file.
+        let blocker = root.join("blocker");
+        std::fs::write(&blocker, "").unwrap();
+        let failing = || {
+            WatchOptions::new()
+                .checksum(ChecksumAlgorithm::Sha256)
+                .snapshots(blocker.join("snapshots"), 3)
+                .settle(Duration::from_millis(100))
+        };
+
+        let watcher = Watcher::new(|_| {}).unwrap();
+        watcher.watch(&watched).unwrap();
+        let before = watcher.watched_paths();
+
+        assert!(watcher.watch_with_options(&added, failing()).is_err());
+        assert_eq!(watcher.watched_paths(), before);
+        assert!(watcher.watch_with_options(&watched, failing()).is_err());
+        assert_eq!(watcher.watched_paths(), before);
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn watch_all_registers_like_watch() {
+        use crate::{EventSink, WatchBackend};
+
+        // A back-end that never reports anything, so only the sweep does.
+        struct Deaf;
+
+        impl WatchBackend for Deaf {
+            fn watch(&mut self, _: &Path, _: bool) -> Result<(), Error> {
+                Ok(())
+            }
+
+            fn unwatch(&mut self, _: &Path) -> Result<(), Error> {
+                Ok(())
+            }
+        }
+
+        let dir = std::env::temp_dir().join("watchit_watch_all_verify_test");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join("single")).unwrap();
+        std::fs::create_dir_all(dir.join("bulk")).unwrap();
+        let dir = dir.canonicalize().unwrap();
+        let single = dir.join("single");
+        let bulk = dir.join("bulk");
+
+        let (tx, rx) = mpsc::channel();
+        let watcher = Watcher::builder()
+            .debounce(Duration::from_millis(100))
+            .custom_backend(|_: EventSink| Ok(Deaf))
+            .verify(Duration::from_millis(300))
+            .build(move |result: DebounceEventResult| {
+                for event in result.unwrap_or_default() {
+                    tx.send(event).unwrap();
+                }
+            })
+            .unwrap();
+        watcher.watch(&single).unwrap();
+        let report = watcher.watch_all([&bulk]);
+        assert_eq!(report.watched, vec![bulk.clone()]);
+        assert!(report.failed.is_empty());
+
+        let mut expected = watcher.watch_info(&single).unwrap();
+        let actual = watcher.watch_info(&bulk).unwrap();
+        expected.path = bulk.clone();
+        expected.added = actual.added;
+        assert_eq!(actual, expected);
+
+        let missed = bulk.join("missed.txt");
+        std::fs::write(&missed, "missed").unwrap();
+        sleep(Duration::from_millis(1000));
+        let paths: Vec<_> = rx.try_iter().flat_map(|event| event.event.paths).collect();
+        assert!(paths.contains(&missed), "{paths:?}");
+
+        drop(watcher);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn panicking_filter_reports_an_error() {
+        let dir = std::env::temp_dir().join("watchit_panicking_filter_test");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let (tx, rx) = mpsc::channel();
+        let watcher = Watcher::builder()
+            .debounce(Duration::from_millis(100))
+            .filter(|_| panic!("filter failed"))
+            .build(move |result: DebounceEventResult| {
+                let _ = tx.send(result);
+            })
+            .unwrap();
+        watcher.watch(&dir).unwrap();
+
+        std::fs::write(dir.join("file.txt"), "content").unwrap();
+        let errors = loop {
+            match rx.recv_timeout(Duration::from_secs(2)).unwrap() {
+                Ok(_) => continue,
+                Err(errors) => break errors,
+            }
+        };
+        assert!(
+            errors[0].to_string().contains("filter failed"),
+            "{errors:?}"
+        );
+
+        drop(watcher);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn middleware_may_use_the_watcher() {
+        use std::sync::{OnceLock, Weak};
+
+        let root = std::env::temp_dir().join("watchit_middleware_reentrant_test");
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(&root).unwrap();
+        let renamed = root.join("renamed.txt");
+
+        let slot: Arc<OnceLock<Weak<Watcher>>> = Arc::new(OnceLock::new());
+        let (tx, rx) = mpsc::channel();
+        let watcher = Watcher::builder()
+            .debounce(Duration::from_millis(200))
+            .middleware({
+                let slot = slot.clone();
+                let renamed = renamed.clone();
+                move |mut event: Event| {
+                    let watcher = slot.get().and_then(Weak::upgrade)?;
+                    (!watcher.is_paused()).then_some(())?;
+                    event.path = renamed.clone();
+                    event.kind = crate::EventKind::Removed;
+                    Some(event)
+                }
+            })
+            .build(move |result: DebounceEventResult| {
+                for event in result.unwrap_or_default() {
+                    let _ = tx.send(event);
+                }
+            })
+            .unwrap();
+        let watcher = Arc::new(watcher);
+        slot.set(Arc::downgrade(&watcher)).unwrap();
+        watcher.watch(&root).unwrap();
+
+        std::fs::write(root.join("written.txt"), b"written").unwrap();
+        let event = rx.recv_timeout(Duration::from_secs(2)).unwrap();
+        assert_eq!(event.paths, vec![renamed]);
+        assert!(event.kind.is_remove(), "{event:?}");
+
+        drop(watcher);
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn max_depth_reregistration_unwatches_deeper_levels() {
+        let root = std::env::temp_dir().join("watchit_max_depth_rewatch_test");
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(root.join("a/b")).unwrap();
+
+        let (tx, rx) = mpsc::channel();
+        let watcher = Watcher::with_debounce(
+            Duration::from_millis(200),
+            move |result: DebounceEventResult| {
+                for event in result.unwrap_or_default() {
+                    let _ = tx.send(event);
+                }
+            },
+        )
+        .unwrap();
+        watcher
+            .watch_with_options(&root, WatchOptions::new().max_depth(3))
+            .unwrap();
+        watcher
+            .watch_with_options(&root, WatchOptions::new().max_depth(1))
+            .unwrap();
+        assert_eq!(watcher.watch_info(&root).unwrap().max_depth, Some(1));
+
+        std::fs::write(root.join("a/b/deep.txt"), b"1").unwrap();
+        std::fs::write(root.join("shallow.txt"), b"1").unwrap();
+        sleep(Duration::from_secs(1));
+        let paths: Vec<PathBuf> = rx
+            .try_iter()
+            .flat_map(|event| event.paths.clone())
+            .collect();
+        assert!(
+            paths.iter().any(|path| path.ends_with("shallow.txt")),
+            "{paths:?}"
+        );
+        assert!(
+            !paths.iter().any(|path| path.ends_with("deep.txt")),
+            "{paths:?}"
+        );
+
+        watcher.unwatch(&root).unwrap();
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn limited_watches_reject_their_own_debouncing() {
+        let root = std::env::temp_dir().join("watchit_limited_strategy_test");
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(&root).unwrap();
+        let watcher = Watcher::new(|_: DebounceEventResult| {}).unwrap();
+
+        let leading = WatchOptions::new()
+            .max_depth(2)
+            .strategy(DebounceStrategy::Leading);
+        assert!(watcher.watch_with_options(&root, leading).is_err());
+        let debounced = WatchOptions::new()
+            .follow_symlinks(true)
+            .debounce(Duration::from_millis(50));
+        assert!(watcher.watch_with_options(&root, debounced).is_err());
+        assert!(watcher.watch_info(&root).is_none());
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn failed_rewatch_keeps_previous_options() {
+        let root = std::env::temp_dir().join("watchit_failed_rewatch_test");
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(&root).unwrap();
+        let blocker = root.join("blocker");
+        std::fs::write(&blocker, b"not a directory").unwrap();
+        let watcher = Watcher::new(|_: DebounceEventResult| {}).unwrap();
+
+        let options = WatchOptions::new()
+            .kinds(crate::EventKindMask::CREATED)
+            .settle(Duration::from_millis(300))
+            .strategy(DebounceStrategy::Throttle);
+        watcher.watch_with_options(&root, options).unwrap();
+        let before = watcher.watch_info(&root).unwrap();
+
+        let failing = WatchOptions::new().snapshots(blocker.join("snapshots"), 2);
+        assert!(watcher.watch_with_options(&root, failing).is_err());
+        assert_eq!(watcher.watch_info(&root).unwrap(), before);
+        let state = watcher.lock();
+        assert_eq!(state.kinds.get(&root), Some(&crate::EventKindMask::CREATED));
+        let dispatcher = dispatch::lock(&state.dispatcher);
+        assert_eq!(
+            dispatcher.root_settle(&root),
+            Some(Duration::from_millis(300))
+        );
+        assert!(dispatcher.root_limit(&root).is_some());
+        drop(dispatcher);
+        drop(state);
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn depth_levels_are_verified() {
+        use crate::{EventSink, WatchBackend};
+
+        // A back-end that never reports anything, so only the sweep does.
+        struct Deaf;
+
+        impl WatchBackend for Deaf {
+            fn watch(&mut self, _: &Path, _: bool) -> Result<(), Error> {
+                Ok(())
+            }
+
+            fn unwatch(&mut self, _: &Path) -> Result<(), Error> {
+                Ok(())
+            }
+        }
+
+        let root = std::env::temp_dir().join("watchit_depth_verify_test");
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(root.join("level")).unwrap();
+        let root = root.canonicalize().unwrap();
+
+        let (tx, rx) = mpsc::channel();
+        let watcher = Watcher::builder()
+            .debounce(Duration::from_millis(100))
+            .custom_backend(|_: EventSink| Ok(Deaf))
+            .verify(Duration::from_millis(300))
+            .build(move |result: DebounceEventResult| {
+                for event in result.unwrap_or_default() {
+                    let _ = tx.send(event);
+                }
+            })
+            .unwrap();
+        watcher
+            .watch_with_options(&root, WatchOptions::new().max_depth(2))
+            .unwrap();
+
+        let missed = root.join("level/missed.txt");
+        std::fs::write(&missed, "missed").unwrap();
+        sleep(Duration::from_millis(1000));
+        let paths: Vec<_> = rx.try_iter().flat_map(|event| event.event.paths).collect();
+        assert!(paths.contains(&missed), "{paths:?}");
+
+        drop(watcher);
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+}
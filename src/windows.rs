@@ -0,0 +1,368 @@
+use std::{
+    collections::BTreeMap,
+    ffi::{c_void, OsString},
+    fs::{self, File},
+    io,
+    os::windows::{
+        ffi::{OsStrExt as _, OsStringExt as _},
+        fs::OpenOptionsExt as _,
+        io::{AsRawHandle as _, RawHandle},
+    },
+    path::{Path, PathBuf},
+    ptr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use notify::{
+    event::{CreateKind, Flag, ModifyKind, RemoveKind, RenameMode},
+    EventKind,
+};
+
+use crate::{Error, EventSink, WatchBackend};
+
+/// The largest buffer network shares accept.
+const NETWORK_BUFFER_SIZE: u32 = 64 * 1024;
+
+const FILE_LIST_DIRECTORY: u32 = 0x0001;
+const FILE_SHARE_ALL: u32 = 0x0001 | 0x0002 | 0x0004;
+const FILE_FLAG_BACKUP_SEMANTICS: u32 = 0x0200_0000;
+const DRIVE_REMOTE: u32 = 4;
+const ERROR_OPERATION_ABORTED: i32 = 995;
+const ERROR_NOTIFY_ENUM_DIR: i32 = 1022;
+
+/// The changes that are reported: names, attributes, sizes, writes, creations and security.
+const NOTIFY_FILTER: u32 = 0x0001 | 0x0002 | 0x0004 | 0x0008 | 0x0010 | 0x0040 | 0x0100;
+
+const FILE_ACTION_ADDED: u32 = 1;
+const FILE_ACTION_REMOVED: u32 = 2;
+const FILE_ACTION_MODIFIED: u32 = 3;
+const FILE_ACTION_RENAMED_OLD_NAME: u32 = 4;
+const FILE_ACTION_RENAMED_NEW_NAME: u32 = 5;
+
+#[link(name = "kernel32")]
+extern "system" {
+    fn ReadDirectoryChangesW(
+        directory: RawHandle,
+        buffer: *mut c_void,
+        length: u32,
+        subtree: i32,
+        filter: u32,
+        returned: *mut u32,
+        overlapped: *mut c_void,
+        completion: *const c_void,
+    ) -> i32;
+    fn CancelSynchronousIo(thread: RawHandle) -> i32;
+    fn GetVolumePathNameW(path: *const u16, volume: *mut u16, length: u32) -> i32;
+    fn GetDriveTypeW(root: *const u16) -> u32;
+}
+
+/// A back-end reading the changes of each watched directory with `ReadDirectoryChangesW`,
+/// see [`Backend::Windows`](crate::Backend::Windows).
+pub(crate) struct ReadDirectoryWatcher {
+    sink: EventSink,
+    buffer_size: u32,
+    network_drives: bool,
+    watches: BTreeMap<PathBuf, Watch>,
+}
+
+impl ReadDirectoryWatcher {
+    pub(crate) fn new(sink: EventSink, buffer_size: u32, network_drives: bool) -> Self {
+        Self {
+            sink,
+            buffer_size,
+            network_drives,
+            watches: BTreeMap::new(),
+        }
+    }
+}
+
+impl WatchBackend for ReadDirectoryWatcher {
+    fn watch(&mut self, path: &Path, recursive: bool) -> Result<(), Error> {
+        let io_error = |error| Error::io(error).add_path(path.to_path_buf());
+        let path = if path.is_absolute() {
+            path.to_path_buf()
+        } else {
+            std::env::current_dir().map_err(io_error)?.join(path)
+        };
+        // A file is watched through its directory, whose other changes are left out.
+        let (dir, file) = if fs::metadata(&path).map_err(io_error)?.is_dir() {
+            (path.clone(), None)
+        } else {
+            let parent = path.parent().map(Path::to_path_buf).unwrap_or_default();
+            (parent, Some(path.clone()))
+        };
+        let handle = fs::OpenOptions::new()
+            .access_mode(FILE_LIST_DIRECTORY)
+            .share_mode(FILE_SHARE_ALL)
+            .custom_flags(FILE_FLAG_BACKUP_SEMANTICS)
+            .open(&dir)
+            .map_err(io_error)?;
+        let buffer_size = if self.network_drives && is_remote(&dir) {
+            self.buffer_size.min(NETWORK_BUFFER_SIZE)
+        } else {
+            self.buffer_size
+        };
+
+        if let Some(previous) = self.watches.remove(&path) {
+            previous.stop();
+        }
+        let stop = Arc::new(AtomicBool::new(false));
+        let reader = Reader {
+            handle,
+            dir,
+            recursive: recursive && file.is_none(),
+            file,
+            buffer_size,
+            stop: Arc::clone(&stop),
+            sink: self.sink.clone(),
+        };
+        let thread = thread::Builder::new()
+            .name("watchit-read-directory".to_string())
+            .spawn(move || reader.run())
+            .map_err(io_error)?;
+        self.watches.insert(path, Watch { stop, thread });
+        Ok(())
+    }
+
+    fn unwatch(&mut self, path: &Path) -> Result<(), Error> {
+        let Some(watch) = self.watches.remove(path) else {
+            return Err(Error::watch_not_found().add_path(path.to_path_buf()));
+        };
+        watch.stop();
+        Ok(())
+    }
+}
+
+impl Drop for ReadDirectoryWatcher {
+    fn drop(&mut self) {
+        for watch in std::mem::take(&mut self.watches).into_values() {
+            watch.stop();
+        }
+    }
+}
+
+/// The reading thread of a watched directory.
+struct Watch {
+    stop: Arc<AtomicBool>,
+    thread: JoinHandle<()>,
+}
+
+impl Watch {
+    fn stop(self) {
+        self.stop.store(true, Ordering::Relaxed);
+        // The read may only start after a cancellation, which is why it is repeated.
+        while !self.thread.is_finished() {
+            // SAFETY: the handle of the thread stays valid until it is joined below.
+            unsafe { CancelSynchronousIo(self.thread.as_raw_handle()) };
+            thread::sleep(Duration::from_millis(1));
+        }
+        let _ = self.thread.join();
+    }
+}
+
+/// Reads the changes of a directory until it is stopped.
+struct Reader {
+    handle: File,
+    dir: PathBuf,
+    /// The only file whose changes are reported, if a file was watched.
+    file: Option<PathBuf>,
+    recursive: bool,
+    buffer_size: u32,
+    stop: Arc<AtomicBool>,
+    sink: EventSink,
+}
+
+impl Reader {
+    fn run(self) {
+        // Aligned for `FILE_NOTIFY_INFORMATION`.
+        let mut buffer = vec![0u64; (self.buffer_size as usize).div_ceil(8)];
+        while !self.stop.load(Ordering::Relaxed) {
+            let mut returned = 0;
+            // SAFETY: `buffer` is valid for writes of `buffer_size` bytes, and the call is
+            // synchronous as no `OVERLAPPED` is passed.
+            let read = unsafe {
+                ReadDirectoryChangesW(
+                    self.handle.as_raw_handle(),
+                    buffer.as_mut_ptr().cast(),
+                    self.buffer_size,
+                    i32::from(self.recursive),
+                    NOTIFY_FILTER,
+                    &mut returned,
+                    ptr::null_mut(),
+                    ptr::null(),
+                )
+            };
+            if read == 0 {
+                let error = io::Error::last_os_error();
+                match error.raw_os_error() {
+                    Some(ERROR_OPERATION_ABORTED) => continue,
+                    Some(ERROR_NOTIFY_ENUM_DIR) => self.overflowed(),
+                    _ => {
+                        self.sink.error(Error::io(error).add_path(self.dir.clone()));
+                        return;
+                    }
+                }
+                continue;
+            }
+            // The changes did not fit into the buffer, and were dropped.
+            if returned == 0 {
+                self.overflowed();
+                continue;
+            }
+            // SAFETY: the call wrote `returned` bytes at the start of `buffer`.
+            let bytes = unsafe {
+                std::slice::from_raw_parts(buffer.as_ptr().cast::<u8>(), returned as usize)
+            };
+            for event in parse(&self.dir, bytes) {
+                if self.file.is_none()
+                    || self.file.as_deref() == event.paths.first().map(PathBuf::as_path)
+                {
+                    self.sink.send(event);
+                }
+            }
+        }
+    }
+
+    /// Reports that changes were lost, which the watcher turns into an
+    /// [`Error::Overflow`].
+    fn overflowed(&self) {
+        tracing::debug!("The change buffer of {} overflowed", self.dir.display());
+        self.sink.send(
+            notify::Event::new(EventKind::Other)
+                .add_path(self.dir.clone())
+                .set_flag(Flag::Rescan),
+        );
+    }
+}
+
+/// Returns the events of the `FILE_NOTIFY_INFORMATION` records in `bytes`, whose names are
+/// relative to `dir`.
+fn parse(dir: &Path, bytes: &[u8]) -> Vec<notify::Event> {
+    let field = |offset: usize| {
+        bytes
+            .get(offset..offset + 4)
+            .and_then(|field| field.try_into().ok())
+            .map(u32::from_ne_bytes)
+    };
+    let mut events = Vec::new();
+    let mut offset = 0;
+    while let (Some(next), Some(action), Some(length)) =
+        (field(offset), field(offset + 4), field(offset + 8))
+    {
+        let name_start = offset + 12;
+        let Some(name) = bytes.get(name_start..name_start + length as usize) else {
+            break;
+        };
+        let name: Vec<u16> = name
+            .chunks_exact(2)
+            .map(|unit| u16::from_ne_bytes([unit[0], unit[1]]))
+            .collect();
+        let path = dir.join(OsString::from_wide(&name));
+        let kind = match action {
+            FILE_ACTION_ADDED => Some(EventKind::Create(CreateKind::Any)),
+            FILE_ACTION_REMOVED => Some(EventKind::Remove(RemoveKind::Any)),
+            FILE_ACTION_MODIFIED => Some(EventKind::Modify(ModifyKind::Any)),
+            FILE_ACTION_RENAMED_OLD_NAME => {
+                Some(EventKind::Modify(ModifyKind::Name(RenameMode::From)))
+            }
+            FILE_ACTION_RENAMED_NEW_NAME => {
+                Some(EventKind::Modify(ModifyKind::Name(RenameMode::To)))
+            }
+            _ => None,
+        };
+        if let Some(kind) = kind {
+            events.push(notify::Event::new(kind).add_path(path));
+        }
+        if next == 0 {
+            break;
+        }
+        offset += next as usize;
+    }
+    events
+}
+
+/// Returns `true` if `dir` is on a network drive.
+fn is_remote(dir: &Path) -> bool {
+    let path: Vec<u16> = dir.as_os_str().encode_wide().chain([0]).collect();
+    let mut volume = vec![0u16; path.len() + 1];
+    // SAFETY: `path` ends with a nul, and `volume` is valid for writes of its length.
+    unsafe {
+        GetVolumePathNameW(path.as_ptr(), volume.as_mut_ptr(), volume.len() as u32) != 0
+            && GetDriveTypeW(volume.as_ptr()) == DRIVE_REMOTE
+    }
+}
+
+#[cfg(test)]
+/// This module contains tests for the `ReadDirectoryChangesW` back-end.
+///
+/// The `parse_reads_records` test verifies that the records of a change buffer are turned
+/// into events below the watched directory.
+///
+/// The `overflow_reports_rescan` test verifies that a read that overflowed the buffer is
+/// reported as the notice of lost events that the watcher turns into an
+/// [`Error::Overflow`].
+mod tests {
+    use std::sync::mpsc;
+
+    use super::*;
+
+    /// Encodes a `FILE_NOTIFY_INFORMATION` record, pointing to the next one if `last` is not
+    /// set.
+    fn record(action: u32, name: &str, last: bool) -> Vec<u8> {
+        let name: Vec<u8> = name.encode_utf16().flat_map(u16::to_ne_bytes).collect();
+        let length = (12 + name.len()).next_multiple_of(4);
+        let next = if last { 0 } else { length as u32 };
+        let mut bytes = [next, action, name.len() as u32]
+            .into_iter()
+            .flat_map(u32::to_ne_bytes)
+            .chain(name)
+            .collect::<Vec<_>>();
+        bytes.resize(length, 0);
+        bytes
+    }
+
+    #[test]
+    fn parse_reads_records() {
+        let dir = Path::new(r"C:\watched");
+        let mut bytes = record(FILE_ACTION_RENAMED_OLD_NAME, "old.txt", false);
+        bytes.extend(record(FILE_ACTION_RENAMED_NEW_NAME, r"sub\new.txt", true));
+
+        let events = parse(dir, &bytes);
+        assert_eq!(events.len(), 2);
+        assert_eq!(
+            events[0].kind,
+            EventKind::Modify(ModifyKind::Name(RenameMode::From))
+        );
+        assert_eq!(events[0].paths, vec![dir.join("old.txt")]);
+        assert_eq!(
+            events[1].kind,
+            EventKind::Modify(ModifyKind::Name(RenameMode::To))
+        );
+        assert_eq!(events[1].paths, vec![dir.join(r"sub\new.txt")]);
+    }
+
+    #[test]
+    fn overflow_reports_rescan() {
+        let (tx, rx) = mpsc::channel();
+        let dir = std::env::temp_dir();
+        let reader = Reader {
+            handle: File::open(std::env::current_exe().unwrap()).unwrap(),
+            dir: dir.clone(),
+            file: None,
+            recursive: false,
+            buffer_size: 0,
+            stop: Arc::new(AtomicBool::new(true)),
+            sink: EventSink::new(tx),
+        };
+        reader.overflowed();
+
+        let event = rx.try_recv().unwrap().unwrap();
+        assert!(event.need_rescan());
+        assert_eq!(event.paths, vec![dir]);
+    }
+}